@@ -7,11 +7,16 @@ import coal from "../img/coal.png";
 import copper from "../img/copper.png";
 import stone from "../img/stone.png";
 import transport from "../img/transport.png";
+import transportCurve from "../img/transport-curve.png";
 import splitter from "../img/splitter.png";
+import balancer from "../img/balancer.png";
 import chest from "../img/chest.png";
+import requesterChest from "../img/requester-chest.png";
 import mine from "../img/mine.png";
+import electricMine from "../img/electric-mine.png";
 import assembler from "../img/assembler.png";
 import furnace from "../img/furnace.png";
+import electricFurnace from "../img/electric-furnace.png";
 import waterWell from "../img/waterwell.png";
 import offshorePump from "../img/offshore-pump.png";
 import boiler from "../img/boiler.png";
@@ -31,6 +36,16 @@ import gear from "../img/gear.png";
 import pipeItem from "../img/pipe-item.png";
 import steamEngine from "../img/steam-engine.png";
 import electPole from "../img/elect-pole.png";
+import accumulator from "../img/accumulator.png";
+import lab from "../img/lab.png";
+import lamp from "../img/lamp.png";
+import sciencePack from "../img/science-pack.png";
+import repairPack from "../img/repair-pack.png";
+import firearmMagazine from "../img/firearm-magazine.png";
+import gunTurret from "../img/gun-turret.png";
+import cliff from "../img/cliff.png";
+import tree from "../img/tree.png";
+import wood from "../img/wood.png";
 import smoke from "../img/smoke.png";
 import fuelAlarm from '../img/fuel-alarm.png';
 import electricityAlarm from '../img/electricity-alarm.png';
@@ -62,6 +77,8 @@ let unlimited = true;
     // we should have bitmaps ready.
     let loadImages = [
         ["dirt", dirt],
+        ["cliff", cliff],
+        ["tree", tree],
         ["backTiles", backTiles],
         ["weeds", weeds],
         ["iron", iron],
@@ -69,14 +86,23 @@ let unlimited = true;
         ["copper", copper],
         ["stone", stone],
         ["transport", transport],
+        ["transportCurve", transportCurve],
         ["chest", chest],
+        ["requesterChest", requesterChest],
         ["mine", mine],
+        ["electricMine", electricMine],
         ["furnace", furnace],
+        ["electricFurnace", electricFurnace],
         ["assembler", assembler],
         ["boiler", boiler],
         ["steamEngine", steamEngine],
         ["electPole", electPole],
+        ["accumulator", accumulator],
+        ["lab", lab],
+        ["lamp", lamp],
+        ["gunTurret", gunTurret],
         ["splitter", splitter],
+        ["balancer", balancer],
         ["waterWell", waterWell],
         ["offshorePump", offshorePump],
         ["pipe", pipe],
@@ -88,9 +114,14 @@ let unlimited = true;
         ["copperOre", copperOre],
         ["stoneOre", stoneOre],
         ["copperPlate", copperPlate],
+        ["steelPlate", steelPlate],
         ["gear", gear],
         ["copperWire", copperWire],
         ["circuit", circuit],
+        ["wood", wood],
+        ["sciencePack", sciencePack],
+        ["repairPack", repairPack],
+        ["firearmMagazine", firearmMagazine],
         ["time", time],
         ["smoke", smoke],
         ["fuelAlarm", fuelAlarm],
@@ -170,6 +201,14 @@ let unlimited = true;
     sliderInit("noiseThreshold", "noiseThresholdLabel", value => noiseThreshold = value);
     let noiseOctaves = 3;
     sliderInit("noiseOctaves", "noiseOctavesLabel", value => noiseOctaves = value);
+    let oreRichness = 1.;
+    sliderInit("oreRichness", "oreRichnessLabel", value => oreRichness = value);
+    let oreFrequency = 1.;
+    sliderInit("oreFrequency", "oreFrequencyLabel", value => oreFrequency = value);
+    let waterScale = 1.;
+    sliderInit("waterScale", "waterScaleLabel", value => waterScale = value);
+    const cliffsElem = document.getElementById("cliffs");
+    const waterWellDepletionElem = document.getElementById("waterWellDepletion");
 
     function initPane(buttonId, containerId){
         const button = document.getElementById(buttonId);
@@ -198,6 +237,11 @@ let unlimited = true;
             noise_scale: noiseScale,
             noise_threshold: noiseThreshold,
             noise_octaves: noiseOctaves,
+            ore_richness: oreRichness,
+            ore_frequency: oreFrequency,
+            water_scale: waterScale,
+            cliffs: cliffsElem.checked,
+            water_well_depletion: waterWellDepletionElem.checked,
         },
         updateInventory,
         scenarioSelectElem.value);
@@ -270,14 +314,31 @@ let unlimited = true;
     };
     miniMapElem.onmousemove = function(evt){
         if(miniMapDrag){
-            sim.delta_viewport_pos(
-                (evt.offsetX - miniMapDrag[0]) * tilesize,
-                (evt.offsetY - miniMapDrag[1]) * tilesize,
-                false);
+            const dx = evt.offsetX - miniMapDrag[0];
+            const dy = evt.offsetY - miniMapDrag[1];
+            // Dragging with shift scrolls the minimap alone
+            if(evt.shiftKey)
+                sim.pan_minimap(-dx, -dy);
+            else{
+                const scale = sim.get_minimap_scale();
+                sim.delta_viewport_pos(dx * scale * tilesize, dy * scale * tilesize, false);
+            }
             miniMapDrag = [evt.offsetX, evt.offsetY, true];
         }
     };
-    miniMapElem.onmouseup = (evt) => miniMapDrag = false;
+    miniMapElem.onwheel = (evt) => {
+        sim.set_minimap_scale(sim.get_minimap_scale() * (evt.deltaY < 0 ? 0.5 : 2));
+        evt.preventDefault();
+    };
+    // Make the minimap follow the view again after scrolling it
+    miniMapElem.ondblclick = (evt) => sim.reset_minimap_pan();
+    miniMapElem.onmouseup = (evt) => {
+        // A click without dragging jumps the view to the clicked location
+        if(miniMapDrag && !miniMapDrag[2]){
+            sim.minimap_click(evt.offsetX, evt.offsetY, miniMapSize, miniMapSize);
+        }
+        miniMapDrag = false;
+    };
     miniMapElem.onmouseleave = (evt) => miniMapDrag = false;
     container.appendChild(miniMapElem);
     miniMapElem.setAttribute("width", miniMapSize);
@@ -321,6 +382,106 @@ let unlimited = true;
     perfLabel.style.backgroundColor = "rgba(0, 0, 0, 0.75)";
     container.appendChild(perfLabel);
 
+    const alertsElem = document.createElement('div');
+    alertsElem.style.position = 'absolute';
+    alertsElem.style.left = '8px';
+    alertsElem.style.top = '8px';
+    alertsElem.style.maxHeight = '200px';
+    alertsElem.style.overflowY = 'auto';
+    alertsElem.style.textAlign = 'left';
+    alertsElem.style.backgroundColor = 'rgba(255, 191, 191, 0.75)';
+    alertsElem.style.border = '1px solid #f00';
+    alertsElem.style.display = 'none';
+    container.appendChild(alertsElem);
+    let lastAlerts = "";
+
+    const alertNames = {NoFuel: "No fuel", NoPower: "No power", OutputBlocked: "Output blocked"};
+    function updateAlerts(){
+        const alerts = sim.get_alerts();
+        // Rebuild the list only when it has changed, so that clicks are not lost
+        const alertsJson = JSON.stringify(alerts.map(alert => [alert.type, alert.x, alert.y]));
+        if(alertsJson === lastAlerts)
+            return;
+        lastAlerts = alertsJson;
+        while(alertsElem.firstChild) alertsElem.removeChild(alertsElem.firstChild);
+        alertsElem.style.display = alerts.length ? "block" : "none";
+        for(const alert of alerts){
+            const alertElem = document.createElement('div');
+            alertElem.style.cursor = 'pointer';
+            alertElem.style.padding = '0 4px';
+            alertElem.innerHTML = `${alertNames[alert.type]} at (${alert.x}, ${alert.y})`;
+            alertElem.addEventListener("click", () => sim.set_viewport_pos(alert.x + 0.5, alert.y + 0.5));
+            alertsElem.appendChild(alertElem);
+        }
+    }
+
+    const objectivesElem = document.createElement('div');
+    objectivesElem.style.position = 'absolute';
+    objectivesElem.style.left = '50%';
+    objectivesElem.style.top = '8px';
+    objectivesElem.style.transform = 'translateX(-50%)';
+    objectivesElem.style.textAlign = 'left';
+    objectivesElem.style.padding = '0 4px';
+    objectivesElem.style.backgroundColor = 'rgba(223, 255, 191, 0.75)';
+    objectivesElem.style.border = '1px solid #0a0';
+    objectivesElem.style.display = 'none';
+    container.appendChild(objectivesElem);
+    let lastObjectives = "";
+
+    function updateObjectives(){
+        const objectives = sim.get_objectives();
+        const objectivesJson = JSON.stringify(objectives);
+        if(objectivesJson === lastObjectives)
+            return;
+        lastObjectives = objectivesJson;
+        while(objectivesElem.firstChild) objectivesElem.removeChild(objectivesElem.firstChild);
+        objectivesElem.style.display = objectives.length ? "block" : "none";
+        for(const objective of objectives){
+            const objectiveElem = document.createElement('div');
+            objectiveElem.innerHTML = `${objective.completed ? "&#x2714;" : "&#x2610;"} ${objective.description} (${objective.progress}/${objective.count})`;
+            if(objective.completed)
+                objectiveElem.style.color = '#007f00';
+            objectivesElem.appendChild(objectiveElem);
+        }
+    }
+
+    const researchElem = document.createElement('div');
+    researchElem.style.position = 'absolute';
+    researchElem.style.right = '8px';
+    researchElem.style.top = '8px';
+    researchElem.style.textAlign = 'left';
+    researchElem.style.padding = '0 4px';
+    researchElem.style.backgroundColor = 'rgba(191, 223, 255, 0.75)';
+    researchElem.style.border = '1px solid #00f';
+    container.appendChild(researchElem);
+    let lastResearch = "";
+
+    function updateResearch(){
+        const research = sim.get_research_progress();
+        const researchJson = JSON.stringify(research);
+        if(researchJson === lastResearch)
+            return;
+        lastResearch = researchJson;
+        // Finished research may have unlocked tools on the tool belt
+        updateToolBarImage();
+        while(researchElem.firstChild) researchElem.removeChild(researchElem.firstChild);
+        for(const tech of research.technologies){
+            const techElem = document.createElement('div');
+            const effects = tech.unlocks.concat(
+                0 < tech.productivity ? [`+${Math.round(tech.productivity * 100)}% productivity`] : []);
+            techElem.innerHTML = `${tech.name} (${effects.join(", ")}): ${tech.researched ? "Done" : `${tech.progress}/${tech.cost}`}`;
+            if(tech.id === research.current)
+                techElem.style.fontWeight = 'bold';
+            if(!tech.researched && tech.available && tech.id !== research.current){
+                techElem.style.cursor = 'pointer';
+                techElem.addEventListener("click", () => sim.start_research(tech.id));
+            }
+            else if(!tech.available)
+                techElem.style.color = '#7f7f7f';
+            researchElem.appendChild(techElem);
+        }
+    }
+
     refreshSize();
 
     const toolBeltSize = 10;
@@ -367,6 +528,19 @@ let unlimited = true;
         toolTip.style.left = (r.left - cr.left) + 'px';
         toolTip.style.top = (r.top - cr.top - toolTipRect.height) + 'px';
     }
+    function itemRecipeText(name){
+        const info = sim.get_item_recipe_info(name);
+        const ingredients = items => Object.entries(items).map(([item, count]) => `${count} ${item}`).join(", ");
+        let text = `<b>${info.item}</b>`;
+        if(info.raw)
+            text += "<br>Raw resource";
+        for(const recipe of info.produced_by)
+            text += `<br>Made from ${ingredients(recipe.input)} in ${recipe.structures.join(", ")}`;
+        if(info.used_in.length)
+            text += `<br>Used in ${info.used_in.map(recipe => Object.keys(recipe.output).join(", ")).join(", ")}`;
+        return text;
+    }
+
     const renderToolTip = (elem, idx) => {
         const tool = sim.get_tool_desc(idx);
         let text = "";
@@ -534,18 +708,28 @@ let unlimited = true;
             return copperWire;
         case 'Circuit':
             return circuit;
+        case 'Wood':
+            return wood;
         case 'Transport Belt':
             return transport;
         case 'Splitter':
             return splitter;
+        case 'Balancer':
+            return balancer;
         case 'Inserter':
             return [inserter, 2];
         case 'Chest':
             return chest;
+        case 'Requester Chest':
+            return requesterChest;
         case 'Ore Mine':
             return [mine, 3];
+        case 'Electric Ore Mine':
+            return [electricMine, 3];
         case 'Furnace':
             return [furnace, 3];
+        case 'Electric Furnace':
+            return [electricFurnace, 3];
         case 'Assembler':
             return [assembler, 4];
         case 'Water Well':
@@ -555,11 +739,26 @@ let unlimited = true;
         case 'Boiler':
             return [boiler, 3];
         case 'Pipe':
+        case 'Valve':
             return pipeItem;
         case 'Steam Engine':
             return [steamEngine, 3];
         case 'Electric Pole':
             return electPole;
+        case 'Accumulator':
+            return accumulator;
+        case 'Lab':
+            return lab;
+        case 'Lamp':
+            return lamp;
+        case 'Science Pack':
+            return sciencePack;
+        case 'Repair Pack':
+            return repairPack;
+        case 'Firearm Magazine':
+            return firearmMagazine;
+        case 'Gun Turret':
+            return gunTurret;
         default:
             return "";
         }
@@ -589,17 +788,28 @@ let unlimited = true;
 
     function generateItemImage(i, iconSize, count){
         var img = document.createElement('div');
-        var imageFile = getImageFile(i);
-        img.style.backgroundImage = 'url(' + (imageFile instanceof Array ?
-            imageFile[0] : imageFile) + ')';
         var size = iconSize ? 32 : objViewSize;
         img.style.width = size + 'px';
         img.style.height = size + 'px';
         img.style.display = 'inline-block';
-        if(imageFile instanceof Array)
-            img.style.backgroundSize = size * imageFile[1] + 'px ' + size + 'px';
-        else
-            img.style.backgroundSize = size + 'px ' + size + 'px';
+        // Items have sprite descriptors in the simulation, which may pick a part of the image
+        var sprite = sim.get_item_sprite(i);
+        if(sprite){
+            img.style.backgroundImage = `url(${sprite.url})`;
+            var source = sprite.source || [0, 0, sprite.width, sprite.height];
+            var scaleX = size / source[2], scaleY = size / source[3];
+            img.style.backgroundSize = `${sprite.width * scaleX}px ${sprite.height * scaleY}px`;
+            img.style.backgroundPosition = `${-source[0] * scaleX}px ${-source[1] * scaleY}px`;
+        }
+        else{
+            var imageFile = getImageFile(i);
+            img.style.backgroundImage = 'url(' + (imageFile instanceof Array ?
+                imageFile[0] : imageFile) + ')';
+            if(imageFile instanceof Array)
+                img.style.backgroundSize = size * imageFile[1] + 'px ' + size + 'px';
+            else
+                img.style.backgroundSize = size + 'px ' + size + 'px';
+        }
         img.setAttribute('draggable', 'false');
         if(iconSize && count){
             var container = document.createElement('span');
@@ -671,6 +881,8 @@ let unlimited = true;
             div.setAttribute('class', 'noselect');
             div.itemName = name;
             div.itemAmount = v;
+            div.onmouseenter = (name => evt => setToolTip(evt.currentTarget, itemRecipeText(name)))(name);
+            div.onmouseleave = () => toolTip.style.display = 'none';
             /// Either clicking or start dragging will select the item, so that
             /// it can be moved on drop
             function selectThisItem(itemName){
@@ -864,6 +1076,18 @@ let unlimited = true;
         }
     }
 
+    function showCraftingProgress([c, r]){
+        const progress = sim.get_factory_progress(c, r);
+        const elem = document.getElementById('craftingProgressBack');
+        if(progress !== undefined){
+            elem.style.display = "block";
+            document.getElementById('craftingProgress').style.width = `${progress * 120}px`;
+        }
+        else{
+            elem.style.display = "none";
+        }
+    }
+
     function showInventory(event){
         if(inventoryElem.style.display !== "none"){
             inventoryElem.style.display = "none";
@@ -884,6 +1108,7 @@ let unlimited = true;
             updateInventoryInt(inventoryContentElem, sim, false, sim.get_structure_inventory(pos[0], pos[1], "Input"), inputInventoryTitleElem);
             updateInventoryInt(outputInventoryContentElem, sim, false, sim.get_structure_inventory(pos[0], pos[1], "Output"), outputInventoryTitleElem);
             showBurnerStatus(pos);
+            showCraftingProgress(pos);
         }
         else{
             inventoryElem.style.display = "block";
@@ -910,6 +1135,8 @@ let unlimited = true;
         inputBox.style.width = "50%";
         for(var k in recipe.input)
             inputBox.innerHTML += getHTML(generateItemImage(k, true, recipe.input[k]), true);
+        if(recipe.input_fluid)
+            inputBox.innerHTML += `<span>${recipe.input_fluid} ${recipe.input_fluid_amount}</span>`;
         recipeBox.appendChild(inputBox);
         const arrowImg = document.createElement("img");
         arrowImg.src = rightarrow;
@@ -954,9 +1181,9 @@ let unlimited = true;
             while(0 < recipeSelectorContent.childNodes.length)
                 recipeSelectorContent.removeChild(recipeSelectorContent.childNodes[0]);
             for(var i = 0; i < recipes.length; i++){
-                const index = i;
+                const id = recipes[i].id;
                 recipeSelectorContent.appendChild(recipeDraw(recipes[i], (evt) => {
-                    sim.select_recipe(recipeTarget[0], recipeTarget[1], index);
+                    sim.select_recipe(recipeTarget[0], recipeTarget[1], id);
                     recipeSelector.style.display = "none";
                 }));
             }
@@ -1015,6 +1242,41 @@ let unlimited = true;
     playerInventoryTitleElem.classList = "inventoryTitle";
     playerElem.appendChild(playerInventoryTitleElem);
 
+    function addInventoryButton(parent, text, title, onclick){
+        const button = document.createElement('button');
+        button.innerHTML = text;
+        button.title = title;
+        button.style.float = 'right';
+        button.style.fontSize = '10px';
+        button.style.padding = '0 2px';
+        button.onclick = (evt) => {
+            onclick();
+            evt.stopPropagation();
+        };
+        parent.appendChild(button);
+    }
+
+    addInventoryButton(playerInventoryTitleElem, "Sort", "Sort the player inventory", () => {
+        sim.sort_inventory(true, 0, 0);
+    });
+    addInventoryButton(playerInventoryTitleElem, "Quick stack",
+        "Move items that the open chest already has into it", () => {
+        const position = sim.get_selected_inventory();
+        if(!position)
+            return;
+        if(0 < sim.quick_stack_to_chest(...position)){
+            updateToolBar();
+            updateStructureInventory();
+        }
+    });
+    addInventoryButton(inputInventoryTitleElem, "Sort", "Sort the structure inventory", () => {
+        const position = sim.get_selected_inventory();
+        if(!position)
+            return;
+        sim.sort_inventory(false, ...position);
+        updateStructureInventory();
+    });
+
     const playerInventoryContainerElem = document.createElement('div');
     playerInventoryContainerElem.style.overflow = 'hidden';
     playerInventoryContainerElem.style.borderStyle = 'solid';
@@ -1071,7 +1333,9 @@ let unlimited = true;
 
     let dragging = null;
     canvas.addEventListener("mousedown", function(evt){
-        processEvents(sim.mouse_down([evt.offsetX, evt.offsetY], evt.button));
+        // Shift-click copies and pastes structure settings on mouseup
+        if(!evt.shiftKey)
+            processEvents(sim.mouse_down([evt.offsetX, evt.offsetY], evt.button));
         if(evt.button === 0)
             dragging = [evt.offsetX, evt.offsetY, false];
         evt.stopPropagation();
@@ -1091,7 +1355,14 @@ let unlimited = true;
     });
     canvas.addEventListener("mouseup", (evt) => {
         if(!dragging || !dragging[2]){
-            if(!paused)
+            const cursor = sim.get_cursor();
+            if(evt.shiftKey && cursor){
+                if(evt.button === 2)
+                    sim.copy_settings(cursor[0], cursor[1]);
+                else if(evt.button === 0 && sim.paste_settings(cursor[0], cursor[1]))
+                    updateStructureInventory(cursor);
+            }
+            else if(!paused)
                 processEvents(sim.mouse_up([evt.offsetX, evt.offsetY], evt.button));
         }
         dragging = null;
@@ -1110,8 +1381,31 @@ let unlimited = true;
         evt.preventDefault();
     });
 
+    // Arrow keys pan the viewport smoothly while they are held.
+    const PAN_SPEED = 20;
+    const panKeys = new Set();
+    function updatePanVelocity(){
+        const dir = (minus, plus) => (panKeys.has(plus) ? 1 : 0) - (panKeys.has(minus) ? 1 : 0);
+        sim.set_viewport_velocity(dir(37, 39) * PAN_SPEED, dir(38, 40) * PAN_SPEED);
+    }
+
     function onKeyDown(event){
-        const result = sim.on_key_down(event.keyCode);
+        if(37 <= event.keyCode && event.keyCode <= 40){
+            panKeys.add(event.keyCode);
+            updatePanVelocity();
+            event.preventDefault();
+            return;
+        }
+        // Shift and a number key switch the row of the tool belt
+        if(event.shiftKey && 49 <= event.keyCode && event.keyCode < 49 + sim.get_tool_row_count()){
+            sim.switch_tool_row(event.keyCode - 49);
+            updateToolBarImage();
+            updateToolBar();
+            updateToolCursor();
+            event.preventDefault();
+            return;
+        }
+        const result = sim.on_key_down(event.keyCode, event.ctrlKey);
         if(result){
             if(result[0] === "ShowInventory"){
                 showInventory();
@@ -1120,10 +1414,23 @@ let unlimited = true;
             updateToolCursor();
             event.preventDefault();
         }
-        else if(event.keyCode === 80)
+        else if(event.keyCode === 80){
             paused = !paused;
+            sim.set_paused(paused);
+        }
+        // Period advances the paused simulation by a step
+        else if(event.keyCode === 190 && paused)
+            processEvents(sim.step_once());
     }
     window.addEventListener( 'keydown', onKeyDown, false );
+    window.addEventListener('keyup', (event) => {
+        if(panKeys.delete(event.keyCode))
+            updatePanVelocity();
+    });
+    window.addEventListener('blur', () => {
+        panKeys.clear();
+        updatePanVelocity();
+    });
 
     try{
         sim.load_game();
@@ -1199,6 +1506,42 @@ let unlimited = true;
         loadFile.click();
     };
 
+    const saveSlotList = document.getElementById("saveSlotList");
+    function updateSaveSlots(){
+        while(saveSlotList.firstChild) saveSlotList.removeChild(saveSlotList.firstChild);
+        for(const save of sim.list_saves()){
+            const option = document.createElement("option");
+            option.value = save.name;
+            option.innerHTML = save.timestamp ? `${save.name} (${new Date(save.timestamp).toLocaleString()})` : save.name;
+            saveSlotList.appendChild(option);
+        }
+    }
+    updateSaveSlots();
+
+    document.getElementById("saveSlotButton").onclick = () => {
+        const name = document.getElementById("saveSlotName").value;
+        try{
+            sim.save_game_to(name);
+            updateSaveSlots();
+        }
+        catch(e){
+            alert(e);
+        }
+    };
+
+    document.getElementById("loadSlotButton").onclick = () => {
+        if(!saveSlotList.value)
+            return;
+        try{
+            sim.load_game_from(saveSlotList.value);
+            updateToolBar();
+            updateInventory(sim.get_player_inventory());
+        }
+        catch(e){
+            alert(e);
+        }
+    };
+
     updateToolBar();
 
     updateInventory(sim.get_player_inventory());
@@ -1219,6 +1562,12 @@ let unlimited = true;
             else if(event.ShowInventoryAt && event.ShowInventoryAt instanceof Object){
                 showInventory(event.ShowInventoryAt);
             }
+            else if(event.ObjectiveCompleted){
+                updateObjectives();
+            }
+            else if(event.ResearchCompleted){
+                updateResearch();
+            }
         }
     }
 
@@ -1244,6 +1593,11 @@ let unlimited = true;
                 noise_scale: noiseScale,
                 noise_threshold: noiseThreshold,
                 noise_octaves: noiseOctaves,
+                ore_richness: oreRichness,
+                ore_frequency: oreFrequency,
+                water_scale: waterScale,
+                cliffs: cliffsElem.checked,
+                water_well_depletion: waterWellDepletionElem.checked,
             },
             updateInventory,
             scenarioSelectElem.value);
@@ -1260,6 +1614,19 @@ let unlimited = true;
     showDebugFluidBox.addEventListener("click", () => sim.set_debug_fluidbox(showDebugFluidBox.checked));
     const showDebugPowerNetwork = document.getElementById("showDebugPowerNetwork");
     showDebugPowerNetwork.addEventListener("click", () => sim.set_debug_power_network(showDebugPowerNetwork.checked));
+    const showDebugPowerTint = document.getElementById("showDebugPowerTint");
+    showDebugPowerTint.addEventListener("click", () => sim.set_debug_power_tint(showDebugPowerTint.checked));
+    const showDebugItems = document.getElementById("showDebugItems");
+    showDebugItems.addEventListener("click", () => sim.set_debug_items(showDebugItems.checked));
+    const logLevel = document.getElementById("logLevel");
+    logLevel.value = sim.get_log_level();
+    logLevel.addEventListener("change", () => sim.set_log_level(logLevel.value));
+    const mergeDropItems = document.getElementById("mergeDropItems");
+    mergeDropItems.addEventListener("click", () => sim.set_merge_drop_items(mergeDropItems.checked));
+    const showPollution = document.getElementById("showPollution");
+    showPollution.addEventListener("click", () => sim.set_pollution_overlay(showPollution.checked));
+    const showFluidFlow = document.getElementById("showFluidFlow");
+    showFluidFlow.addEventListener("click", () => sim.set_fluid_flow_overlay(showFluidFlow.checked));
     const showPerfGraph = document.getElementById("showPerfGraph");
     showPerfGraph.addEventListener("click", updatePerfVisibility);
 
@@ -1271,33 +1638,25 @@ let unlimited = true;
     updatePerfVisibility();
 
     window.setInterval(function(){
-        if(!paused)
-            processEvents(sim.simulate(0.05));
+        processEvents(sim.simulate(0.05));
         let result = sim.render(ctx);
+        updateAlerts();
+        updateResearch();
+        updateObjectives();
 
         const selPos = sim.get_selected_inventory();
         if(selPos){
             showBurnerStatus(selPos);
+            showCraftingProgress(selPos);
         }
 
         const minimapData = sim.render_minimap(miniMapSize, miniMapSize);
-        const viewportScale = sim.get_viewport_scale();
         if(minimapData){
             (async () => {
                 const imageBitmap = await createImageBitmap(minimapData);
                 miniMapContext.fillStyle = "#7f7f7f";
                 miniMapContext.fillRect(0, 0, miniMapSize, miniMapSize);
                 miniMapContext.drawImage(imageBitmap, 0, 0, miniMapSize, miniMapSize, 0, 0, miniMapSize, miniMapSize);
-                miniMapContext.strokeStyle = "blue";
-                miniMapContext.lineWidth = 1.;
-                const miniMapRect = miniMapElem.getBoundingClientRect();
-                const viewport = canvas.getBoundingClientRect();
-                miniMapContext.strokeRect(
-                    (miniMapRect.width - viewport.width / 32. / viewportScale) / 2,
-                    (miniMapRect.height - viewport.height / 32. / viewportScale) / 2,
-                    viewport.width / 32. / viewportScale,
-                    viewport.height / 32. / viewportScale,
-                );
             })()
         }
 
@@ -0,0 +1,119 @@
+use super::{structure::Structure, FactorishState, Position, TILE_SIZE};
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+use web_sys::CanvasRenderingContext2d;
+
+#[derive(Serialize, Deserialize)]
+pub(crate) struct Accumulator {
+    position: Position,
+    power: f64,
+    max_power: f64,
+}
+
+impl Accumulator {
+    pub(crate) fn new(position: &Position) -> Self {
+        Accumulator {
+            position: *position,
+            power: 0.,
+            max_power: 5000.,
+        }
+    }
+}
+
+impl Structure for Accumulator {
+    fn name(&self) -> &str {
+        "Accumulator"
+    }
+
+    fn position(&self) -> &Position {
+        &self.position
+    }
+
+    fn draw(
+        &self,
+        state: &FactorishState,
+        context: &CanvasRenderingContext2d,
+        depth: i32,
+        is_toolbar: bool,
+    ) -> Result<(), JsValue> {
+        let (x, y) = (
+            self.position.x as f64 * TILE_SIZE,
+            self.position.y as f64 * TILE_SIZE,
+        );
+        if depth == 0 {
+            match state.image_accumulator.as_ref() {
+                Some(img) => {
+                    context.draw_image_with_image_bitmap(&img.bitmap, x, y)?;
+                }
+                None => return js_err!("accumulator image not available"),
+            }
+            return Ok(());
+        }
+        if depth == 1 && !is_toolbar {
+            // Charge level bar, drawn in the same way as the fluid box debug overlay
+            const BAR_MARGIN: f64 = 4.;
+            const BAR_WIDTH: f64 = 4.;
+            let bar_x = x + TILE_SIZE - BAR_MARGIN - BAR_WIDTH;
+            let full_height = TILE_SIZE - BAR_MARGIN * 2.;
+            context.save();
+            context.set_stroke_style(&js_str!("black"));
+            context.set_fill_style(&js_str!("black"));
+            context.fill_rect(bar_x, y + BAR_MARGIN, BAR_WIDTH, full_height);
+            context.stroke_rect(bar_x, y + BAR_MARGIN, BAR_WIDTH, full_height);
+            context.set_fill_style(&js_str!("#ffff00"));
+            let bar_height = if 0. < self.max_power {
+                self.power / self.max_power * full_height
+            } else {
+                0.
+            };
+            context.fill_rect(
+                bar_x,
+                y + TILE_SIZE - BAR_MARGIN - bar_height,
+                BAR_WIDTH,
+                bar_height,
+            );
+            context.restore();
+        }
+        Ok(())
+    }
+
+    fn desc(&self, _state: &FactorishState) -> String {
+        format!(
+            r#"Charge: {:.1}kJ / {:.0}kJ <div style='position: relative; width: 100px; height: 10px; background-color: #001f1f; margin: 2px; border: 1px solid #3f3f3f'>
+            <div style='position: absolute; width: {}px; height: 10px; background-color: #ffff00'></div></div>"#,
+            self.power,
+            self.max_power,
+            if 0. < self.max_power {
+                self.power / self.max_power * 100.
+            } else {
+                0.
+            }
+        )
+    }
+
+    fn power_sink(&self) -> bool {
+        true
+    }
+
+    fn power_source(&self) -> bool {
+        true
+    }
+
+    fn power_outlet(&mut self, demand: f64) -> Option<f64> {
+        let energy = demand.min(self.power);
+        self.power -= energy;
+        Some(energy)
+    }
+
+    fn power_storage(&self) -> Option<(f64, f64)> {
+        Some((self.power, self.max_power))
+    }
+
+    fn power_inlet(&mut self, energy: f64) -> f64 {
+        let energy = energy.min(self.max_power - self.power).max(0.);
+        self.power += energy;
+        energy
+    }
+
+    crate::serialize_impl!();
+}
@@ -0,0 +1,68 @@
+use super::{structure::Position, FactorishState};
+use serde::Serialize;
+use wasm_bindgen::prelude::*;
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum AlertType {
+    NoFuel,
+    NoPower,
+    /// The structure cannot output its products.
+    OutputBlocked,
+}
+
+/// A structure that is stalled, reported to the alert panel.
+#[derive(Serialize, Clone, Copy, Debug)]
+pub(crate) struct Alert {
+    #[serde(rename = "type")]
+    type_: AlertType,
+    x: i32,
+    y: i32,
+    /// Simulation time when the condition started, so the panel can show how long it has been stalled.
+    since: f64,
+}
+
+impl FactorishState {
+    /// Collect stalled structures into the alert list.
+    /// Alerts that are still active keep their original entry, and the resolved ones are removed.
+    pub(crate) fn update_alerts(&mut self) {
+        let mut alerts = Vec::with_capacity(self.alerts.len());
+        for structure in self.structure_iter() {
+            let type_ = if let Some(type_) = structure.alert() {
+                type_
+            } else {
+                continue;
+            };
+            let Position { x, y } = *structure.position();
+            if alerts
+                .iter()
+                .any(|alert: &Alert| alert.type_ == type_ && alert.x == x && alert.y == y)
+            {
+                continue;
+            }
+            let since = self
+                .alerts
+                .iter()
+                .find(|alert| alert.type_ == type_ && alert.x == x && alert.y == y)
+                .map(|alert| alert.since)
+                .unwrap_or(self.sim_time);
+            alerts.push(Alert { type_, x, y, since });
+        }
+        // Oldest alerts first, since they are most likely the cause of a stalled factory
+        alerts.sort_by(|a, b| {
+            a.since
+                .partial_cmp(&b.since)
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+        self.alerts = alerts;
+    }
+}
+
+#[wasm_bindgen]
+impl FactorishState {
+    /// Returns an array of active alerts in the form of
+    /// `{type: "NoFuel" | "NoPower" | "OutputBlocked", x, y, since}`,
+    /// where x and y are in tiles that can be passed to `set_viewport_pos`.
+    pub fn get_alerts(&self) -> Result<JsValue, JsValue> {
+        JsValue::from_serde(&self.alerts).map_err(|e| js_str!("Serialize error: {}", e))
+    }
+}
@@ -1,9 +1,16 @@
 use super::{
+    alerts::AlertType,
+    draw_direction_arrow,
     drop_items::DropItem,
     inventory::{Inventory, InventoryTrait},
     items::get_item_image_url,
+    power_network::{drain_power, full_power_ratio, power_ratio},
+    recipes::RECIPES,
     serialize_impl,
-    structure::{Structure, StructureDynIter, StructureId},
+    structure::{
+        default_output_side, RotateErr, Rotation, Structure, StructureDynIter, StructureId,
+    },
+    water_well::FluidBox,
     FactorishState, FrameProcResult, ItemType, Position, Recipe, TILE_SIZE,
 };
 use serde::{Deserialize, Serialize};
@@ -43,26 +50,70 @@ fn _recipe_html(state: &FactorishState, recipe: &Recipe) -> String {
 #[derive(Serialize, Deserialize)]
 pub(crate) struct Assembler {
     position: Position,
+    /// The side to drop the outputs on, shown with an arrow.
+    #[serde(default = "default_output_side")]
+    rotation: Rotation,
     input_inventory: Inventory,
     output_inventory: Inventory,
     progress: Option<f64>,
     power: f64,
     max_power: f64,
     recipe: Option<Recipe>,
+    #[serde(skip, default = "full_power_ratio")]
+    power_ratio: f64,
+    /// Drop full stacks of outputs on the ground, see `FactorishState::spill_output`.
+    #[serde(default)]
+    output_to_ground: bool,
+    /// Takes the fluid of a recipe with `input_fluid` from pipes, and is closed otherwise.
+    #[serde(default = "closed_fluid_box")]
+    input_fluid_box: FluidBox,
+}
+
+fn closed_fluid_box() -> FluidBox {
+    FluidBox::new(false, false)
 }
 
 impl Assembler {
     pub(crate) fn new(position: &Position) -> Self {
         Assembler {
             position: *position,
+            rotation: default_output_side(),
             input_inventory: Inventory::new(),
             output_inventory: Inventory::new(),
             progress: None,
             power: 0.,
             max_power: 20.,
             recipe: None,
+            power_ratio: full_power_ratio(),
+            output_to_ground: false,
+            input_fluid_box: closed_fluid_box(),
+        }
+    }
+
+    /// The fraction of the recipe that the fluid in the input fluid box can make progress,
+    /// which is unlimited if the recipe does not need a fluid.
+    fn fluid_progress(&self, recipe: &Recipe) -> f64 {
+        match recipe.input_fluid {
+            Some(fluid) if 0. < recipe.input_fluid_amount => {
+                if self.input_fluid_box.type_ == Some(fluid) {
+                    self.input_fluid_box.amount / recipe.input_fluid_amount
+                } else {
+                    0.
+                }
+            }
+            _ => f64::INFINITY,
         }
     }
+
+    /// Whether the inputs are enough to start the recipe. The fluid is consumed as the recipe
+    /// progresses, so it only needs to be enough for the first step.
+    fn can_start(&self, recipe: &Recipe) -> bool {
+        recipe
+            .input
+            .iter()
+            .all(|(item, count)| *count <= self.input_inventory.count_item(item))
+            && 1. / recipe.recipe_time <= self.fluid_progress(recipe)
+    }
 }
 
 impl Structure for Assembler {
@@ -70,10 +121,32 @@ impl Structure for Assembler {
         "Assembler"
     }
 
+    fn alert(&self) -> Option<AlertType> {
+        if self.recipe.is_some() && self.power == 0. {
+            Some(AlertType::NoPower)
+        } else {
+            None
+        }
+    }
+
     fn position(&self) -> &Position {
         &self.position
     }
 
+    fn rotate(&mut self, _others: &StructureDynIter) -> Result<(), RotateErr> {
+        self.rotation = self.rotation.next();
+        Ok(())
+    }
+
+    fn rotation(&self) -> Option<Rotation> {
+        Some(self.rotation)
+    }
+
+    fn set_rotation(&mut self, rotation: &Rotation) -> Result<(), ()> {
+        self.rotation = *rotation;
+        Ok(())
+    }
+
     fn draw(
         &self,
         state: &FactorishState,
@@ -110,6 +183,10 @@ impl Structure for Assembler {
             }
             return Ok(());
         }
+        if depth == 2 {
+            let (x, y) = (self.position.x as f64 * 32., self.position.y as f64 * 32.);
+            draw_direction_arrow((x, y), &self.rotation, state, context)?;
+        }
         if !is_toolbar && self.recipe.is_some() && self.power == 0. && state.sim_time % 1. < 0.5 {
             if let Some(img) = state.image_electricity_alarm.as_ref() {
                 let (x, y) = (self.position.x as f64 * 32., self.position.y as f64 * 32.);
@@ -127,7 +204,7 @@ impl Structure for Assembler {
             "{}<br>{}{}",
             if let Some(recipe) = &self.recipe {
                 // Progress bar
-                format!("{}{}{}{}",
+                format!("{}{}{}{}{}",
                     format!("Progress: {:.0}%<br>", self.progress.unwrap_or(0.) * 100.),
                     "<div style='position: relative; width: 100px; height: 10px; background-color: #001f1f; margin: 2px; border: 1px solid #3f3f3f'>",
                     format!("<div style='position: absolute; width: {}px; height: 10px; background-color: #ff00ff'></div></div>",
@@ -136,6 +213,11 @@ impl Structure for Assembler {
                     <div style='position: absolute; width: {}px; height: 10px; background-color: #ff00ff'></div></div>"#,
                     self.power,
                     if 0. < self.max_power { (self.power) / self.max_power * 100. } else { 0. }),
+                    if self.power_ratio < 1. {
+                        format!("<div style='color: #ff7f00'>Power: {:.0}%</div>", self.power_ratio * 100.)
+                    } else {
+                        "".to_string()
+                    },
                     )
                 + &generate_item_image(&_state.image_time.as_ref().unwrap().url, true, recipe.recipe_time as usize) + "<br>" +
                 "Outputs: <br>" +
@@ -145,39 +227,51 @@ impl Structure for Assembler {
             } else {
                 String::from("No recipe")
             },
-            format!("Input Items: <br>{}", self.input_inventory.describe()),
+            format!(
+                "{}Input Items: <br>{}",
+                if self.input_fluid_box.input_enable {
+                    self.input_fluid_box.desc() + "<br>"
+                } else {
+                    "".to_string()
+                },
+                self.input_inventory.describe()
+            ),
             format!("Output Items: <br>{}", self.output_inventory.describe())
         )
     }
 
+    fn idle(&self) -> bool {
+        let missing_ingredients = self
+            .recipe
+            .as_ref()
+            .map_or(true, |recipe| !self.can_start(recipe));
+        self.progress.is_none()
+            && missing_ingredients
+            && !(self.output_to_ground && !self.output_inventory.is_empty())
+    }
+
     fn frame_proc(
         &mut self,
         me: StructureId,
         state: &mut FactorishState,
         structures: &mut StructureDynIter,
     ) -> Result<FrameProcResult, ()> {
+        if self.output_to_ground {
+            let bb = self.bounding_box();
+            state.spill_output(&bb, self.rotation, &mut self.output_inventory, structures);
+        }
+        self.power_ratio = power_ratio(&state.power_networks, me);
         if let Some(recipe) = &self.recipe {
             let mut ret = FrameProcResult::None;
             // First, check if we need to refill the energy buffer in order to continue the current work.
             // Refill the energy from the fuel
             if self.power < recipe.power_cost {
-                let mut accumulated = 0.;
-                for network in &state
-                    .power_networks
-                    .iter()
-                    .find(|network| network.sinks.contains(&me))
-                {
-                    for id in network.sources.iter() {
-                        if let Some(source) = structures.get_mut(*id) {
-                            let demand = self.max_power - self.power - accumulated;
-                            if let Some(energy) = source.power_outlet(demand) {
-                                accumulated += energy;
-                                // console_log!("draining {:?}kJ of energy with {:?} demand, from {:?}, accumulated {:?}", energy, demand, structure.name(), accumulated);
-                            }
-                        }
-                    }
-                }
-                self.power += accumulated;
+                self.power += drain_power(
+                    &state.power_networks,
+                    structures,
+                    me,
+                    self.max_power - self.power,
+                );
             }
 
             if self.progress.is_none() {
@@ -185,14 +279,10 @@ impl Structure for Assembler {
                 // If we do, consume the ingredients and start the progress timer.
                 // We can't start as soon as the recipe is set because we may not have enough ingredients
                 // at the point we set the recipe.
-                if recipe
-                    .input
-                    .iter()
-                    .map(|(item, count)| count <= &self.input_inventory.count_item(item))
-                    .all(|b| b)
-                {
+                if self.can_start(recipe) {
                     for (item, count) in &recipe.input {
                         self.input_inventory.remove_items(item, *count);
+                        state.production_stats.add_consumed(item, *count);
                     }
                     self.progress = Some(0.);
                     ret = FrameProcResult::InventoryChanged(self.position);
@@ -201,9 +291,15 @@ impl Structure for Assembler {
 
             if let Some(prev_progress) = self.progress {
                 // Proceed only if we have sufficient energy in the buffer.
+                // The recipe stalls when the fluid runs out.
                 let progress = (self.power / recipe.power_cost)
                     .min(1. / recipe.recipe_time)
+                    .min(self.fluid_progress(recipe))
                     .min(1.);
+                if recipe.input_fluid.is_some() {
+                    self.input_fluid_box.amount -=
+                        progress.min(1. - prev_progress) * recipe.input_fluid_amount;
+                }
                 if 1. <= prev_progress + progress {
                     self.progress = None;
 
@@ -211,6 +307,9 @@ impl Structure for Assembler {
                     for output_item in &recipe.output {
                         self.output_inventory
                             .add_items(&output_item.0, *output_item.1);
+                        state
+                            .production_stats
+                            .add_produced(&output_item.0, *output_item.1);
                     }
                     return Ok(FrameProcResult::InventoryChanged(self.position));
                 } else {
@@ -225,7 +324,7 @@ impl Structure for Assembler {
 
     fn input(&mut self, o: &DropItem) -> Result<(), JsValue> {
         if let Some(recipe) = &self.recipe {
-            if 0 < recipe.input.count_item(&o.type_) || 0 < recipe.output.count_item(&o.type_) {
+            if recipe.input.contains_key(&o.type_) || recipe.output.contains_key(&o.type_) {
                 self.input_inventory.add_item(&o.type_);
                 return Ok(());
             } else {
@@ -275,122 +374,123 @@ impl Structure for Assembler {
         ret
     }
 
-    fn get_recipes(&self) -> std::borrow::Cow<[Recipe]> {
-        static RECIPES: once_cell::sync::Lazy<Vec<Recipe>> = once_cell::sync::Lazy::new(|| {
-            vec![
-                Recipe::new(
-                    hash_map!(ItemType::IronPlate => 2usize),
-                    hash_map!(ItemType::Gear => 1usize),
-                    20.,
-                    50.,
-                ),
-                Recipe::new(
-                    hash_map!(ItemType::IronPlate => 1usize, ItemType::Gear => 1usize),
-                    hash_map!(ItemType::TransportBelt => 1usize),
-                    20.,
-                    50.,
-                ),
-                Recipe::new(
-                    hash_map!(ItemType::TransportBelt => 2, ItemType::Gear => 2),
-                    hash_map!(ItemType::Splitter => 1),
-                    25.,
-                    40.,
-                ),
-                Recipe::new(
-                    hash_map!(ItemType::IronPlate => 5usize),
-                    hash_map!(ItemType::Chest => 1usize),
-                    20.,
-                    50.,
-                ),
-                Recipe::new(
-                    hash_map!(ItemType::StoneOre => 5usize),
-                    hash_map!(ItemType::Furnace => 1usize),
-                    20.,
-                    20.,
-                ),
-                Recipe::new(
-                    hash_map!(ItemType::CopperPlate => 1usize),
-                    hash_map!(ItemType::CopperWire => 2usize),
-                    20.,
-                    20.,
-                ),
-                Recipe::new(
-                    hash_map!(ItemType::IronPlate => 1, ItemType::CopperWire => 3usize),
-                    hash_map!(ItemType::Circuit => 1usize),
-                    20.,
-                    50.,
-                ),
-                Recipe::new(
-                    hash_map!(ItemType::IronPlate => 5, ItemType::Gear => 5, ItemType::Circuit => 3),
-                    hash_map!(ItemType::Assembler => 1),
-                    20.,
-                    120.,
-                ),
-                Recipe::new(
-                    hash_map!(ItemType::IronPlate => 1, ItemType::Gear => 1, ItemType::Circuit => 1),
-                    hash_map!(ItemType::Inserter => 1),
-                    20.,
-                    20.,
-                ),
-                Recipe::new(
-                    hash_map!(ItemType::IronPlate => 1, ItemType::Gear => 5, ItemType::Circuit => 3),
-                    hash_map!(ItemType::OreMine => 1),
-                    100.,
-                    100.,
-                ),
-                Recipe::new(
-                    hash_map!(ItemType::IronPlate => 2),
-                    hash_map!(ItemType::Pipe => 1),
-                    20.,
-                    20.,
-                ),
-                Recipe::new(
-                    hash_map!(ItemType::IronPlate => 5, ItemType::Gear => 5),
-                    hash_map!(ItemType::OffshorePump => 1),
-                    150.,
-                    150.,
-                ),
-                Recipe::new(
-                    hash_map!(ItemType::IronPlate => 5, ItemType::CopperPlate => 5),
-                    hash_map!(ItemType::Boiler => 1),
-                    100.,
-                    100.,
-                ),
-                Recipe::new(
-                    hash_map!(ItemType::IronPlate => 5, ItemType::Gear => 5, ItemType::CopperPlate => 5),
-                    hash_map!(ItemType::SteamEngine => 1),
-                    200.,
-                    200.,
-                ),
-                Recipe::new(
-                    hash_map!(ItemType::IronPlate => 2, ItemType::CopperWire => 2),
-                    hash_map!(ItemType::ElectPole => 1),
-                    20.,
-                    20.,
-                ),
-            ]
-        });
-
-        std::borrow::Cow::from(&RECIPES[..])
-    }
+    fn select_recipe(&mut self, id: &str) -> Result<bool, JsValue> {
+        let recipe = RECIPES
+            .get(id)
+            .filter(|entry| entry.structures.contains(&self.name()))
+            .ok_or_else(|| js_str!("recipe {:?} is not available in {}", id, self.name()))?
+            .recipe
+            .clone();
 
-    fn select_recipe(&mut self, index: usize) -> Result<bool, JsValue> {
-        self.recipe = Some(
-            self.get_recipes()
-                .get(index)
-                .ok_or_else(|| js_str!("recipes index out of bound {:?}", index))?
-                .clone(),
-        );
+        // Give back the ingredients of the recipe in progress, since it will never finish.
+        if let Some(old_recipe) = self.recipe.take() {
+            if self.progress.take().is_some() {
+                self.input_inventory.merge(old_recipe.input);
+            }
+        }
+
+        // Items that the new recipe does not use would be stuck in the input inventory,
+        // so eject them into the output inventory where inserters or the player can retrieve them.
+        let (keep, eject): (Inventory, Inventory) = std::mem::take(&mut self.input_inventory)
+            .into_iter()
+            .partition(|(item, _)| recipe.input.contains_key(item));
+        self.input_inventory = keep;
+        self.output_inventory.merge(eject);
+
+        // Pipes connect to the fluid box only if the recipe needs the fluid.
+        self.input_fluid_box.input_enable = recipe.input_fluid.is_some();
+        self.input_fluid_box.filter = recipe.input_fluid;
+
+        self.recipe = Some(recipe);
         Ok(true)
     }
 
+    fn set_output_to_ground(&mut self, enable: bool) -> Result<(), JsValue> {
+        self.output_to_ground = enable;
+        Ok(())
+    }
+
+    fn crafting_progress(&self) -> Option<f64> {
+        self.progress
+    }
+
     fn get_selected_recipe(&self) -> Option<&Recipe> {
         self.recipe.as_ref()
     }
 
+    fn fluid_box(&self) -> Option<Vec<&FluidBox>> {
+        Some(vec![&self.input_fluid_box])
+    }
+
+    fn fluid_box_mut(&mut self) -> Option<Vec<&mut FluidBox>> {
+        Some(vec![&mut self.input_fluid_box])
+    }
+
     fn power_sink(&self) -> bool {
         true
     }
 
+    fn power_demand(&self) -> f64 {
+        match &self.recipe {
+            Some(recipe) if self.power < recipe.power_cost => self.max_power - self.power,
+            _ => 0.,
+        }
+    }
+
     serialize_impl!();
 }
+
+#[test]
+fn test_switch_recipe_ejects_items() {
+    let mut assembler = Assembler::new(&Position::new(0, 0));
+
+    // Start crafting a gear, consuming 2 out of 3 iron plates like frame_proc does.
+    assembler.select_recipe("gear").unwrap();
+    assembler.input_inventory.add_items(&ItemType::IronPlate, 3);
+    assembler
+        .input_inventory
+        .remove_items(&ItemType::IronPlate, 2);
+    assembler.progress = Some(0.5);
+
+    assembler.select_recipe("copper-wire").unwrap();
+
+    assert!(assembler.progress.is_none());
+    assert_eq!(
+        assembler.input_inventory.count_item(&ItemType::IronPlate),
+        0
+    );
+    assert_eq!(
+        assembler.output_inventory.count_item(&ItemType::IronPlate),
+        3
+    );
+
+    let recovered = assembler.destroy_inventory();
+    assert_eq!(recovered.count_item(&ItemType::IronPlate), 3);
+}
+
+#[test]
+fn test_fluid_recipe() {
+    use super::water_well::FluidType;
+    let mut assembler = Assembler::new(&Position::new(0, 0));
+    assert!(!assembler.input_fluid_box.input_enable);
+
+    assembler.select_recipe("steel-plate").unwrap();
+    assert!(assembler.input_fluid_box.input_enable);
+    assert_eq!(assembler.input_fluid_box.filter, Some(FluidType::Water));
+    assembler.input_inventory.add_items(&ItemType::IronPlate, 5);
+    let recipe = assembler.recipe.clone().unwrap();
+    assert!(!assembler.can_start(&recipe));
+
+    // Enough water for a step lets it start, and the progress is limited by the water
+    assembler.input_fluid_box.type_ = Some(FluidType::Water);
+    assembler.input_fluid_box.amount = recipe.input_fluid_amount / 10.;
+    assert!(assembler.can_start(&recipe));
+    assert_eq!(assembler.fluid_progress(&recipe), 0.1);
+
+    assembler.select_recipe("gear").unwrap();
+    assert!(!assembler.input_fluid_box.input_enable);
+    assert_eq!(
+        assembler.fluid_progress(&RECIPES.get("gear").unwrap().recipe),
+        f64::INFINITY
+    );
+}
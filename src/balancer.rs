@@ -0,0 +1,318 @@
+use super::{
+    drop_items::DropItem,
+    structure::{
+        BoundingBox, ItemResponse, ItemResponseResult, RotateErr, Size, Structure,
+        StructureDynIter, StructureId,
+    },
+    transport_belt::snap_to_lane,
+    FactorishState, FrameProcResult, Position, Rotation, TILE_SIZE,
+};
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+use web_sys::CanvasRenderingContext2d;
+
+/// The number of belts that the balancer spans
+pub(crate) const LANES: usize = 4;
+
+/// Distributes the items from up to four belts evenly over the belts in front of it.
+/// Unlike the splitter, every item that passes the mid point goes to the next output
+/// in turn, skipping the outputs that have no belt or did not take the item.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct Balancer {
+    position: Position,
+    rotation: Rotation,
+    /// The output lane that the next item tries first
+    next_lane: usize,
+    /// Whether a belt or another movable structure takes the items out of each lane.
+    #[serde(skip)]
+    outputs: [bool; LANES],
+    /// Positions of the items routed in the last frame with the lanes they have tried as bits.
+    /// An item that is still at the same position failed to move into the lane.
+    #[serde(skip)]
+    prev_attempts: Vec<((i32, i32), u8)>,
+    #[serde(skip)]
+    attempts: Vec<((i32, i32), u8)>,
+}
+
+impl Balancer {
+    pub(crate) fn new(x: i32, y: i32, rotation: Rotation) -> Self {
+        Balancer {
+            position: Position { x, y },
+            rotation,
+            next_lane: 0,
+            outputs: [false; LANES],
+            prev_attempts: vec![],
+            attempts: vec![],
+        }
+    }
+
+    /// The tile of the lane in this balancer, where lane 0 is the balancer's position and
+    /// the others are shifted to the right of the flow.
+    fn lane_position(&self, lane: usize) -> Position {
+        let shift = self.rotation.next().delta();
+        Position::new(
+            self.position.x + shift.0 * lane as i32,
+            self.position.y + shift.1 * lane as i32,
+        )
+    }
+
+    fn update_outputs(&mut self, other: &dyn Structure, construct: bool) {
+        for lane in 0..LANES {
+            let output = self.lane_position(lane).add(self.rotation.delta());
+            if other.contains(&output) {
+                self.outputs[lane] = construct && other.movable();
+            }
+        }
+    }
+
+    /// Decide the output lane for an item that passes the mid point, in a round robin over
+    /// the connected outputs. If no output is connected, the items go to all lanes in turn.
+    fn output_lane(&mut self, item: &DropItem) -> usize {
+        let pos = (item.x, item.y);
+        let any_connected = self.outputs.iter().any(|connected| *connected);
+        let candidates = (0..LANES)
+            .map(|i| (self.next_lane + i) % LANES)
+            .filter(|lane| !any_connected || self.outputs[*lane])
+            .collect::<Vec<_>>();
+        let mut tried = self
+            .prev_attempts
+            .iter()
+            .find(|(prev, _)| *prev == pos)
+            .map_or(0, |(_, tried)| *tried);
+        let lane = match candidates.iter().find(|lane| tried & (1 << *lane) == 0) {
+            Some(lane) => *lane,
+            None => {
+                // Every output is blocked, so start over in the next frame.
+                tried = 0;
+                candidates.first().copied().unwrap_or(self.next_lane)
+            }
+        };
+        self.next_lane = (lane + 1) % LANES;
+        self.attempts.push((pos, tried | 1 << lane));
+        lane
+    }
+}
+
+impl Structure for Balancer {
+    fn name(&self) -> &str {
+        "Balancer"
+    }
+
+    fn position(&self) -> &Position {
+        &self.position
+    }
+
+    fn size(&self) -> Size {
+        Size {
+            width: 1,
+            height: LANES as i32,
+        }
+    }
+
+    fn bounding_box(&self) -> BoundingBox {
+        let last = self.lane_position(LANES - 1);
+        BoundingBox {
+            x0: self.position.x.min(last.x),
+            y0: self.position.y.min(last.y),
+            x1: self.position.x.max(last.x) + 1,
+            y1: self.position.y.max(last.y) + 1,
+        }
+    }
+
+    fn draw(
+        &self,
+        state: &FactorishState,
+        context: &CanvasRenderingContext2d,
+        depth: i32,
+        _is_toolbar: bool,
+    ) -> Result<(), JsValue> {
+        if depth != 0 && depth != 1 {
+            return Ok(());
+        }
+        let mut ret = Ok(());
+        let (x, y) = (self.position.x as f64 * 32., self.position.y as f64 * 32.);
+        context.save();
+        context.translate(x + 16., y + 16.)?;
+        context.rotate(self.rotation.angle_rad())?;
+        context.translate(-(x + 16.), -(y + 16.))?;
+        if depth == 0 {
+            if let Some(belt) = state.image_belt.as_ref() {
+                for n in 0..LANES {
+                    for i in 0..2 {
+                        context
+                            .draw_image_with_image_bitmap_and_sw_and_sh_and_dx_and_dy_and_dw_and_dh(
+                                &belt.bitmap,
+                                i as f64 * 32. - (state.sim_time * 16.) % 32.,
+                                0.,
+                                32.,
+                                32.,
+                                x,
+                                y + n as f64 * TILE_SIZE,
+                                32.,
+                                32.,
+                            )?;
+                    }
+                }
+            } else {
+                ret = js_err!("belt image not available");
+            }
+        } else if let Some(balancer) = state.image_balancer.as_ref() {
+            // Highlight the lane that the next item goes to
+            for n in 0..LANES {
+                context.draw_image_with_image_bitmap_and_sw_and_sh_and_dx_and_dy_and_dw_and_dh(
+                    &balancer.bitmap,
+                    0.,
+                    (n == self.next_lane) as i32 as f64 * TILE_SIZE,
+                    TILE_SIZE,
+                    TILE_SIZE,
+                    x,
+                    y + n as f64 * TILE_SIZE,
+                    TILE_SIZE,
+                    TILE_SIZE,
+                )?;
+            }
+        } else {
+            ret = js_err!("balancer image not available");
+        }
+        context.restore();
+
+        ret
+    }
+
+    fn desc(&self, _state: &FactorishState) -> String {
+        format!(
+            "Connected outputs: {}/{}",
+            self.outputs.iter().filter(|connected| **connected).count(),
+            LANES
+        )
+    }
+
+    fn frame_proc(
+        &mut self,
+        _me: StructureId,
+        _state: &mut FactorishState,
+        _structures: &mut StructureDynIter,
+    ) -> Result<FrameProcResult, ()> {
+        self.prev_attempts = std::mem::take(&mut self.attempts);
+        Ok(FrameProcResult::None)
+    }
+
+    fn on_construction(
+        &mut self,
+        _other_id: StructureId,
+        other: &dyn Structure,
+        construct: bool,
+    ) -> Result<(), JsValue> {
+        self.update_outputs(other, construct);
+        Ok(())
+    }
+
+    fn on_construction_self(
+        &mut self,
+        _id: StructureId,
+        others: &StructureDynIter,
+        construct: bool,
+    ) -> Result<(), JsValue> {
+        self.outputs = [false; LANES];
+        if construct {
+            for (_, other) in others.dyn_iter_id() {
+                self.update_outputs(other, true);
+            }
+        }
+        Ok(())
+    }
+
+    fn movable(&self) -> bool {
+        true
+    }
+
+    fn walkable(&self) -> bool {
+        true
+    }
+
+    fn rotate(&mut self, others: &StructureDynIter) -> Result<(), RotateErr> {
+        self.rotation = self.rotation.next();
+        self.outputs = [false; LANES];
+        for (_, other) in others.dyn_iter_id() {
+            self.update_outputs(other, true);
+        }
+        Ok(())
+    }
+
+    fn rotation(&self) -> Option<Rotation> {
+        Some(self.rotation)
+    }
+
+    fn set_rotation(&mut self, rotation: &Rotation) -> Result<(), ()> {
+        self.rotation = *rotation;
+        Ok(())
+    }
+
+    /// Items move straight like on a splitter, and change the lane where they pass
+    /// the mid point of the balancer.
+    fn item_response(&mut self, item: &DropItem) -> Result<ItemResponseResult, ()> {
+        let (vx, vy) = self.rotation.delta();
+        let mut ax = if self.rotation.is_vertial() {
+            snap_to_lane(item.x) as f64
+        } else {
+            item.x as f64
+        };
+        let mut ay = if self.rotation.is_horizontal() {
+            snap_to_lane(item.y) as f64
+        } else {
+            item.y as f64
+        };
+        let halftilesize = TILE_SIZE / 2.;
+
+        let (along, velocity) = if self.rotation.is_horizontal() {
+            (ax, vx)
+        } else {
+            (ay, vy)
+        };
+        let crossing = ((along + halftilesize) / TILE_SIZE).floor()
+            != ((along + velocity as f64 + halftilesize) / TILE_SIZE).floor();
+
+        if crossing {
+            let lane = self.output_lane(item);
+            let lane = self.lane_position(lane);
+            if self.rotation.is_horizontal() {
+                ay = lane.y as f64 * TILE_SIZE + ay.rem_euclid(TILE_SIZE);
+            } else {
+                ax = lane.x as f64 * TILE_SIZE + ax.rem_euclid(TILE_SIZE);
+            }
+        }
+
+        Ok((ItemResponse::Move(ax as i32 + vx, ay as i32 + vy), None))
+    }
+
+    crate::serialize_impl!();
+}
+
+#[test]
+fn test_balancer_round_robin() {
+    use super::items::ItemType;
+    let mut balancer = Balancer::new(0, 0, Rotation::Right);
+    let bb = balancer.bounding_box();
+    assert_eq!((bb.x0, bb.y0, bb.x1, bb.y1), (0, 0, 1, 4));
+    balancer.set_rotation(&Rotation::Top).unwrap();
+    let bb = balancer.bounding_box();
+    assert_eq!((bb.x0, bb.y0, bb.x1, bb.y1), (0, 0, 4, 1));
+
+    balancer.outputs = [true, false, true, true];
+    let mut lanes = vec![];
+    for i in 0..4 {
+        lanes.push(balancer.output_lane(&DropItem::new(ItemType::IronOre, i * 8, 0)));
+    }
+    assert_eq!(lanes, [0, 2, 3, 0]);
+
+    // An item that is still at the same position in the next frame was blocked,
+    // so it tries the next connected lane.
+    balancer.prev_attempts = std::mem::take(&mut balancer.attempts);
+    let blocked = DropItem::new(ItemType::IronOre, 24, 0);
+    assert_eq!(balancer.output_lane(&blocked), 2);
+    balancer.prev_attempts = std::mem::take(&mut balancer.attempts);
+    assert_eq!(balancer.output_lane(&blocked), 3);
+    balancer.prev_attempts = std::mem::take(&mut balancer.attempts);
+    // Every connected lane has been tried, so it starts over.
+    assert_eq!(balancer.output_lane(&blocked), 0);
+}
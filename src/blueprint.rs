@@ -0,0 +1,441 @@
+use super::{
+    ghost::Ghost,
+    items::{item_to_str, str_to_item, ItemType},
+    replay::ReplayInput,
+    structure::BoundingBox,
+    FactorishState, Position, Recipe, Rotation,
+};
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+
+/// A structure in a blueprint, with position relative to the top left corner of the captured region.
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct BlueprintEntry {
+    type_: ItemType,
+    x: i32,
+    y: i32,
+    #[serde(default)]
+    rotation: Option<Rotation>,
+    #[serde(default)]
+    recipe: Option<Recipe>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct Blueprint {
+    entries: Vec<BlueprintEntry>,
+}
+
+fn flip_rotation(rotation: Rotation) -> Rotation {
+    match rotation {
+        Rotation::Left => Rotation::Right,
+        Rotation::Right => Rotation::Left,
+        _ => rotation,
+    }
+}
+
+impl Blueprint {
+    /// Mirror the blueprint horizontally if `flip`, then rotate it clockwise by `quarter_turns`
+    /// times 90 degrees, keeping the top left corner of the whole footprint in place.
+    /// `bounding_box` returns the tiles that the structure of an entry occupies, which may
+    /// extend to the left of or above its position depending on the rotation, like a splitter.
+    fn transform(
+        &mut self,
+        bounding_box: impl Fn(&BlueprintEntry) -> Result<BoundingBox, JsValue>,
+        quarter_turns: u32,
+        flip: bool,
+    ) -> Result<(), JsValue> {
+        // Rectangles of the footprints as (x, y, width, height)
+        let mut rects = self
+            .entries
+            .iter()
+            .map(|entry| {
+                let bb = bounding_box(entry)?;
+                Ok((bb.x0, bb.y0, bb.x1 - bb.x0, bb.y1 - bb.y0))
+            })
+            .collect::<Result<Vec<_>, JsValue>>()?;
+        let extent = |rects: &[(i32, i32, i32, i32)]| {
+            rects.iter().fold(
+                (i32::MAX, i32::MAX, i32::MIN, i32::MIN),
+                |acc, (x, y, width, height)| {
+                    (
+                        acc.0.min(*x),
+                        acc.1.min(*y),
+                        acc.2.max(x + width),
+                        acc.3.max(y + height),
+                    )
+                },
+            )
+        };
+        let (left, top, right, _) = extent(&rects);
+        if flip {
+            for (rect, entry) in rects.iter_mut().zip(&mut self.entries) {
+                rect.0 = left + right - rect.0 - rect.2;
+                entry.rotation = entry.rotation.map(flip_rotation);
+            }
+        }
+        for _ in 0..quarter_turns % 4 {
+            for (rect, entry) in rects.iter_mut().zip(&mut self.entries) {
+                *rect = (-rect.1 - rect.3, rect.0, rect.3, rect.2);
+                entry.rotation = entry.rotation.map(Rotation::next);
+            }
+        }
+        let (new_left, new_top, _, _) = extent(&rects);
+        // Place each structure so that its rotated bounding box covers the transformed rectangle
+        for (rect, entry) in rects.iter().zip(&mut self.entries) {
+            entry.x = 0;
+            entry.y = 0;
+            let offset = bounding_box(entry)?;
+            entry.x = rect.0 - new_left + left - offset.x0;
+            entry.y = rect.1 - new_top + top - offset.y0;
+        }
+        Ok(())
+    }
+}
+
+/// An entry of the report returned by `paste_blueprint` for a structure that could not be placed.
+#[derive(Serialize)]
+pub(crate) struct PasteFailure {
+    type_: String,
+    x: i32,
+    y: i32,
+    reason: &'static str,
+}
+
+#[wasm_bindgen]
+impl FactorishState {
+    /// Capture the structures whose positions are in the rectangle from (x0, y0) to (x1, y1),
+    /// both ends inclusive, into a serialized blueprint. The blueprint is also kept in the
+    /// clipboard for `save_blueprint`.
+    pub fn capture_blueprint(
+        &mut self,
+        x0: i32,
+        y0: i32,
+        x1: i32,
+        y1: i32,
+    ) -> Result<String, JsValue> {
+        // The clipboard is a part of the state that `save_blueprint` depends on.
+        self.record_input(ReplayInput::CaptureBlueprint { x0, y0, x1, y1 });
+        let (left, right) = (x0.min(x1), x0.max(x1));
+        let (top, bottom) = (y0.min(y1), y0.max(y1));
+        let entries = self
+            .structure_iter()
+            .filter(|s| {
+                let pos = s.position();
+                left <= pos.x && pos.x <= right && top <= pos.y && pos.y <= bottom
+            })
+            .map(|s| {
+                Ok(BlueprintEntry {
+                    type_: str_to_item(s.name())
+                        .ok_or_else(|| js_str!("wrong structure name: {:?}", s.name()))?,
+                    x: s.position().x - left,
+                    y: s.position().y - top,
+                    rotation: s.rotation(),
+                    recipe: s.get_selected_recipe().cloned(),
+                })
+            })
+            .collect::<Result<Vec<_>, JsValue>>()?;
+        let blueprint = Blueprint { entries };
+        let data = serde_json::to_string(&blueprint)
+            .map_err(|e| js_str!("blueprint serialization error: {}", e))?;
+        self.blueprint_clipboard = Some(blueprint);
+        Ok(data)
+    }
+
+    /// Save the blueprint in the clipboard into the library with the name, replacing the one
+    /// with the same name. The library is saved with the game.
+    pub fn save_blueprint(&mut self, name: &str) -> Result<(), JsValue> {
+        self.record_input(ReplayInput::SaveBlueprint {
+            name: name.to_string(),
+        });
+        if name.is_empty() {
+            return js_err!("Blueprint name must not be empty");
+        }
+        let blueprint = self
+            .blueprint_clipboard
+            .clone()
+            .ok_or_else(|| js_str!("No blueprint is captured"))?;
+        self.blueprints.insert(name.to_string(), blueprint);
+        Ok(())
+    }
+
+    /// Returns the names of the blueprints in the library in alphabetical order.
+    pub fn list_blueprints(&self) -> js_sys::Array {
+        self.blueprints
+            .keys()
+            .map(|name| JsValue::from_str(name))
+            .collect()
+    }
+
+    /// Returns the serialized blueprint in the library for `paste_blueprint`, or undefined if
+    /// there is no blueprint with the name.
+    pub fn get_blueprint(&self, name: &str) -> Result<Option<String>, JsValue> {
+        self.blueprints
+            .get(name)
+            .map(|blueprint| {
+                serde_json::to_string(blueprint)
+                    .map_err(|e| js_str!("blueprint serialization error: {}", e))
+            })
+            .transpose()
+    }
+
+    /// Remove the blueprint from the library.
+    /// @returns false if there is no blueprint with the name.
+    pub fn delete_blueprint(&mut self, name: &str) -> bool {
+        self.record_input(ReplayInput::DeleteBlueprint {
+            name: name.to_string(),
+        });
+        self.blueprints.remove(name).is_some()
+    }
+
+    /// Place the structures in a blueprint with its top left corner at (x, y), consuming
+    /// items from the player's inventory. Structures lacking items are placed as ghosts.
+    /// @returns an array of `{type_, x, y, reason}` for the structures that could not be placed.
+    pub fn paste_blueprint(&mut self, x: i32, y: i32, data: &str) -> Result<JsValue, JsValue> {
+        self.paste_blueprint_transformed(x, y, data, 0, false)
+    }
+
+    /// Same as `paste_blueprint`, but the blueprint is mirrored horizontally if `flip` and then
+    /// rotated clockwise by `rotation` degrees, which should be one of 0, 90, 180 and 270.
+    pub fn paste_blueprint_transformed(
+        &mut self,
+        x: i32,
+        y: i32,
+        data: &str,
+        rotation: u32,
+        flip: bool,
+    ) -> Result<JsValue, JsValue> {
+        let failures = self.place_blueprint(x, y, data, rotation, flip)?;
+        JsValue::from_serde(&failures).map_err(|e| js_str!("serialize error: {}", e))
+    }
+}
+
+impl FactorishState {
+    /// The body of `paste_blueprint_transformed`, which returns the failures without converting
+    /// them to JavaScript, so that it can be replayed natively.
+    pub(crate) fn place_blueprint(
+        &mut self,
+        x: i32,
+        y: i32,
+        data: &str,
+        rotation: u32,
+        flip: bool,
+    ) -> Result<Vec<PasteFailure>, JsValue> {
+        self.record_input(ReplayInput::PasteBlueprint {
+            x,
+            y,
+            data: data.to_string(),
+            rotation,
+            flip,
+        });
+        if rotation % 90 != 0 {
+            return js_err!("rotation must be a multiple of 90 degrees: {}", rotation);
+        }
+        let mut blueprint: Blueprint =
+            serde_json::from_str(data).map_err(|e| js_str!("blueprint parse error: {}", e))?;
+        if rotation % 360 != 0 || flip {
+            let bounding_box = |entry: &BlueprintEntry| {
+                Ok(self
+                    .ghost_structure(&Ghost {
+                        type_: entry.type_,
+                        position: Position {
+                            x: entry.x,
+                            y: entry.y,
+                        },
+                        rotation: entry.rotation,
+                        recipe: None,
+                    })?
+                    .bounding_box())
+            };
+            blueprint.transform(bounding_box, rotation / 90, flip)?;
+        }
+        let mut failures = vec![];
+        let mut placed = false;
+        for entry in blueprint.entries {
+            let ghost = Ghost {
+                type_: entry.type_,
+                position: Position {
+                    x: x + entry.x,
+                    y: y + entry.y,
+                },
+                rotation: entry.rotation,
+                recipe: entry.recipe,
+            };
+            // Structures that the player does not have items for are left as ghosts.
+            let result = match self.build_ghost(&ghost)? {
+                Err("Not enough items") => self.add_ghost(ghost)?,
+                Ok(()) => {
+                    placed = true;
+                    continue;
+                }
+                Err(reason) => Err(reason),
+            };
+            if let Err(reason) = result {
+                failures.push(PasteFailure {
+                    type_: item_to_str(&entry.type_),
+                    x: x + entry.x,
+                    y: y + entry.y,
+                    reason,
+                });
+            }
+        }
+        if placed {
+            self.notify_player_update()
+                .unwrap_or_else(|_| JsValue::from(true));
+        }
+        Ok(failures)
+    }
+}
+
+#[test]
+fn test_rotated_belt_loop() {
+    use super::{splitter::Splitter, structure::Structure};
+    use std::collections::{HashMap, HashSet};
+
+    let bounding_box = |entry: &BlueprintEntry| {
+        Ok(match entry.type_ {
+            ItemType::Splitter => {
+                Splitter::new(entry.x, entry.y, entry.rotation.unwrap()).bounding_box()
+            }
+            _ => BoundingBox {
+                x0: entry.x,
+                y0: entry.y,
+                x1: entry.x + 1,
+                y1: entry.y + 1,
+            },
+        })
+    };
+    let tiles = |entry: &BlueprintEntry| {
+        let bb = bounding_box(entry).unwrap();
+        (bb.x0..bb.x1)
+            .flat_map(|x| (bb.y0..bb.y1).map(move |y| (x, y)))
+            .collect::<HashSet<_>>()
+    };
+    let assert_loop = |blueprint: &Blueprint| {
+        let belts = blueprint.entries[..4]
+            .iter()
+            .map(|entry| ((entry.x, entry.y), entry.rotation.unwrap()))
+            .collect::<HashMap<_, _>>();
+        assert_eq!(belts.len(), 4);
+        // Every belt should feed into another belt of the loop
+        for ((x, y), rotation) in &belts {
+            let (dx, dy) = rotation.delta();
+            assert!(belts.contains_key(&(x + dx, y + dy)));
+        }
+    };
+
+    let belt = |x, y, rotation| BlueprintEntry {
+        type_: ItemType::TransportBelt,
+        x,
+        y,
+        rotation: Some(rotation),
+        recipe: None,
+    };
+    // A clockwise loop of 2x2 belts with a splitter hanging off its right side
+    let mut blueprint = Blueprint {
+        entries: vec![
+            belt(0, 0, Rotation::Right),
+            belt(1, 0, Rotation::Bottom),
+            belt(1, 1, Rotation::Left),
+            belt(0, 1, Rotation::Top),
+            BlueprintEntry {
+                type_: ItemType::Splitter,
+                x: 2,
+                y: 0,
+                rotation: Some(Rotation::Right),
+                recipe: None,
+            },
+        ],
+    };
+    blueprint.transform(bounding_box, 1, false).unwrap();
+    assert_loop(&blueprint);
+    // The splitter ends up below the loop, lying horizontally and facing down.
+    // A splitter facing down extends to the left of its position.
+    let splitter = &blueprint.entries[4];
+    assert!(matches!(splitter.rotation, Some(Rotation::Bottom)));
+    assert_eq!((splitter.x, splitter.y), (1, 2));
+    assert_eq!(tiles(splitter), [(0, 2), (1, 2)].iter().copied().collect());
+    let belt_tiles = blueprint.entries[..4]
+        .iter()
+        .flat_map(tiles)
+        .collect::<HashSet<_>>();
+    assert_eq!(
+        belt_tiles,
+        [(0, 0), (1, 0), (0, 1), (1, 1)].iter().copied().collect()
+    );
+
+    // Another quarter turn puts the splitter to the left of the loop facing left,
+    // where it extends above its position.
+    blueprint.transform(bounding_box, 1, false).unwrap();
+    assert_loop(&blueprint);
+    let splitter = &blueprint.entries[4];
+    assert!(matches!(splitter.rotation, Some(Rotation::Left)));
+    assert_eq!((splitter.x, splitter.y), (0, 1));
+    assert_eq!(tiles(splitter), [(0, 0), (0, 1)].iter().copied().collect());
+    assert!(blueprint.entries[..4]
+        .iter()
+        .all(|entry| 1 <= entry.x && entry.x <= 2 && 0 <= entry.y && entry.y <= 1));
+
+    // Mirroring turns the loop counterclockwise, which is still a loop, and brings the splitter
+    // back to the right side.
+    blueprint.transform(bounding_box, 0, true).unwrap();
+    assert_loop(&blueprint);
+    let splitter = &blueprint.entries[4];
+    assert!(matches!(splitter.rotation, Some(Rotation::Right)));
+    assert_eq!(tiles(splitter), [(2, 0), (2, 1)].iter().copied().collect());
+}
+
+#[test]
+fn test_blueprint_inputs_round_trip() {
+    use super::{scenarios::empty_state, transport_belt::TransportBelt, InventoryTrait};
+
+    let mut state = empty_state(16, 16);
+    state
+        .add_structure(Box::new(TransportBelt::new(1, 1, Rotation::Bottom)))
+        .unwrap();
+    state
+        .player
+        .inventory
+        .add_items(&ItemType::TransportBelt, 1);
+    let data = serde_json::to_string(&Blueprint {
+        entries: vec![BlueprintEntry {
+            type_: ItemType::TransportBelt,
+            x: 0,
+            y: 0,
+            rotation: Some(Rotation::Bottom),
+            recipe: None,
+        }],
+    })
+    .unwrap();
+    // The inputs as they are stored in a replay log
+    let inputs = [
+        ReplayInput::CaptureBlueprint {
+            x0: 1,
+            y0: 1,
+            x1: 1,
+            y1: 1,
+        },
+        ReplayInput::SaveBlueprint {
+            name: "belt".to_string(),
+        },
+        ReplayInput::PasteBlueprint {
+            x: 4,
+            y: 2,
+            data,
+            rotation: 90,
+            flip: false,
+        },
+    ];
+    for input in &inputs {
+        let input: ReplayInput =
+            serde_json::from_str(&serde_json::to_string(input).unwrap()).unwrap();
+        state.apply_input(input).unwrap();
+    }
+
+    assert_eq!(state.blueprints["belt"].entries.len(), 1);
+    assert!(matches!(
+        state.blueprints["belt"].entries[0].rotation,
+        Some(Rotation::Bottom)
+    ));
+    let pasted = state.find_structure_tile(&[4, 2]).unwrap();
+    assert!(matches!(pasted.rotation(), Some(Rotation::Left)));
+}
@@ -1,8 +1,10 @@
 use super::pipe::Pipe;
 use super::{
+    alerts::AlertType,
     drop_items::DropItem,
     serialize_impl,
     structure::{Structure, StructureDynIter, StructureId},
+    unburnt_fuel,
     water_well::{FluidBox, FluidType},
     FactorishState, FrameProcResult, Inventory, InventoryTrait, ItemType, Position, Recipe,
     TempEnt, COAL_POWER,
@@ -25,6 +27,9 @@ pub(crate) struct Boiler {
     recipe: Option<Recipe>,
     input_fluid_box: FluidBox,
     output_fluid_box: FluidBox,
+    /// Steam produced per second in the last frame, for display.
+    #[serde(skip)]
+    steam_rate: f64,
 }
 
 impl Boiler {
@@ -38,6 +43,7 @@ impl Boiler {
             recipe: Some(Recipe {
                 input: hash_map!(ItemType::CoalOre => 1usize),
                 input_fluid: Some(FluidType::Water),
+                input_fluid_amount: 0.,
                 output: HashMap::new(),
                 output_fluid: Some(FluidType::Steam),
                 power_cost: 100.,
@@ -45,6 +51,7 @@ impl Boiler {
             }),
             input_fluid_box: FluidBox::new_with_filter(true, false, Some(FluidType::Water)),
             output_fluid_box: FluidBox::new(false, true),
+            steam_rate: 0.,
         }
     }
 
@@ -65,6 +72,33 @@ impl Boiler {
             0.
         }
     }
+
+    /// Whether the steam is backed up, which throttles the combustion.
+    fn output_full(&self) -> bool {
+        self.output_fluid_box.max_amount - self.output_fluid_box.amount
+            < Self::COMBUSTION_EPSILON * Self::FLUID_PER_PROGRESS
+    }
+
+    /// Burn a piece of coal into the energy buffer if it runs low. Fuel is not consumed while
+    /// the output is full, so that the coal is not wasted when the steam backs up.
+    /// @returns true if fuel was consumed.
+    fn refuel(&mut self) -> bool {
+        let power_cost = if let Some(recipe) = &self.recipe {
+            recipe.power_cost
+        } else {
+            return false;
+        };
+        if self.output_full() || power_cost <= self.power {
+            return false;
+        }
+        if self.inventory.remove_item(&ItemType::CoalOre) {
+            self.power += COAL_POWER;
+            self.max_power = self.power;
+            true
+        } else {
+            false
+        }
+    }
 }
 
 impl Structure for Boiler {
@@ -72,6 +106,17 @@ impl Structure for Boiler {
         "Boiler"
     }
 
+    fn alert(&self) -> Option<AlertType> {
+        if self.recipe.is_some()
+            && self.power == 0.
+            && self.inventory.count_item(&ItemType::CoalOre) == 0
+        {
+            Some(AlertType::NoFuel)
+        } else {
+            None
+        }
+    }
+
     fn position(&self) -> &Position {
         &self.position
     }
@@ -134,6 +179,9 @@ impl Structure for Boiler {
                     if 0. < self.max_power { (self.power) / self.max_power * 100. } else { 0. }),
                     self.input_fluid_box.desc(),
                     self.output_fluid_box.desc())
+                + &format!("<br>Steam production: {:.1}/s{}",
+                    self.steam_rate,
+                    if self.output_full() { " (throttled by full output)" } else { "" })
             // getHTML(generateItemImage("time", true, this.recipe.time), true) + "<br>" +
             // "Outputs: <br>" +
             // getHTML(generateItemImage(this.recipe.output, true, 1), true) + "<br>";
@@ -154,25 +202,20 @@ impl Structure for Boiler {
         &mut self,
         _me: StructureId,
         state: &mut FactorishState,
-        structures: &mut StructureDynIter,
+        _structures: &mut StructureDynIter,
     ) -> Result<FrameProcResult, ()> {
-        self.input_fluid_box.simulate(structures);
-        self.output_fluid_box.simulate(structures);
+        self.steam_rate = 0.;
+        // First, check if we need to refill the energy buffer in order to continue the current work.
+        let refueled = self.refuel();
         if let Some(recipe) = &self.recipe {
             if self.input_fluid_box.type_ == Some(FluidType::Water) {
                 self.progress = Some(0.);
             }
-            let mut ret = FrameProcResult::None;
-            // First, check if we need to refill the energy buffer in order to continue the current work.
-            if self.inventory.get(&ItemType::CoalOre).is_some() {
-                // Refill the energy from the fuel
-                if self.power < recipe.power_cost {
-                    self.power += COAL_POWER;
-                    self.max_power = self.power;
-                    self.inventory.remove_item(&ItemType::CoalOre);
-                    ret = FrameProcResult::InventoryChanged(self.position);
-                }
-            }
+            let ret = if refueled {
+                FrameProcResult::InventoryChanged(self.position)
+            } else {
+                FrameProcResult::None
+            };
 
             if let Some(prev_progress) = self.progress {
                 // Proceed only if we have sufficient energy in the buffer.
@@ -193,9 +236,13 @@ impl Structure for Boiler {
                 } else if Self::COMBUSTION_EPSILON < progress {
                     self.progress = Some(prev_progress + progress);
                     self.power -= progress * recipe.power_cost;
+                    state.emit_pollution(&self.position, progress * recipe.power_cost);
                     self.output_fluid_box.type_ = Some(FluidType::Steam);
                     self.output_fluid_box.amount += progress * Self::FLUID_PER_PROGRESS;
                     self.input_fluid_box.amount -= progress * Self::FLUID_PER_PROGRESS;
+                    if 0. < state.delta_time {
+                        self.steam_rate = progress * Self::FLUID_PER_PROGRESS / state.delta_time;
+                    }
                 }
             }
             return Ok(ret);
@@ -253,15 +300,16 @@ impl Structure for Boiler {
     }
 
     fn destroy_inventory(&mut self) -> Inventory {
+        let mut ret = std::mem::take(&mut self.inventory);
+        // The boiler takes a coal even without water, which would be lost otherwise.
+        ret.merge(unburnt_fuel(std::mem::take(&mut self.power)));
         // Return the ingredients if it was in the middle of processing a recipe.
         if let Some(recipe) = self.recipe.take() {
             if self.progress.is_some() {
-                let mut ret = std::mem::take(&mut self.inventory);
                 ret.merge(recipe.input);
-                return ret;
             }
         }
-        std::mem::take(&mut self.inventory)
+        ret
     }
 
     fn get_selected_recipe(&self) -> Option<&Recipe> {
@@ -278,3 +326,17 @@ impl Structure for Boiler {
 
     serialize_impl!();
 }
+
+#[test]
+fn test_no_fuel_consumed_when_output_full() {
+    let mut boiler = Boiler::new(&Position::new(0, 0));
+    boiler.inventory.add_items(&ItemType::CoalOre, 2);
+    boiler.output_fluid_box.amount = boiler.output_fluid_box.max_amount;
+    assert!(!boiler.refuel());
+    assert_eq!(boiler.inventory.count_item(&ItemType::CoalOre), 2);
+    assert_eq!(boiler.power, 0.);
+
+    boiler.output_fluid_box.amount = 0.;
+    assert!(boiler.refuel());
+    assert_eq!(boiler.inventory.count_item(&ItemType::CoalOre), 1);
+}
@@ -1,28 +1,62 @@
 use super::{
     drop_items::DropItem,
-    items::ItemType,
+    items::{stack_size, ItemType},
     structure::{ItemResponse, ItemResponseResult, Structure},
     FactorishState, FrameProcResult, Inventory, InventoryTrait, Position,
 };
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::BTreeMap;
 use wasm_bindgen::prelude::*;
 use web_sys::CanvasRenderingContext2d;
 
-const CHEST_CAPACITY: usize = 100;
+/// The number of stacks that a chest can hold
+pub(crate) const CHEST_CAPACITY: usize = 20;
+
+/// The capacity is not serialized, so restore it when loading a chest.
+pub(crate) fn deserialize_chest_inventory<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Inventory, D::Error> {
+    let mut inventory = Inventory::deserialize(deserializer)?;
+    inventory.set_capacity(Some(CHEST_CAPACITY));
+    Ok(inventory)
+}
 
 #[derive(Serialize, Deserialize)]
 pub(crate) struct Chest {
     position: Position,
+    #[serde(deserialize_with = "deserialize_chest_inventory")]
     inventory: Inventory,
+    /// Slots reserved for an item type by the slot index. The other items cannot use them.
+    #[serde(default)]
+    filters: BTreeMap<usize, ItemType>,
 }
 
 impl Chest {
     pub(crate) fn new(position: &Position) -> Self {
         Chest {
             position: *position,
-            inventory: Inventory::new(),
+            inventory: Inventory::with_capacity(CHEST_CAPACITY),
+            filters: BTreeMap::new(),
         }
     }
+
+    /// The number of items of the given type that can be added, where the filtered slots
+    /// only take the item they are reserved for.
+    fn room_for(&self, item: &ItemType) -> usize {
+        let reserved = |item: &ItemType| self.filters.values().filter(|f| *f == item).count();
+        // Stacks of the other items that do not fit in their own slots take the unfiltered ones.
+        let overflow: usize = self
+            .inventory
+            .iter()
+            .filter(|(other, _)| *other != item)
+            .map(|(other, count)| {
+                let stacks = (count + stack_size(other) - 1) / stack_size(other);
+                stacks.saturating_sub(reserved(other))
+            })
+            .sum();
+        let slots = (CHEST_CAPACITY - self.filters.len() + reserved(item)).saturating_sub(overflow);
+        (slots * stack_size(item)).saturating_sub(self.inventory.count_item(item))
+    }
 }
 
 impl Structure for Chest {
@@ -64,9 +98,9 @@ impl Structure for Chest {
         )
     }
 
-    fn item_response(&mut self, _item: &DropItem) -> Result<ItemResponseResult, ()> {
-        if self.inventory.len() < CHEST_CAPACITY {
-            self.inventory.add_item(&_item.type_);
+    fn item_response(&mut self, item: &DropItem) -> Result<ItemResponseResult, ()> {
+        // Leave the item on the belt if the chest is full.
+        if 0 < self.room_for(&item.type_) && self.inventory.add_item(&item.type_) {
             Ok((
                 ItemResponse::Consume,
                 Some(FrameProcResult::InventoryChanged(self.position)),
@@ -82,9 +116,9 @@ impl Structure for Chest {
             .map_err(|_| JsValue::from_str("ItemResponse failed"))
     }
 
-    /// Chest can put any item
-    fn can_input(&self, _o: &ItemType) -> bool {
-        self.inventory.len() < CHEST_CAPACITY
+    /// Chest can put any item as long as it has room for it
+    fn can_input(&self, o: &ItemType) -> bool {
+        0 < self.room_for(o)
     }
 
     fn can_output(&self) -> Inventory {
@@ -99,6 +133,10 @@ impl Structure for Chest {
         }
     }
 
+    fn logistic_provider(&self) -> bool {
+        true
+    }
+
     fn inventory(&self, is_input: bool) -> Option<&Inventory> {
         if is_input {
             Some(&self.inventory)
@@ -115,5 +153,119 @@ impl Structure for Chest {
         }
     }
 
+    fn signal_output(&self) -> Option<&Inventory> {
+        Some(&self.inventory)
+    }
+
+    fn slot_filters(&self) -> Option<&BTreeMap<usize, ItemType>> {
+        Some(&self.filters)
+    }
+
+    fn set_slot_filter(&mut self, slot: usize, item: Option<ItemType>) -> Result<(), JsValue> {
+        if CHEST_CAPACITY <= slot {
+            return js_err!(
+                "Slot {} is out of the chest capacity {}",
+                slot,
+                CHEST_CAPACITY
+            );
+        }
+        if let Some(item) = item {
+            self.filters.insert(slot, item);
+        } else {
+            self.filters.remove(&slot);
+        }
+        Ok(())
+    }
+
     super::serialize_impl!();
 }
+
+#[test]
+fn test_full_chest_rejects_items() {
+    use super::items::stack_size;
+    let mut chest = Chest::new(&Position::new(0, 0));
+    let stack = stack_size(&ItemType::IronOre);
+    assert_eq!(
+        chest
+            .inventory
+            .add_items(&ItemType::IronOre, stack * CHEST_CAPACITY + 5),
+        5
+    );
+    assert!(!chest.can_input(&ItemType::IronOre));
+    assert!(chest
+        .item_response(&DropItem::new(ItemType::IronOre, 0, 0))
+        .is_err());
+
+    // The capacity should survive a save and load.
+    let mut loaded: Chest = serde_json::from_value(serde_json::to_value(&chest).unwrap()).unwrap();
+    assert!(!loaded.can_input(&ItemType::Gear));
+    loaded.inventory.remove_item(&ItemType::IronOre);
+    assert!(loaded.can_input(&ItemType::IronOre));
+}
+
+#[test]
+fn test_chest_slot_filters() {
+    let mut chest = Chest::new(&Position::new(0, 0));
+    chest.set_slot_filter(0, Some(ItemType::IronOre)).unwrap();
+    chest.set_slot_filter(1, Some(ItemType::IronOre)).unwrap();
+    let stack = stack_size(&ItemType::Gear);
+    chest
+        .inventory
+        .add_items(&ItemType::Gear, stack * (CHEST_CAPACITY - 2));
+    // The unfiltered slots are full, but the filtered ones still take their item.
+    assert!(!chest.can_input(&ItemType::Gear));
+    assert!(chest.can_input(&ItemType::IronOre));
+    assert_eq!(
+        chest.room_for(&ItemType::IronOre),
+        stack_size(&ItemType::IronOre) * 2
+    );
+
+    // Removing a filter frees the slot for any item.
+    chest.set_slot_filter(1, None).unwrap();
+    assert!(chest
+        .item_response(&DropItem::new(ItemType::Gear, 0, 0))
+        .is_ok());
+    assert_eq!(chest.room_for(&ItemType::Gear), stack - 1);
+
+    let loaded: Chest = serde_json::from_value(serde_json::to_value(&chest).unwrap()).unwrap();
+    assert!(loaded.filters == chest.filters);
+}
+
+#[test]
+fn test_quick_stack_to_chest() {
+    use super::{requester_chest::RequesterChest, scenarios::empty_state};
+    let mut state = empty_state(16, 16);
+    let mut chest = Chest::new(&Position::new(0, 0));
+    chest.set_slot_filter(0, Some(ItemType::IronOre)).unwrap();
+    let stack = stack_size(&ItemType::Gear);
+    // Two unfiltered slots are left for gears.
+    chest
+        .inventory
+        .add_items(&ItemType::Gear, stack * (CHEST_CAPACITY - 3));
+    chest.inventory.add_item(&ItemType::IronOre);
+    state.add_structure(Box::new(chest)).unwrap();
+
+    state.player.inventory.clear();
+    state.player.inventory.add_items(&ItemType::Gear, stack * 3);
+    state.player.inventory.add_items(&ItemType::IronOre, 5);
+    state.player.inventory.add_items(&ItemType::CoalOre, 5);
+
+    assert_eq!(state.quick_stack_to_chest(0, 0).unwrap(), stack * 2 + 5);
+    // The filtered slot does not take the gears.
+    assert_eq!(state.player.inventory.count_item(&ItemType::Gear), stack);
+    assert_eq!(state.player.inventory.count_item(&ItemType::IronOre), 0);
+    // The chest had no coal ore.
+    assert_eq!(state.player.inventory.count_item(&ItemType::CoalOre), 5);
+
+    // Any structure with an inventory takes the items, like a requester chest.
+    state
+        .add_structure(Box::new(RequesterChest::new(&Position::new(2, 0))))
+        .unwrap();
+    state
+        .find_structure_tile_mut(&[2, 0])
+        .and_then(|s| s.inventory_mut(true))
+        .unwrap()
+        .add_item(&ItemType::CoalOre);
+    assert_eq!(state.quick_stack_to_chest(2, 0).unwrap(), 5);
+    assert_eq!(state.player.inventory.count_item(&ItemType::CoalOre), 0);
+}
@@ -0,0 +1,311 @@
+use super::{
+    items::{item_to_str, str_to_item, ItemType},
+    replay::ReplayInput,
+    structure::{StructureDynIter, StructureEntry, StructureId},
+    FactorishState, Inventory, InventoryTrait,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use wasm_bindgen::prelude::*;
+
+/// Maximum distance in tiles that a signal wire can span.
+const SIGNAL_WIRE_REACH: i32 = 10;
+
+/// A wire carrying signals between structures, distinct from a `PowerWire`.
+#[derive(Eq, PartialEq, Hash, Copy, Clone, Serialize, Deserialize, Debug)]
+pub(crate) struct SignalWire(pub StructureId, pub StructureId);
+
+#[derive(Eq, PartialEq, Copy, Clone, Serialize, Deserialize, Debug)]
+pub(crate) enum CompareOp {
+    Less,
+    LessEq,
+    Equal,
+    NotEqual,
+    GreaterEq,
+    Greater,
+}
+
+impl CompareOp {
+    fn from_str(s: &str) -> Option<Self> {
+        Some(match s {
+            "<" => Self::Less,
+            "<=" => Self::LessEq,
+            "=" | "==" => Self::Equal,
+            "!=" => Self::NotEqual,
+            ">=" => Self::GreaterEq,
+            ">" => Self::Greater,
+            _ => return None,
+        })
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Less => "<",
+            Self::LessEq => "<=",
+            Self::Equal => "=",
+            Self::NotEqual => "!=",
+            Self::GreaterEq => ">=",
+            Self::Greater => ">",
+        }
+    }
+
+    fn compare(&self, lhs: usize, rhs: usize) -> bool {
+        match self {
+            Self::Less => lhs < rhs,
+            Self::LessEq => lhs <= rhs,
+            Self::Equal => lhs == rhs,
+            Self::NotEqual => lhs != rhs,
+            Self::GreaterEq => lhs >= rhs,
+            Self::Greater => lhs > rhs,
+        }
+    }
+}
+
+/// A condition on a signal, e.g. "Iron Plate < 100".
+#[derive(Copy, Clone, Serialize, Deserialize, Debug)]
+pub(crate) struct SignalCondition {
+    pub item: ItemType,
+    pub op: CompareOp,
+    pub value: usize,
+}
+
+impl SignalCondition {
+    /// A missing signal or a structure without a network reads as 0.
+    pub(crate) fn is_met(&self, signals: Option<&Inventory>) -> bool {
+        let count = signals.map_or(0, |signals| signals.count_item(&self.item));
+        self.op.compare(count, self.value)
+    }
+
+    pub(crate) fn desc(&self) -> String {
+        format!(
+            "{} {} {}",
+            item_to_str(&self.item),
+            self.op.as_str(),
+            self.value
+        )
+    }
+}
+
+/// A set of structures connected with signal wires, sharing the sum of their signals.
+pub(crate) struct SignalNetwork {
+    pub members: HashSet<StructureId>,
+    pub signals: Inventory,
+}
+
+/// Restore the signal wires saved with the indices of the loaded structures, whose gens are all
+/// 0, like `load_power_wires`. Wires that do not connect two distinct structures are dropped
+/// along with the duplicates.
+/// @returns the restored wires and the dropped ones.
+pub(crate) fn load_signal_wires(
+    saved: &[(u32, u32)],
+    structures: &[StructureEntry],
+) -> (Vec<SignalWire>, Vec<(u32, u32)>) {
+    let exists = |idx: u32| {
+        structures
+            .get(idx as usize)
+            .map_or(false, |s| s.dynamic.is_some())
+    };
+    let id = |idx| StructureId { id: idx, gen: 0 };
+    let mut wires = vec![];
+    let mut dropped = vec![];
+    for &(a, b) in saved {
+        let wire = SignalWire(id(a), id(b));
+        if a == b
+            || !exists(a)
+            || !exists(b)
+            || wires.contains(&wire)
+            || wires.contains(&SignalWire(id(b), id(a)))
+        {
+            dropped.push((a, b));
+        } else {
+            wires.push(wire);
+        }
+    }
+    (wires, dropped)
+}
+
+pub(crate) fn build_signal_networks(signal_wires: &[SignalWire]) -> Vec<SignalNetwork> {
+    let mut left_wires = signal_wires.iter().collect::<HashSet<_>>();
+    let mut ret = vec![];
+
+    while let Some(&&first) = left_wires.iter().next() {
+        left_wires.remove(&first);
+        let mut members = HashSet::new();
+        let mut expand_list = vec![first.0, first.1];
+        while let Some(id) = expand_list.pop() {
+            if !members.insert(id) {
+                continue;
+            }
+            while let Some(wire) = left_wires.iter().find(|w| w.0 == id || w.1 == id).copied() {
+                expand_list.push(if wire.0 == id { wire.1 } else { wire.0 });
+                left_wires.remove(&wire);
+            }
+        }
+        ret.push(SignalNetwork {
+            members,
+            signals: Inventory::new(),
+        });
+    }
+
+    ret
+}
+
+/// Sum up the signals that the members emit in this frame.
+pub(crate) fn update_signals(networks: &mut [SignalNetwork], structures: &StructureDynIter) {
+    for network in networks {
+        network.signals.clear();
+        for id in &network.members {
+            if let Some(signals) = structures.get(*id).and_then(|s| s.signal_output()) {
+                for (item, count) in signals {
+                    network.signals.add_items(item, *count);
+                }
+            }
+        }
+    }
+}
+
+impl FactorishState {
+    pub(crate) fn structure_id_at(&self, tile: &[i32]) -> Option<StructureId> {
+        if tile.len() < 2 {
+            return None;
+        }
+        self.structures.iter().enumerate().find_map(|(i, s)| {
+            let position = s.dynamic.as_deref()?.position();
+            if position.x == tile[0] && position.y == tile[1] {
+                Some(StructureId {
+                    id: i as u32,
+                    gen: s.gen,
+                })
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Returns the aggregated signals of the network that the structure is connected to.
+    pub(crate) fn signals_of(&self, id: StructureId) -> Option<&Inventory> {
+        self.signal_networks
+            .iter()
+            .find(|network| network.members.contains(&id))
+            .map(|network| &network.signals)
+    }
+
+    pub(crate) fn rebuild_signal_networks(&mut self) {
+        self.signal_networks = build_signal_networks(&self.signal_wires);
+    }
+}
+
+#[wasm_bindgen]
+impl FactorishState {
+    /// Connect the structures at the tiles with a signal wire.
+    /// @param a 2-array of tile coordinates of the first structure.
+    /// @param b 2-array of tile coordinates of the second structure.
+    /// @returns false if the wire already exists.
+    pub fn connect_signal(&mut self, a: &[i32], b: &[i32]) -> Result<bool, JsValue> {
+        self.record_input(ReplayInput::ConnectSignal {
+            a: a.to_vec(),
+            b: b.to_vec(),
+        });
+        let first = self
+            .structure_id_at(a)
+            .ok_or_else(|| js_str!("Structure is not found at {:?}", a))?;
+        let second = self
+            .structure_id_at(b)
+            .ok_or_else(|| js_str!("Structure is not found at {:?}", b))?;
+        if first == second {
+            return js_err!("Cannot connect a structure to itself");
+        }
+        if SIGNAL_WIRE_REACH < (a[0] - b[0]).abs().max((a[1] - b[1]).abs()) {
+            return js_err!(
+                "Signal wire cannot reach more than {} tiles",
+                SIGNAL_WIRE_REACH
+            );
+        }
+        if self
+            .signal_wires
+            .iter()
+            .any(|w| *w == SignalWire(first, second) || *w == SignalWire(second, first))
+        {
+            return Ok(false);
+        }
+        self.signal_wires.push(SignalWire(first, second));
+        self.rebuild_signal_networks();
+        Ok(true)
+    }
+
+    /// Set a condition to enable the inserter at the tile. It only swings while the signal
+    /// in its network meets the condition.
+    /// @param item name of the item used as the signal, or an empty string to clear the condition.
+    /// @param op one of "<", "<=", "=", "!=", ">=" and ">".
+    pub fn set_inserter_condition(
+        &mut self,
+        c: i32,
+        r: i32,
+        item: &str,
+        op: &str,
+        value: usize,
+    ) -> Result<(), JsValue> {
+        self.record_input(ReplayInput::SetInserterCondition {
+            c,
+            r,
+            item: item.to_string(),
+            op: op.to_string(),
+            value,
+        });
+        let condition = if item.is_empty() {
+            None
+        } else {
+            Some(SignalCondition {
+                item: str_to_item(item).ok_or_else(|| js_str!("Item name not valid: {}", item))?,
+                op: CompareOp::from_str(op)
+                    .ok_or_else(|| js_str!("Comparison operator not valid: {}", op))?,
+                value,
+            })
+        };
+        self.find_structure_tile_mut(&[c, r])
+            .ok_or_else(|| js_str!("Structure is not found"))?
+            .set_signal_condition(condition)
+    }
+}
+
+#[test]
+fn test_build_signal_networks() {
+    let id = |id| StructureId { id, gen: 0 };
+    let networks = build_signal_networks(&[
+        SignalWire(id(0), id(1)),
+        SignalWire(id(2), id(1)),
+        SignalWire(id(3), id(4)),
+    ]);
+    assert_eq!(networks.len(), 2);
+    let members = |i: u32| {
+        networks
+            .iter()
+            .find(|network| network.members.contains(&id(i)))
+            .map(|network| network.members.len())
+    };
+    assert_eq!(members(0), Some(3));
+    assert_eq!(members(2), Some(3));
+    assert_eq!(members(4), Some(2));
+    assert_eq!(members(5), None);
+}
+
+#[test]
+fn test_load_signal_wires() {
+    use super::{chest::Chest, Position};
+    let structures = vec![
+        StructureEntry::new(Box::new(Chest::new(&Position::new(0, 0)))),
+        StructureEntry::new(Box::new(Chest::new(&Position::new(1, 0)))),
+        StructureEntry {
+            gen: 0,
+            dynamic: None,
+            damage: 0.,
+            active: true,
+        },
+    ];
+    let (wires, dropped) =
+        load_signal_wires(&[(0, 1), (1, 0), (0, 0), (0, 2), (1, 9)], &structures);
+    let id = |id| StructureId { id, gen: 0 };
+    assert_eq!(wires, [SignalWire(id(0), id(1))]);
+    // The duplicate, the loop and the dangling wires are dropped.
+    assert_eq!(dropped, [(1, 0), (0, 0), (0, 2), (1, 9)]);
+}
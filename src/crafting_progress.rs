@@ -0,0 +1,67 @@
+use super::{structure::StructureId, FactorishState, TILE_SIZE};
+use std::f64::consts::PI;
+use wasm_bindgen::prelude::*;
+use web_sys::CanvasRenderingContext2d;
+
+/// Size of the clock icon in pixels, drawn at the bottom right corner of the structure.
+const ICON_SIZE: f64 = 12.;
+
+impl FactorishState {
+    /// Draw a clock icon with an arc of the recipe progress on the structures that are crafting.
+    /// A stalled structure keeps showing the arc where it stopped.
+    pub(crate) fn render_crafting_progress(
+        &self,
+        context: &CanvasRenderingContext2d,
+        ids: &[StructureId],
+    ) -> Result<(), JsValue> {
+        let img = if let Some(img) = self.image_time.as_ref() {
+            img
+        } else {
+            return js_err!("time image not available");
+        };
+        context.save();
+        context.set_line_width(2.);
+        for structure in ids.iter().filter_map(|id| self.get_structure(*id)) {
+            let progress = if let Some(progress) = structure.crafting_progress() {
+                progress.max(0.).min(1.)
+            } else {
+                continue;
+            };
+            let bb = structure.bounding_box();
+            let (x, y) = (
+                bb.x1 as f64 * TILE_SIZE - ICON_SIZE - 2.,
+                bb.y1 as f64 * TILE_SIZE - ICON_SIZE - 2.,
+            );
+            let (cx, cy, radius) = (x + ICON_SIZE / 2., y + ICON_SIZE / 2., ICON_SIZE / 2. + 1.);
+            context.set_fill_style(&js_str!("rgba(0,0,0,0.7)"));
+            context.begin_path();
+            context.arc(cx, cy, radius + 1., 0., PI * 2.)?;
+            context.fill();
+            context.draw_image_with_image_bitmap_and_dw_and_dh(
+                &img.bitmap,
+                x,
+                y,
+                ICON_SIZE,
+                ICON_SIZE,
+            )?;
+            context.set_stroke_style(&js_str!("#00ff7f"));
+            context.begin_path();
+            context.arc(cx, cy, radius, -PI / 2., -PI / 2. + progress * PI * 2.)?;
+            context.stroke();
+        }
+        context.restore();
+        Ok(())
+    }
+}
+
+#[wasm_bindgen]
+impl FactorishState {
+    /// Returns the completion ratio in [0, 1] of the recipe that the structure at the tile is
+    /// crafting, or undefined if it is not crafting.
+    pub fn get_factory_progress(&self, c: i32, r: i32) -> Result<Option<f64>, JsValue> {
+        Ok(self
+            .find_structure_tile(&[c, r])
+            .ok_or_else(|| js_str!("Structure is not found"))?
+            .crafting_progress())
+    }
+}
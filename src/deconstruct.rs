@@ -0,0 +1,96 @@
+use super::{
+    drop_items::{remove_index, DropItemId},
+    items::item_to_str,
+    power_network::build_power_networks,
+    replay::ReplayInput,
+    structure::StructureDynIter,
+    FactorishState, Inventory, InventoryTrait, Position, TILE_SIZE,
+};
+use wasm_bindgen::prelude::*;
+
+#[wasm_bindgen]
+impl FactorishState {
+    /// Remove all structures overlapping the rectangle from (x0, y0) to (x1, y1), both ends
+    /// inclusive, and pick up the items lying in it, including the ones on removed belts.
+    /// Everything goes to the player's inventory.
+    /// @returns 2-array of [number of structures removed, number of items picked up].
+    pub fn deconstruct_area(
+        &mut self,
+        x0: i32,
+        y0: i32,
+        x1: i32,
+        y1: i32,
+    ) -> Result<js_sys::Array, JsValue> {
+        self.record_input(ReplayInput::DeconstructArea { x0, y0, x1, y1 });
+        let (left, right) = (x0.min(x1), x0.max(x1));
+        let (top, bottom) = (y0.min(y1), y0.max(y1));
+        let mut reclaimed = Inventory::new();
+        let mut removed_positions = vec![];
+        for i in 0..self.structures.len() {
+            let overlaps = self.structures[i]
+                .dynamic
+                .as_deref()
+                .map(|s| {
+                    let bb = s.bounding_box();
+                    left < bb.x1 && bb.x0 <= right && top < bb.y1 && bb.y0 <= bottom
+                })
+                .unwrap_or(false);
+            if !overlaps {
+                continue;
+            }
+            let (position, item_type, contents) = self.take_structure(i)?;
+            reclaimed.add_item(&item_type);
+            for (item_type, count) in contents {
+                reclaimed.add_items(&item_type, count);
+            }
+            removed_positions.push(position);
+        }
+
+        let mut picked_items = 0;
+        for i in 0..self.drop_items.len() {
+            let entry = &mut self.drop_items[i];
+            let item = if let Some(item) = entry.item.as_ref() {
+                item
+            } else {
+                continue;
+            };
+            let Position { x, y } = item.tile();
+            if !(left <= x && x <= right && top <= y && y <= bottom) {
+                continue;
+            }
+            let id = DropItemId::new(i as u32, entry.gen);
+            remove_index(&mut self.drop_items_index, id, item.x, item.y);
+            let (item_type, count) = (item.type_, item.count as usize);
+            entry.item = None;
+            self.player.add_item(&item_type, count);
+            reclaimed.add_items(&item_type, count);
+            picked_items += count;
+        }
+
+        // Rebuild the networks only once, since it is expensive to do for each structure.
+        if !removed_positions.is_empty() {
+            self.power_networks = build_power_networks(
+                &StructureDynIter::new_all(&mut self.structures),
+                &self.power_wires,
+            );
+            for position in &removed_positions {
+                self.update_fluid_connections(position);
+            }
+        }
+
+        if !reclaimed.is_empty() {
+            let popup_text = reclaimed
+                .iter()
+                .map(|(item_type, count)| format!("+{} {}\n", count, &item_to_str(item_type)))
+                .collect();
+            self.new_popup_text(popup_text, left as f64 * TILE_SIZE, top as f64 * TILE_SIZE);
+            self.notify_player_update()
+                .unwrap_or_else(|_| JsValue::from(true));
+        }
+
+        Ok(js_sys::Array::of2(
+            &JsValue::from(removed_positions.len() as u32),
+            &JsValue::from(picked_items as u32),
+        ))
+    }
+}
@@ -1,10 +1,35 @@
-use super::{items::ItemType, Position, TILE_SIZE_I};
+use super::{
+    items::ItemType,
+    replay::ReplayInput,
+    structure::{StructureEntry, StructureTileIndex},
+    transport_belt::LANE_OFFSET,
+    FactorishState, Position, TILE_SIZE_I,
+};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use wasm_bindgen::prelude::*;
+use web_sys::CanvasRenderingContext2d;
 
 pub(crate) const DROP_ITEM_SIZE: f64 = 8.;
 pub(crate) const DROP_ITEM_SIZE_I: i32 = DROP_ITEM_SIZE as i32;
 
+/// The maximum number of items merged into a drop item. A stack moves on a belt as a single
+/// item, so a large stack would multiply the throughput of the belt.
+pub(crate) const MAX_DROP_ITEM_COUNT: u32 = 4;
+
+/// The smallest spacing between items on a belt in pixels, which `set_belt_density` clamps to
+/// so that the items never overlap.
+pub(crate) const MIN_BELT_SPACING: i32 = DROP_ITEM_SIZE_I;
+
+/// The largest spacing between items on a belt in pixels. The two lanes of a belt are this far
+/// apart, so a larger spacing would let the items on one lane block the other.
+pub(crate) const MAX_BELT_SPACING: i32 = 2 * LANE_OFFSET;
+
+/// The spacing between items on a belt in pixels for the density factor.
+fn belt_spacing(factor: f64) -> i32 {
+    ((DROP_ITEM_SIZE / factor).round() as i32).clamp(MIN_BELT_SPACING, MAX_BELT_SPACING)
+}
+
 pub(crate) type DropItemId = GenId;
 
 #[derive(Serialize, Deserialize)]
@@ -12,6 +37,18 @@ pub(crate) struct DropItem {
     pub type_: ItemType,
     pub x: i32,
     pub y: i32,
+    /// The number of identical items stacked in this drop item, which is more than 1 only if
+    /// they are merged on a belt.
+    #[serde(default = "default_count", skip_serializing_if = "is_single")]
+    pub count: u32,
+}
+
+fn default_count() -> u32 {
+    1
+}
+
+fn is_single(count: &u32) -> bool {
+    *count == 1
 }
 
 impl DropItem {
@@ -20,9 +57,15 @@ impl DropItem {
             type_,
             x: c * TILE_SIZE_I + TILE_SIZE_I / 2,
             y: r * TILE_SIZE_I + TILE_SIZE_I / 2,
+            count: 1,
         };
         ret
     }
+
+    /// The tile that this item is on.
+    pub(crate) fn tile(&self) -> Position {
+        Position::from_pixel(self.x, self.y)
+    }
 }
 
 pub(crate) type DropItemEntry = GenEntry<DropItem>;
@@ -74,6 +117,14 @@ pub(crate) fn drop_item_id_iter_mut(
     })
 }
 
+/// Returns the first item on the tile.
+pub(crate) fn find_item_at<'a>(
+    drop_items: &'a [DropItemEntry],
+    pos: &Position,
+) -> Option<(DropItemId, &'a DropItem)> {
+    drop_item_id_iter(drop_items).find(|(_, item)| item.tile() == *pos)
+}
+
 /// Returns an iterator over valid structures
 pub(crate) fn drop_item_iter(drop_items: &[DropItemEntry]) -> impl Iterator<Item = &DropItem> {
     drop_items
@@ -81,7 +132,7 @@ pub(crate) fn drop_item_iter(drop_items: &[DropItemEntry]) -> impl Iterator<Item
         .filter_map(|item| Some(item.item.as_ref()?))
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Clone, Copy, Debug)]
+#[derive(Serialize, Deserialize, PartialEq, Eq, Hash, Clone, Copy, Debug)]
 pub(crate) struct GenId {
     pub id: u32,
     pub gen: u32,
@@ -121,11 +172,13 @@ pub(crate) fn build_index(items: &[DropItemEntry]) -> DropItemIndex {
     ret
 }
 
-/// Check whether given coordinates hits some object
+/// Check whether given coordinates hits some object, which is closer than `spacing` pixels
+/// in both axes.
 pub(crate) fn hit_check(
     items: &[DropItemEntry],
     x: i32,
     y: i32,
+    spacing: i32,
     ignore: Option<DropItemId>,
 ) -> bool {
     for (id, entry) in items.iter().enumerate() {
@@ -136,7 +189,7 @@ pub(crate) fn hit_check(
                     continue;
                 }
             }
-            if (x - item.x).abs() < DROP_ITEM_SIZE_I && (y - item.y).abs() < DROP_ITEM_SIZE_I {
+            if (x - item.x).abs() < spacing && (y - item.y).abs() < spacing {
                 return true;
             }
         }
@@ -187,23 +240,36 @@ pub(crate) fn remove_index(index: &mut DropItemIndex, id: GenId, old_x: i32, old
     }
 }
 
-fn intersecting_chunks(x: i32, y: i32) -> [i32; 4] {
-    let left = (x - DROP_ITEM_SIZE_I).div_euclid(INDEX_GRID_SIZE_I);
-    let top = (y - DROP_ITEM_SIZE_I).div_euclid(INDEX_GRID_SIZE_I);
-    let right = (x + DROP_ITEM_SIZE_I).div_euclid(INDEX_GRID_SIZE_I);
-    let bottom = (y + DROP_ITEM_SIZE_I).div_euclid(INDEX_GRID_SIZE_I);
+fn intersecting_chunks(x: i32, y: i32, margin: i32) -> [i32; 4] {
+    let left = (x - margin).div_euclid(INDEX_GRID_SIZE_I);
+    let top = (y - margin).div_euclid(INDEX_GRID_SIZE_I);
+    let right = (x + margin).div_euclid(INDEX_GRID_SIZE_I);
+    let bottom = (y + margin).div_euclid(INDEX_GRID_SIZE_I);
     [left, top, right, bottom]
 }
 
-/// Check whether given coordinates hits some object
+/// Check whether given coordinates hits some object, like `hit_check`.
 pub(crate) fn hit_check_with_index(
     items: &[DropItemEntry],
     index: &DropItemIndex,
     x: i32,
     y: i32,
+    spacing: i32,
     ignore: Option<DropItemId>,
 ) -> bool {
-    let [left, top, right, bottom] = intersecting_chunks(x, y);
+    hit_item_with_index(items, index, x, y, spacing, ignore).is_some()
+}
+
+/// Returns the id of the first object that the given coordinates hit
+pub(crate) fn hit_item_with_index(
+    items: &[DropItemEntry],
+    index: &DropItemIndex,
+    x: i32,
+    y: i32,
+    spacing: i32,
+    ignore: Option<DropItemId>,
+) -> Option<DropItemId> {
+    let [left, top, right, bottom] = intersecting_chunks(x, y, spacing);
     for cy in top..=bottom {
         for cx in left..=right {
             if let Some(start) = index.get(&(cx, cy)) {
@@ -218,17 +284,152 @@ pub(crate) fn hit_check_with_index(
                             entry.item.as_ref()
                         }
                     }) {
-                        if (x - item.x).abs() < DROP_ITEM_SIZE_I
-                            && (y - item.y).abs() < DROP_ITEM_SIZE_I
-                        {
-                            return true;
+                        if (x - item.x).abs() < spacing && (y - item.y).abs() < spacing {
+                            return Some(*id);
                         }
                     }
                 }
             }
         }
     }
-    false
+    None
+}
+
+/// The outcome of `move_destination`.
+pub(crate) enum Destination {
+    /// The item can move to the pixel.
+    Free(i32, i32),
+    /// Another item is in the way at the pixel.
+    BlockedByItem(i32, i32),
+    /// There is no structure to carry the item to the pixel.
+    Blocked,
+}
+
+/// Find where the item `id` ends up if it moves to `(x, y)`. If it enters another tile, the
+/// structure there places it with `item_entry`, e.g. on the near lane of a belt fed from the side,
+/// and the hit check is done at that final position so that the item never overlaps others.
+pub(crate) fn move_destination(
+    items: &[DropItemEntry],
+    index: &DropItemIndex,
+    structures: &[StructureEntry],
+    tile_index: &StructureTileIndex,
+    id: DropItemId,
+    (x, y): (i32, i32),
+    spacing: i32,
+) -> Destination {
+    let structure = if let Some(s) = tile_index
+        .get(&Position::from_pixel(x, y))
+        .and_then(|i| structures[*i].dynamic.as_deref())
+    {
+        s
+    } else {
+        return Destination::Blocked;
+    };
+    if !structure.movable() {
+        return Destination::Blocked;
+    }
+    let from = items
+        .get(id.id as usize)
+        .and_then(|entry| entry.item.as_ref())
+        .map(DropItem::tile);
+    let (x, y) = if from == Some(Position::from_pixel(x, y)) {
+        (x, y)
+    } else {
+        structure.item_entry(x, y)
+    };
+    if hit_check_with_index(items, index, x, y, spacing, Some(id)) {
+        Destination::BlockedByItem(x, y)
+    } else {
+        Destination::Free(x, y)
+    }
+}
+
+/// Merge the item `id` into the item of the same type that blocks it from moving to `(x, y)`.
+/// @returns whether the item is merged and removed.
+pub(crate) fn merge_blocked_item(
+    items: &mut [DropItemEntry],
+    index: &mut DropItemIndex,
+    id: DropItemId,
+    x: i32,
+    y: i32,
+    spacing: i32,
+) -> bool {
+    let target = if let Some(target) = hit_item_with_index(items, index, x, y, spacing, Some(id)) {
+        target
+    } else {
+        return false;
+    };
+    let (type_, count, old_x, old_y) = match items[id.id as usize].item.as_ref() {
+        Some(item) => (item.type_, item.count, item.x, item.y),
+        None => return false,
+    };
+    let target = items[target.id as usize].item.as_mut().unwrap();
+    if target.type_ != type_ || MAX_DROP_ITEM_COUNT < target.count + count {
+        return false;
+    }
+    target.count += count;
+    remove_index(index, id, old_x, old_y);
+    items[id.id as usize].item = None;
+    true
+}
+
+impl FactorishState {
+    /// Draw the number of items at the bottom right of the stacked drop items.
+    pub(crate) fn render_drop_item_counts(
+        &self,
+        context: &CanvasRenderingContext2d,
+    ) -> Result<(), JsValue> {
+        context.save();
+        context.set_font("bold 8px sans-serif");
+        context.set_text_align("right");
+        context.set_stroke_style(&JsValue::from_str("black"));
+        context.set_line_width(2.);
+        context.set_fill_style(&JsValue::from_str("white"));
+        for item in drop_item_iter(&self.drop_items).filter(|item| 1 < item.count) {
+            let (x, y) = (item.x as f64 + 8., item.y as f64 + 8.);
+            let text = item.count.to_string();
+            context.stroke_text(&text, x, y)?;
+            context.fill_text(&text, x, y)?;
+        }
+        context.restore();
+        Ok(())
+    }
+}
+
+#[wasm_bindgen]
+impl FactorishState {
+    /// Let an item blocked on a belt merge into the item of the same type in front of it, up to
+    /// `MAX_DROP_ITEM_COUNT` items, to reduce the number of drop items on dense belts.
+    /// The stacks are taken one item at a time by the structures and the inserters.
+    /// It is disabled by default and not saved.
+    pub fn set_merge_drop_items(&mut self, enable: bool) {
+        self.record_input(ReplayInput::SetMergeDropItems { enable });
+        self.merge_drop_items = enable;
+    }
+
+    pub fn get_merge_drop_items(&self) -> bool {
+        self.merge_drop_items
+    }
+
+    /// Space the items on belts by `DROP_ITEM_SIZE` pixels divided by `factor`, where 1 is the
+    /// default and densest spacing, since the items would overlap any closer, and 0.5 puts half as
+    /// many items on a belt. Since items move a pixel every tick, the throughput of a belt lane is
+    /// one item per spacing ticks, so the factor multiplies the throughput as well, while fewer
+    /// items are simulated on the same belts.
+    /// The factor is clamped to the range between the ones of `MAX_BELT_SPACING` and 1.
+    /// @returns the factor actually applied after rounding the spacing to pixels.
+    pub fn set_belt_density(&mut self, factor: f64) -> Result<f64, JsValue> {
+        self.record_input(ReplayInput::SetBeltDensity { factor });
+        if !(0. < factor && factor.is_finite()) {
+            return js_err!("Belt density must be a positive number: {}", factor);
+        }
+        self.belt_spacing = belt_spacing(factor);
+        Ok(self.get_belt_density())
+    }
+
+    pub fn get_belt_density(&self) -> f64 {
+        DROP_ITEM_SIZE / self.belt_spacing as f64
+    }
 }
 
 #[test]
@@ -255,6 +456,7 @@ fn test_hit_check() {
             type_: ItemType::CoalOre,
             x: tr(x),
             y: tr(y),
+            count: 1,
         }),
     })
     .collect::<Vec<_>>();
@@ -272,15 +474,15 @@ fn test_hit_check() {
     );
 
     assert_eq!(
-        hit_check_with_index(&items, &index, tr(3), tr(1), None),
+        hit_check_with_index(&items, &index, tr(3), tr(1), DROP_ITEM_SIZE_I, None),
         true
     );
     assert_eq!(
-        hit_check_with_index(&items, &index, tr(3), tr(10), None),
+        hit_check_with_index(&items, &index, tr(3), tr(10), DROP_ITEM_SIZE_I, None),
         true
     );
     assert_eq!(
-        hit_check_with_index(&items, &index, tr(3), tr(5), None),
+        hit_check_with_index(&items, &index, tr(3), tr(5), DROP_ITEM_SIZE_I, None),
         false
     );
 }
@@ -288,14 +490,149 @@ fn test_hit_check() {
 #[test]
 fn test_rounding() {
     assert_eq!(
-        intersecting_chunks(INDEX_GRID_SIZE_I / 2, INDEX_GRID_SIZE_I / 2),
+        intersecting_chunks(
+            INDEX_GRID_SIZE_I / 2,
+            INDEX_GRID_SIZE_I / 2,
+            DROP_ITEM_SIZE_I
+        ),
         [0; 4]
     );
-    assert_eq!(intersecting_chunks(0, INDEX_GRID_SIZE_I / 2), [-1, 0, 0, 0]);
-    assert_eq!(intersecting_chunks(0, 0), [-1, -1, 0, 0]);
-    assert_eq!(intersecting_chunks(-INDEX_GRID_SIZE_I, 0), [-2, -1, -1, 0]);
     assert_eq!(
-        intersecting_chunks(INDEX_GRID_SIZE_I, DROP_ITEM_SIZE_I),
+        intersecting_chunks(0, INDEX_GRID_SIZE_I / 2, DROP_ITEM_SIZE_I),
+        [-1, 0, 0, 0]
+    );
+    assert_eq!(intersecting_chunks(0, 0, DROP_ITEM_SIZE_I), [-1, -1, 0, 0]);
+    assert_eq!(
+        intersecting_chunks(-INDEX_GRID_SIZE_I, 0, DROP_ITEM_SIZE_I),
+        [-2, -1, -1, 0]
+    );
+    assert_eq!(
+        intersecting_chunks(INDEX_GRID_SIZE_I, DROP_ITEM_SIZE_I, DROP_ITEM_SIZE_I),
         [0, 0, 1, 0]
     );
 }
+
+#[test]
+fn test_merge_blocked_item() {
+    let mut items = [
+        (0, ItemType::IronOre),
+        (8, ItemType::IronOre),
+        (16, ItemType::CoalOre),
+    ]
+    .iter()
+    .map(|(x, type_)| DropItemEntry {
+        gen: 0,
+        item: Some(DropItem {
+            type_: *type_,
+            x: *x,
+            y: 0,
+            count: 1,
+        }),
+    })
+    .collect::<Vec<_>>();
+    let mut index = build_index(&items);
+
+    // An item of another type blocks the way
+    assert!(!merge_blocked_item(
+        &mut items,
+        &mut index,
+        GenId::new(1, 0),
+        9,
+        0,
+        DROP_ITEM_SIZE_I
+    ));
+    assert!(merge_blocked_item(
+        &mut items,
+        &mut index,
+        GenId::new(0, 0),
+        1,
+        0,
+        DROP_ITEM_SIZE_I
+    ));
+    assert!(items[0].item.is_none());
+    assert_eq!(items[1].item.as_ref().unwrap().count, 2);
+    assert!(!hit_check_with_index(
+        &items,
+        &index,
+        0,
+        0,
+        DROP_ITEM_SIZE_I,
+        Some(GenId::new(1, 0))
+    ));
+
+    // The stack does not grow beyond the limit
+    items[1].item.as_mut().unwrap().count = MAX_DROP_ITEM_COUNT;
+    items[0].item = Some(DropItem {
+        type_: ItemType::IronOre,
+        x: 0,
+        y: 0,
+        count: 1,
+    });
+    add_index(&mut index, GenId::new(0, 0), 0, 0);
+    assert!(!merge_blocked_item(
+        &mut items,
+        &mut index,
+        GenId::new(0, 0),
+        1,
+        0,
+        DROP_ITEM_SIZE_I
+    ));
+}
+
+#[test]
+fn test_find_item_at_negative_tiles() {
+    assert_eq!(Position::from_pixel(-1, -32), Position::new(-1, -1));
+    assert_eq!(Position::from_pixel(-33, 31), Position::new(-2, 0));
+
+    let items = [(-1, -1), (0, 0), (-2, 3)]
+        .iter()
+        .map(|(c, r)| DropItemEntry::new(ItemType::IronOre, &Position::new(*c, *r)))
+        .collect::<Vec<_>>();
+    for (i, (c, r)) in [(-1, -1), (0, 0), (-2, 3)].iter().enumerate() {
+        let (id, item) = find_item_at(&items, &Position::new(*c, *r)).unwrap();
+        assert_eq!(id.id, i as u32);
+        assert_eq!(item.tile(), Position::new(*c, *r));
+    }
+    // Truncating division would have put the item at (-1, -1) on the tile (0, 0) as well.
+    assert!(find_item_at(&items, &Position::new(-1, 0)).is_none());
+    assert!(find_item_at(&items, &Position::new(0, -1)).is_none());
+}
+
+#[test]
+fn test_belt_spacing() {
+    assert_eq!(belt_spacing(1.), DROP_ITEM_SIZE_I);
+    assert_eq!(belt_spacing(0.5), DROP_ITEM_SIZE_I * 2);
+    // Denser factors than the default would let the items overlap.
+    assert_eq!(belt_spacing(2.), MIN_BELT_SPACING);
+    assert_eq!(belt_spacing(0.1), MAX_BELT_SPACING);
+
+    // An item 12 pixels ahead blocks only with the sparse spacing, which needs the index lookup
+    // to reach the next grid cell beyond the item size.
+    let items = [DropItemEntry {
+        gen: 0,
+        item: Some(DropItem {
+            type_: ItemType::IronOre,
+            x: INDEX_GRID_SIZE_I + 2,
+            y: 0,
+            count: 1,
+        }),
+    }];
+    let index = build_index(&items);
+    let x = INDEX_GRID_SIZE_I - 10;
+    assert!(!hit_check_with_index(
+        &items,
+        &index,
+        x,
+        0,
+        belt_spacing(1.),
+        None
+    ));
+    assert!(hit_check_with_index(
+        &items,
+        &index,
+        x,
+        0,
+        belt_spacing(0.5),
+        None
+    ));
+}
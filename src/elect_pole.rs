@@ -63,6 +63,10 @@ impl Structure for ElectPole {
         true
     }
 
+    fn power_available(&self) -> f64 {
+        self.power
+    }
+
     fn power_outlet(&mut self, demand: f64) -> Option<f64> {
         let power = demand.min(self.power);
         self.power -= power;
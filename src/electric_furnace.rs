@@ -0,0 +1,377 @@
+use super::{
+    alerts::AlertType,
+    draw_direction_arrow,
+    items::item_to_str,
+    power_network::{drain_power, full_power_ratio, power_ratio},
+    productivity::{describe_productivity, take_bonus_items},
+    recipes::RECIPES,
+    structure::{
+        default_output_side, RotateErr, Rotation, Structure, StructureDynIter, StructureId,
+    },
+    DropItem, FactorishState, FrameProcResult, Inventory, InventoryTrait, ItemType, Position,
+    Recipe,
+};
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+use web_sys::CanvasRenderingContext2d;
+
+/// A furnace that smelts the same recipes as Furnace, but with electricity instead of coal.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct ElectricFurnace {
+    position: Position,
+    /// The side to drop the outputs on, shown with an arrow.
+    #[serde(default = "default_output_side")]
+    rotation: Rotation,
+    input_inventory: Inventory,
+    output_inventory: Inventory,
+    progress: Option<f64>,
+    power: f64,
+    max_power: f64,
+    recipe: Option<Recipe>,
+    #[serde(skip, default = "full_power_ratio")]
+    power_ratio: f64,
+    /// Drop full stacks of outputs on the ground, see `FactorishState::spill_output`.
+    #[serde(default)]
+    output_to_ground: bool,
+    /// Fraction of a bonus item accumulated from the productivity, see `take_bonus_items`.
+    #[serde(default)]
+    productivity_accum: f64,
+}
+
+impl ElectricFurnace {
+    pub(crate) fn new(position: &Position) -> Self {
+        ElectricFurnace {
+            position: *position,
+            rotation: default_output_side(),
+            input_inventory: Inventory::new(),
+            output_inventory: Inventory::new(),
+            progress: None,
+            power: 0.,
+            max_power: 20.,
+            recipe: None,
+            power_ratio: full_power_ratio(),
+            output_to_ground: false,
+            productivity_accum: 0.,
+        }
+    }
+
+    fn is_active(&self) -> bool {
+        self.progress.is_some() && 0. < self.power
+    }
+}
+
+impl Structure for ElectricFurnace {
+    fn name(&self) -> &str {
+        "Electric Furnace"
+    }
+
+    fn alert(&self) -> Option<AlertType> {
+        if self.recipe.is_some() && self.power == 0. {
+            Some(AlertType::NoPower)
+        } else {
+            None
+        }
+    }
+
+    fn position(&self) -> &Position {
+        &self.position
+    }
+
+    fn rotate(&mut self, _others: &StructureDynIter) -> Result<(), RotateErr> {
+        self.rotation = self.rotation.next();
+        Ok(())
+    }
+
+    fn rotation(&self) -> Option<Rotation> {
+        Some(self.rotation)
+    }
+
+    fn set_rotation(&mut self, rotation: &Rotation) -> Result<(), ()> {
+        self.rotation = *rotation;
+        Ok(())
+    }
+
+    fn draw(
+        &self,
+        state: &FactorishState,
+        context: &CanvasRenderingContext2d,
+        depth: i32,
+        is_toolbar: bool,
+    ) -> Result<(), JsValue> {
+        let (x, y) = (self.position.x as f64 * 32., self.position.y as f64 * 32.);
+        match depth {
+            0 => match state.image_electric_furnace.as_ref() {
+                Some(img) => {
+                    let sx = if self.is_active() {
+                        ((((state.sim_time * 5.) as isize) % 2 + 1) * 32) as f64
+                    } else {
+                        0.
+                    };
+                    context
+                        .draw_image_with_image_bitmap_and_sw_and_sh_and_dx_and_dy_and_dw_and_dh(
+                            &img.bitmap,
+                            sx,
+                            0.,
+                            32.,
+                            32.,
+                            x,
+                            y,
+                            32.,
+                            32.,
+                        )?;
+                }
+                None => return js_err!("electric furnace image not available"),
+            },
+            1 => {
+                if self.is_active() {
+                    // Pulsating glow around the furnace mouth
+                    context.save();
+                    context.set_global_alpha(0.25 + 0.15 * (state.sim_time * 3.).sin());
+                    context.set_fill_style(&js_str!("#ff9f3f"));
+                    context.begin_path();
+                    context.arc(x + 16., y + 20., 12., 0., std::f64::consts::PI * 2.)?;
+                    context.fill();
+                    context.restore();
+                }
+            }
+            2 => {
+                draw_direction_arrow((x, y), &self.rotation, state, context)?;
+                if !is_toolbar
+                    && self.recipe.is_some()
+                    && self.power == 0.
+                    && state.sim_time % 1. < 0.5
+                {
+                    if let Some(img) = state.image_electricity_alarm.as_ref() {
+                        context.draw_image_with_image_bitmap(&img.bitmap, x, y)?;
+                    } else {
+                        return js_err!("electricity alarm image not available");
+                    }
+                }
+            }
+            _ => (),
+        }
+
+        Ok(())
+    }
+
+    fn desc(&self, state: &FactorishState) -> String {
+        format!(
+            "{}<br>{}{}",
+            if self.recipe.is_some() {
+                // Progress bar
+                format!("{}{}{}{}{}",
+                    format!("Progress: {:.0}%<br>", self.progress.unwrap_or(0.) * 100.),
+                    "<div style='position: relative; width: 100px; height: 10px; background-color: #001f1f; margin: 2px; border: 1px solid #3f3f3f'>",
+                    format!("<div style='position: absolute; width: {}px; height: 10px; background-color: #ff00ff'></div></div>",
+                        self.progress.unwrap_or(0.) * 100.),
+                    format!(r#"Electricity: {:.1}kJ <div style='position: relative; width: 100px; height: 10px; background-color: #001f1f; margin: 2px; border: 1px solid #3f3f3f'>
+                    <div style='position: absolute; width: {}px; height: 10px; background-color: #ffff00'></div></div>"#,
+                    self.power,
+                    if 0. < self.max_power { (self.power) / self.max_power * 100. } else { 0. }),
+                    if self.power_ratio < 1. {
+                        format!("<div style='color: #ff7f00'>Power: {:.0}%</div>", self.power_ratio * 100.)
+                    } else {
+                        "".to_string()
+                    },
+                    )
+            } else {
+                String::from("No recipe")
+            },
+            format!(
+                "{}Input Items: <br>{}",
+                describe_productivity(state.productivity_bonus()),
+                self.input_inventory.describe()
+            ),
+            format!("Output Items: <br>{}", self.output_inventory.describe())
+        )
+    }
+
+    fn idle(&self) -> bool {
+        self.recipe.is_none()
+            && self.input_inventory.is_empty()
+            && !(self.output_to_ground && !self.output_inventory.is_empty())
+    }
+
+    fn frame_proc(
+        &mut self,
+        me: StructureId,
+        state: &mut FactorishState,
+        structures: &mut StructureDynIter,
+    ) -> Result<FrameProcResult, ()> {
+        if self.output_to_ground {
+            let bb = self.bounding_box();
+            state.spill_output(&bb, self.rotation, &mut self.output_inventory, structures);
+        }
+        self.power_ratio = power_ratio(&state.power_networks, me);
+        if self.recipe.is_none() {
+            self.recipe = RECIPES
+                .for_structure("Electric Furnace")
+                .map(|entry| &entry.recipe)
+                .find(|recipe| {
+                    recipe
+                        .input
+                        .iter()
+                        .all(|(type_, count)| *count <= self.input_inventory.count_item(&type_))
+                })
+                .cloned();
+        }
+        if let Some(recipe) = &self.recipe {
+            let mut ret = FrameProcResult::None;
+            // Refill the energy buffer from the power grid.
+            if self.power < recipe.power_cost {
+                self.power += drain_power(
+                    &state.power_networks,
+                    structures,
+                    me,
+                    self.max_power - self.power,
+                );
+            }
+
+            if self.progress.is_none() {
+                // First, check if we have enough ingredients to finish this recipe.
+                // If we do, consume the ingredients and start the progress timer.
+                if recipe
+                    .input
+                    .iter()
+                    .map(|(item, count)| count <= &self.input_inventory.count_item(item))
+                    .all(|b| b)
+                {
+                    for (item, count) in &recipe.input {
+                        self.input_inventory.remove_items(item, *count);
+                        state.production_stats.add_consumed(item, *count);
+                    }
+                    self.progress = Some(0.);
+                    ret = FrameProcResult::InventoryChanged(self.position);
+                } else {
+                    self.recipe = None;
+                    return Ok(FrameProcResult::None); // Return here to avoid borrow checker
+                }
+            }
+
+            if let Some(prev_progress) = self.progress {
+                // Proceed only if we have sufficient energy in the buffer.
+                let progress = (self.power / recipe.power_cost)
+                    .min(1. / recipe.recipe_time)
+                    .min(1.);
+                if 1. <= prev_progress + progress {
+                    self.progress = None;
+
+                    // Produce outputs into inventory, with the bonus items from the productivity
+                    let count = 1 + take_bonus_items(
+                        &mut self.productivity_accum,
+                        state.productivity_bonus(),
+                    );
+                    for output_item in &recipe.output {
+                        self.output_inventory.add_items(&output_item.0, count);
+                        state.production_stats.add_produced(&output_item.0, count);
+                    }
+                    return Ok(FrameProcResult::InventoryChanged(self.position));
+                } else {
+                    self.progress = Some(prev_progress + progress);
+                    self.power -= progress * recipe.power_cost;
+                }
+            }
+            return Ok(ret);
+        }
+        Ok(FrameProcResult::None)
+    }
+
+    fn input(&mut self, o: &DropItem) -> Result<(), JsValue> {
+        if self.recipe.is_none() {
+            self.recipe = Some(
+                RECIPES
+                    .for_structure("Electric Furnace")
+                    .map(|entry| &entry.recipe)
+                    .find(|recipe| recipe.input.get(&o.type_).is_some())
+                    .ok_or_else(|| js_str!("Cannot smelt {}", item_to_str(&o.type_)))?
+                    .clone(),
+            );
+        }
+
+        if let Some(recipe) = &self.recipe {
+            if recipe.input.contains_key(&o.type_) || recipe.output.contains_key(&o.type_) {
+                self.input_inventory.add_item(&o.type_);
+                return Ok(());
+            } else {
+                return js_err!("Item is not part of recipe");
+            }
+        }
+        js_err!("Recipe is not initialized")
+    }
+
+    fn can_input(&self, item_type: &ItemType) -> bool {
+        if let Some(recipe) = &self.recipe {
+            recipe.input.get(item_type).is_some()
+        } else {
+            matches!(item_type, ItemType::IronOre | ItemType::CopperOre)
+        }
+    }
+
+    fn can_output(&self) -> Inventory {
+        self.output_inventory.clone()
+    }
+
+    fn output(&mut self, _state: &mut FactorishState, item_type: &ItemType) -> Result<(), ()> {
+        if self.output_inventory.remove_item(item_type) {
+            Ok(())
+        } else {
+            Err(())
+        }
+    }
+
+    fn inventory(&self, is_input: bool) -> Option<&Inventory> {
+        Some(if is_input {
+            &self.input_inventory
+        } else {
+            &self.output_inventory
+        })
+    }
+
+    fn inventory_mut(&mut self, is_input: bool) -> Option<&mut Inventory> {
+        Some(if is_input {
+            &mut self.input_inventory
+        } else {
+            &mut self.output_inventory
+        })
+    }
+
+    fn destroy_inventory(&mut self) -> Inventory {
+        let mut ret = std::mem::take(&mut self.input_inventory);
+        ret.merge(std::mem::take(&mut self.output_inventory));
+        // Return the ingredients if it was in the middle of processing a recipe.
+        if let Some(mut recipe) = self.recipe.take() {
+            if self.progress.is_some() {
+                ret.merge(std::mem::take(&mut recipe.input));
+            }
+        }
+        ret
+    }
+
+    fn set_output_to_ground(&mut self, enable: bool) -> Result<(), JsValue> {
+        self.output_to_ground = enable;
+        Ok(())
+    }
+
+    fn crafting_progress(&self) -> Option<f64> {
+        self.progress
+    }
+
+    fn get_selected_recipe(&self) -> Option<&Recipe> {
+        self.recipe.as_ref()
+    }
+
+    /// Power wires to the electric poles within `wire_reach` are created by the caller for
+    /// any `power_sink` on construction.
+    fn power_sink(&self) -> bool {
+        true
+    }
+
+    fn power_demand(&self) -> f64 {
+        match &self.recipe {
+            Some(recipe) if self.power < recipe.power_cost => self.max_power - self.power,
+            _ => 0.,
+        }
+    }
+
+    crate::serialize_impl!();
+}
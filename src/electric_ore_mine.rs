@@ -0,0 +1,343 @@
+use super::{
+    alerts::AlertType,
+    draw_direction_arrow,
+    inventory::Inventory,
+    ore_mine::{MineOutput, OUTPUT_BUFFER_CAPACITY},
+    power_network::{drain_power, full_power_ratio, power_ratio},
+    productivity::describe_productivity,
+    structure::{RotateErr, Structure, StructureDynIter, StructureId},
+    FactorishState, FrameProcResult, Position, Recipe, Rotation, TILE_SIZE,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use wasm_bindgen::prelude::*;
+use web_sys::CanvasRenderingContext2d;
+
+/// A variant of OreMine that draws electricity from the power grid instead of burning coal.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct ElectricOreMine {
+    position: Position,
+    rotation: Rotation,
+    progress: f64,
+    power: f64,
+    max_power: f64,
+    recipe: Option<Recipe>,
+    output_structure: Option<StructureId>,
+    #[serde(skip)]
+    digging: bool,
+    #[serde(skip, default = "full_power_ratio")]
+    power_ratio: f64,
+    #[serde(flatten)]
+    output: MineOutput,
+}
+
+impl ElectricOreMine {
+    pub(crate) fn new(x: i32, y: i32, rotation: Rotation) -> Self {
+        ElectricOreMine {
+            position: Position { x, y },
+            rotation,
+            progress: 0.,
+            power: 0.,
+            max_power: 20.,
+            recipe: None,
+            output_structure: None,
+            digging: false,
+            power_ratio: full_power_ratio(),
+            output: MineOutput::default(),
+        }
+    }
+
+    fn on_construction_common(
+        &mut self,
+        other_id: StructureId,
+        other: &dyn Structure,
+        construct: bool,
+    ) -> Result<(), JsValue> {
+        let output_position = self.position.add(self.rotation.delta());
+        if *other.position() == output_position {
+            self.output_structure = if construct { Some(other_id) } else { None };
+        }
+        Ok(())
+    }
+}
+
+impl Structure for ElectricOreMine {
+    fn name(&self) -> &str {
+        "Electric Ore Mine"
+    }
+
+    fn alert(&self) -> Option<AlertType> {
+        if self.recipe.is_some() && self.power == 0. {
+            Some(AlertType::NoPower)
+        } else if self.output.is_stalled() {
+            Some(AlertType::OutputBlocked)
+        } else {
+            None
+        }
+    }
+
+    fn position(&self) -> &Position {
+        &self.position
+    }
+
+    fn draw(
+        &self,
+        state: &FactorishState,
+        context: &CanvasRenderingContext2d,
+        depth: i32,
+        is_toolbar: bool,
+    ) -> Result<(), JsValue> {
+        let (x, y) = (
+            self.position.x as f64 * TILE_SIZE,
+            self.position.y as f64 * TILE_SIZE,
+        );
+        match depth {
+            0 => match state.image_electric_mine.as_ref() {
+                Some(img) => {
+                    let sx = if self.digging {
+                        (((state.sim_time * 5.) as isize) % 2 + 1) as f64 * TILE_SIZE
+                    } else {
+                        0.
+                    };
+                    context
+                        .draw_image_with_image_bitmap_and_sw_and_sh_and_dx_and_dy_and_dw_and_dh(
+                            &img.bitmap,
+                            sx,
+                            0.,
+                            TILE_SIZE,
+                            TILE_SIZE,
+                            x,
+                            y,
+                            TILE_SIZE,
+                            TILE_SIZE,
+                        )?;
+                }
+                None => return js_err!("electric mine image not available"),
+            },
+            2 => {
+                draw_direction_arrow((x, y), &self.rotation, state, context)?;
+                if !is_toolbar
+                    && self.recipe.is_some()
+                    && self.power == 0.
+                    && state.sim_time % 1. < 0.5
+                {
+                    if let Some(img) = state.image_electricity_alarm.as_ref() {
+                        context.draw_image_with_image_bitmap(&img.bitmap, x, y)?;
+                    } else {
+                        return js_err!("electricity alarm image not available");
+                    }
+                }
+            }
+            _ => (),
+        }
+
+        Ok(())
+    }
+
+    fn desc(&self, state: &FactorishState) -> String {
+        let tile = if let Some(tile) = state.tile_at(&self.position) {
+            tile
+        } else {
+            return "Cell not found".to_string();
+        };
+        if self.recipe.is_some() {
+            // Progress bar
+            format!("{}{}{}{}{}{}{}{}",
+                if self.output.is_stalled() { "<b>Output blocked</b><br>" } else { "" },
+                format!("Progress: {:.0}%<br>", self.progress * 100.),
+                "<div style='position: relative; width: 100px; height: 10px; background-color: #001f1f; margin: 2px; border: 1px solid #3f3f3f'>",
+                format!("<div style='position: absolute; width: {}px; height: 10px; background-color: #ff00ff'></div></div>",
+                    self.progress * 100.),
+                format!(r#"Electricity: {:.1}kJ <div style='position: relative; width: 100px; height: 10px; background-color: #001f1f; margin: 2px; border: 1px solid #3f3f3f'>
+                 <div style='position: absolute; width: {}px; height: 10px; background-color: #ffff00'></div></div>"#,
+                    self.power,
+                    if 0. < self.max_power { (self.power) / self.max_power * 100. } else { 0. }),
+                if self.power_ratio < 1. {
+                    format!("<div style='color: #ff7f00'>Power: {:.0}%</div>", self.power_ratio * 100.)
+                } else {
+                    "".to_string()
+                },
+                describe_productivity(state.productivity_bonus()),
+                format!("Expected output: {}<br>Output buffer: {}/{}",
+                    tile.ore.map(|ore| ore.1).unwrap_or(0),
+                    self.output.buffered_count(),
+                    OUTPUT_BUFFER_CAPACITY))
+        } else {
+            String::from("Empty")
+        }
+    }
+
+    fn frame_proc(
+        &mut self,
+        me: StructureId,
+        state: &mut FactorishState,
+        structures: &mut StructureDynIter,
+    ) -> Result<FrameProcResult, ()> {
+        self.power_ratio = power_ratio(&state.power_networks, me);
+        let otile = &state.tile_at(&self.position);
+        if otile.is_none() {
+            return Ok(FrameProcResult::None);
+        }
+        let tile = otile.unwrap();
+
+        if self.recipe.is_none() {
+            if let Some(item_type) = tile.get_ore_type() {
+                self.recipe = Some(Recipe::new(
+                    HashMap::new(),
+                    hash_map!(item_type => 1usize),
+                    8.,
+                    80.,
+                ));
+            }
+        }
+        if let Some(recipe) = &self.recipe {
+            // Refill the energy buffer from the power grid.
+            if self.power < recipe.power_cost {
+                self.power += drain_power(
+                    &state.power_networks,
+                    structures,
+                    me,
+                    self.max_power - self.power,
+                );
+            }
+
+            // Proceed only if we have sufficient energy in the buffer.
+            let progress = (self.power / recipe.power_cost)
+                .min(1. / recipe.recipe_time)
+                .min(1. - self.progress);
+            if 1. <= self.progress + progress {
+                // The mined ore goes to the output buffer, so it is not lost even if the output
+                // is blocked.
+                self.output.complete_operation(
+                    state,
+                    &self.position,
+                    &mut self.recipe,
+                    &mut self.progress,
+                )?;
+            } else {
+                self.progress += progress;
+                self.power -= progress * recipe.power_cost;
+                self.digging = 0. < progress;
+            }
+        } else {
+            self.digging = false;
+        }
+
+        let output_position = self.position.add(self.rotation.delta());
+        let mut ret = FrameProcResult::None;
+        if self
+            .output
+            .output(state, structures, self.output_structure, &output_position)
+        {
+            ret = FrameProcResult::InventoryChanged(output_position);
+        }
+        if self.output.is_stalled() {
+            self.digging = false;
+        }
+        Ok(ret)
+    }
+
+    fn rotate(&mut self, others: &StructureDynIter) -> Result<(), RotateErr> {
+        self.rotation = self.rotation.next();
+        self.output_structure = None;
+        for (id, s) in others.dyn_iter_id() {
+            self.on_construction_common(id, s, true)
+                .map_err(|e| RotateErr::Other(e))?;
+        }
+        Ok(())
+    }
+
+    fn rotation(&self) -> Option<Rotation> {
+        Some(self.rotation)
+    }
+
+    fn set_rotation(&mut self, rotation: &Rotation) -> Result<(), ()> {
+        self.rotation = *rotation;
+        Ok(())
+    }
+
+    fn on_construction(
+        &mut self,
+        other_id: StructureId,
+        other: &dyn Structure,
+        construct: bool,
+    ) -> Result<(), JsValue> {
+        self.on_construction_common(other_id, other, construct)
+    }
+
+    /// Power wires to the electric poles within `wire_reach` are created by the caller for
+    /// any `power_sink`, so we only need to look for the output structure here.
+    fn on_construction_self(
+        &mut self,
+        _self_id: StructureId,
+        others: &StructureDynIter,
+        construct: bool,
+    ) -> Result<(), JsValue> {
+        for (id, s) in others.dyn_iter_id() {
+            self.on_construction_common(id, s, construct)?;
+        }
+        Ok(())
+    }
+
+    fn destroy_inventory(&mut self) -> Inventory {
+        // The ores already mined are returned.
+        self.output.take_inventory()
+    }
+
+    fn power_sink(&self) -> bool {
+        true
+    }
+
+    fn power_demand(&self) -> f64 {
+        match &self.recipe {
+            Some(recipe) if self.power < recipe.power_cost => self.max_power - self.power,
+            _ => 0.,
+        }
+    }
+
+    crate::serialize_impl!();
+}
+
+#[test]
+fn test_blocked_output_keeps_ore() {
+    use super::{
+        inventory::InventoryTrait, items::ItemType, scenarios::empty_state, Ore, OreValue,
+    };
+    const ORE_AMOUNT: u32 = 100;
+    let mut state = empty_state(16, 16);
+    let position = Position::new(1, 1);
+    state.tile_at_mut(&position).unwrap().ore = Some(OreValue(Ore::Iron, ORE_AMOUNT));
+    // A stone on the output tile blocks the ore from being dropped.
+    state
+        .new_object(&Position::new(2, 1), ItemType::StoneOre)
+        .unwrap();
+
+    fn mine(state: &FactorishState, id: StructureId) -> &dyn Structure {
+        state.structures[id.id as usize].dynamic.as_deref().unwrap()
+    }
+    let mut new_mine = ElectricOreMine::new(position.x, position.y, Rotation::Right);
+    // Enough energy to mine without a power grid
+    new_mine.power = 1000.;
+    let id = state.add_structure(Box::new(new_mine)).unwrap();
+
+    for _ in 0..1000 {
+        if mine(&state, id).alert() == Some(AlertType::OutputBlocked) {
+            break;
+        }
+        state.tick_headless(0.05).unwrap();
+    }
+    assert_eq!(mine(&state, id).alert(), Some(AlertType::OutputBlocked));
+
+    // Every mined ore is kept in the mine.
+    let mined = ORE_AMOUNT - state.tile_at(&position).unwrap().ore.unwrap().1;
+    assert_eq!(mined as usize, OUTPUT_BUFFER_CAPACITY);
+    let contents = state.structures[id.id as usize]
+        .dynamic
+        .as_deref_mut()
+        .unwrap()
+        .destroy_inventory();
+    assert_eq!(
+        contents.count_item(&ItemType::IronOre),
+        OUTPUT_BUFFER_CAPACITY
+    );
+}
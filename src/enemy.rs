@@ -0,0 +1,221 @@
+use super::{
+    structure::{BoundingBox, Position},
+    terrain::{CHUNK_SIZE_F, CHUNK_SIZE_I},
+    FactorishState, TILE_SIZE,
+};
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+use web_sys::CanvasRenderingContext2d;
+
+pub(crate) const ENEMY_MAX_HEALTH: f64 = 30.;
+/// Walking speed in tiles per second
+const ENEMY_SPEED: f64 = 1.5;
+/// Enemies notice structures within this distance in tiles, otherwise they wander.
+const AGGRO_RANGE: f64 = 24.;
+/// Enemies bite structures within this distance in tiles from the bounding box.
+const ATTACK_RANGE: f64 = 0.3;
+const ATTACK_DAMAGE: f64 = 10.;
+/// Seconds between bites
+const ATTACK_INTERVAL: f64 = 1.;
+/// Chunks with less pollution than this do not attract enemies.
+const SPAWN_POLLUTION: f64 = 20.;
+/// Expected number of spawns per second per unit of pollution above `SPAWN_POLLUTION`.
+const SPAWN_RATE: f64 = 5e-4;
+/// Distance in tiles from the center of the polluted chunk to spawn at.
+const SPAWN_DISTANCE: f64 = 20.;
+const MAX_ENEMIES: usize = 50;
+/// Radius of an enemy in tiles, used for drawing and hit tests.
+pub(crate) const ENEMY_RADIUS: f64 = 0.3;
+
+/// A wandering creature attracted by pollution that attacks structures.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct Enemy {
+    /// Position in tiles, not snapped to the grid
+    pub position: (f64, f64),
+    pub health: f64,
+    /// Direction of wandering in radians
+    heading: f64,
+    /// Seconds until the next bite
+    cooldown: f64,
+}
+
+impl Enemy {
+    pub(crate) fn new(position: (f64, f64), heading: f64) -> Self {
+        Self {
+            position,
+            health: ENEMY_MAX_HEALTH,
+            heading,
+            cooldown: 0.,
+        }
+    }
+
+    pub(crate) fn distance(&self, (x, y): (f64, f64)) -> f64 {
+        (self.position.0 - x).hypot(self.position.1 - y)
+    }
+}
+
+/// Distance from a point in tiles to the nearest point of the bounding box.
+fn distance_to_box((x, y): (f64, f64), bb: &BoundingBox) -> ((f64, f64), f64) {
+    let nearest = (
+        x.max(bb.x0 as f64).min(bb.x1 as f64),
+        y.max(bb.y0 as f64).min(bb.y1 as f64),
+    );
+    (nearest, (nearest.0 - x).hypot(nearest.1 - y))
+}
+
+impl FactorishState {
+    /// Spawn enemies around the chunks with heavy pollution.
+    fn spawn_enemies(&mut self, delta_time: f64) {
+        let polluted = self
+            .board
+            .iter()
+            .filter(|(_, chunk)| SPAWN_POLLUTION < chunk.pollution)
+            .map(|(chunk_pos, chunk)| (*chunk_pos, chunk.pollution - SPAWN_POLLUTION))
+            .collect::<Vec<_>>();
+        for (chunk_pos, excess) in polluted {
+            if MAX_ENEMIES <= self.enemies.len() {
+                break;
+            }
+            if excess * SPAWN_RATE * delta_time < self.rng.next() {
+                continue;
+            }
+            let angle = self.rng.next() * std::f64::consts::PI * 2.;
+            let position = (
+                (chunk_pos.x as f64 + 0.5) * CHUNK_SIZE_F + angle.cos() * SPAWN_DISTANCE,
+                (chunk_pos.y as f64 + 0.5) * CHUNK_SIZE_F + angle.sin() * SPAWN_DISTANCE,
+            );
+            let tile = Position::new(position.0.floor() as i32, position.1.floor() as i32);
+            // Do not spawn out of the generated area or inside a building
+            if !self.board.contains_key(&tile.div_mod(CHUNK_SIZE_I).0)
+                || self
+                    .structure_iter()
+                    .any(|s| !s.walkable() && s.contains(&tile))
+            {
+                continue;
+            }
+            self.enemies
+                .push(Enemy::new(position, angle + std::f64::consts::PI));
+        }
+    }
+
+    /// Move enemies toward the nearest structure and let them bite it when they reach it.
+    /// Enemies walk over walkable structures such as belts instead of attacking them.
+    /// Pathing is a greedy step toward the target, so enemies get stuck behind cliffs or water.
+    pub(crate) fn update_enemies(&mut self, delta_time: f64) -> Result<(), JsValue> {
+        self.enemies.retain(|enemy| 0. < enemy.health);
+        self.spawn_enemies(delta_time);
+
+        let mut enemies = std::mem::take(&mut self.enemies);
+        let mut bites = vec![];
+        for enemy in &mut enemies {
+            enemy.cooldown = (enemy.cooldown - delta_time).max(0.);
+            let target = self
+                .structures
+                .iter()
+                .enumerate()
+                .filter_map(|(i, entry)| {
+                    let structure = entry.dynamic.as_deref().filter(|s| !s.walkable())?;
+                    let (nearest, dist) =
+                        distance_to_box(enemy.position, &structure.bounding_box());
+                    Some((i, nearest, dist))
+                })
+                .filter(|(_, _, dist)| *dist < AGGRO_RANGE)
+                .min_by(|a, b| a.2.partial_cmp(&b.2).unwrap_or(std::cmp::Ordering::Equal));
+            let step = ENEMY_SPEED * delta_time;
+            match target {
+                Some((i, _, dist)) if dist <= ATTACK_RANGE => {
+                    if enemy.cooldown <= 0. {
+                        bites.push(i);
+                        enemy.cooldown = ATTACK_INTERVAL;
+                    }
+                }
+                Some((_, nearest, dist)) => {
+                    let step = step.min(dist - ATTACK_RANGE * 0.5);
+                    enemy.heading =
+                        (nearest.1 - enemy.position.1).atan2(nearest.0 - enemy.position.0);
+                    enemy.position.0 += enemy.heading.cos() * step;
+                    enemy.position.1 += enemy.heading.sin() * step;
+                }
+                None => {
+                    enemy.heading += (self.rng.next() - 0.5) * delta_time * 4.;
+                    enemy.position.0 += enemy.heading.cos() * step * 0.5;
+                    enemy.position.1 += enemy.heading.sin() * step * 0.5;
+                }
+            }
+        }
+        self.enemies = enemies;
+
+        for i in bites {
+            self.apply_damage(i, ATTACK_DAMAGE)?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn render_enemies(
+        &self,
+        context: &CanvasRenderingContext2d,
+        (left, top, right, bottom): (i32, i32, i32, i32),
+    ) -> Result<(), JsValue> {
+        context.save();
+        context.set_stroke_style(&js_str!("#1f0000"));
+        context.set_line_width(1.5);
+        for enemy in &self.enemies {
+            let (x, y) = enemy.position;
+            if x < left as f64 - 1.
+                || right as f64 + 1. < x
+                || y < top as f64 - 1.
+                || bottom as f64 + 1. < y
+            {
+                continue;
+            }
+            let (x, y) = (x * TILE_SIZE, y * TILE_SIZE);
+            let radius = ENEMY_RADIUS * TILE_SIZE;
+            // Legs wiggling while walking
+            let wiggle = (self.sim_time * 20. + x).sin() * 0.3;
+            context.begin_path();
+            for side in &[-1., 1.] {
+                for leg in &[-0.6, 0., 0.6] {
+                    let angle = enemy.heading + side * (std::f64::consts::FRAC_PI_2 + leg + wiggle);
+                    context.move_to(x, y);
+                    context.line_to(
+                        x + angle.cos() * radius * 1.6,
+                        y + angle.sin() * radius * 1.6,
+                    );
+                }
+            }
+            context.stroke();
+            context.set_fill_style(&js_str!("#7f1f0f"));
+            context.begin_path();
+            context.arc(x, y, radius, 0., std::f64::consts::PI * 2.)?;
+            context.fill();
+            context.stroke();
+
+            if enemy.health < ENEMY_MAX_HEALTH {
+                context.set_fill_style(&js_str!("rgba(0,0,0,0.7)"));
+                context.fill_rect(x - radius, y - radius - 5., radius * 2., 3.);
+                context.set_fill_style(&js_str!("#ff3f00"));
+                context.fill_rect(
+                    x - radius,
+                    y - radius - 5.,
+                    radius * 2. * enemy.health / ENEMY_MAX_HEALTH,
+                    3.,
+                );
+            }
+        }
+        context.restore();
+        Ok(())
+    }
+}
+
+#[test]
+fn test_distance_to_box() {
+    let bb = BoundingBox {
+        x0: 0,
+        y0: 0,
+        x1: 2,
+        y1: 2,
+    };
+    assert_eq!(distance_to_box((1., 1.), &bb), ((1., 1.), 0.));
+    assert_eq!(distance_to_box((4., 1.), &bb), ((2., 1.), 2.));
+    assert_eq!(distance_to_box((-3., -4.), &bb), ((0., 0.), 5.));
+}
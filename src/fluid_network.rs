@@ -0,0 +1,310 @@
+use super::{
+    structure::{Structure, StructureDynIter, StructureEntry, StructureId},
+    FactorishState, Rotation, TILE_SIZE,
+};
+use std::collections::{HashMap, HashSet};
+use wasm_bindgen::prelude::*;
+use web_sys::CanvasRenderingContext2d;
+
+/// Offsets of the neighbors in the order of `FluidBox::connect_to`
+const NEIGHBOR_OFFSETS: [(f64, f64); 4] = [(-1., 0.), (0., -1.), (1., 0.), (0., 1.)];
+/// Flows smaller than this are not drawn, since the pressure never settles exactly.
+const MIN_VISIBLE_FLOW: f64 = 0.01;
+
+/// A connection from a fluid box of a structure to the neighboring structure in `direction`.
+#[derive(Debug)]
+pub(crate) struct FluidEdge {
+    pub from: StructureId,
+    /// Index of the fluid box in `Structure::fluid_box_mut` of `from`.
+    pub from_box: usize,
+    pub to: StructureId,
+}
+
+/// A connected component of fluid boxes, e.g. a pipe network with its pumps and boilers.
+#[derive(Debug)]
+pub(crate) struct FluidNetwork {
+    /// Edges in the order of structure id, fluid box index and direction, which is the same
+    /// order that each fluid box used to scan its neighbors.
+    pub edges: Vec<FluidEdge>,
+}
+
+/// Whether the fluid boxes of two structures on neighboring tiles face each other.
+fn fluid_connected(a: &dyn Structure, b: &dyn Structure) -> bool {
+    let (pa, pb) = (a.position(), b.position());
+    Rotation::from_delta((pb.x - pa.x, pb.y - pa.y)).map_or(false, |direction| {
+        a.fluid_connects(direction) && b.fluid_connects(direction.next().next())
+    })
+}
+
+/// Point the fluid boxes of the structure facing `direction` to the neighbor, or disconnect
+/// them with None. The other fluid boxes are disconnected from that side.
+fn set_fluid_connections(s: &mut dyn Structure, direction: Rotation, to: Option<StructureId>) {
+    let faces = (0..s.fluid_box().map_or(0, |fluid_boxes| fluid_boxes.len()))
+        .map(|i| s.fluid_box_connects(i, direction))
+        .collect::<Vec<_>>();
+    if let Some(fluid_boxes) = s.fluid_box_mut() {
+        for (fluid_box, faces) in fluid_boxes.into_iter().zip(faces) {
+            fluid_box.connect_to[direction as usize] = to.filter(|_| faces);
+        }
+    }
+}
+
+/// Connect the fluid boxes of two structures if they are on neighboring tiles and face each
+/// other, or disconnect them otherwise, e.g. after one of them is turned around.
+pub(crate) fn connect_fluid_boxes(
+    (a_id, a): (StructureId, &mut dyn Structure),
+    (b_id, b): (StructureId, &mut dyn Structure),
+) {
+    let (pa, pb) = (*a.position(), *b.position());
+    let a_to_b = if let Some(direction) = Rotation::from_delta((pb.x - pa.x, pb.y - pa.y)) {
+        direction
+    } else {
+        return;
+    };
+    let connected = a.fluid_box().is_some() && b.fluid_box().is_some() && fluid_connected(a, b);
+    set_fluid_connections(a, a_to_b, Some(b_id).filter(|_| connected));
+    set_fluid_connections(b, a_to_b.next().next(), Some(a_id).filter(|_| connected));
+}
+
+pub(crate) fn build_fluid_networks(structures: &StructureDynIter) -> Vec<FluidNetwork> {
+    let mut neighbors = HashMap::<StructureId, Vec<StructureId>>::new();
+    let mut edges = vec![];
+
+    for (id, s) in structures.dyn_iter_id() {
+        let fluid_boxes = if let Some(fluid_boxes) = s.fluid_box() {
+            fluid_boxes
+        } else {
+            continue;
+        };
+        neighbors.entry(id).or_default();
+        for (from_box, fluid_box) in fluid_boxes.iter().enumerate() {
+            for to in fluid_box.connect_to.iter().filter_map(|c| *c) {
+                // Connections may be one-sided, so follow them in both directions.
+                neighbors.entry(id).or_default().push(to);
+                neighbors.entry(to).or_default().push(id);
+                edges.push(FluidEdge {
+                    from: id,
+                    from_box,
+                    to,
+                });
+            }
+        }
+    }
+
+    let mut visited = HashSet::<StructureId>::new();
+    let mut ret = vec![];
+    let mut component_of = HashMap::<StructureId, usize>::new();
+
+    for (id, _) in structures.dyn_iter_id() {
+        if !neighbors.contains_key(&id) || visited.contains(&id) {
+            continue;
+        }
+        let mut expand_list = vec![id];
+        while let Some(id) = expand_list.pop() {
+            if !visited.insert(id) {
+                continue;
+            }
+            component_of.insert(id, ret.len());
+            for neighbor in neighbors.get(&id).into_iter().flatten() {
+                if !visited.contains(neighbor) {
+                    expand_list.push(*neighbor);
+                }
+            }
+        }
+        ret.push(FluidNetwork { edges: vec![] });
+    }
+
+    for edge in edges {
+        if let Some(nw) = component_of.get(&edge.from).and_then(|i| ret.get_mut(*i)) {
+            nw.edges.push(edge);
+        }
+    }
+
+    // Drop isolated fluid boxes, which have nothing to exchange
+    ret.into_iter().filter(|nw| !nw.edges.is_empty()).collect()
+}
+
+/// Exchange fluids along the edges of the networks. Since networks do not share fluid boxes,
+/// iterating network by network gives the same result as iterating all edges in order.
+pub(crate) fn simulate_fluid_networks(
+    networks: &[FluidNetwork],
+    structures: &mut [StructureEntry],
+) -> Result<(), JsValue> {
+    // The conflicts are detected again in this frame, so that they clear once resolved.
+    for fluid_boxes in structures
+        .iter_mut()
+        .filter_map(|s| s.dynamic.as_deref_mut()?.fluid_box_mut())
+    {
+        for fluid_box in fluid_boxes {
+            fluid_box.blocked = false;
+            fluid_box.flow = None;
+        }
+    }
+    for edge in networks.iter().flat_map(|nw| &nw.edges) {
+        if structures.len() <= edge.from.id as usize {
+            continue;
+        }
+        let (center, mut others) = StructureDynIter::new(structures, edge.from.id as usize)?;
+        if center.gen != edge.from.gen {
+            continue;
+        }
+        let fluid_box = center
+            .dynamic
+            .as_deref_mut()
+            .and_then(|s| s.fluid_box_mut())
+            .and_then(|fluid_boxes| fluid_boxes.into_iter().nth(edge.from_box));
+        let fluid_box = if let Some(fluid_box) = fluid_box {
+            fluid_box
+        } else {
+            continue;
+        };
+        if let Some(fluid_boxes) = others.get_mut(edge.to).and_then(|s| s.fluid_box_mut()) {
+            let mut amount = 0.;
+            // Only the fluid boxes facing this one exchange the fluid, e.g. one side of a valve.
+            for other in fluid_boxes
+                .into_iter()
+                .filter(|other| other.connect_to.contains(&Some(edge.from)))
+            {
+                amount += fluid_box.flow_to(other);
+            }
+            let direction = fluid_box
+                .connect_to
+                .iter()
+                .position(|c| *c == Some(edge.to));
+            if let Some(direction) = direction {
+                if fluid_box.flow.map_or(true, |(_, biggest)| biggest < amount) {
+                    fluid_box.flow = Some((direction, amount));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+impl FactorishState {
+    /// Draw arrows moving in the direction of the biggest outflow of each fluid box,
+    /// at a speed in proportion to the flow.
+    pub(crate) fn render_fluid_flow(&self, context: &CanvasRenderingContext2d) {
+        context.save();
+        context.set_fill_style(&js_str!("rgba(255,255,255,0.8)"));
+        context.set_stroke_style(&js_str!("rgba(0,0,0,0.8)"));
+        context.set_line_width(1.);
+        for structure in self.structure_iter() {
+            let fluid_boxes = if let Some(fluid_boxes) = structure.fluid_box() {
+                fluid_boxes
+            } else {
+                continue;
+            };
+            let bb = structure.bounding_box();
+            let center = (
+                (bb.x0 + bb.x1) as f64 / 2. * TILE_SIZE,
+                (bb.y0 + bb.y1) as f64 / 2. * TILE_SIZE,
+            );
+            for (direction, amount) in fluid_boxes.iter().filter_map(|fb| fb.flow) {
+                if amount < MIN_VISIBLE_FLOW {
+                    continue;
+                }
+                let (dx, dy) = NEIGHBOR_OFFSETS[direction];
+                // The arrow travels half a tile from the center toward the neighbor
+                let phase = (self.sim_time * amount.sqrt()).fract();
+                let (x, y) = (
+                    center.0 + dx * phase * TILE_SIZE / 2.,
+                    center.1 + dy * phase * TILE_SIZE / 2.,
+                );
+                context.begin_path();
+                context.move_to(x + dx * 5., y + dy * 5.);
+                context.line_to(x - dx * 3. - dy * 4., y - dy * 3. + dx * 4.);
+                context.line_to(x - dx * 3. + dy * 4., y - dy * 3. - dx * 4.);
+                context.close_path();
+                context.fill();
+                context.stroke();
+            }
+        }
+        context.restore();
+    }
+}
+
+#[wasm_bindgen]
+impl FactorishState {
+    /// Show the direction of fluid flows with animated arrows.
+    pub fn set_fluid_flow_overlay(&mut self, value: bool) {
+        self.fluid_flow_overlay = value;
+    }
+}
+
+#[test]
+fn test_network_order_matches_edge_order() {
+    use super::{pipe::Pipe, scenarios::empty_state, water_well::FluidType, Position};
+
+    // Two separate lines of pipes added alternately, so that the edges of the networks
+    // interleave in the order of structure ids.
+    let build = || {
+        let mut state = empty_state(16, 16);
+        for x in 0..6 {
+            for y in &[0, 2] {
+                state
+                    .add_structure(Box::new(Pipe::new(&Position::new(x, *y))))
+                    .unwrap();
+            }
+        }
+        let mut fill = |id: usize, type_: FluidType, amount: f64| {
+            let mut boxes = state.structures[id]
+                .dynamic
+                .as_deref_mut()
+                .unwrap()
+                .fluid_box_mut()
+                .unwrap();
+            boxes[0].type_ = Some(type_);
+            boxes[0].amount = amount;
+        };
+        fill(0, FluidType::Water, 100.);
+        fill(11, FluidType::Steam, 60.);
+        state
+    };
+    let amounts = |structures: &[StructureEntry]| {
+        structures
+            .iter()
+            .map(|s| s.dynamic.as_deref().unwrap().fluid_box().unwrap()[0].amount)
+            .collect::<Vec<_>>()
+    };
+
+    let mut by_network = build();
+    let networks = build_fluid_networks(&StructureDynIter::new_all(&mut by_network.structures));
+    assert_eq!(networks.len(), 2);
+
+    // All the edges in the order that each fluid box used to scan its neighbors
+    let mut by_edge = build();
+    let edges = StructureDynIter::new_all(&mut by_edge.structures)
+        .dyn_iter_id()
+        .flat_map(|(from, s)| {
+            s.fluid_box()
+                .unwrap()
+                .into_iter()
+                .enumerate()
+                .flat_map(|(from_box, fluid_box)| {
+                    fluid_box
+                        .connect_to
+                        .iter()
+                        .flatten()
+                        .map(move |to| FluidEdge {
+                            from,
+                            from_box,
+                            to: *to,
+                        })
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    let all_edges = [FluidNetwork { edges }];
+
+    for _ in 0..100 {
+        simulate_fluid_networks(&networks, &mut by_network.structures).unwrap();
+        simulate_fluid_networks(&all_edges, &mut by_edge.structures).unwrap();
+        assert_eq!(
+            amounts(&by_network.structures),
+            amounts(&by_edge.structures)
+        );
+    }
+    // The fluids have spread along the lines
+    assert!(0. < amounts(&by_network.structures)[10]);
+}
@@ -1,55 +1,58 @@
 use super::{
+    alerts::AlertType,
+    draw_direction_arrow,
     items::item_to_str,
-    structure::{Structure, StructureDynIter, StructureId},
-    DropItem, FactorishState, FrameProcResult, Inventory, InventoryTrait, ItemType, Position,
-    Recipe, TempEnt, COAL_POWER,
+    productivity::{describe_productivity, take_bonus_items},
+    recipes::RECIPES,
+    structure::{
+        default_output_side, RotateErr, Rotation, Structure, StructureDynIter, StructureId,
+    },
+    unburnt_fuel, DropItem, FactorishState, FrameProcResult, Inventory, InventoryTrait, ItemType,
+    Position, Recipe, TempEnt, COAL_POWER,
 };
-use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
 use wasm_bindgen::prelude::*;
 use web_sys::CanvasRenderingContext2d;
 
 const FUEL_CAPACITY: usize = 10;
 
-/// A list of fixed recipes, because dynamic get_recipes() can only return a Vec.
-static RECIPES: Lazy<[Recipe; 2]> = Lazy::new(|| {
-    [
-        Recipe::new(
-            hash_map!(ItemType::IronOre => 1usize),
-            hash_map!(ItemType::IronPlate => 1usize),
-            20.,
-            50.,
-        ),
-        Recipe::new(
-            hash_map!(ItemType::CopperOre => 1usize),
-            hash_map!(ItemType::CopperPlate => 1usize),
-            20.,
-            50.,
-        ),
-    ]
-});
-
 #[derive(Serialize, Deserialize)]
 pub(crate) struct Furnace {
     position: Position,
+    /// The side to drop the outputs on, shown with an arrow.
+    #[serde(default = "default_output_side")]
+    rotation: Rotation,
     input_inventory: Inventory,
     output_inventory: Inventory,
+    /// Fuel is kept apart from the ores, so that an inserter or the player can tell them apart.
+    #[serde(default)]
+    burner_inventory: Inventory,
     progress: Option<f64>,
     power: f64,
     max_power: f64,
     recipe: Option<Recipe>,
+    /// Drop full stacks of outputs on the ground, see `FactorishState::spill_output`.
+    #[serde(default)]
+    output_to_ground: bool,
+    /// Fraction of a bonus item accumulated from the productivity, see `take_bonus_items`.
+    #[serde(default)]
+    productivity_accum: f64,
 }
 
 impl Furnace {
     pub(crate) fn new(position: &Position) -> Self {
         Furnace {
             position: *position,
+            rotation: default_output_side(),
             input_inventory: Inventory::new(),
             output_inventory: Inventory::new(),
+            burner_inventory: Inventory::new(),
             progress: None,
             power: 20.,
             max_power: 20.,
             recipe: None,
+            output_to_ground: false,
+            productivity_accum: 0.,
         }
     }
 }
@@ -59,10 +62,32 @@ impl Structure for Furnace {
         "Furnace"
     }
 
+    fn alert(&self) -> Option<AlertType> {
+        if self.recipe.is_some() && self.power == 0. {
+            Some(AlertType::NoFuel)
+        } else {
+            None
+        }
+    }
+
     fn position(&self) -> &Position {
         &self.position
     }
 
+    fn rotate(&mut self, _others: &StructureDynIter) -> Result<(), RotateErr> {
+        self.rotation = self.rotation.next();
+        Ok(())
+    }
+
+    fn rotation(&self) -> Option<Rotation> {
+        Some(self.rotation)
+    }
+
+    fn set_rotation(&mut self, rotation: &Rotation) -> Result<(), ()> {
+        self.rotation = *rotation;
+        Ok(())
+    }
+
     fn draw(
         &self,
         state: &FactorishState,
@@ -70,10 +95,13 @@ impl Structure for Furnace {
         depth: i32,
         is_toolbar: bool,
     ) -> Result<(), JsValue> {
+        let (x, y) = (self.position.x as f64 * 32., self.position.y as f64 * 32.);
+        if depth == 2 {
+            return draw_direction_arrow((x, y), &self.rotation, state, context);
+        }
         if depth != 0 {
             return Ok(());
         };
-        let (x, y) = (self.position.x as f64 * 32., self.position.y as f64 * 32.);
         match state.image_furnace.as_ref() {
             Some(img) => {
                 let sx = if self.progress.is_some() && 0. < self.power {
@@ -102,7 +130,7 @@ impl Structure for Furnace {
         Ok(())
     }
 
-    fn desc(&self, _state: &FactorishState) -> String {
+    fn desc(&self, state: &FactorishState) -> String {
         format!(
             "{}<br>{}{}",
             if self.recipe.is_some() {
@@ -123,20 +151,36 @@ impl Structure for Furnace {
             } else {
                 String::from("No recipe")
             },
-            format!("Input Items: <br>{}", self.input_inventory.describe()),
+            format!(
+                "{}Fuel: <br>{}Input Items: <br>{}",
+                describe_productivity(state.productivity_bonus()),
+                self.burner_inventory.describe(),
+                self.input_inventory.describe()
+            ),
             format!("Output Items: <br>{}", self.output_inventory.describe())
         )
     }
 
+    fn idle(&self) -> bool {
+        self.recipe.is_none()
+            && self.input_inventory.is_empty()
+            && !(self.output_to_ground && !self.output_inventory.is_empty())
+    }
+
     fn frame_proc(
         &mut self,
         _me: StructureId,
         state: &mut FactorishState,
-        _structures: &mut StructureDynIter,
+        structures: &mut StructureDynIter,
     ) -> Result<FrameProcResult, ()> {
+        if self.output_to_ground {
+            let bb = self.bounding_box();
+            state.spill_output(&bb, self.rotation, &mut self.output_inventory, structures);
+        }
         if self.recipe.is_none() {
             self.recipe = RECIPES
-                .iter()
+                .for_structure("Furnace")
+                .map(|entry| &entry.recipe)
                 .find(|recipe| {
                     recipe
                         .input
@@ -148,12 +192,18 @@ impl Structure for Furnace {
         if let Some(recipe) = &self.recipe {
             let mut ret = FrameProcResult::None;
             // First, check if we need to refill the energy buffer in order to continue the current work.
-            if self.input_inventory.get(&ItemType::CoalOre).is_some() {
+            // Saves from before the burner inventory have the fuel in the input inventory.
+            let fuel_inventory = if self.burner_inventory.get(&ItemType::CoalOre).is_some() {
+                &mut self.burner_inventory
+            } else {
+                &mut self.input_inventory
+            };
+            if fuel_inventory.get(&ItemType::CoalOre).is_some() {
                 // Refill the energy from the fuel
                 if self.power < recipe.power_cost {
                     self.power += COAL_POWER;
                     self.max_power = self.power;
-                    self.input_inventory.remove_item(&ItemType::CoalOre);
+                    fuel_inventory.remove_item(&ItemType::CoalOre);
                     ret = FrameProcResult::InventoryChanged(self.position);
                 }
             }
@@ -171,6 +221,7 @@ impl Structure for Furnace {
                 {
                     for (item, count) in &recipe.input {
                         self.input_inventory.remove_items(item, *count);
+                        state.production_stats.add_consumed(item, *count);
                     }
                     self.progress = Some(0.);
                     ret = FrameProcResult::InventoryChanged(self.position);
@@ -193,14 +244,20 @@ impl Structure for Furnace {
                 if 1. <= prev_progress + progress {
                     self.progress = None;
 
-                    // Produce outputs into inventory
+                    // Produce outputs into inventory, with the bonus items from the productivity
+                    let count = 1 + take_bonus_items(
+                        &mut self.productivity_accum,
+                        state.productivity_bonus(),
+                    );
                     for output_item in &recipe.output {
-                        self.output_inventory.add_item(&output_item.0);
+                        self.output_inventory.add_items(&output_item.0, count);
+                        state.production_stats.add_produced(&output_item.0, count);
                     }
                     return Ok(FrameProcResult::InventoryChanged(self.position));
                 } else {
                     self.progress = Some(prev_progress + progress);
                     self.power -= progress * recipe.power_cost;
+                    state.emit_pollution(&self.position, progress * recipe.power_cost);
                 }
             }
             return Ok(ret);
@@ -210,10 +267,7 @@ impl Structure for Furnace {
 
     fn input(&mut self, o: &DropItem) -> Result<(), JsValue> {
         // Fuels are always welcome.
-        if o.type_ == ItemType::CoalOre
-            && self.input_inventory.count_item(&ItemType::CoalOre) < FUEL_CAPACITY
-        {
-            self.input_inventory.add_item(&ItemType::CoalOre);
+        if self.add_burner_inventory(&o.type_, 1) == 1 {
             return Ok(());
         }
 
@@ -245,7 +299,7 @@ impl Structure for Furnace {
         }
 
         if let Some(recipe) = &self.recipe {
-            if 0 < recipe.input.count_item(&o.type_) || 0 < recipe.output.count_item(&o.type_) {
+            if recipe.input.contains_key(&o.type_) || recipe.output.contains_key(&o.type_) {
                 self.input_inventory.add_item(&o.type_);
                 return Ok(());
             } else {
@@ -257,7 +311,7 @@ impl Structure for Furnace {
 
     fn can_input(&self, item_type: &ItemType) -> bool {
         if *item_type == ItemType::CoalOre {
-            if self.input_inventory.count_item(item_type) < FUEL_CAPACITY {
+            if self.burner_inventory.count_item(item_type) < FUEL_CAPACITY {
                 return true;
             }
         }
@@ -296,9 +350,37 @@ impl Structure for Furnace {
         })
     }
 
+    fn burner_inventory(&self) -> Option<&Inventory> {
+        Some(&self.burner_inventory)
+    }
+
+    fn add_burner_inventory(&mut self, item_type: &ItemType, amount: isize) -> isize {
+        if amount < 0 {
+            let existing = self.burner_inventory.count_item(item_type);
+            let removed = existing.min((-amount) as usize);
+            self.burner_inventory.remove_items(item_type, removed);
+            -(removed as isize)
+        } else if *item_type == ItemType::CoalOre {
+            let add_amount = amount.min(
+                (FUEL_CAPACITY - self.burner_inventory.count_item(&ItemType::CoalOre)) as isize,
+            );
+            self.burner_inventory
+                .add_items(item_type, add_amount as usize);
+            add_amount
+        } else {
+            0
+        }
+    }
+
+    fn burner_energy(&self) -> Option<(f64, f64)> {
+        Some((self.power, self.max_power))
+    }
+
     fn destroy_inventory(&mut self) -> Inventory {
         let mut ret = std::mem::take(&mut self.input_inventory);
         ret.merge(std::mem::take(&mut self.output_inventory));
+        ret.merge(std::mem::take(&mut self.burner_inventory));
+        ret.merge(unburnt_fuel(std::mem::take(&mut self.power)));
         // Return the ingredients if it was in the middle of processing a recipe.
         if let Some(mut recipe) = self.recipe.take() {
             if self.progress.is_some() {
@@ -308,8 +390,13 @@ impl Structure for Furnace {
         ret
     }
 
-    fn get_recipes(&self) -> std::borrow::Cow<[Recipe]> {
-        std::borrow::Cow::from(&RECIPES[..])
+    fn set_output_to_ground(&mut self, enable: bool) -> Result<(), JsValue> {
+        self.output_to_ground = enable;
+        Ok(())
+    }
+
+    fn crafting_progress(&self) -> Option<f64> {
+        self.progress
     }
 
     fn get_selected_recipe(&self) -> Option<&Recipe> {
@@ -320,3 +407,39 @@ impl Structure for Furnace {
         serde_json::to_value(self)
     }
 }
+
+#[test]
+fn test_destroy_inventory_mid_smelt() {
+    let mut furnace = Furnace::new(&Position::new(0, 0));
+    for type_ in [ItemType::IronOre; 3]
+        .iter()
+        .chain([ItemType::CoalOre; 2].iter())
+    {
+        furnace.input(&DropItem::new(*type_, 0, 0)).unwrap();
+    }
+    furnace.output_inventory.add_item(&ItemType::IronPlate);
+
+    // Start smelting the way frame_proc does, which needs the whole game state, after the
+    // initial energy has run out.
+    furnace.power = 0.;
+    let recipe = furnace.recipe.clone().unwrap();
+    furnace.burner_inventory.remove_item(&ItemType::CoalOre);
+    furnace.power += COAL_POWER;
+    for (item, count) in &recipe.input {
+        furnace.input_inventory.remove_items(item, *count);
+    }
+    furnace.progress = Some(0.);
+
+    // A coal in the energy buffer is given back as long as it has not started burning.
+    let mut unburnt = Inventory::new();
+    unburnt.merge(unburnt_fuel(furnace.power));
+    assert_eq!(unburnt.count_item(&ItemType::CoalOre), 1);
+
+    furnace.progress = Some(0.4);
+    furnace.power -= 0.4 * recipe.power_cost;
+    let refund = furnace.destroy_inventory();
+    assert_eq!(refund.count_item(&ItemType::IronOre), 3);
+    assert_eq!(refund.count_item(&ItemType::IronPlate), 1);
+    // The burning coal is lost.
+    assert_eq!(refund.count_item(&ItemType::CoalOre), 1);
+}
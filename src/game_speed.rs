@@ -0,0 +1,77 @@
+use super::FactorishState;
+use wasm_bindgen::prelude::*;
+
+const MIN_GAME_SPEED: f64 = 0.125;
+/// Every step runs all the structures, so high speeds are limited by the performance.
+const MAX_GAME_SPEED: f64 = 8.;
+/// The step of `step_once` before any frame has been simulated, which is the frame interval
+/// of the JavaScript main loop.
+const DEFAULT_DELTA_TIME: f64 = 0.05;
+
+/// Returns the number of simulation steps to run in a frame at the game speed. The fraction is
+/// carried over to the next frames in `accum`, so that slow motion runs a step every few frames.
+/// Each step moves items on belts by a pixel at most, so items do not tunnel through each other
+/// at any speed.
+pub(crate) fn game_speed_steps(speed: f64, accum: &mut f64) -> usize {
+    *accum += speed;
+    let steps = accum.floor();
+    *accum -= steps;
+    steps as usize
+}
+
+#[wasm_bindgen]
+impl FactorishState {
+    /// Set the multiplier of the simulation speed, e.g. 4 to fast-forward or 0.5 for slow motion.
+    /// It is clamped to the range from `MIN_GAME_SPEED` to `MAX_GAME_SPEED` and not saved.
+    /// @returns the speed actually set.
+    pub fn set_game_speed(&mut self, multiplier: f64) -> Result<f64, JsValue> {
+        if !multiplier.is_finite() {
+            return js_err!("Game speed must be a finite number: {}", multiplier);
+        }
+        self.game_speed = multiplier.max(MIN_GAME_SPEED).min(MAX_GAME_SPEED);
+        self.game_speed_accum = 0.;
+        Ok(self.game_speed)
+    }
+
+    pub fn get_game_speed(&self) -> f64 {
+        self.game_speed
+    }
+
+    /// Freeze the simulation in `simulate`, while the viewport and the popup texts keep moving.
+    /// The paused state is not saved.
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+        self.game_speed_accum = 0.;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Run exactly one simulation step regardless of the game speed, e.g. to inspect the items
+    /// on belts frame by frame while paused. The step counts towards the autosave interval.
+    /// @returns the events like `simulate`.
+    pub fn step_once(&mut self) -> Result<js_sys::Array, JsValue> {
+        self.proc_autosave()?;
+        let delta_time = if 0. < self.delta_time {
+            self.delta_time
+        } else {
+            DEFAULT_DELTA_TIME
+        };
+        let events = self.tick(delta_time)?;
+        self.process_tick_events(events)
+    }
+}
+
+#[test]
+fn test_game_speed_steps() {
+    let mut accum = 0.;
+    assert_eq!(game_speed_steps(1., &mut accum), 1);
+    assert_eq!(game_speed_steps(4., &mut accum), 4);
+    let slow = (0..8)
+        .map(|_| game_speed_steps(0.25, &mut accum))
+        .collect::<Vec<_>>();
+    assert_eq!(slow, [0, 0, 0, 1, 0, 0, 0, 1]);
+    assert_eq!(game_speed_steps(1.5, &mut accum), 1);
+    assert_eq!(game_speed_steps(1.5, &mut accum), 2);
+}
@@ -0,0 +1,276 @@
+use super::{
+    items::ItemType, offshore_pump::coast_direction, recipes::RECIPES, structure::Structure, Cell,
+    FactorishState, InventoryTrait, NewObjectErr, Position, Recipe, Rotation, TILE_SIZE,
+};
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+use web_sys::CanvasRenderingContext2d;
+
+/// A construction plan of a structure, which will be built as soon as the player has the item.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct Ghost {
+    pub type_: ItemType,
+    pub position: Position,
+    #[serde(default)]
+    pub rotation: Option<Rotation>,
+    #[serde(default)]
+    pub recipe: Option<Recipe>,
+}
+
+/// Check the terrain of a tile for an object, which is shared by dropping items with `new_object`
+/// and placing structures. `cell` is None out of the map. Nothing but an offshore pump may stand
+/// on the water, so `water_ok` should be true only for it.
+pub(crate) fn check_tile(cell: Option<Cell>, water_ok: bool) -> Result<Cell, NewObjectErr> {
+    let cell = cell.ok_or(NewObjectErr::OutOfMap)?;
+    if cell.cliff {
+        return Err(NewObjectErr::OnCliff);
+    }
+    if cell.water && !water_ok {
+        return Err(NewObjectErr::OnWater);
+    }
+    Ok(cell)
+}
+
+/// Check whether `place_structure` can put the tool at the position, looking up the tiles with
+/// `tile_at`. Whatever is on the tile does not matter, since it is harvested to make room.
+/// An offshore pump has to be on the water next to land, which it turns to.
+/// @returns the rotation to place the structure with, or the reason it cannot be placed.
+pub(crate) fn check_placement(
+    tool: &ItemType,
+    position: &Position,
+    rotation: Rotation,
+    tile_at: impl Fn(&Position) -> Option<Cell>,
+) -> Result<Rotation, &'static str> {
+    let cell = check_tile(tile_at(position), *tool == ItemType::OffshorePump)
+        .map_err(NewObjectErr::reason)?;
+    if cell.tree {
+        return Err("Blocked by tree");
+    }
+    if *tool != ItemType::OffshorePump {
+        return Ok(rotation);
+    }
+    coast_direction(position, rotation, |pos| {
+        tile_at(pos).map_or(false, |cell| cell.water)
+    })
+    .ok_or(if cell.water {
+        "Offshore pump must be placed next to land"
+    } else {
+        "Offshore pump must be placed on water"
+    })
+}
+
+impl FactorishState {
+    /// Check whether the structure can be placed without harvesting anything.
+    pub(crate) fn is_placeable(
+        &self,
+        structure: &dyn Structure,
+        type_: &ItemType,
+    ) -> Result<(), &'static str> {
+        let bbox = structure.bounding_box();
+        for y in bbox.y0..bbox.y1 {
+            for x in bbox.x0..bbox.x1 {
+                let position = Position { x, y };
+                let is_pump = *type_ == ItemType::OffshorePump;
+                let cell = check_tile(self.tile_in_bounds(&position), is_pump)
+                    .map_err(NewObjectErr::reason)?;
+                if is_pump && !cell.water {
+                    return Err("Offshore pump must be placed on water");
+                }
+                if cell.tree {
+                    return Err("Blocked by tree");
+                }
+                if self.structure_tiles.contains_key(&position) {
+                    return Err("Blocked by structure");
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn ghost_structure(&self, ghost: &Ghost) -> Result<Box<dyn Structure>, JsValue> {
+        let mut new_s = self.create_structure(&ghost.type_, &ghost.position)?;
+        if let Some(rotation) = ghost.rotation {
+            new_s.set_rotation(&rotation).ok();
+        }
+        Ok(new_s)
+    }
+
+    /// Try to build a structure from a ghost, consuming an item from the player's inventory.
+    /// @returns Ok(Err(reason)) if the structure cannot be built for now.
+    pub(crate) fn build_ghost(
+        &mut self,
+        ghost: &Ghost,
+    ) -> Result<Result<(), &'static str>, JsValue> {
+        if self.player.inventory.count_item(&ghost.type_) == 0 {
+            return Ok(Err("Not enough items"));
+        }
+        if !self.is_unlocked(&ghost.type_) {
+            return Ok(Err("Locked"));
+        }
+        let mut new_s = self.ghost_structure(ghost)?;
+        if let Err(reason) = self.is_placeable(new_s.as_ref(), &ghost.type_) {
+            return Ok(Err(reason));
+        }
+        if let Some(entry) = ghost
+            .recipe
+            .as_ref()
+            .and_then(|recipe| RECIPES.find(recipe))
+        {
+            new_s.select_recipe(&entry.id)?;
+        }
+        self.add_structure(new_s)?;
+        self.player.inventory.remove_item(&ghost.type_);
+        Ok(Ok(()))
+    }
+
+    /// Add a ghost if the tiles are not occupied by structures or other ghosts.
+    pub(crate) fn add_ghost(&mut self, ghost: Ghost) -> Result<Result<(), &'static str>, JsValue> {
+        if !self.is_unlocked(&ghost.type_) {
+            return Ok(Err("Locked"));
+        }
+        let new_s = self.ghost_structure(&ghost)?;
+        if let Err(reason) = self.is_placeable(new_s.as_ref(), &ghost.type_) {
+            return Ok(Err(reason));
+        }
+        if self.ghost_at(&ghost.position).is_some() {
+            return Ok(Err("Blocked by ghost"));
+        }
+        self.ghosts.push(ghost);
+        Ok(Ok(()))
+    }
+
+    pub(crate) fn ghost_at(&self, position: &Position) -> Option<usize> {
+        self.ghosts.iter().position(|g| g.position == *position)
+    }
+
+    /// Build the ghosts that the player has items for. Called every frame from `simulate`.
+    /// @returns true if any ghost is built, which consumes the player's items.
+    pub(crate) fn build_ghosts(&mut self) -> Result<bool, JsValue> {
+        if self.ghosts.is_empty() {
+            return Ok(false);
+        }
+        let mut built = false;
+        for ghost in std::mem::take(&mut self.ghosts) {
+            if let Ok(()) = self.build_ghost(&ghost)? {
+                built = true;
+            } else {
+                self.ghosts.push(ghost);
+            }
+        }
+        Ok(built)
+    }
+
+    /// Draw a translucent preview of a structure, like the tool under the cursor.
+    pub(crate) fn draw_preview(
+        &self,
+        context: &CanvasRenderingContext2d,
+        structure: &dyn Structure,
+        alpha: f64,
+    ) -> Result<(), JsValue> {
+        context.save();
+        context.set_global_alpha(alpha);
+        for depth in 0..3 {
+            structure.draw(self, context, depth, false)?;
+        }
+        context.restore();
+        Ok(())
+    }
+
+    /// Draw the structure about to be placed, tinted red if it cannot be placed there.
+    pub(crate) fn draw_placement_preview(
+        &self,
+        context: &CanvasRenderingContext2d,
+        structure: &dyn Structure,
+        placeable: bool,
+    ) -> Result<(), JsValue> {
+        self.draw_preview(context, structure, 0.5)?;
+        if !placeable {
+            let bb = structure.bounding_box();
+            context.save();
+            context.set_fill_style(&js_str!("rgba(255,0,0,0.4)"));
+            context.fill_rect(
+                bb.x0 as f64 * TILE_SIZE,
+                bb.y0 as f64 * TILE_SIZE,
+                (bb.x1 - bb.x0) as f64 * TILE_SIZE,
+                (bb.y1 - bb.y0) as f64 * TILE_SIZE,
+            );
+            context.restore();
+        }
+        Ok(())
+    }
+
+    pub(crate) fn draw_ghosts(&self, context: &CanvasRenderingContext2d) -> Result<(), JsValue> {
+        for ghost in &self.ghosts {
+            self.draw_preview(context, self.ghost_structure(ghost)?.as_ref(), 0.3)?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_check_placement() {
+    // A lake on x < 0 with a cliff at (1, 0) and a tree at (1, 1)
+    let tile_at = |pos: &Position| {
+        if 3 < pos.x.abs() || 3 < pos.y.abs() {
+            return None;
+        }
+        Some(Cell {
+            water: pos.x < 0,
+            cliff: *pos == Position::new(1, 0),
+            tree: *pos == Position::new(1, 1),
+            ..Cell::default()
+        })
+    };
+    let check = |tool, x, y| check_placement(&tool, &Position::new(x, y), Rotation::Top, tile_at);
+
+    assert!(matches!(check(ItemType::Chest, 0, 0), Ok(Rotation::Top)));
+    assert_eq!(
+        check(ItemType::Chest, -1, 0).err(),
+        Some("Blocked by water")
+    );
+    assert_eq!(check(ItemType::Chest, 1, 0).err(), Some("Blocked by cliff"));
+    assert_eq!(check(ItemType::Chest, 1, 1).err(), Some("Blocked by tree"));
+    assert_eq!(check(ItemType::Chest, 4, 0).err(), Some("Out of map"));
+
+    // The pump on the shore turns to the land on its right.
+    assert!(matches!(
+        check(ItemType::OffshorePump, -1, 0),
+        Ok(Rotation::Right)
+    ));
+    assert!(check(ItemType::OffshorePump, -2, 0).is_err());
+    assert!(check(ItemType::OffshorePump, 0, 0).is_err());
+}
+
+#[test]
+fn test_placement_shares_checks_with_new_object() {
+    use super::scenarios::empty_state;
+
+    let mut state = empty_state(16, 16);
+    // Narrow the world so that the generated chunk has tiles beyond the bounds.
+    state.bounds.as_mut().unwrap().width = 8;
+    let inside = Position::new(7, 3);
+    let outside = Position::new(8, 3);
+    assert!(state.tile_at(&outside).is_some());
+
+    let check = |state: &FactorishState, pos| {
+        check_placement(&ItemType::Chest, pos, Rotation::Top, |pos| {
+            state.tile_in_bounds(pos)
+        })
+    };
+    assert!(check(&state, &inside).is_ok());
+    assert_eq!(check(&state, &outside).err(), Some("Out of map"));
+    assert!(matches!(
+        state.new_object(&outside, ItemType::IronOre),
+        Err(NewObjectErr::OutOfMap)
+    ));
+    assert!(state.new_object(&inside, ItemType::IronOre).is_ok());
+
+    assert!(check_tile(state.tile_at(&inside), false).is_ok());
+    let mut water = state.tile_at(&inside).unwrap();
+    water.water = true;
+    assert!(matches!(
+        check_tile(Some(water), false),
+        Err(NewObjectErr::OnWater)
+    ));
+    assert!(check_tile(Some(water), true).is_ok());
+}
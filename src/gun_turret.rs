@@ -0,0 +1,192 @@
+use super::{
+    enemy::ENEMY_RADIUS,
+    structure::{Structure, StructureDynIter, StructureId},
+    DropItem, FactorishState, FrameProcResult, Inventory, InventoryTrait, ItemType, Position,
+    TempEnt, TILE_SIZE,
+};
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+use web_sys::CanvasRenderingContext2d;
+
+/// Range of the turret in tiles
+const RANGE: f64 = 12.;
+/// Damage dealt to an enemy by a round
+const ROUND_DAMAGE: f64 = 5.;
+const ROUNDS_PER_MAGAZINE: usize = 10;
+/// Seconds between shots
+const FIRE_INTERVAL: f64 = 0.3;
+/// Speed of the drawn projectiles in pixels per second
+const PROJECTILE_SPEED: f64 = 400.;
+
+/// Shoots the nearest enemy in range with rounds from firearm magazines.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct GunTurret {
+    position: Position,
+    input_inventory: Inventory,
+    /// Rounds left in the loaded magazine
+    rounds: usize,
+    /// Seconds until the next shot
+    cooldown: f64,
+    /// Direction of the barrel in radians
+    aim: f64,
+}
+
+impl GunTurret {
+    pub(crate) fn new(position: &Position) -> Self {
+        GunTurret {
+            position: *position,
+            input_inventory: Inventory::new(),
+            rounds: 0,
+            cooldown: 0.,
+            aim: -std::f64::consts::FRAC_PI_2,
+        }
+    }
+
+    fn center(&self) -> (f64, f64) {
+        (self.position.x as f64 + 0.5, self.position.y as f64 + 0.5)
+    }
+}
+
+impl Structure for GunTurret {
+    fn name(&self) -> &str {
+        "Gun Turret"
+    }
+
+    fn position(&self) -> &Position {
+        &self.position
+    }
+
+    fn draw(
+        &self,
+        state: &FactorishState,
+        context: &CanvasRenderingContext2d,
+        depth: i32,
+        _is_toolbar: bool,
+    ) -> Result<(), JsValue> {
+        let (x, y) = (
+            self.position.x as f64 * TILE_SIZE,
+            self.position.y as f64 * TILE_SIZE,
+        );
+        match depth {
+            0 => match state.image_gun_turret.as_ref() {
+                Some(img) => {
+                    context.draw_image_with_image_bitmap(&img.bitmap, x, y)?;
+                }
+                None => return js_err!("gun turret image not available"),
+            },
+            1 => {
+                context.save();
+                context.set_stroke_style(&js_str!("#3f3f3f"));
+                context.set_line_width(4.);
+                context.begin_path();
+                context.move_to(x + 16., y + 16.);
+                context.line_to(
+                    x + 16. + self.aim.cos() * 14.,
+                    y + 16. + self.aim.sin() * 14.,
+                );
+                context.stroke();
+                context.restore();
+            }
+            _ => (),
+        }
+        Ok(())
+    }
+
+    fn desc(&self, _state: &FactorishState) -> String {
+        format!(
+            "Rounds: {}<br>Input Items: <br>{}",
+            self.rounds,
+            self.input_inventory.describe()
+        )
+    }
+
+    fn frame_proc(
+        &mut self,
+        _me: StructureId,
+        state: &mut FactorishState,
+        _structures: &mut StructureDynIter,
+    ) -> Result<FrameProcResult, ()> {
+        self.cooldown = (self.cooldown - state.delta_time).max(0.);
+        let center = self.center();
+        let target = state
+            .enemies
+            .iter_mut()
+            .filter(|enemy| 0. < enemy.health)
+            .map(|enemy| (enemy.distance(center), enemy))
+            .filter(|(dist, _)| *dist < RANGE)
+            .min_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+        let (dist, enemy) = if let Some(target) = target {
+            target
+        } else {
+            return Ok(FrameProcResult::None);
+        };
+        self.aim = (enemy.position.1 - center.1).atan2(enemy.position.0 - center.0);
+        if 0. < self.cooldown {
+            return Ok(FrameProcResult::None);
+        }
+
+        let mut ret = FrameProcResult::None;
+        if self.rounds == 0 {
+            if !self.input_inventory.remove_item(&ItemType::FirearmMagazine) {
+                return Ok(FrameProcResult::None);
+            }
+            state
+                .production_stats
+                .add_consumed(&ItemType::FirearmMagazine, 1);
+            self.rounds = ROUNDS_PER_MAGAZINE;
+            ret = FrameProcResult::InventoryChanged(self.position);
+        }
+        self.rounds -= 1;
+        self.cooldown = FIRE_INTERVAL;
+        enemy.health -= ROUND_DAMAGE;
+
+        // The projectile is only a visual, since the damage is dealt instantly.
+        let from = (
+            center.0 + self.aim.cos() * 0.45,
+            center.1 + self.aim.sin() * 0.45,
+        );
+        let length = (dist - 0.45 - ENEMY_RADIUS).max(0.) * TILE_SIZE;
+        state.temp_ents.push(TempEnt::new_projectile(
+            (from.0 * TILE_SIZE, from.1 * TILE_SIZE),
+            self.aim,
+            PROJECTILE_SPEED,
+            length / PROJECTILE_SPEED,
+        ));
+        Ok(ret)
+    }
+
+    fn input(&mut self, o: &DropItem) -> Result<(), JsValue> {
+        if o.type_ == ItemType::FirearmMagazine {
+            self.input_inventory.add_item(&o.type_);
+            Ok(())
+        } else {
+            js_err!("Gun turret accepts only firearm magazines")
+        }
+    }
+
+    fn can_input(&self, item_type: &ItemType) -> bool {
+        *item_type == ItemType::FirearmMagazine
+    }
+
+    fn inventory(&self, is_input: bool) -> Option<&Inventory> {
+        if is_input {
+            Some(&self.input_inventory)
+        } else {
+            None
+        }
+    }
+
+    fn inventory_mut(&mut self, is_input: bool) -> Option<&mut Inventory> {
+        if is_input {
+            Some(&mut self.input_inventory)
+        } else {
+            None
+        }
+    }
+
+    fn destroy_inventory(&mut self) -> Inventory {
+        std::mem::take(&mut self.input_inventory)
+    }
+
+    crate::serialize_impl!();
+}
@@ -0,0 +1,156 @@
+use super::{
+    items::{item_to_str, ItemType},
+    power_network::build_power_networks,
+    replay::ReplayInput,
+    structure::{StructureDynIter, StructureId},
+    FactorishState, InventoryTrait, Position, TILE_SIZE, TILE_SIZE_I,
+};
+use wasm_bindgen::prelude::*;
+use web_sys::CanvasRenderingContext2d;
+
+/// Health points restored by a repair pack
+const REPAIR_AMOUNT: f64 = 50.;
+/// Items are spilled on a grid of this spacing in pixels, which is the size of a drop item.
+const SPILL_SPACING: i32 = 8;
+
+impl FactorishState {
+    fn structure_index_at(&self, position: &Position) -> Option<usize> {
+        self.structure_tiles.get(position).copied()
+    }
+
+    /// Remove a structure whose health dropped to zero. Unlike harvesting, the structure
+    /// itself is lost and its contents are spilled on the ground around it.
+    fn destroy_structure(&mut self, i: usize) -> Result<(), JsValue> {
+        let (mut structure, _) = self.detach_structure(i)?;
+        let position = *structure.position();
+        let bb = structure.bounding_box();
+        let mut contents = structure
+            .destroy_inventory()
+            .into_iter()
+            .flat_map(|(item, count)| std::iter::repeat(item).take(count));
+        let mut lost = 0;
+        'spill: for y in bb.y0 - 1..bb.y1 + 1 {
+            for x in bb.x0 - 1..bb.x1 + 1 {
+                for sy in 0..TILE_SIZE_I / SPILL_SPACING {
+                    for sx in 0..TILE_SIZE_I / SPILL_SPACING {
+                        let item = match contents.next() {
+                            Some(item) => item,
+                            None => break 'spill,
+                        };
+                        if self
+                            .new_object_pixel(
+                                x * TILE_SIZE_I + sx * SPILL_SPACING + SPILL_SPACING / 2,
+                                y * TILE_SIZE_I + sy * SPILL_SPACING + SPILL_SPACING / 2,
+                                item,
+                            )
+                            .is_err()
+                        {
+                            lost += 1;
+                        }
+                    }
+                }
+            }
+        }
+        lost += contents.count();
+        console_debug!(self, "{} destroyed, {} items lost", structure.name(), lost);
+
+        self.power_networks = build_power_networks(
+            &StructureDynIter::new_all(&mut self.structures),
+            &self.power_wires,
+        );
+        self.update_fluid_connections(&position);
+        self.new_popup_text(
+            format!("{} destroyed", structure.name()),
+            position.x as f64 * TILE_SIZE,
+            position.y as f64 * TILE_SIZE,
+        );
+        Ok(())
+    }
+
+    /// Reduce the health of the structure at the index, destroying it if the health drops to zero.
+    /// @returns true if the structure was destroyed.
+    pub(crate) fn apply_damage(&mut self, i: usize, amount: f64) -> Result<bool, JsValue> {
+        let entry = match self.structures.get_mut(i) {
+            Some(entry) if entry.dynamic.is_some() => entry,
+            _ => return Ok(false),
+        };
+        let max_health = entry.dynamic.as_deref().map_or(0., |s| s.max_health());
+        entry.damage = (entry.damage + amount.max(0.)).min(max_health);
+        if max_health <= entry.damage {
+            self.destroy_structure(i)?;
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    /// Draw health bars above the damaged structures among the given ones.
+    pub(crate) fn render_health_bars(
+        &self,
+        context: &CanvasRenderingContext2d,
+        ids: &[StructureId],
+    ) {
+        for entry in ids
+            .iter()
+            .filter_map(|id| self.structures.get(id.id as usize))
+            .filter(|entry| 0. < entry.damage)
+        {
+            let structure = if let Some(s) = entry.dynamic.as_deref() {
+                s
+            } else {
+                continue;
+            };
+            let bb = structure.bounding_box();
+            let ratio = 1. - entry.damage / structure.max_health();
+            let (x, y, width) = (
+                bb.x0 as f64 * TILE_SIZE + 2.,
+                bb.y0 as f64 * TILE_SIZE - 6.,
+                (bb.x1 - bb.x0) as f64 * TILE_SIZE - 4.,
+            );
+            context.set_fill_style(&JsValue::from_str("rgba(0,0,0,0.7)"));
+            context.fill_rect(x - 1., y - 1., width + 2., 6.);
+            context.set_fill_style(&js_str!(
+                "rgb({},{},0)",
+                ((1. - ratio) * 2. * 255.).min(255.),
+                (ratio * 2. * 255.).min(255.)
+            ));
+            context.fill_rect(x, y, width * ratio, 4.);
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl FactorishState {
+    /// Damage the structure covering the tile, destroying it if the health drops to zero.
+    /// @returns true if the structure was destroyed.
+    pub fn damage_structure(&mut self, c: i32, r: i32, amount: f64) -> Result<bool, JsValue> {
+        self.record_input(ReplayInput::DamageStructure { c, r, amount });
+        let i = self
+            .structure_index_at(&Position::new(c, r))
+            .ok_or_else(|| js_str!("structure not found at position"))?;
+        self.apply_damage(i, amount)
+    }
+
+    /// Repair the structure covering the tile with a repair pack from the player inventory.
+    /// @returns false if the structure is not damaged or the player does not have a repair pack.
+    pub fn repair_structure(&mut self, c: i32, r: i32) -> Result<bool, JsValue> {
+        self.record_input(ReplayInput::RepairStructure { c, r });
+        let i = self
+            .structure_index_at(&Position::new(c, r))
+            .ok_or_else(|| js_str!("structure not found at position"))?;
+        if self.structures[i].damage <= 0. {
+            return Ok(false);
+        }
+        if !self.player.inventory.remove_item(&ItemType::RepairPack) {
+            self.new_popup_text(
+                format!("No {}", item_to_str(&ItemType::RepairPack)),
+                c as f64 * TILE_SIZE,
+                r as f64 * TILE_SIZE,
+            );
+            return Ok(false);
+        }
+        let entry = &mut self.structures[i];
+        entry.damage = (entry.damage - REPAIR_AMOUNT).max(0.);
+        self.notify_player_update()?;
+        Ok(true)
+    }
+}
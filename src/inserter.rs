@@ -1,20 +1,67 @@
 use super::{
+    circuit_network::SignalCondition,
     draw_direction_arrow,
-    drop_items::DropItem,
-    items::{render_drop_item, ItemType},
+    drop_items::{drop_item_id_iter, DropItem},
+    items::{item_to_str, render_drop_item, str_to_item, ItemType},
+    replay::ReplayInput,
     structure::{RotateErr, Structure, StructureDynIter, StructureId},
-    FactorishState, FrameProcResult, Inventory, InventoryTrait, Position, Rotation,
+    transport_belt::near_lane_point,
+    FactorishState, FrameProcResult, Inventory, InventoryTrait, Position, Rotation, TILE_SIZE_I,
 };
 use serde::{Deserialize, Serialize};
 use wasm_bindgen::prelude::*;
 use web_sys::CanvasRenderingContext2d;
 
+/// Stop the inserter when the chest it inserts into has this many items of the type.
+#[derive(Copy, Clone, Serialize, Deserialize)]
+pub(crate) struct ChestLimit {
+    pub item: ItemType,
+    pub max: usize,
+}
+
+impl ChestLimit {
+    /// Whether the output has reached the limit. The limit is inert if the output has no
+    /// inventory, e.g. a belt, but any storage counts, e.g. a requester chest.
+    fn is_reached(&self, output: &dyn Structure) -> bool {
+        output.inventory(true).map_or(false, |inventory| {
+            self.max <= inventory.count_item(&self.item)
+        })
+    }
+}
+
+/// The items that an inserter picks up. The listed items are the only ones picked up, or the
+/// ones left behind if `blacklist`. An empty list picks up everything in either mode.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub(crate) struct InserterFilter {
+    pub items: Vec<ItemType>,
+    pub blacklist: bool,
+}
+
+impl InserterFilter {
+    pub(crate) fn accepts(&self, item: &ItemType) -> bool {
+        self.items.is_empty() || self.items.contains(item) != self.blacklist
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 pub(crate) struct Inserter {
     position: Position,
     rotation: Rotation,
     cooldown: f64,
     hold_item: Option<ItemType>,
+    /// Number of items of `hold_item` in the hand.
+    #[serde(default = "default_stack_size")]
+    hold_count: usize,
+    /// Maximum number of identical items to move in a swing.
+    #[serde(default = "default_stack_size")]
+    stack_size: usize,
+    /// The inserter only picks up items while this condition is met, if any.
+    #[serde(default)]
+    signal_condition: Option<SignalCondition>,
+    #[serde(default)]
+    chest_limit: Option<ChestLimit>,
+    #[serde(default)]
+    filter: InserterFilter,
     #[serde(skip)]
     input_structure: Option<StructureId>,
     #[serde(skip)]
@@ -22,6 +69,31 @@ pub(crate) struct Inserter {
 }
 
 const INSERTER_TIME: f64 = 20.;
+pub(crate) const MAX_STACK_SIZE: usize = 12;
+
+fn default_stack_size() -> usize {
+    1
+}
+
+/// An item that a structure accepts both as fuel and as an ingredient goes to the burner
+/// when the stored energy is below this fraction of the capacity.
+const LOW_FUEL_RATIO: f64 = 0.25;
+
+/// Put an item into a structure, sending fuels to the burner inventory and the rest to the
+/// input inventory, like the player would.
+fn insert_item(structure: &mut dyn Structure, item: &DropItem) -> Result<(), JsValue> {
+    if item.type_ == ItemType::CoalOre && structure.burner_inventory().is_some() {
+        let low_fuel = structure
+            .burner_energy()
+            .map_or(true, |(energy, max)| energy <= max * LOW_FUEL_RATIO);
+        if (low_fuel || !structure.can_input(&item.type_))
+            && structure.add_burner_inventory(&item.type_, 1) == 1
+        {
+            return Ok(());
+        }
+    }
+    structure.input(item)
+}
 
 impl Inserter {
     pub(crate) fn new(x: i32, y: i32, rotation: Rotation) -> Self {
@@ -30,11 +102,25 @@ impl Inserter {
             rotation,
             cooldown: 0.,
             hold_item: None,
+            hold_count: 0,
+            stack_size: default_stack_size(),
+            signal_condition: None,
+            chest_limit: None,
+            filter: InserterFilter::default(),
             input_structure: None,
             output_structure: None,
         }
     }
 
+    /// Release the hand only after all the items in the stack are dropped.
+    fn drop_one(&mut self) {
+        self.hold_count = self.hold_count.saturating_sub(1);
+        if self.hold_count == 0 {
+            self.cooldown += INSERTER_TIME;
+            self.hold_item = None;
+        }
+    }
+
     fn get_arm_angles(&self) -> (f64, f64) {
         let phase = if self.hold_item.is_some() {
             self.cooldown / INSERTER_TIME
@@ -57,21 +143,9 @@ impl Inserter {
         let output_position = self.position.add(self.rotation.delta());
         if *other.position() == input_position {
             self.input_structure = if construct { Some(other_id) } else { None };
-            console_log!(
-                "Inserter{:?}: {} input_structure {:?}",
-                self.position,
-                if construct { "set" } else { "unset" },
-                other_id
-            );
         }
         if *other.position() == output_position {
             self.output_structure = if construct { Some(other_id) } else { None };
-            console_log!(
-                "Inserter{:?}: {} output_structure {:?}",
-                self.position,
-                if construct { "set" } else { "unset" },
-                other_id
-            );
         }
         Ok(())
     }
@@ -163,9 +237,48 @@ impl Structure for Inserter {
         Ok(())
     }
 
+    fn desc(&self, _state: &FactorishState) -> String {
+        let mut ret = format!(
+            "Stack size: {}<br>Holding: {}",
+            self.stack_size,
+            self.hold_item.map_or("Nothing".to_string(), |item| format!(
+                "{} x{}",
+                item_to_str(&item),
+                self.hold_count
+            ))
+        );
+        if let Some(condition) = &self.signal_condition {
+            ret += &format!("<br>Enabled if: {}", condition.desc());
+        }
+        if !self.filter.items.is_empty() {
+            ret += &format!(
+                "<br>{}: {}",
+                if self.filter.blacklist {
+                    "Rejects"
+                } else {
+                    "Picks up"
+                },
+                self.filter
+                    .items
+                    .iter()
+                    .map(item_to_str)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        }
+        if let Some(limit) = &self.chest_limit {
+            ret += &format!(
+                "<br>Chest limit: {} {}",
+                limit.max,
+                item_to_str(&limit.item)
+            );
+        }
+        ret
+    }
+
     fn frame_proc(
         &mut self,
-        _me: StructureId,
+        me: StructureId,
         state: &mut FactorishState,
         structures: &mut StructureDynIter,
     ) -> Result<FrameProcResult, ()> {
@@ -177,6 +290,24 @@ impl Structure for Inserter {
                 self.cooldown = 0.;
                 let ret = FrameProcResult::None;
 
+                // Wait with an empty hand while the circuit condition is not met.
+                if let Some(condition) = &self.signal_condition {
+                    if !condition.is_met(state.signals_of(me)) {
+                        return Ok(ret);
+                    }
+                }
+
+                if let Some(limit) = &self.chest_limit {
+                    if self
+                        .output_structure
+                        .and_then(|id| structures.get(id))
+                        .map_or(false, |output| limit.is_reached(output))
+                    {
+                        return Ok(ret);
+                    }
+                }
+
+                let filter = self.filter.clone();
                 let mut try_hold = |structures: &mut StructureDynIter, type_| -> bool {
                     if let Some(structure) =
                         self.output_structure.map(|id| structures.get(id)).flatten()
@@ -184,6 +315,7 @@ impl Structure for Inserter {
                         if structure.can_input(&type_) || structure.movable() {
                             // ret = FrameProcResult::InventoryChanged(output_position);
                             self.hold_item = Some(type_);
+                            self.hold_count = 1;
                             self.cooldown += INSERTER_TIME;
                             true
                         } else {
@@ -191,15 +323,32 @@ impl Structure for Inserter {
                         }
                     } else {
                         self.hold_item = Some(type_);
+                        self.hold_count = 1;
                         self.cooldown += INSERTER_TIME;
                         true
                     }
                 };
 
                 let mut lets_try_hold = None;
-                if let Some((id, &DropItem { type_, .. })) = state.find_item(&input_position) {
+                let ground_item = drop_item_id_iter(&state.drop_items)
+                    .find(|(_, item)| item.tile() == input_position && filter.accepts(&item.type_))
+                    .map(|(id, item)| (id, item.type_));
+                if let Some((id, type_)) = ground_item {
                     if try_hold(structures, type_) {
                         state.remove_item(id);
+                        // Grab more items of the same type lying on the input tile.
+                        while self.hold_count < self.stack_size {
+                            if let Some(id) = state
+                                .find_item(&input_position)
+                                .filter(|(_, item)| item.type_ == type_)
+                                .map(|(id, _)| id)
+                            {
+                                state.remove_item(id);
+                                self.hold_count += 1;
+                            } else {
+                                break;
+                            }
+                        }
                     } else {
                         // console_log!("fail output_object: {:?}", type_);
                     }
@@ -208,7 +357,13 @@ impl Structure for Inserter {
                     .map(|id| structures.get_mut(id))
                     .flatten()
                 {
-                    lets_try_hold = Some(structure.can_output());
+                    lets_try_hold = Some(
+                        structure
+                            .can_output()
+                            .into_iter()
+                            .filter(|(item, _)| filter.accepts(item))
+                            .collect::<Vec<_>>(),
+                    );
                     // console_log!("outputting from a structure at {:?}", structure.position());
                     // if let Ok((item, callback)) = structure.output(state, &output_position) {
                     //     lets_try_hold = Some((item, callback));
@@ -234,6 +389,7 @@ impl Structure for Inserter {
                                 if structure.can_input(&item.0) || structure.movable() {
                                     // ret = FrameProcResult::InventoryChanged(output_position);
                                     self.hold_item = Some(item.0);
+                                    self.hold_count = 1;
                                     self.cooldown += INSERTER_TIME;
                                     return Some(item);
                                 }
@@ -241,6 +397,7 @@ impl Structure for Inserter {
                         } else if let Some(item) = output_items.into_iter().next() {
                             // If there is no structures at the output, anything can output.
                             self.hold_item = Some(item.0);
+                            self.hold_count = 1;
                             self.cooldown += INSERTER_TIME;
                             return Some(item);
                         }
@@ -252,9 +409,16 @@ impl Structure for Inserter {
                             .flatten()
                         {
                             structure.output(state, &type_.0)?;
+                            // Take the rest of the stack, as long as the source has them.
+                            while self.hold_count < self.stack_size.min(type_.1)
+                                && structure.output(state, &type_.0).is_ok()
+                            {
+                                self.hold_count += 1;
+                            }
                             return Ok(FrameProcResult::InventoryChanged(input_position));
                         } else {
-                            console_log!(
+                            console_error!(
+                                state,
                                 "We have confirmed that there is input structure, right???"
                             );
                             return Err(());
@@ -264,7 +428,7 @@ impl Structure for Inserter {
                     //     if pos == input_position {
                     //         return Ok(FrameProcResult::InventoryChanged(input_position));
                     //         // if let Err(e) = state.on_show_inventory.call2(&window(), &JsValue::from(output_position.x), &JsValue::from(output_position.y)) {
-                    //         //     console_log!("on_show_inventory fail: {:?}", e);
+                    //         //     console_log!(self, "on_show_inventory fail: {:?}", e);
                     //         // }
                     //     }
                     // }
@@ -278,36 +442,42 @@ impl Structure for Inserter {
         } else if self.cooldown < 1. {
             self.cooldown = 0.;
             if let Some(item_type) = self.hold_item {
-                let Self {
-                    cooldown,
-                    hold_item,
-                    output_structure,
-                    ..
-                } = self;
-                let mut try_move = |state: &mut FactorishState| {
-                    if let Ok(()) = state.new_object(&output_position, item_type) {
-                        *cooldown += INSERTER_TIME;
-                        *hold_item = None;
-                    }
-                };
-                if let Some(structure) = output_structure.map(|id| structures.get_mut(id)).flatten()
+                // Items are put on the ground one per frame, so that the stack does not
+                // overlap with each other or with the items already on the belt.
+                let drop_pos = if let Some(structure) = self
+                    .output_structure
+                    .map(|id| structures.get_mut(id))
+                    .flatten()
                 {
-                    if structure
-                        .input(&DropItem::new(
-                            item_type,
-                            output_position.x,
-                            output_position.y,
-                        ))
+                    let mut dropped = false;
+                    while self.hold_item.is_some()
+                        && insert_item(
+                            structure,
+                            &DropItem::new(item_type, output_position.x, output_position.y),
+                        )
                         .is_ok()
                     {
-                        *cooldown += INSERTER_TIME;
-                        *hold_item = None;
+                        self.drop_one();
+                        dropped = true;
+                    }
+                    if dropped {
                         return Ok(FrameProcResult::InventoryChanged(output_position));
                     } else if structure.movable() {
-                        try_move(state)
+                        // Put the item on the near lane of the belt, like Factorio does.
+                        Some(near_lane_point(&output_position, &self.position))
+                    } else {
+                        None
                     }
                 } else {
-                    try_move(state);
+                    Some((
+                        output_position.x * TILE_SIZE_I + TILE_SIZE_I / 2,
+                        output_position.y * TILE_SIZE_I + TILE_SIZE_I / 2,
+                    ))
+                };
+                if let Some((x, y)) = drop_pos {
+                    if let Ok(()) = state.new_object_pixel(x, y, item_type) {
+                        self.drop_one();
+                    }
                 }
             }
         } else {
@@ -346,6 +516,10 @@ impl Structure for Inserter {
         Ok(())
     }
 
+    fn rotation(&self) -> Option<Rotation> {
+        Some(self.rotation)
+    }
+
     fn set_rotation(&mut self, rotation: &Rotation) -> Result<(), ()> {
         self.rotation = *rotation;
         Ok(())
@@ -354,10 +528,167 @@ impl Structure for Inserter {
     fn destroy_inventory(&mut self) -> Inventory {
         let mut ret = Inventory::new();
         if let Some(item) = self.hold_item {
-            ret.add_item(&item);
+            ret.add_items(&item, self.hold_count.max(1));
         }
         ret
     }
 
+    fn set_stack_size(&mut self, stack_size: usize) -> Result<(), JsValue> {
+        if stack_size < 1 || MAX_STACK_SIZE < stack_size {
+            return js_err!("Stack size must be between 1 and {}", MAX_STACK_SIZE);
+        }
+        self.stack_size = stack_size;
+        Ok(())
+    }
+
+    fn stack_size(&self) -> Option<usize> {
+        Some(self.stack_size)
+    }
+
+    fn set_signal_condition(&mut self, condition: Option<SignalCondition>) -> Result<(), JsValue> {
+        self.signal_condition = condition;
+        Ok(())
+    }
+
+    fn set_chest_limit(&mut self, limit: Option<ChestLimit>) -> Result<(), JsValue> {
+        self.chest_limit = limit;
+        Ok(())
+    }
+
+    fn inserter_filter(&self) -> Option<&InserterFilter> {
+        Some(&self.filter)
+    }
+
+    fn inserter_filter_mut(&mut self) -> Option<&mut InserterFilter> {
+        Some(&mut self.filter)
+    }
+
     crate::serialize_impl!();
 }
+
+/// The filter of an inserter as returned by `get_filter`.
+#[derive(Serialize)]
+struct FilterSerial {
+    items: Vec<String>,
+    blacklist: bool,
+}
+
+#[wasm_bindgen]
+impl FactorishState {
+    fn inserter_filter_mut_at(&mut self, c: i32, r: i32) -> Result<&mut InserterFilter, JsValue> {
+        self.find_structure_tile_mut(&[c, r])
+            .ok_or_else(|| js_str!("Structure is not found"))?
+            .inserter_filter_mut()
+            .ok_or_else(|| js_str!("Structure is not an inserter"))
+    }
+
+    /// Same as `set_inserter_filter` with the parsed items, which can be replayed.
+    pub(crate) fn set_inserter_filter_items(
+        &mut self,
+        c: i32,
+        r: i32,
+        items: Vec<ItemType>,
+    ) -> Result<(), JsValue> {
+        self.record_input(ReplayInput::SetInserterFilter {
+            c,
+            r,
+            items: items.clone(),
+        });
+        self.inserter_filter_mut_at(c, r)?.items = items;
+        Ok(())
+    }
+
+    /// Set the names of the items that the inserter at the tile picks up, or rejects in the
+    /// blacklist mode. An empty array removes the filter.
+    pub fn set_inserter_filter(&mut self, c: i32, r: i32, items: JsValue) -> Result<(), JsValue> {
+        let items = serde_wasm_bindgen::from_value::<Vec<String>>(items)?
+            .iter()
+            .map(|name| str_to_item(name).ok_or_else(|| js_str!("Item name not valid: {}", name)))
+            .collect::<Result<Vec<_>, JsValue>>()?;
+        self.set_inserter_filter_items(c, r, items)
+    }
+
+    /// Make the inserter at the tile reject the items in the filter and pick up everything else,
+    /// rather than picking up only them.
+    pub fn set_inserter_filter_mode(
+        &mut self,
+        c: i32,
+        r: i32,
+        is_blacklist: bool,
+    ) -> Result<(), JsValue> {
+        self.record_input(ReplayInput::SetInserterFilterMode { c, r, is_blacklist });
+        self.inserter_filter_mut_at(c, r)?.blacklist = is_blacklist;
+        Ok(())
+    }
+
+    /// Returns the filter of the inserter at the tile as `{items, blacklist}`, where `items` are
+    /// the item names.
+    pub fn get_filter(&self, c: i32, r: i32) -> Result<JsValue, JsValue> {
+        let filter = self
+            .find_structure_tile(&[c, r])
+            .ok_or_else(|| js_str!("Structure is not found"))?
+            .inserter_filter()
+            .ok_or_else(|| js_str!("Structure is not an inserter"))?;
+        JsValue::from_serde(&FilterSerial {
+            items: filter.items.iter().map(item_to_str).collect(),
+            blacklist: filter.blacklist,
+        })
+        .map_err(|e| js_str!("serialize error: {}", e))
+    }
+}
+
+#[test]
+fn test_insert_item_routes_fuel() {
+    use super::furnace::Furnace;
+
+    let mut furnace = Furnace::new(&Position::new(0, 0));
+    for type_ in &[
+        ItemType::CoalOre,
+        ItemType::IronOre,
+        ItemType::CoalOre,
+        ItemType::IronOre,
+        ItemType::IronOre,
+    ] {
+        assert!(insert_item(&mut furnace, &DropItem::new(*type_, 0, 0)).is_ok());
+    }
+    let burner = furnace.burner_inventory().unwrap();
+    assert_eq!(burner.count_item(&ItemType::CoalOre), 2);
+    assert_eq!(burner.count_item(&ItemType::IronOre), 0);
+    let input = furnace.inventory(true).unwrap();
+    assert_eq!(input.count_item(&ItemType::IronOre), 3);
+    assert_eq!(input.count_item(&ItemType::CoalOre), 0);
+}
+
+#[test]
+fn test_filter_blacklist() {
+    let mut filter = InserterFilter::default();
+    assert!(filter.accepts(&ItemType::StoneOre));
+    // An empty blacklist accepts everything, like an empty allow-list.
+    filter.blacklist = true;
+    assert!(filter.accepts(&ItemType::StoneOre));
+
+    filter.items = vec![ItemType::StoneOre];
+    assert!(!filter.accepts(&ItemType::StoneOre));
+    assert!(filter.accepts(&ItemType::IronOre));
+
+    filter.blacklist = false;
+    assert!(filter.accepts(&ItemType::StoneOre));
+    assert!(!filter.accepts(&ItemType::IronOre));
+}
+
+#[test]
+fn test_chest_limit() {
+    use super::{requester_chest::RequesterChest, transport_belt::TransportBelt};
+    let limit = ChestLimit {
+        item: ItemType::IronOre,
+        max: 2,
+    };
+    let mut chest = RequesterChest::new(&Position::new(0, 0));
+    assert!(!limit.is_reached(&chest));
+    for _ in 0..2 {
+        insert_item(&mut chest, &DropItem::new(ItemType::IronOre, 0, 0)).unwrap();
+    }
+    assert!(limit.is_reached(&chest));
+    // A belt has no inventory to limit.
+    assert!(!limit.is_reached(&TransportBelt::new(0, 0, Rotation::Left)));
+}
@@ -1,21 +1,172 @@
-use super::ItemType;
+use super::{
+    items::{item_to_str, stack_size},
+    tool_defs, ItemType,
+};
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, convert::TryFrom};
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    ops::{Deref, DerefMut},
+};
 use wasm_bindgen::prelude::*;
 
-pub(crate) type Inventory = HashMap<ItemType, usize>;
+/// A bag of items. It dereferences to the map of item counts, so it can be inspected like a map.
+/// Only the items are serialized; the owner should restore the capacity after deserialization.
+#[derive(Default, Clone, Debug, Serialize, Deserialize)]
+#[serde(transparent)]
+pub(crate) struct Inventory {
+    items: HashMap<ItemType, usize>,
+    /// The maximum number of stacks, or None for unlimited.
+    #[serde(skip)]
+    capacity: Option<usize>,
+    /// The order to show the items, which is the order they were added until sorted.
+    #[serde(skip)]
+    order: Vec<ItemType>,
+}
+
+impl PartialEq for Inventory {
+    fn eq(&self, other: &Self) -> bool {
+        self.items == other.items
+    }
+}
+
+/// Materials come before placeable structures, then alphabetical.
+fn sort_key(item: &ItemType) -> (bool, String) {
+    (
+        tool_defs.iter().any(|tool| tool.item_type == *item),
+        item_to_str(item),
+    )
+}
+
+impl Inventory {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn with_capacity(capacity: usize) -> Self {
+        Self {
+            capacity: Some(capacity),
+            ..Self::default()
+        }
+    }
+
+    pub(crate) fn set_capacity(&mut self, capacity: Option<usize>) {
+        self.capacity = capacity;
+    }
+
+    /// The number of stacks that the items occupy.
+    pub(crate) fn stacks(&self) -> usize {
+        self.items
+            .iter()
+            .map(|(item, count)| (count + stack_size(item) - 1) / stack_size(item))
+            .sum()
+    }
+
+    /// The number of items of the given type that can be added without overflowing.
+    pub(crate) fn room_for(&self, item: &ItemType) -> usize {
+        match self.capacity {
+            Some(capacity) => {
+                let stack = stack_size(item);
+                // The last stack of the same item type may have some room left.
+                let partial = (stack - self.count_item(item) % stack) % stack;
+                capacity.saturating_sub(self.stacks()) * stack + partial
+            }
+            None => usize::MAX,
+        }
+    }
+
+    pub(crate) fn is_full_for(&self, item: &ItemType) -> bool {
+        self.room_for(item) == 0
+    }
+
+    /// Returns the items in the order to show. Items that were put without add_items(),
+    /// e.g. loaded from a save, follow the ordered ones in sorted order.
+    pub(crate) fn ordered_items(&self) -> Vec<(ItemType, usize)> {
+        let mut rest = self
+            .items
+            .keys()
+            .filter(|item| !self.order.contains(item))
+            .copied()
+            .collect::<Vec<_>>();
+        rest.sort_by_key(sort_key);
+        self.order
+            .iter()
+            .chain(rest.iter())
+            .filter_map(|item| Some((*item, *self.items.get(item)?)))
+            .collect()
+    }
+
+    pub(crate) fn sort(&mut self) {
+        self.order = self.items.keys().copied().collect();
+        self.order.sort_by_key(sort_key);
+    }
+}
+
+impl Deref for Inventory {
+    type Target = HashMap<ItemType, usize>;
+    fn deref(&self) -> &Self::Target {
+        &self.items
+    }
+}
+
+impl DerefMut for Inventory {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.items
+    }
+}
+
+impl From<HashMap<ItemType, usize>> for Inventory {
+    fn from(items: HashMap<ItemType, usize>) -> Self {
+        Self {
+            items,
+            ..Self::default()
+        }
+    }
+}
+
+impl std::iter::FromIterator<(ItemType, usize)> for Inventory {
+    fn from_iter<T: IntoIterator<Item = (ItemType, usize)>>(iter: T) -> Self {
+        Self::from(iter.into_iter().collect::<HashMap<_, _>>())
+    }
+}
+
+impl Extend<(ItemType, usize)> for Inventory {
+    fn extend<T: IntoIterator<Item = (ItemType, usize)>>(&mut self, iter: T) {
+        self.items.extend(iter)
+    }
+}
+
+impl IntoIterator for Inventory {
+    type Item = (ItemType, usize);
+    type IntoIter = std::collections::hash_map::IntoIter<ItemType, usize>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.items.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a Inventory {
+    type Item = (&'a ItemType, &'a usize);
+    type IntoIter = std::collections::hash_map::Iter<'a, ItemType, usize>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.items.iter()
+    }
+}
 
 pub(crate) trait InventoryTrait {
     fn remove_item(&mut self, item: &ItemType) -> bool {
         self.remove_items(item, 1)
     }
     fn remove_items(&mut self, item: &ItemType, count: usize) -> bool;
-    fn add_item(&mut self, item: &ItemType) {
-        self.add_items(item, 1);
+    /// @returns true if the item fit in the inventory.
+    fn add_item(&mut self, item: &ItemType) -> bool {
+        self.add_items(item, 1) == 0
     }
-    fn add_items(&mut self, item: &ItemType, count: usize);
+    /// Add items as many as the capacity allows.
+    /// @returns the number of items that overflowed and were not added.
+    fn add_items(&mut self, item: &ItemType, count: usize) -> usize;
     fn count_item(&self, item: &ItemType) -> usize;
-    fn merge(&mut self, other: Inventory);
+    /// Merge the other items regardless of the capacity, e.g. to give back ingredients.
+    fn merge(&mut self, other: impl IntoIterator<Item = (ItemType, usize)>);
     fn describe(&self) -> String;
 }
 
@@ -33,24 +184,35 @@ impl InventoryTrait for Inventory {
         }
     }
 
-    fn add_items(&mut self, item: &ItemType, count: usize) {
+    fn add_items(&mut self, item: &ItemType, count: usize) -> usize {
+        let added = count.min(self.room_for(item));
+        if added == 0 {
+            return count;
+        }
         if let Some(entry) = self.get_mut(item) {
-            *entry += count;
+            *entry += added;
         } else {
-            self.insert(*item, count);
+            self.insert(*item, added);
+            if !self.order.contains(item) {
+                self.order.push(*item);
+            }
         }
+        count - added
     }
 
     fn count_item(&self, item: &ItemType) -> usize {
         *self.get(item).unwrap_or(&0)
     }
 
-    fn merge(&mut self, other: Inventory) {
+    fn merge(&mut self, other: impl IntoIterator<Item = (ItemType, usize)>) {
         for (k, v) in other {
             if let Some(vv) = self.get_mut(&k) {
                 *vv += v;
             } else {
                 self.insert(k, v);
+                if !self.order.contains(&k) {
+                    self.order.push(k);
+                }
             }
         }
     }
@@ -62,7 +224,7 @@ impl InventoryTrait for Inventory {
     }
 }
 
-#[derive(PartialEq, Debug, Serialize, Deserialize)]
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
 pub(crate) enum InventoryType {
     Input,
     Output,
@@ -75,3 +237,52 @@ impl TryFrom<JsValue> for InventoryType {
         value.into_serde().map_err(|e| js_str!("{}", e.to_string()))
     }
 }
+
+#[test]
+fn test_add_items_overflow() {
+    let mut inventory = Inventory::with_capacity(2);
+    let stack = stack_size(&ItemType::IronPlate);
+    assert_eq!(inventory.add_items(&ItemType::IronPlate, stack + 1), 0);
+    assert_eq!(inventory.stacks(), 2);
+    // The second stack of iron plates still has room, but no new stack can be started.
+    assert_eq!(inventory.room_for(&ItemType::IronPlate), stack - 1);
+    assert_eq!(inventory.add_items(&ItemType::IronPlate, stack), 1);
+    assert_eq!(inventory.count_item(&ItemType::IronPlate), stack * 2);
+    assert!(!inventory.add_item(&ItemType::Gear));
+    assert_eq!(inventory.count_item(&ItemType::Gear), 0);
+
+    // Removing items makes room again
+    inventory.remove_items(&ItemType::IronPlate, stack);
+    assert_eq!(inventory.add_items(&ItemType::Gear, 3), 0);
+}
+
+#[test]
+fn test_unlimited_inventory() {
+    let mut inventory = Inventory::new();
+    assert_eq!(inventory.add_items(&ItemType::IronOre, 10000), 0);
+    assert_eq!(inventory.count_item(&ItemType::IronOre), 10000);
+}
+
+#[test]
+fn test_sort_inventory() {
+    let mut inventory = Inventory::new();
+    inventory.add_items(&ItemType::TransportBelt, 3);
+    inventory.add_items(&ItemType::IronPlate, 5);
+    inventory.add_items(&ItemType::Gear, 2);
+    let names = |inventory: &Inventory| {
+        inventory
+            .ordered_items()
+            .into_iter()
+            .map(|(item, _)| item)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        names(&inventory),
+        [ItemType::TransportBelt, ItemType::IronPlate, ItemType::Gear]
+    );
+    inventory.sort();
+    assert_eq!(
+        names(&inventory),
+        [ItemType::Gear, ItemType::IronPlate, ItemType::TransportBelt]
+    );
+}
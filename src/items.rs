@@ -11,23 +11,37 @@ pub(crate) enum ItemType {
     IronPlate,
     StoneOre,
     CopperPlate,
+    SteelPlate,
     Gear,
     CopperWire,
     Circuit,
+    Wood,
+    SciencePack,
+    RepairPack,
+    FirearmMagazine,
 
     TransportBelt,
     Chest,
     Inserter,
     OreMine,
+    ElectricOreMine,
     Furnace,
+    ElectricFurnace,
     Assembler,
     Boiler,
     WaterWell,
     OffshorePump,
     Pipe,
+    Valve,
     SteamEngine,
     ElectPole,
     Splitter,
+    Balancer,
+    Accumulator,
+    RequesterChest,
+    Lab,
+    Lamp,
+    GunTurret,
 }
 
 pub(crate) fn item_to_str(type_: &ItemType) -> String {
@@ -38,23 +52,37 @@ pub(crate) fn item_to_str(type_: &ItemType) -> String {
         ItemType::StoneOre => "Stone Ore".to_string(),
         ItemType::IronPlate => "Iron Plate".to_string(),
         ItemType::CopperPlate => "Copper Plate".to_string(),
+        ItemType::SteelPlate => "Steel Plate".to_string(),
         ItemType::Gear => "Gear".to_string(),
         ItemType::CopperWire => "Copper Wire".to_string(),
         ItemType::Circuit => "Circuit".to_string(),
+        ItemType::Wood => "Wood".to_string(),
+        ItemType::SciencePack => "Science Pack".to_string(),
+        ItemType::RepairPack => "Repair Pack".to_string(),
+        ItemType::FirearmMagazine => "Firearm Magazine".to_string(),
 
         ItemType::TransportBelt => "Transport Belt".to_string(),
         ItemType::Chest => "Chest".to_string(),
         ItemType::Inserter => "Inserter".to_string(),
         ItemType::OreMine => "Ore Mine".to_string(),
+        ItemType::ElectricOreMine => "Electric Ore Mine".to_string(),
         ItemType::Furnace => "Furnace".to_string(),
+        ItemType::ElectricFurnace => "Electric Furnace".to_string(),
         ItemType::Assembler => "Assembler".to_string(),
         ItemType::Boiler => "Boiler".to_string(),
         ItemType::WaterWell => "Water Well".to_string(),
         ItemType::OffshorePump => "Offshore Pump".to_string(),
         ItemType::Pipe => "Pipe".to_string(),
+        ItemType::Valve => "Valve".to_string(),
         ItemType::SteamEngine => "Steam Engine".to_string(),
         ItemType::ElectPole => "Electric Pole".to_string(),
         ItemType::Splitter => "Splitter".to_string(),
+        ItemType::Balancer => "Balancer".to_string(),
+        ItemType::Accumulator => "Accumulator".to_string(),
+        ItemType::RequesterChest => "Requester Chest".to_string(),
+        ItemType::Lab => "Lab".to_string(),
+        ItemType::Lamp => "Lamp".to_string(),
+        ItemType::GunTurret => "Gun Turret".to_string(),
     }
 }
 
@@ -66,28 +94,159 @@ pub(crate) fn str_to_item(name: &str) -> Option<ItemType> {
         "Stone Ore" => Some(ItemType::StoneOre),
         "Iron Plate" => Some(ItemType::IronPlate),
         "Copper Plate" => Some(ItemType::CopperPlate),
+        "Steel Plate" => Some(ItemType::SteelPlate),
         "Gear" => Some(ItemType::Gear),
         "Copper Wire" => Some(ItemType::CopperWire),
         "Circuit" => Some(ItemType::Circuit),
+        "Wood" => Some(ItemType::Wood),
+        "Science Pack" => Some(ItemType::SciencePack),
+        "Repair Pack" => Some(ItemType::RepairPack),
+        "Firearm Magazine" => Some(ItemType::FirearmMagazine),
 
         "Transport Belt" => Some(ItemType::TransportBelt),
         "Chest" => Some(ItemType::Chest),
         "Inserter" => Some(ItemType::Inserter),
         "Ore Mine" => Some(ItemType::OreMine),
+        "Electric Ore Mine" => Some(ItemType::ElectricOreMine),
         "Furnace" => Some(ItemType::Furnace),
+        "Electric Furnace" => Some(ItemType::ElectricFurnace),
         "Assembler" => Some(ItemType::Assembler),
         "Boiler" => Some(ItemType::Boiler),
         "Water Well" => Some(ItemType::WaterWell),
         "Offshore Pump" => Some(ItemType::OffshorePump),
         "Pipe" => Some(ItemType::Pipe),
+        "Valve" => Some(ItemType::Valve),
         "Steam Engine" => Some(ItemType::SteamEngine),
         "Electric Pole" => Some(ItemType::ElectPole),
         "Splitter" => Some(ItemType::Splitter),
+        "Balancer" => Some(ItemType::Balancer),
+        "Accumulator" => Some(ItemType::Accumulator),
+        "Requester Chest" => Some(ItemType::RequesterChest),
+        "Lab" => Some(ItemType::Lab),
+        "Lamp" => Some(ItemType::Lamp),
+        "Gun Turret" => Some(ItemType::GunTurret),
 
         _ => None,
     }
 }
 
+/// The number of items that fit in a slot of a capped inventory, e.g. a chest.
+pub(crate) fn stack_size(type_: &ItemType) -> usize {
+    match type_ {
+        ItemType::IronOre
+        | ItemType::CoalOre
+        | ItemType::CopperOre
+        | ItemType::StoneOre
+        | ItemType::Wood
+        | ItemType::IronPlate
+        | ItemType::CopperPlate
+        | ItemType::SteelPlate => 50,
+        ItemType::Gear | ItemType::TransportBelt | ItemType::Pipe | ItemType::RepairPack => 100,
+        ItemType::CopperWire
+        | ItemType::Circuit
+        | ItemType::SciencePack
+        | ItemType::FirearmMagazine => 200,
+        ItemType::Inserter
+        | ItemType::ElectPole
+        | ItemType::Splitter
+        | ItemType::Balancer
+        | ItemType::Valve
+        | ItemType::Lamp
+        | ItemType::Chest
+        | ItemType::RequesterChest => 50,
+        ItemType::OreMine
+        | ItemType::ElectricOreMine
+        | ItemType::Furnace
+        | ItemType::ElectricFurnace
+        | ItemType::Assembler
+        | ItemType::Boiler
+        | ItemType::WaterWell
+        | ItemType::OffshorePump
+        | ItemType::SteamEngine
+        | ItemType::Accumulator
+        | ItemType::Lab
+        | ItemType::GunTurret => 10,
+    }
+}
+
+/// How an item is drawn from its image, on the ground, on belts and as an inventory icon.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub(crate) struct ItemSprite {
+    /// Width and height in pixels of the item drawn on the ground or a belt
+    pub size: f64,
+    /// Rectangle [x, y, width, height] in the image to draw, or None for the whole image.
+    /// Structures with animated images show their first frame.
+    pub source: Option<[f64; 4]>,
+}
+
+const WHOLE_IMAGE: ItemSprite = ItemSprite {
+    size: 16.,
+    source: None,
+};
+
+const FIRST_FRAME: ItemSprite = ItemSprite {
+    size: 16.,
+    source: Some([0., 0., 32., 32.]),
+};
+
+pub(crate) fn item_sprite(item_type: &ItemType) -> ItemSprite {
+    match item_type {
+        ItemType::Inserter
+        | ItemType::OreMine
+        | ItemType::ElectricOreMine
+        | ItemType::Furnace
+        | ItemType::ElectricFurnace
+        | ItemType::Assembler
+        | ItemType::Boiler
+        | ItemType::SteamEngine
+        | ItemType::Balancer => FIRST_FRAME,
+        // The pipe image is a sheet of the connection patterns, the first one being unconnected
+        ItemType::Pipe | ItemType::Valve => FIRST_FRAME,
+        _ => WHOLE_IMAGE,
+    }
+}
+
+fn item_image<'a>(state: &'a FactorishState, item_type: &ItemType) -> &'a Option<ImageBundle> {
+    match item_type {
+        ItemType::IronOre => &state.image_iron_ore,
+        ItemType::CoalOre => &state.image_coal_ore,
+        ItemType::CopperOre => &state.image_copper_ore,
+        ItemType::StoneOre => &state.image_stone_ore,
+        ItemType::IronPlate => &state.image_iron_plate,
+        ItemType::CopperPlate => &state.image_copper_plate,
+        ItemType::SteelPlate => &state.image_steel_plate,
+        ItemType::Gear => &state.image_gear,
+        ItemType::CopperWire => &state.image_copper_wire,
+        ItemType::Circuit => &state.image_circuit,
+        ItemType::Wood => &state.image_wood,
+        ItemType::SciencePack => &state.image_science_pack,
+        ItemType::RepairPack => &state.image_repair_pack,
+        ItemType::FirearmMagazine => &state.image_firearm_magazine,
+
+        ItemType::TransportBelt => &state.image_belt,
+        ItemType::Chest => &state.image_chest,
+        ItemType::Inserter => &state.image_inserter,
+        ItemType::OreMine => &state.image_mine,
+        ItemType::ElectricOreMine => &state.image_electric_mine,
+        ItemType::Furnace => &state.image_furnace,
+        ItemType::ElectricFurnace => &state.image_electric_furnace,
+        ItemType::Assembler => &state.image_assembler,
+        ItemType::Boiler => &state.image_boiler,
+        ItemType::WaterWell => &state.image_water_well,
+        ItemType::OffshorePump => &state.image_offshore_pump,
+        ItemType::Pipe | ItemType::Valve => &state.image_pipe,
+        ItemType::SteamEngine => &state.image_steam_engine,
+        ItemType::ElectPole => &state.image_elect_pole,
+        ItemType::Splitter => &state.image_splitter,
+        ItemType::Balancer => &state.image_balancer,
+        ItemType::Accumulator => &state.image_accumulator,
+        ItemType::RequesterChest => &state.image_requester_chest,
+        ItemType::Lab => &state.image_lab,
+        ItemType::Lamp => &state.image_lamp,
+        ItemType::GunTurret => &state.image_gun_turret,
+    }
+}
+
 pub(crate) fn render_drop_item(
     state: &FactorishState,
     context: &CanvasRenderingContext2d,
@@ -95,85 +254,73 @@ pub(crate) fn render_drop_item(
     x: i32,
     y: i32,
 ) -> Result<(), JsValue> {
-    let render16 = |img: &Option<ImageBundle>| -> Result<(), JsValue> {
-        if let Some(image) = img.as_ref() {
-            context.draw_image_with_image_bitmap_and_dw_and_dh(
-                &image.bitmap,
-                x as f64 - 8.,
-                y as f64 - 8.,
-                16.,
-                16.,
-            )?;
-        }
-        Ok(())
+    let image = if let Some(image) = item_image(state, item_type).as_ref() {
+        image
+    } else {
+        return Ok(());
     };
-    let render_animated32 = |img: &Option<ImageBundle>| -> Result<(), JsValue> {
-        if let Some(image) = img.as_ref() {
-            context.draw_image_with_image_bitmap_and_sw_and_sh_and_dx_and_dy_and_dw_and_dh(
+    let sprite = item_sprite(item_type);
+    let (dx, dy) = (x as f64 - sprite.size / 2., y as f64 - sprite.size / 2.);
+    match sprite.source {
+        Some([sx, sy, sw, sh]) => context
+            .draw_image_with_image_bitmap_and_sw_and_sh_and_dx_and_dy_and_dw_and_dh(
                 &image.bitmap,
-                0.,
-                0.,
-                32.,
-                32.,
-                x as f64 - 8.,
-                y as f64 - 8.,
-                16.,
-                16.,
-            )?;
-        }
-        Ok(())
-    };
-    match item_type {
-        ItemType::IronOre => render16(&state.image_iron_ore),
-        ItemType::CoalOre => render16(&state.image_coal_ore),
-        ItemType::CopperOre => render16(&state.image_copper_ore),
-        ItemType::StoneOre => render16(&state.image_stone_ore),
-        ItemType::IronPlate => render16(&state.image_iron_plate),
-        ItemType::CopperPlate => render16(&state.image_copper_plate),
-        ItemType::Gear => render16(&state.image_gear),
-        ItemType::CopperWire => render16(&state.image_copper_wire),
-        ItemType::Circuit => render16(&state.image_circuit),
-
-        ItemType::TransportBelt => render16(&state.image_belt),
-        ItemType::Chest => render16(&state.image_chest),
-        ItemType::Inserter => render_animated32(&state.image_inserter),
-        ItemType::OreMine => render16(&state.image_mine),
-        ItemType::Furnace => render_animated32(&state.image_furnace),
-        ItemType::Assembler => render16(&state.image_assembler),
-        ItemType::Boiler => render16(&state.image_boiler),
-        ItemType::WaterWell => render16(&state.image_water_well),
-        ItemType::OffshorePump => render16(&state.image_offshore_pump),
-        ItemType::Pipe => render16(&state.image_pipe),
-        ItemType::SteamEngine => render16(&state.image_steam_engine),
-        ItemType::ElectPole => render16(&state.image_elect_pole),
-        ItemType::Splitter => render16(&state.image_splitter),
+                sx,
+                sy,
+                sw,
+                sh,
+                dx,
+                dy,
+                sprite.size,
+                sprite.size,
+            )?,
+        None => context.draw_image_with_image_bitmap_and_dw_and_dh(
+            &image.bitmap,
+            dx,
+            dy,
+            sprite.size,
+            sprite.size,
+        )?,
     }
+    Ok(())
 }
 
 pub(crate) fn get_item_image_url<'a>(state: &'a FactorishState, item_type: &ItemType) -> &'a str {
-    match item_type {
-        ItemType::IronOre => &state.image_iron_ore.as_ref().unwrap().url,
-        ItemType::CoalOre => &state.image_coal_ore.as_ref().unwrap().url,
-        ItemType::CopperOre => &state.image_copper_ore.as_ref().unwrap().url,
-        ItemType::StoneOre => &state.image_stone_ore.as_ref().unwrap().url,
-        ItemType::IronPlate => &state.image_iron_plate.as_ref().unwrap().url,
-        ItemType::CopperPlate => &state.image_copper_plate.as_ref().unwrap().url,
-        ItemType::Gear => &state.image_gear.as_ref().unwrap().url,
-        ItemType::CopperWire => &state.image_copper_wire.as_ref().unwrap().url,
-        ItemType::Circuit => &state.image_circuit.as_ref().unwrap().url,
-
-        ItemType::TransportBelt => &state.image_belt.as_ref().unwrap().url,
-        ItemType::Chest => &state.image_chest.as_ref().unwrap().url,
-        ItemType::Inserter => &state.image_inserter.as_ref().unwrap().url,
-        ItemType::OreMine => &state.image_mine.as_ref().unwrap().url,
-        ItemType::Furnace => &state.image_furnace.as_ref().unwrap().url,
-        ItemType::Assembler => &state.image_assembler.as_ref().unwrap().url,
-        ItemType::Boiler => &state.image_boiler.as_ref().unwrap().url,
-        ItemType::WaterWell => &state.image_water_well.as_ref().unwrap().url,
-        ItemType::OffshorePump => &state.image_offshore_pump.as_ref().unwrap().url,
-        ItemType::Pipe => &state.image_pipe.as_ref().unwrap().url,
-        ItemType::SteamEngine => &state.image_steam_engine.as_ref().unwrap().url,
-        ItemType::ElectPole => &state.image_elect_pole.as_ref().unwrap().url,
-        ItemType::Splitter => &state.image_splitter.as_ref().unwrap().url,
+    &item_image(state, item_type).as_ref().unwrap().url
+}
+
+#[derive(Serialize)]
+struct ItemSpriteInfo<'a> {
+    url: &'a str,
+    #[serde(flatten)]
+    sprite: ItemSprite,
+    /// Size of the whole image in pixels, which the icon needs to scale the source rectangle
+    width: u32,
+    height: u32,
+}
+
+#[wasm_bindgen]
+impl FactorishState {
+    /// Returns `{url, size, source, width, height}` describing how to draw the item's icon,
+    /// where `source` is `[x, y, width, height]` in the image of `width` and `height`, or null
+    /// for the whole image. Returns null if the item name is unknown or its image is not loaded.
+    pub fn get_item_sprite(&self, name: &str) -> Result<JsValue, JsValue> {
+        let item_type = if let Some(item_type) = str_to_item(name) {
+            item_type
+        } else {
+            return Ok(JsValue::null());
+        };
+        let image = if let Some(image) = item_image(self, &item_type).as_ref() {
+            image
+        } else {
+            return Ok(JsValue::null());
+        };
+        JsValue::from_serde(&ItemSpriteInfo {
+            url: &image.url,
+            sprite: item_sprite(&item_type),
+            width: image.bitmap.width(),
+            height: image.bitmap.height(),
+        })
+        .map_err(|e| js_str!("Serialize error: {}", e))
     }
 }
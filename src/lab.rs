@@ -0,0 +1,286 @@
+use super::{
+    alerts::AlertType,
+    power_network::{drain_power, full_power_ratio, power_ratio},
+    structure::{Structure, StructureDynIter, StructureId},
+    DropItem, FactorishState, FrameProcResult, Inventory, InventoryTrait, ItemType, Position,
+    TILE_SIZE,
+};
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+use web_sys::CanvasRenderingContext2d;
+
+/// Energy in kJ to consume a science pack
+const POWER_COST: f64 = 60.;
+/// Frames to consume a science pack
+const RESEARCH_TIME: f64 = 100.;
+
+/// Consumes science packs with electricity to advance the active research.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct Lab {
+    position: Position,
+    input_inventory: Inventory,
+    progress: Option<f64>,
+    power: f64,
+    max_power: f64,
+    #[serde(skip, default = "full_power_ratio")]
+    power_ratio: f64,
+}
+
+impl Lab {
+    pub(crate) fn new(position: &Position) -> Self {
+        Lab {
+            position: *position,
+            input_inventory: Inventory::new(),
+            progress: None,
+            power: 0.,
+            max_power: 60.,
+            power_ratio: full_power_ratio(),
+        }
+    }
+
+    fn is_active(&self) -> bool {
+        self.progress.is_some() && 0. < self.power
+    }
+}
+
+impl Structure for Lab {
+    fn name(&self) -> &str {
+        "Lab"
+    }
+
+    fn alert(&self) -> Option<AlertType> {
+        if self.progress.is_some() && self.power == 0. {
+            Some(AlertType::NoPower)
+        } else {
+            None
+        }
+    }
+
+    fn position(&self) -> &Position {
+        &self.position
+    }
+
+    fn draw(
+        &self,
+        state: &FactorishState,
+        context: &CanvasRenderingContext2d,
+        depth: i32,
+        is_toolbar: bool,
+    ) -> Result<(), JsValue> {
+        let (x, y) = (
+            self.position.x as f64 * TILE_SIZE,
+            self.position.y as f64 * TILE_SIZE,
+        );
+        match depth {
+            0 => match state.image_lab.as_ref() {
+                Some(img) => {
+                    context.draw_image_with_image_bitmap(&img.bitmap, x, y)?;
+                }
+                None => return js_err!("lab image not available"),
+            },
+            1 => {
+                if self.is_active() {
+                    // Glowing dome while researching
+                    context.save();
+                    context.set_global_alpha(0.3 + 0.2 * (state.sim_time * 4.).sin());
+                    context.set_fill_style(&js_str!("#7fdfff"));
+                    context.begin_path();
+                    context.arc(x + 16., y + 16., 8., 0., std::f64::consts::PI * 2.)?;
+                    context.fill();
+                    context.restore();
+                }
+            }
+            2 => {
+                if !is_toolbar
+                    && self.progress.is_some()
+                    && self.power == 0.
+                    && state.sim_time % 1. < 0.5
+                {
+                    if let Some(img) = state.image_electricity_alarm.as_ref() {
+                        context.draw_image_with_image_bitmap(&img.bitmap, x, y)?;
+                    } else {
+                        return js_err!("electricity alarm image not available");
+                    }
+                }
+            }
+            _ => (),
+        }
+        Ok(())
+    }
+
+    fn desc(&self, state: &FactorishState) -> String {
+        format!(
+            "{}<br>{}{}{}<br>{}",
+            if let Some(tech) = state.research.current_technology() {
+                format!("Researching: {}", tech.name)
+            } else {
+                String::from("No research")
+            },
+            format!("Progress: {:.0}%<br>", self.progress.unwrap_or(0.) * 100.),
+            format!(
+                r#"Electricity: {:.1}kJ <div style='position: relative; width: 100px; height: 10px; background-color: #001f1f; margin: 2px; border: 1px solid #3f3f3f'>
+            <div style='position: absolute; width: {}px; height: 10px; background-color: #ffff00'></div></div>"#,
+                self.power,
+                if 0. < self.max_power {
+                    self.power / self.max_power * 100.
+                } else {
+                    0.
+                }
+            ),
+            if self.power_ratio < 1. {
+                format!(
+                    "<div style='color: #ff7f00'>Power: {:.0}%</div>",
+                    self.power_ratio * 100.
+                )
+            } else {
+                "".to_string()
+            },
+            format!("Input Items: <br>{}", self.input_inventory.describe()),
+        )
+    }
+
+    fn frame_proc(
+        &mut self,
+        me: StructureId,
+        state: &mut FactorishState,
+        structures: &mut StructureDynIter,
+    ) -> Result<FrameProcResult, ()> {
+        self.power_ratio = power_ratio(&state.power_networks, me);
+        if !state.research.is_active() {
+            return Ok(FrameProcResult::None);
+        }
+        let mut ret = FrameProcResult::None;
+        if self.power < POWER_COST {
+            self.power += drain_power(
+                &state.power_networks,
+                structures,
+                me,
+                self.max_power - self.power,
+            );
+        }
+
+        if self.progress.is_none() {
+            if !self.input_inventory.remove_item(&ItemType::SciencePack) {
+                return Ok(FrameProcResult::None);
+            }
+            state
+                .production_stats
+                .add_consumed(&ItemType::SciencePack, 1);
+            self.progress = Some(0.);
+            ret = FrameProcResult::InventoryChanged(self.position);
+        }
+
+        if let Some(prev_progress) = self.progress {
+            let progress = (self.power / POWER_COST)
+                .min(1. / RESEARCH_TIME)
+                .min(1. - prev_progress);
+            self.power -= progress * POWER_COST;
+            if 1. <= prev_progress + progress {
+                self.progress = None;
+                // The completion is reported to JavaScript by `tick`.
+                if let Some(tech) = state.research.add_progress() {
+                    state.new_popup_text(
+                        format!("{} researched", tech.name),
+                        self.position.x as f64 * TILE_SIZE,
+                        self.position.y as f64 * TILE_SIZE,
+                    );
+                }
+            } else {
+                self.progress = Some(prev_progress + progress);
+            }
+        }
+        Ok(ret)
+    }
+
+    fn input(&mut self, o: &DropItem) -> Result<(), JsValue> {
+        if o.type_ == ItemType::SciencePack {
+            self.input_inventory.add_item(&o.type_);
+            Ok(())
+        } else {
+            js_err!("Lab accepts only science packs")
+        }
+    }
+
+    fn can_input(&self, item_type: &ItemType) -> bool {
+        *item_type == ItemType::SciencePack
+    }
+
+    fn inventory(&self, is_input: bool) -> Option<&Inventory> {
+        if is_input {
+            Some(&self.input_inventory)
+        } else {
+            None
+        }
+    }
+
+    fn inventory_mut(&mut self, is_input: bool) -> Option<&mut Inventory> {
+        if is_input {
+            Some(&mut self.input_inventory)
+        } else {
+            None
+        }
+    }
+
+    fn destroy_inventory(&mut self) -> Inventory {
+        let mut ret = std::mem::take(&mut self.input_inventory);
+        // The science pack being researched is consumed when the research starts.
+        if self.progress.take().is_some() {
+            ret.merge(Some((ItemType::SciencePack, 1)));
+        }
+        ret
+    }
+
+    fn power_sink(&self) -> bool {
+        true
+    }
+
+    fn power_demand(&self) -> f64 {
+        let has_work =
+            self.progress.is_some() || 0 < self.input_inventory.count_item(&ItemType::SciencePack);
+        if has_work && self.power < POWER_COST {
+            self.max_power - self.power
+        } else {
+            0.
+        }
+    }
+
+    crate::serialize_impl!();
+}
+
+#[test]
+fn test_research_completion() {
+    use super::{scenarios::empty_state, JSEvent};
+    let mut state = empty_state(16, 16);
+    state.start_research("logistics").unwrap();
+    let tech = state.research.current_technology().unwrap();
+    // Leave the last science pack to the lab
+    for _ in 1..tech.cost {
+        assert!(state.research.add_progress().is_none());
+    }
+    state.research.take_completed();
+
+    let mut lab = Lab::new(&Position::new(0, 0));
+    lab.input_inventory.add_item(&ItemType::SciencePack);
+    // Energy for two science packs, since there is no power grid
+    lab.power = POWER_COST * 2.;
+    let id = state.add_structure(Box::new(lab)).unwrap();
+
+    let mut completed = vec![];
+    for _ in 0..RESEARCH_TIME as usize * 2 {
+        for event in state.tick(0.05).unwrap() {
+            if let JSEvent::ResearchCompleted(name) = event {
+                completed.push(name);
+            }
+        }
+    }
+    assert!(state.research.is_researched("logistics"));
+    assert_eq!(completed, ["Logistics"]);
+    // The whole research step is paid for, including the one that completes it.
+    let saved = state.structures[id.id as usize]
+        .dynamic
+        .as_deref()
+        .unwrap()
+        .serialize()
+        .unwrap();
+    assert!((saved["power"].as_f64().unwrap() - POWER_COST).abs() < 1e-6);
+}
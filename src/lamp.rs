@@ -0,0 +1,150 @@
+use super::{
+    power_network::drain_power,
+    structure::{Structure, StructureDynIter, StructureId},
+    FactorishState, FrameProcResult, Position, TILE_SIZE,
+};
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+use web_sys::CanvasRenderingContext2d;
+
+/// Energy in kJ that a lamp consumes every tick while lit
+const POWER_COST: f64 = 0.5;
+/// Radius of the light in tiles
+const LIGHT_RADIUS: f64 = 6.;
+
+/// Lights up the surroundings in the night with electricity. It stays off in the daytime.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct Lamp {
+    position: Position,
+    power: f64,
+    max_power: f64,
+    #[serde(skip)]
+    lit: bool,
+}
+
+impl Lamp {
+    pub(crate) fn new(position: &Position) -> Self {
+        Lamp {
+            position: *position,
+            power: 0.,
+            max_power: 5.,
+            lit: false,
+        }
+    }
+
+    /// Consume the power for a tick if it is night and there is enough of it.
+    /// @returns whether the lamp is lit.
+    fn consume_power(&mut self, night: bool) -> bool {
+        if night && POWER_COST <= self.power {
+            self.power -= POWER_COST;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+impl Structure for Lamp {
+    fn name(&self) -> &str {
+        "Lamp"
+    }
+
+    fn position(&self) -> &Position {
+        &self.position
+    }
+
+    fn draw(
+        &self,
+        state: &FactorishState,
+        context: &CanvasRenderingContext2d,
+        depth: i32,
+        _is_toolbar: bool,
+    ) -> Result<(), JsValue> {
+        let (x, y) = (
+            self.position.x as f64 * TILE_SIZE,
+            self.position.y as f64 * TILE_SIZE,
+        );
+        match depth {
+            0 => match state.image_lamp.as_ref() {
+                Some(img) => {
+                    context.draw_image_with_image_bitmap(&img.bitmap, x, y)?;
+                }
+                None => return js_err!("lamp image not available"),
+            },
+            1 => {
+                if self.lit {
+                    context.save();
+                    context.set_global_alpha(0.5);
+                    context.set_fill_style(&js_str!("#ffffbf"));
+                    context.begin_path();
+                    context.arc(x + 16., y + 10., 8., 0., std::f64::consts::PI * 2.)?;
+                    context.fill();
+                    context.restore();
+                }
+            }
+            _ => (),
+        }
+        Ok(())
+    }
+
+    fn desc(&self, _state: &FactorishState) -> String {
+        format!(
+            "{}<br>Electricity: {:.1}kJ",
+            if self.lit { "Lit" } else { "Off" },
+            self.power
+        )
+    }
+
+    fn frame_proc(
+        &mut self,
+        me: StructureId,
+        state: &mut FactorishState,
+        structures: &mut StructureDynIter,
+    ) -> Result<FrameProcResult, ()> {
+        let night = state.brightness() < 1.;
+        if night && self.power < POWER_COST {
+            self.power += drain_power(
+                &state.power_networks,
+                structures,
+                me,
+                self.max_power - self.power,
+            );
+        }
+        self.lit = self.consume_power(night);
+        Ok(FrameProcResult::None)
+    }
+
+    fn power_sink(&self) -> bool {
+        true
+    }
+
+    fn power_demand(&self) -> f64 {
+        if self.power < POWER_COST {
+            self.max_power - self.power
+        } else {
+            0.
+        }
+    }
+
+    fn light_radius(&self) -> f64 {
+        if self.lit {
+            LIGHT_RADIUS
+        } else {
+            0.
+        }
+    }
+
+    crate::serialize_impl!();
+}
+
+#[test]
+fn test_lamp_lit_only_at_night_with_power() {
+    let mut lamp = Lamp::new(&Position::new(0, 0));
+    assert!(!lamp.consume_power(true));
+    lamp.power = 1.;
+    assert!(!lamp.consume_power(false));
+    assert_eq!(lamp.power, 1.);
+    assert!(lamp.consume_power(true));
+    assert!(lamp.consume_power(true));
+    assert!(!lamp.consume_power(true));
+}
@@ -4,71 +4,152 @@
 #[macro_use]
 mod macros;
 
+mod accumulator;
+mod alerts;
 mod assembler;
+mod balancer;
+mod blueprint;
 mod boiler;
 mod chest;
+mod circuit_network;
+mod crafting_progress;
+mod deconstruct;
 mod drop_items;
 mod dyn_iter;
 mod elect_pole;
+mod electric_furnace;
+mod electric_ore_mine;
+mod enemy;
+mod fluid_network;
 mod furnace;
+mod game_speed;
+mod ghost;
+mod gun_turret;
+mod health;
 mod inserter;
 mod inventory;
 mod items;
+mod lab;
+mod lamp;
+mod lighting;
+mod logging;
 mod minimap;
+mod missing_ingredients;
+mod objectives;
 mod offshore_pump;
 mod ore_mine;
+mod ore_patch;
+mod panning;
 mod perf;
 mod perlin_noise;
 mod pipe;
+mod pollution;
 mod power_network;
+mod production_stats;
+mod productivity;
+mod recipes;
+mod replay;
+mod requester_chest;
+mod research;
+mod save_data;
+mod save_slots;
 mod scenarios;
+mod sleep;
 mod splitter;
 mod steam_engine;
 mod structure;
+mod structure_search;
+mod structure_settings;
 mod terrain;
+mod tool_belt;
 mod transport_belt;
+mod undo;
 mod utils;
+mod valve;
 mod water_well;
+mod world_summary;
+mod zoom;
 
 use crate::{
     drop_items::{
-        add_index, build_index, drop_item_id_iter, drop_item_iter, hit_check, hit_check_with_index,
-        remove_index, update_index, DropItem, DropItemEntry, DropItemId, DropItemIndex,
-        DROP_ITEM_SIZE, INDEX_CHUNK_SIZE,
+        add_index, build_index, drop_item_id_iter, drop_item_iter, find_item_at, hit_check,
+        merge_blocked_item, move_destination, remove_index, update_index, Destination, DropItem,
+        DropItemEntry, DropItemId, DropItemIndex, DROP_ITEM_SIZE, DROP_ITEM_SIZE_I,
+        INDEX_CHUNK_SIZE, MAX_BELT_SPACING, MIN_BELT_SPACING,
     },
-    perf::PerfStats,
-    scenarios::select_scenario,
+    perf::{Clock, ManualClock, PerfStats, PerformanceClock},
+    production_stats::ProductionStats,
+    scenarios::{apply_scenario_params, scenario_objectives, scenario_unlocks, select_scenario},
     terrain::{
-        calculate_back_image, calculate_back_image_all, gen_chunk, Chunk, Chunks,
+        calculate_back_image, calculate_back_image_all, gen_chunk, Chunk, Chunks, ChunksExt,
         TerrainParameters, CHUNK_SIZE, CHUNK_SIZE2, CHUNK_SIZE_I,
     },
 };
+use accumulator::Accumulator;
+use alerts::Alert;
 use assembler::Assembler;
+use balancer::Balancer;
+use blueprint::Blueprint;
 use boiler::Boiler;
 use chest::Chest;
-use dyn_iter::{Chained, DynIterMut, MutRef};
+use circuit_network::{load_signal_wires, update_signals, SignalNetwork, SignalWire};
+use dyn_iter::{Chained, DynIter, DynIterMut, MutRef};
 use elect_pole::ElectPole;
+use electric_furnace::ElectricFurnace;
+use electric_ore_mine::ElectricOreMine;
+use enemy::Enemy;
+use fluid_network::{
+    build_fluid_networks, connect_fluid_boxes, simulate_fluid_networks, FluidNetwork,
+};
 use furnace::Furnace;
-use inserter::Inserter;
+use game_speed::game_speed_steps;
+use ghost::{check_placement, check_tile, Ghost};
+use gun_turret::GunTurret;
+use inserter::{ChestLimit, Inserter};
 use inventory::{Inventory, InventoryTrait, InventoryType};
-use items::{item_to_str, render_drop_item, str_to_item, ItemType};
+use items::{item_to_str, render_drop_item, stack_size, str_to_item, ItemType};
+use lab::Lab;
+use lamp::Lamp;
+use logging::LogLevel;
+use minimap::MinimapRegion;
+use objectives::Objective;
 use offshore_pump::OffshorePump;
 use ore_mine::OreMine;
+use ore_patch::OrePatch;
 use perlin_noise::Xor128;
 use pipe::Pipe;
-use power_network::{build_power_networks, PowerNetwork};
-use splitter::Splitter;
+use power_network::{
+    build_power_networks, charge_accumulators, load_power_wires, save_power_wires,
+    saved_structure_indices, update_power_ratios, would_connect, PowerNetwork,
+};
+use recipes::{RecipeEntry, RECIPES};
+use replay::{ReplayInput, ReplayLog};
+use requester_chest::RequesterChest;
+use research::TechTree;
+use save_data::migrate_save;
+use save_slots::{AUTOSAVE_SLOT, DEFAULT_AUTOSAVE_INTERVAL};
+use sleep::is_awake;
+use splitter::{Splitter, SplitterSide};
 use steam_engine::SteamEngine;
 use structure::{
+    add_structure_tiles, build_structure_tile_index, remove_structure_tiles, BoundingBox,
     FrameProcResult, ItemResponse, Position, RotateErr, Rotation, Structure, StructureBoxed,
-    StructureDynIter, StructureEntry, StructureId,
+    StructureDynIter, StructureEntry, StructureId, StructureTileIndex,
 };
+use structure_settings::StructureSettings;
+use tool_belt::{fill_tool_rows, ToolBeltRow, TOOL_BELT_ROWS};
 use transport_belt::TransportBelt;
+use undo::{structure_to_json, UndoAction, UndoStack};
+use valve::Valve;
 use water_well::{FluidType, WaterWell};
+use zoom::Zoom;
 
 use serde::{Deserialize, Serialize};
 use std::hash::Hash;
-use std::{collections::HashMap, convert::TryFrom};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    convert::TryFrom,
+};
 use wasm_bindgen::prelude::*;
 use wasm_bindgen::JsCast;
 use web_sys::{CanvasRenderingContext2d, HtmlCanvasElement, HtmlDivElement, ImageBitmap};
@@ -77,6 +158,10 @@ use web_sys::{CanvasRenderingContext2d, HtmlCanvasElement, HtmlDivElement, Image
 extern "C" {
     #[wasm_bindgen(js_namespace = console)]
     pub(crate) fn log(s: &str);
+    #[wasm_bindgen(js_namespace = console)]
+    pub(crate) fn warn(s: &str);
+    #[wasm_bindgen(js_namespace = console)]
+    pub(crate) fn error(s: &str);
 }
 
 // When the `wee_alloc` feature is enabled, use `wee_alloc` as the global
@@ -128,9 +213,24 @@ const TILE_SIZE: f64 = 32.;
 const TILE_SIZE_I: i32 = TILE_SIZE as i32;
 
 const COAL_POWER: f64 = 100.; // kilojoules
-const SAVE_VERSION: i64 = 5;
+
+/// The coal loaded into the energy buffer of a burner that has not started burning yet, which
+/// is given back when the structure is removed. The coal that is burning is lost.
+fn unburnt_fuel(power: f64) -> Option<(ItemType, usize)> {
+    let count = (power / COAL_POWER).floor() as usize;
+    if 0 < count {
+        Some((ItemType::CoalOre, count))
+    } else {
+        None
+    }
+}
+const SAVE_VERSION: i64 = 7;
 const ORE_HARVEST_TIME: i32 = 20;
 const POPUP_TEXT_LIFE: i32 = 30;
+/// Brightness at midnight, in the range [0, 1].
+const MIN_BRIGHTNESS: f64 = 0.35;
+/// Items that have not moved for this many seconds are drawn fully red in the item debug overlay.
+const JAMMED_ITEM_TIME: f64 = 3.;
 
 /// Event types that can be communicated to the JavaScript code.
 /// It is serialized into a JavaScript Object through serde.
@@ -143,6 +243,8 @@ enum JSEvent {
         recipe_enable: bool,
     },
     UpdateStructureInventory(i32, i32),
+    ObjectiveCompleted(String),
+    ResearchCompleted(String),
 }
 
 #[derive(Copy, Clone, Serialize, Deserialize, PartialEq, Debug)]
@@ -159,6 +261,10 @@ struct OreValue(Ore, u32);
 #[derive(Copy, Clone, Serialize, Deserialize)]
 struct Cell {
     water: bool,
+    #[serde(default)]
+    cliff: bool,
+    #[serde(default)]
+    tree: bool,
     ore: Option<OreValue>,
     #[serde(skip)]
     image: u8,
@@ -170,6 +276,8 @@ impl Default for Cell {
     fn default() -> Self {
         Cell {
             water: false,
+            cliff: false,
+            tree: false,
             ore: None,
             image: 0,
             grass_image: 0,
@@ -194,7 +302,7 @@ struct ToolDef {
     item_type: ItemType,
     desc: &'static str,
 }
-const tool_defs: [ToolDef; 13] = [
+const tool_defs: [ToolDef; 22] = [
     ToolDef {
         item_type: ItemType::TransportBelt,
         desc: "Transports items on ground",
@@ -207,18 +315,34 @@ const tool_defs: [ToolDef; 13] = [
         item_type: ItemType::Splitter,
         desc: "Connects to transport belt. Splits inputs and outputs into two lanes.",
     },
+    ToolDef {
+        item_type: ItemType::Balancer,
+        desc: "Connects to four transport belts.<br>Distributes the inputs evenly over the connected outputs.",
+    },
     ToolDef {
         item_type: ItemType::OreMine,
         desc: "Mines ores and puts them to adjacent ground<br>or a structure in the direction indicated by an arrow.<br>Requires coal ores to operate.",
     },
+    ToolDef {
+        item_type: ItemType::ElectricOreMine,
+        desc: "Mines ores and puts them to adjacent ground<br>or a structure in the direction indicated by an arrow.<br>Requires electricity to operate.",
+    },
     ToolDef {
         item_type: ItemType::Chest,
-        desc: "Can store 100 items.<br>Use inserters to automatically store/retrieve items.",
+        desc: "Can store 20 stacks of items.<br>Use inserters to automatically store/retrieve items.",
+    },
+    ToolDef {
+        item_type: ItemType::RequesterChest,
+        desc: "Can store 20 stacks of items.<br>Pulls requested items from chests within 10 tiles.",
     },
     ToolDef {
         item_type: ItemType::Furnace,
         desc: "Smelts metal ores into metal bars.<br>Requires coal ores to operate.",
     },
+    ToolDef {
+        item_type: ItemType::ElectricFurnace,
+        desc: "Smelts metal ores into metal bars.<br>Requires electricity to operate.",
+    },
     ToolDef {
         item_type: ItemType::Assembler,
         desc: "Assembles items from ingredients with recipes.<br>Set a recipe in the inventory GUI to operate.<br>Requires electricity to operate.",
@@ -239,6 +363,10 @@ const tool_defs: [ToolDef; 13] = [
         item_type: ItemType::Pipe,
         desc: "Conveys fluid such as water or steam.",
     },
+    ToolDef {
+        item_type: ItemType::Valve,
+        desc: "Conveys fluid only in the direction indicated by an arrow.<br>Prevents steam from flowing back into boilers.",
+    },
     ToolDef {
         item_type: ItemType::SteamEngine,
         desc: "Consumes steam and transmits electricity within a range of 3 tiles.",
@@ -247,6 +375,22 @@ const tool_defs: [ToolDef; 13] = [
         item_type: ItemType::ElectPole,
         desc: "Electric pole.",
     },
+    ToolDef {
+        item_type: ItemType::Accumulator,
+        desc: "Stores surplus electricity of the power network<br>and supplies it back when demand exceeds supply.",
+    },
+    ToolDef {
+        item_type: ItemType::Lab,
+        desc: "Consumes science packs to advance the active research.<br>Requires electricity to operate.",
+    },
+    ToolDef {
+        item_type: ItemType::Lamp,
+        desc: "Lights up the surroundings in the night.<br>Requires electricity to operate.",
+    },
+    ToolDef {
+        item_type: ItemType::GunTurret,
+        desc: "Shoots enemies within a range of 12 tiles.<br>Consumes firearm magazines.",
+    },
 ];
 
 fn draw_direction_arrow(
@@ -271,10 +415,13 @@ fn draw_direction_arrow(
 
 type ItemSet = HashMap<ItemType, usize>;
 
-#[derive(Clone, Serialize, Deserialize)]
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
 struct Recipe {
     input: ItemSet,
     input_fluid: Option<FluidType>,
+    /// The amount of `input_fluid` consumed over the course of the recipe
+    #[serde(default)]
+    input_fluid_amount: f64,
     output: ItemSet,
     output_fluid: Option<FluidType>,
     power_cost: f64,
@@ -286,26 +433,40 @@ impl Recipe {
         Recipe {
             input,
             input_fluid: None,
+            input_fluid_amount: 0.,
             output,
             output_fluid: None,
             power_cost,
             recipe_time,
         }
     }
+
+    fn with_input_fluid(mut self, fluid: FluidType, amount: f64) -> Self {
+        self.input_fluid = Some(fluid);
+        self.input_fluid_amount = amount;
+        self
+    }
 }
 
 #[derive(Serialize)]
 struct RecipeSerial {
+    id: String,
     input: HashMap<String, usize>,
+    input_fluid: Option<String>,
+    input_fluid_amount: f64,
     output: HashMap<String, usize>,
     power_cost: f64,
     recipe_time: f64,
 }
 
-impl From<Recipe> for RecipeSerial {
-    fn from(o: Recipe) -> Self {
+impl From<&RecipeEntry> for RecipeSerial {
+    fn from(entry: &RecipeEntry) -> Self {
+        let o = &entry.recipe;
         Self {
+            id: entry.id.clone(),
             input: o.input.iter().map(|(k, v)| (item_to_str(k), *v)).collect(),
+            input_fluid: o.input_fluid.map(|fluid| format!("{:?}", fluid)),
+            input_fluid_amount: o.input_fluid_amount,
             output: o.output.iter().map(|(k, v)| (item_to_str(k), *v)).collect(),
             power_cost: o.power_cost,
             recipe_time: o.recipe_time,
@@ -313,6 +474,57 @@ impl From<Recipe> for RecipeSerial {
     }
 }
 
+/// A hand crafting job in the player's crafting queue.
+#[derive(Clone, Serialize, Deserialize)]
+struct CraftJob {
+    recipe: Recipe,
+    /// Elapsed time in the same unit as `recipe.recipe_time`.
+    progress: f64,
+}
+
+#[derive(Serialize)]
+struct CraftJobSerial {
+    output: HashMap<String, usize>,
+    progress: f64,
+}
+
+impl From<&CraftJob> for CraftJobSerial {
+    fn from(o: &CraftJob) -> Self {
+        Self {
+            output: o
+                .recipe
+                .output
+                .iter()
+                .map(|(k, v)| (item_to_str(k), *v))
+                .collect(),
+            progress: if 0. < o.recipe.recipe_time {
+                (o.progress / o.recipe.recipe_time).min(1.)
+            } else {
+                1.
+            },
+        }
+    }
+}
+
+/// Returns each tile in a drag path with the rotation pointing to the next tile.
+/// The last tile keeps the direction of the previous one.
+fn drag_path_rotations(path: &[Position]) -> Vec<(Position, Rotation)> {
+    let mut ret: Vec<(Position, Rotation)> = vec![];
+    for (i, position) in path.iter().enumerate() {
+        let rotation = path
+            .get(i + 1)
+            .and_then(|next| Rotation::from_delta((next.x - position.x, next.y - position.y)))
+            .or_else(|| ret.last().map(|(_, rotation)| *rotation))
+            .unwrap_or(Rotation::Left);
+        ret.push((*position, rotation));
+    }
+    ret
+}
+
+/// Recipe time is defined in the number of frames of a machine, while hand crafting
+/// advances by `delta_time`, so we need a conversion factor between them.
+const HAND_CRAFT_TIME_SCALE: f64 = 20.;
+
 #[derive(Serialize, Deserialize)]
 struct Player {
     inventory: Inventory,
@@ -335,7 +547,14 @@ impl<'a> From<&'a ImageBundle> for &'a ImageBitmap {
     }
 }
 
+enum TempEntKind {
+    Smoke,
+    /// A bullet flying with the velocity in pixels per second
+    Projectile((f64, f64)),
+}
+
 struct TempEnt {
+    kind: TempEntKind,
     position: (f64, f64),
     rotation: f64,
     life: f64,
@@ -346,6 +565,7 @@ impl TempEnt {
     fn new(rng: &mut Xor128, position: Position) -> Self {
         let life = rng.next() * 3. + 6.;
         TempEnt {
+            kind: TempEntKind::Smoke,
             position: (
                 (position.x as f64 + 0.5 + rng.next() * 0.5) * 32.,
                 (position.y as f64 + rng.next() * 0.5) * 32.,
@@ -355,6 +575,17 @@ impl TempEnt {
             max_life: life,
         }
     }
+
+    /// A projectile starting at a pixel position, which disappears after `life` seconds.
+    fn new_projectile(position: (f64, f64), rotation: f64, speed: f64, life: f64) -> Self {
+        TempEnt {
+            kind: TempEntKind::Projectile((rotation.cos() * speed, rotation.sin() * speed)),
+            position,
+            rotation,
+            life,
+            max_life: life,
+        }
+    }
 }
 
 #[derive(Eq, PartialEq, Hash, Copy, Clone, Serialize, Deserialize, Debug)]
@@ -367,7 +598,7 @@ struct PopupText {
     life: i32,
 }
 
-#[derive(Eq, PartialEq, Copy, Clone, Debug)]
+#[derive(Eq, PartialEq, Copy, Clone, Debug, Serialize, Deserialize)]
 enum SelectedItem {
     /// This is index into `tool_belt`. It is kind of duplicate of `player.selected_item`,
     /// but we make it separate field because multiple tool belt slots refer to the same item type.
@@ -390,7 +621,7 @@ impl SelectedItem {
     }
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Serialize, Deserialize)]
 struct OreHarvesting {
     pos: Position,
     ore_type: ItemType,
@@ -402,6 +633,12 @@ struct Viewport {
     x: f64,
     y: f64,
     scale: f64,
+    /// Panning velocity in tiles per second in screen scale
+    #[serde(skip)]
+    velocity: (f64, f64),
+    /// The velocity requested by the player, which `velocity` approaches to.
+    #[serde(skip)]
+    pan_input: (f64, f64),
 }
 
 impl Default for Viewport {
@@ -410,16 +647,54 @@ impl Default for Viewport {
             x: 0.,
             y: 0.,
             scale: 1.,
+            velocity: (0., 0.),
+            pan_input: (0., 0.),
         }
     }
 }
 
+impl Viewport {
+    /// Convert a position on the canvas in pixels to the world in tiles.
+    fn screen_to_world(&self, (px, py): (f64, f64)) -> (f64, f64) {
+        (
+            px / self.scale / TILE_SIZE - self.x,
+            py / self.scale / TILE_SIZE - self.y,
+        )
+    }
+
+    /// Convert a position in the world in tiles to the canvas in pixels.
+    fn world_to_screen(&self, (x, y): (f64, f64)) -> (f64, f64) {
+        (
+            (x + self.x) * TILE_SIZE * self.scale,
+            (y + self.y) * TILE_SIZE * self.scale,
+        )
+    }
+
+    /// The tile under a position on the canvas in pixels.
+    fn screen_to_tile(&self, pos: (f64, f64)) -> Position {
+        let (x, y) = self.screen_to_world(pos);
+        Position::new(x.floor() as i32, y.floor() as i32)
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 struct Bounds {
     width: i32,
     height: i32,
 }
 
+/// The most tiles that the bounding box of a structure reaches beyond its position in any
+/// direction, which is the lanes of a balancer after the first one.
+const MAX_STRUCTURE_EXTENT: i32 = balancer::LANES as i32 - 1;
+
+/// Returns the chunk position that a structure at the given tile belongs to.
+fn structure_chunk(position: &Position) -> Position {
+    Position::new(
+        position.x.div_euclid(CHUNK_SIZE_I),
+        position.y.div_euclid(CHUNK_SIZE_I),
+    )
+}
+
 fn apply_bounds(
     bounds: &Option<Bounds>,
     viewport: &Viewport,
@@ -456,34 +731,111 @@ pub struct FactorishState {
     viewport_height: f64,
     viewport: Viewport,
     board: Chunks,
+    /// Cache of the ore patches that have been queried, to avoid flood filling every mouse move.
+    ore_patches: Vec<OrePatch>,
     terrain_params: TerrainParameters,
     structures: Vec<StructureEntry>,
+    /// Structure ids bucketed by the chunk of their positions, to cull drawing outside the viewport.
+    structure_chunks: HashMap<Position, Vec<StructureId>>,
+    /// The structure index on each tile, kept up to date on construction, removal and rotation
+    /// for looking up the structure under every drop item in each tick.
+    structure_tiles: StructureTileIndex,
     selected_structure_inventory: Option<Position>,
     drop_items: Vec<DropItemEntry>,
     drop_items_index: DropItemIndex,
-    tool_belt: [Option<ItemType>; 10],
+    /// Rows of `TOOL_BELT_SIZE` slots, which always has `TOOL_BELT_ROWS` rows.
+    tool_belt: Vec<ToolBeltRow>,
+    /// The row of `tool_belt` that is shown in the toolbar
+    tool_row: usize,
     power_networks: Vec<PowerNetwork>,
+    fluid_networks: Vec<FluidNetwork>,
+    /// Set when fluid connections change, so that `fluid_networks` is rebuilt before next use.
+    fluid_networks_dirty: bool,
 
     selected_item: Option<SelectedItem>,
     ore_harvesting: Option<OreHarvesting>,
 
     tool_rotation: Rotation,
     player: Player,
+    crafting_queue: Vec<CraftJob>,
+    /// Structures planned to be built when the player has the items.
+    ghosts: Vec<Ghost>,
+    undo_stack: UndoStack,
+    /// Tiles that the mouse cursor went through while dragging with a transport belt.
+    drag_path: Option<Vec<Position>>,
     temp_ents: Vec<TempEnt>,
+    enemies: Vec<Enemy>,
     rng: Xor128,
+    /// Time source of the performance measurements
+    clock: Box<dyn Clock>,
+    /// The inputs recorded for a replay, if recording
+    recording: Option<ReplayLog>,
 
     // rendering states
     cursor: Option<[i32; 2]>,
     info_elem: Option<HtmlDivElement>,
-    on_player_update: js_sys::Function,
+    /// Called with the player inventory when it changes, unless the game runs headless.
+    on_player_update: Option<js_sys::Function>,
     minimap_buffer: Vec<u8>,
+    /// Names of the structure types marked on the minimap, or None to mark all of them.
+    minimap_structure_filter: Option<HashSet<String>>,
+    /// Tiles per pixel of the minimap
+    minimap_scale: f64,
+    /// The tile coordinates the minimap is panned to, or None to follow the viewport.
+    minimap_center: Option<(f64, f64)>,
+    /// The region rendered by the last `render_minimap`
+    minimap_region: MinimapRegion,
+    /// Offscreen canvas to draw the night with the lights, created on the first use.
+    light_canvas: Option<HtmlCanvasElement>,
+    alerts: Vec<Alert>,
+    copied_settings: Option<StructureSettings>,
+    /// The blueprint captured last, which can be saved into the library by a name.
+    blueprint_clipboard: Option<Blueprint>,
+    /// Named blueprints that are saved with the game, so that they can be pasted in later sessions.
+    blueprints: BTreeMap<String, Blueprint>,
     power_wires: Vec<PowerWire>,
+    signal_wires: Vec<SignalWire>,
+    signal_networks: Vec<SignalNetwork>,
     popup_texts: Vec<PopupText>,
     debug_bbox: bool,
     debug_fluidbox: bool,
+    debug_items: bool,
+    /// `sim_time` when each item moved on a belt last time, tracked while `debug_items` is on.
+    item_last_moved: HashMap<DropItemId, f64>,
     debug_power_network: bool,
+    debug_power_tint: bool,
+    pollution_overlay: bool,
+    fluid_flow_overlay: bool,
+    /// Ticks between autosaves, or 0 if disabled
+    autosave_interval: u32,
+    autosave_ticks: u32,
+    autosave_slot: String,
+    /// Multiplier of the number of simulation steps per frame, which is not saved.
+    game_speed: f64,
+    /// The fraction of a step carried over to the next frame.
+    game_speed_accum: f64,
+    /// Whether `simulate` skips the simulation steps, which can be advanced by `step_once`
+    paused: bool,
+    /// Whether idle structures out of the awake area skip `frame_proc`, which is not saved.
+    sleep_idle_structures: bool,
+    /// Whether blocked drop items merge into the ones in front of them, which is not saved.
+    merge_drop_items: bool,
+    /// The verbosity of the logging macros, which is not saved. It starts at `Warn` in every
+    /// game, since the informational messages slow down profiling in the console.
+    log_level: LogLevel,
+    /// The spacing between items on belts in pixels, set by `set_belt_density`.
+    belt_spacing: i32,
+    zoom: Zoom,
+    research: TechTree,
+    /// Productivity bonus set by the scenario, to which the research adds.
+    productivity: f64,
+    /// Structures that a staged scenario allows the player to place, besides the ones unlocked
+    /// by research. `None` in the sandbox, where every tool is available.
+    unlocked: Option<HashSet<ItemType>>,
+    objectives: Vec<Objective>,
 
     // Performance measurements
+    production_stats: ProductionStats,
     perf_structures: PerfStats,
     perf_drop_items: PerfStats,
     perf_simulate: PerfStats,
@@ -492,6 +844,8 @@ pub struct FactorishState {
 
     // on_show_inventory: js_sys::Function,
     image_dirt: Option<ImageBundle>,
+    image_cliff: Option<ImageBundle>,
+    image_tree: Option<ImageBundle>,
     image_back_tiles: Option<ImageBundle>,
     image_weeds: Option<ImageBundle>,
     image_ore: Option<ImageBundle>,
@@ -499,9 +853,12 @@ pub struct FactorishState {
     image_copper: Option<ImageBundle>,
     image_stone: Option<ImageBundle>,
     image_belt: Option<ImageBundle>,
+    image_belt_curve: Option<ImageBundle>,
     image_chest: Option<ImageBundle>,
     image_mine: Option<ImageBundle>,
+    image_electric_mine: Option<ImageBundle>,
     image_furnace: Option<ImageBundle>,
+    image_electric_furnace: Option<ImageBundle>,
     image_assembler: Option<ImageBundle>,
     image_boiler: Option<ImageBundle>,
     image_steam_engine: Option<ImageBundle>,
@@ -509,7 +866,13 @@ pub struct FactorishState {
     image_offshore_pump: Option<ImageBundle>,
     image_pipe: Option<ImageBundle>,
     image_elect_pole: Option<ImageBundle>,
+    image_accumulator: Option<ImageBundle>,
+    image_requester_chest: Option<ImageBundle>,
+    image_lab: Option<ImageBundle>,
+    image_lamp: Option<ImageBundle>,
+    image_gun_turret: Option<ImageBundle>,
     image_splitter: Option<ImageBundle>,
+    image_balancer: Option<ImageBundle>,
     image_inserter: Option<ImageBundle>,
     image_direction: Option<ImageBundle>,
     image_iron_ore: Option<ImageBundle>,
@@ -518,9 +881,14 @@ pub struct FactorishState {
     image_stone_ore: Option<ImageBundle>,
     image_iron_plate: Option<ImageBundle>,
     image_copper_plate: Option<ImageBundle>,
+    image_steel_plate: Option<ImageBundle>,
     image_gear: Option<ImageBundle>,
     image_copper_wire: Option<ImageBundle>,
     image_circuit: Option<ImageBundle>,
+    image_wood: Option<ImageBundle>,
+    image_science_pack: Option<ImageBundle>,
+    image_repair_pack: Option<ImageBundle>,
+    image_firearm_magazine: Option<ImageBundle>,
     image_time: Option<ImageBundle>,
     image_smoke: Option<ImageBundle>,
     image_fuel_alarm: Option<ImageBundle>,
@@ -533,27 +901,36 @@ enum NewObjectErr {
     BlockedByItem,
     OutOfMap,
     OnWater,
+    OnCliff,
+}
+
+impl NewObjectErr {
+    fn reason(self) -> &'static str {
+        match self {
+            Self::BlockedByStructure => "Blocked by structure",
+            Self::BlockedByItem => "Blocked by item",
+            Self::OutOfMap => "Out of map",
+            Self::OnWater => "Blocked by water",
+            Self::OnCliff => "Blocked by cliff",
+        }
+    }
 }
 
-#[wasm_bindgen]
 impl FactorishState {
-    #[wasm_bindgen(constructor)]
-    pub fn new(
-        terrain_params: JsValue,
-        on_player_update: js_sys::Function,
-        // on_show_inventory: js_sys::Function,
+    /// Create a game that runs without a browser, e.g. in tests. Changes to the player inventory
+    /// are not reported to JavaScript and the performance is timed with a `ManualClock`.
+    pub(crate) fn new_headless(
+        mut terrain_params: TerrainParameters,
         scenario: &str,
     ) -> Result<FactorishState, JsValue> {
-        console_log!("FactorishState constructor");
-
-        let terrain_params: TerrainParameters = serde_wasm_bindgen::from_value(terrain_params)?;
-
-        let mut tool_belt = [None; 10];
-        tool_belt[0] = Some(ItemType::OreMine);
-        tool_belt[1] = Some(ItemType::Inserter);
-        tool_belt[2] = Some(ItemType::TransportBelt);
-        tool_belt[3] = Some(ItemType::Furnace);
-
+        let mut tool_belt = vec![];
+        fill_tool_rows(&mut tool_belt);
+        tool_belt[0][0] = Some(ItemType::OreMine);
+        tool_belt[0][1] = Some(ItemType::Inserter);
+        tool_belt[0][2] = Some(ItemType::TransportBelt);
+        tool_belt[0][3] = Some(ItemType::Furnace);
+
+        apply_scenario_params(scenario, &mut terrain_params);
         let (structures, board, drop_items) = select_scenario(scenario, &terrain_params)?;
 
         let mut ret = FactorishState {
@@ -571,13 +948,10 @@ impl FactorishState {
             },
             viewport_height: 0.,
             viewport_width: 0.,
-            viewport: Viewport {
-                x: 0.,
-                y: 0.,
-                scale: 1.,
-            },
+            viewport: Viewport::default(),
             cursor: None,
             tool_belt,
+            tool_row: 0,
             selected_item: None,
             tool_rotation: Rotation::Left,
             player: Player {
@@ -597,20 +971,62 @@ impl FactorishState {
                 .copied()
                 .collect(),
             },
+            crafting_queue: vec![],
+            structure_chunks: HashMap::new(),
+            structure_tiles: StructureTileIndex::new(),
+            ghosts: vec![],
+            undo_stack: UndoStack::new(),
+            drag_path: None,
             info_elem: None,
             minimap_buffer: vec![],
+            minimap_structure_filter: None,
+            minimap_scale: 1.,
+            minimap_center: None,
+            minimap_region: MinimapRegion::default(),
+            light_canvas: None,
+            alerts: vec![],
+            copied_settings: None,
+            blueprint_clipboard: None,
+            blueprints: BTreeMap::new(),
             power_wires: vec![],
+            signal_wires: vec![],
+            signal_networks: vec![],
             power_networks: vec![],
+            fluid_networks: vec![],
+            fluid_networks_dirty: true,
             popup_texts: vec![],
             debug_bbox: false,
             debug_fluidbox: false,
+            debug_items: false,
+            item_last_moved: HashMap::new(),
             debug_power_network: false,
+            debug_power_tint: false,
+            pollution_overlay: false,
+            fluid_flow_overlay: false,
+            autosave_interval: DEFAULT_AUTOSAVE_INTERVAL,
+            autosave_ticks: 0,
+            autosave_slot: AUTOSAVE_SLOT.to_string(),
+            game_speed: 1.,
+            game_speed_accum: 0.,
+            paused: false,
+            sleep_idle_structures: true,
+            merge_drop_items: false,
+            log_level: LogLevel::Warn,
+            belt_spacing: DROP_ITEM_SIZE_I,
+            zoom: Zoom::default(),
+            research: TechTree::default(),
+            productivity: 0.,
+            unlocked: scenario_unlocks(scenario),
+            objectives: scenario_objectives(scenario),
+            production_stats: ProductionStats::default(),
             perf_structures: PerfStats::default(),
             perf_drop_items: PerfStats::default(),
             perf_simulate: PerfStats::default(),
             perf_minimap: PerfStats::default(),
             perf_render: PerfStats::default(),
             image_dirt: None,
+            image_cliff: None,
+            image_tree: None,
             image_back_tiles: None,
             image_weeds: None,
             image_ore: None,
@@ -618,9 +1034,12 @@ impl FactorishState {
             image_stone: None,
             image_copper: None,
             image_belt: None,
+            image_belt_curve: None,
             image_chest: None,
             image_mine: None,
+            image_electric_mine: None,
             image_furnace: None,
+            image_electric_furnace: None,
             image_assembler: None,
             image_boiler: None,
             image_steam_engine: None,
@@ -628,7 +1047,13 @@ impl FactorishState {
             image_offshore_pump: None,
             image_pipe: None,
             image_elect_pole: None,
+            image_accumulator: None,
+            image_requester_chest: None,
+            image_lab: None,
+            image_lamp: None,
+            image_gun_turret: None,
             image_splitter: None,
+            image_balancer: None,
             image_inserter: None,
             image_direction: None,
             image_iron_ore: None,
@@ -637,34 +1062,67 @@ impl FactorishState {
             image_stone_ore: None,
             image_iron_plate: None,
             image_copper_plate: None,
+            image_steel_plate: None,
             image_gear: None,
             image_copper_wire: None,
             image_circuit: None,
+            image_wood: None,
+            image_science_pack: None,
+            image_repair_pack: None,
+            image_firearm_magazine: None,
             image_time: None,
             image_smoke: None,
             image_fuel_alarm: None,
             image_electricity_alarm: None,
             board,
+            ore_patches: vec![],
             terrain_params,
             structures,
             selected_structure_inventory: None,
             ore_harvesting: None,
             drop_items,
             drop_items_index: DropItemIndex::default(),
-            on_player_update,
+            on_player_update: None,
             temp_ents: vec![],
+            enemies: vec![],
             rng: Xor128::new(3142125),
+            clock: Box::new(ManualClock::default()),
+            recording: None,
             // on_show_inventory,
         };
 
+        ret.rebuild_structure_chunks();
         ret.update_cache()?;
 
         Ok(ret)
     }
+}
+
+#[wasm_bindgen]
+impl FactorishState {
+    #[wasm_bindgen(constructor)]
+    pub fn new(
+        terrain_params: JsValue,
+        on_player_update: js_sys::Function,
+        // on_show_inventory: js_sys::Function,
+        scenario: &str,
+    ) -> Result<FactorishState, JsValue> {
+        let terrain_params: TerrainParameters = serde_wasm_bindgen::from_value(terrain_params)?;
+        let mut ret = Self::new_headless(terrain_params, scenario)?;
+        console_debug!(ret, "FactorishState constructor");
+        ret.on_player_update = Some(on_player_update);
+        ret.set_clock(Box::new(PerformanceClock));
+        Ok(ret)
+    }
 
     pub fn serialize_game(&self) -> Result<String, JsValue> {
+        serde_json::to_string(&self.serialize_game_value()?)
+            .map_err(|e| js_str!("Serialize error: {}", e))
+    }
+
+    fn serialize_game_value(&self) -> Result<serde_json::Value, JsValue> {
         use serde_json::Value as SValue;
-        console_log!("Serializing...");
+        console_debug!(self, "Serializing...");
 
         fn map_err(
             result: Result<SValue, serde_json::Error>,
@@ -681,6 +1139,11 @@ impl FactorishState {
         map.insert("version".to_string(), to_value(&SAVE_VERSION, "version")?);
         map.insert("sim_time".to_string(), SValue::from(self.sim_time));
         map.insert("player".to_string(), to_value(&self.player, "player")?);
+        map.insert(
+            "crafting_queue".to_string(),
+            to_value(&self.crafting_queue, "crafting_queue")?,
+        );
+        map.insert("ghosts".to_string(), to_value(&self.ghosts, "ghosts")?);
         map.insert(
             "viewport".to_string(),
             to_value(&self.viewport, "viewport")?,
@@ -695,8 +1158,8 @@ impl FactorishState {
             serde_json::Value::from(
                 self.structures
                     .iter()
-                    .filter_map(|entry| entry.dynamic.as_ref())
-                    .map(|structure| {
+                    .filter_map(|entry| Some((entry.dynamic.as_ref()?, entry.damage)))
+                    .map(|(structure, damage)| {
                         let mut map = serde_json::Map::new();
                         map.insert(
                             "type".to_string(),
@@ -708,6 +1171,12 @@ impl FactorishState {
                                 .serialize()
                                 .map_err(|e| js_str!("Serialize error: {}", e))?,
                         );
+                        if 0. < damage {
+                            map.insert(
+                                "health".to_string(),
+                                serde_json::Value::from(structure.max_health() - damage),
+                            );
+                        }
                         Ok(serde_json::Value::Object(map))
                     })
                     .collect::<Result<Vec<serde_json::Value>, JsValue>>()?,
@@ -715,28 +1184,18 @@ impl FactorishState {
         );
 
         // This mapping is necessary to fill the gaps from deleted structures since we only serialize live structures.
-        let id_to_index = self
-            .structures
-            .iter()
-            .enumerate()
-            .map(|(id, s)| {
-                (
-                    StructureId {
-                        id: id as u32,
-                        gen: s.gen,
-                    },
-                    s,
-                )
-            })
-            .filter(|(_, s)| s.dynamic.is_some())
-            .enumerate()
-            .map(|(idx, (id, _))| (id, idx))
-            .collect::<HashMap<_, _>>();
+        let id_to_index = saved_structure_indices(&self.structures);
         map.insert(
             "power_wires".to_string(),
+            serde_json::to_value(&save_power_wires(&self.power_wires, &id_to_index))
+                .map_err(|e| js_str!("Serialize error: {}", e))?,
+        );
+
+        map.insert(
+            "signal_wires".to_string(),
             serde_json::to_value(
                 &self
-                    .power_wires
+                    .signal_wires
                     .iter()
                     .filter_map(|w| Some((id_to_index.get(&w.0)?, id_to_index.get(&w.1)?)))
                     .collect::<Vec<_>>(),
@@ -744,6 +1203,39 @@ impl FactorishState {
             .map_err(|e| js_str!("Serialize error: {}", e))?,
         );
 
+        map.insert(
+            "enemies".to_string(),
+            map_err(serde_json::to_value(&self.enemies), "enemies")?,
+        );
+        map.insert(
+            "research".to_string(),
+            map_err(serde_json::to_value(&self.research), "research")?,
+        );
+        map.insert("productivity".to_string(), SValue::from(self.productivity));
+        map.insert(
+            "water_well_rate".to_string(),
+            SValue::from(self.terrain_params.water_well_rate),
+        );
+        map.insert(
+            "water_well_depletion".to_string(),
+            SValue::from(self.terrain_params.water_well_depletion),
+        );
+        map.insert("belt_spacing".to_string(), SValue::from(self.belt_spacing));
+        if let Some(unlocked) = &self.unlocked {
+            map.insert(
+                "scenario_unlocks".to_string(),
+                map_err(serde_json::to_value(unlocked), "scenario_unlocks")?,
+            );
+        }
+        map.insert(
+            "objectives".to_string(),
+            map_err(serde_json::to_value(&self.objectives), "objectives")?,
+        );
+        map.insert(
+            "blueprints".to_string(),
+            map_err(serde_json::to_value(&self.blueprints), "blueprints")?,
+        );
+
         map.insert(
             "items".to_string(),
             serde_json::Value::from(
@@ -757,8 +1249,9 @@ impl FactorishState {
         );
         map.insert(
             "tool_belt".to_string(),
-            map_err(serde_json::to_value(self.tool_belt), "toolbelt")?,
+            map_err(serde_json::to_value(&self.tool_belt), "toolbelt")?,
         );
+        map.insert("tool_row".to_string(), SValue::from(self.tool_row));
         map.insert(
             "board".to_string(),
             serde_json::to_value(
@@ -772,7 +1265,9 @@ impl FactorishState {
                                 .cells
                                 .iter()
                                 .enumerate()
-                                .filter(|(_, cell)| cell.ore.is_some() || cell.water)
+                                .filter(|(_, cell)| {
+                                    cell.ore.is_some() || cell.water || cell.cliff || cell.tree
+                                })
                                 .map(|(idx, cell)| {
                                     let mut map = serde_json::Map::new();
                                     let x = idx % self.width as usize;
@@ -792,41 +1287,21 @@ impl FactorishState {
             )
             .map_err(|e| js_str!("Serialize error on board: {}", e))?,
         );
-        serde_json::to_string(&map).map_err(|e| js_str!("Serialize error: {}", e))
-    }
-
-    pub fn save_game(&self) -> Result<(), JsValue> {
-        if let Some(storage) = window().local_storage()? {
-            storage.set_item("FactorishWasmGameSave", &self.serialize_game()?)?;
-            Ok(())
-        } else {
-            js_err!("The subsystem does not support localStorage")
-        }
+        Ok(SValue::Object(map))
     }
 
     pub fn deserialize_game(&mut self, data: &str) -> Result<(), JsValue> {
-        use serde_json::Value;
-
-        console_log!("deserialize");
-
-        let mut json: Value =
-            serde_json::from_str(&data).map_err(|_| js_str!("Deserialize error"))?;
-
-        // Check version first
-        let version = if let Some(version) = json.get("version") {
-            version
-                .as_i64()
-                .ok_or_else(|| js_str!("Version string cannot be parsed as int"))?
-        } else {
-            0
-        };
+        let json = serde_json::from_str(&data).map_err(|_| js_str!("Deserialize error"))?;
+        self.deserialize_game_value(json)
+    }
 
-        if version < SAVE_VERSION {
-            return js_err!("Save data version is too old. Please start a new game.");
-        }
+    /// Load the game from the save data. Everything is parsed before the current game is
+    /// replaced, so that the game is kept intact if the data is broken.
+    fn deserialize_game_value(&mut self, mut json: serde_json::Value) -> Result<(), JsValue> {
+        console_debug!(self, "deserialize");
 
-        self.structures.clear();
-        self.drop_items.clear();
+        // Check version first and upgrade older save data to the current format
+        migrate_save(&mut json)?;
 
         fn json_get<I: serde_json::value::Index + std::fmt::Display + Copy>(
             value: &serde_json::Value,
@@ -857,20 +1332,28 @@ impl FactorishState {
             serde_json::from_value(value).map_err(|e| js_str!("deserialization error {}", e))
         }
 
-        self.sim_time = json_get(&json, "sim_time")?
+        let sim_time = json_get(&json, "sim_time")?
             .as_f64()
             .ok_or_else(|| js_str!("sim_time is not float"))?;
 
-        self.player = from_value(json_take(&mut json, "player")?)?;
+        let player = from_value(json_take(&mut json, "player")?)?;
+
+        let crafting_queue = json_take(&mut json, "crafting_queue")
+            .and_then(from_value)
+            .unwrap_or_default();
+
+        let ghosts = json_take(&mut json, "ghosts")
+            .and_then(from_value)
+            .unwrap_or_default();
 
-        self.viewport = json_take(&mut json, "viewport")
+        let viewport = json_take(&mut json, "viewport")
             .and_then(from_value)
             .unwrap_or_default();
 
-        self.width = json_as_u64(json_get(&json, "width")?)? as u32;
-        self.height = json_as_u64(json_get(&json, "height")?)? as u32;
+        let width = json_as_u64(json_get(&json, "width")?)? as u32;
+        let height = json_as_u64(json_get(&json, "height")?)? as u32;
 
-        self.bounds = json_take(&mut json, "bounds")
+        let bounds = json_take(&mut json, "bounds")
             .and_then(from_value)
             .unwrap_or(None);
 
@@ -879,7 +1362,7 @@ impl FactorishState {
             .ok_or_else(|| js_str!("board not found in saved data"))?
             .as_array_mut()
             .ok_or_else(|| js_str!("board in saved data is not an array"))?;
-        self.board = HashMap::new();
+        let mut board = HashMap::new();
         for chunk in chunks {
             let chunk_pair = chunk
                 .as_array_mut()
@@ -895,77 +1378,133 @@ impl FactorishState {
                 .as_array_mut()
                 .ok_or_else(|| js_str!("Chunk data is not an array"))?;
             let mut new_chunk = vec![Cell::default(); CHUNK_SIZE2];
-            console_log!("new chunk {:?}", chunk_pos);
+            console_debug!(self, "new chunk {:?}", chunk_pos);
             for tile in chunk_data {
                 let position = json_get(tile, "position")?;
                 let x: usize = json_as_u64(json_get(&position, 0)?)? as usize;
                 let y: usize = json_as_u64(json_get(&position, 1)?)? as usize;
                 new_chunk[x + y * CHUNK_SIZE] = from_value(json_take(tile, "cell")?)?;
             }
-            self.board.insert(chunk_pos, Chunk::new(new_chunk));
+            board.insert(chunk_pos, Chunk::new(new_chunk));
         }
-        calculate_back_image_all(&mut self.board);
+        calculate_back_image_all(&mut board);
 
-        let structures = json
+        let mut structures = json
             .get_mut("structures")
             .ok_or_else(|| js_str!("structures not found in saved data"))?
             .as_array_mut()
             .ok_or_else(|| js_str!("structures in saved data is not an array"))?
             .iter_mut()
             .map(|structure| {
+                let health = structure.get("health").and_then(|health| health.as_f64());
+                let dynamic = Self::structure_from_json(structure)?;
                 Ok(StructureEntry {
                     gen: 0,
-                    dynamic: Some(Self::structure_from_json(structure)?),
+                    damage: health.map_or(0., |health| (dynamic.max_health() - health).max(0.)),
+                    dynamic: Some(dynamic),
+                    active: true,
                 })
             })
             .collect::<Result<Vec<StructureEntry>, JsValue>>()?;
 
-        self.power_wires = serde_json::from_value::<Vec<(u32, u32)>>(
-            json.get_mut("power_wires")
-                .ok_or_else(|| js_str!("power_wires not found in saved data"))?
-                .take(),
-        )
-        .map_err(|e| js_str!("power_wires deserialization error: {}", e))?
-        .into_iter()
-        .map(|w| {
-            PowerWire(
-                StructureId {
-                    id: w.0 as u32,
-                    gen: 0,
-                },
-                StructureId { id: w.1, gen: 0 },
+        let (power_wires, dropped_wires) = load_power_wires(
+            &serde_json::from_value::<Vec<(u32, u32)>>(
+                json.get_mut("power_wires")
+                    .ok_or_else(|| js_str!("power_wires not found in saved data"))?
+                    .take(),
             )
-        })
-        .collect();
-
-        self.structures = structures;
-
-        // We need to collect the positions into a temporary Vec to allow passing &mut self to update_fluid_connections
-        for pos in self
-            .structures
-            .iter()
-            .filter_map(|s| Some(*s.dynamic.as_deref()?.position()))
-            .collect::<Vec<_>>()
-        {
-            self.update_fluid_connections(&pos)?;
+            .map_err(|e| js_str!("power_wires deserialization error: {}", e))?,
+            &structures,
+        );
+        if !dropped_wires.is_empty() {
+            console_warn!(
+                self,
+                "Dropped {} invalid power wires: {:?}",
+                dropped_wires.len(),
+                dropped_wires
+            );
         }
 
-        for i in 0..self.structures.len() {
-            let (s, others) = StructureDynIter::new(&mut self.structures, i)?;
-            let id = StructureId {
-                id: i as u32,
-                gen: s.gen,
-            };
-            s.dynamic
-                .as_deref_mut()
-                .map(|d| d.on_construction_self(id, &others, true))
-                .unwrap_or(Ok(()))?;
+        // Older saves do not have signal wires
+        let (signal_wires, dropped_signal_wires) = load_signal_wires(
+            &json
+                .get_mut("signal_wires")
+                .map(|value| serde_json::from_value::<Vec<(u32, u32)>>(value.take()))
+                .transpose()
+                .map_err(|e| js_str!("signal_wires deserialization error: {}", e))?
+                .unwrap_or_default(),
+            &structures,
+        );
+        if !dropped_signal_wires.is_empty() {
+            console_warn!(
+                self,
+                "Dropped {} invalid signal wires: {:?}",
+                dropped_signal_wires.len(),
+                dropped_signal_wires
+            );
         }
 
-        let s_d_iter = StructureDynIter::new_all(&mut self.structures);
-        self.power_networks = build_power_networks(&s_d_iter, &self.power_wires);
+        let enemies = json
+            .get_mut("enemies")
+            .map(|value| serde_json::from_value::<Vec<Enemy>>(value.take()))
+            .transpose()
+            .map_err(|e| js_str!("enemies deserialization error: {}", e))?
+            .unwrap_or_default();
 
-        self.drop_items = json
+        // Older saves do not have research, in which case nothing is researched
+        let research = json
+            .get_mut("research")
+            .map(|value| serde_json::from_value::<TechTree>(value.take()))
+            .transpose()
+            .map_err(|e| js_str!("research deserialization error: {}", e))?
+            .unwrap_or_default();
+
+        let productivity = json
+            .get("productivity")
+            .and_then(|value| value.as_f64())
+            .unwrap_or(0.);
+
+        // Older saves have the water wells of the sandbox, which pump at the base rate forever
+        let water_well_rate = json
+            .get("water_well_rate")
+            .and_then(|value| value.as_f64())
+            .unwrap_or(1.);
+        let water_well_depletion = json
+            .get("water_well_depletion")
+            .and_then(|value| value.as_bool())
+            .unwrap_or(false);
+
+        // Older saves have the default spacing. Clamp it in case the save is edited.
+        let belt_spacing = json
+            .get("belt_spacing")
+            .and_then(|value| value.as_i64())
+            .map_or(DROP_ITEM_SIZE_I, |spacing| {
+                (spacing as i32).clamp(MIN_BELT_SPACING, MAX_BELT_SPACING)
+            });
+
+        // Only staged scenarios save the unlocked set, so the sandbox and older saves get every tool
+        let unlocked = json
+            .get_mut("scenario_unlocks")
+            .map(|value| serde_json::from_value::<HashSet<ItemType>>(value.take()))
+            .transpose()
+            .map_err(|e| js_str!("scenario_unlocks deserialization error: {}", e))?;
+
+        let objectives = json
+            .get_mut("objectives")
+            .map(|value| serde_json::from_value::<Vec<Objective>>(value.take()))
+            .transpose()
+            .map_err(|e| js_str!("objectives deserialization error: {}", e))?
+            .unwrap_or_default();
+
+        // Older saves do not have the blueprint library
+        let blueprints = json
+            .get_mut("blueprints")
+            .map(|value| serde_json::from_value::<BTreeMap<String, Blueprint>>(value.take()))
+            .transpose()
+            .map_err(|e| js_str!("blueprints deserialization error: {}", e))?
+            .unwrap_or_default();
+
+        let drop_items = json
             .get_mut("items")
             .ok_or_else(|| js_str!("\"items\" not found"))?
             .as_array_mut()
@@ -979,27 +1518,79 @@ impl FactorishState {
             })
             .collect::<Result<Vec<DropItemEntry>, JsValue>>()?;
 
-        self.drop_items_index = build_index(&self.drop_items);
+        let mut tool_belt: Vec<ToolBeltRow> = from_value(json_take(&mut json, "tool_belt")?)?;
+        fill_tool_rows(&mut tool_belt);
+        let tool_row = json
+            .get("tool_row")
+            .and_then(|value| value.as_u64())
+            .map_or(0, |row| (row as usize).min(TOOL_BELT_ROWS - 1));
+
+        for i in 0..structures.len() {
+            let (s, others) = StructureDynIter::new(&mut structures, i)?;
+            let id = StructureId {
+                id: i as u32,
+                gen: s.gen,
+            };
+            s.dynamic
+                .as_deref_mut()
+                .map(|d| d.on_construction_self(id, &others, true))
+                .unwrap_or(Ok(()))?;
+        }
+
+        // Nothing can fail with the data from here on, so we replace the current game
+        self.sim_time = sim_time;
+        self.player = player;
+        self.crafting_queue = crafting_queue;
+        self.ghosts = ghosts;
+        self.viewport = viewport;
+        self.width = width;
+        self.height = height;
+        self.bounds = bounds;
+        self.board = board;
+        self.ore_patches.clear();
+        self.power_wires = power_wires;
+        self.signal_wires = signal_wires;
+        self.enemies = enemies;
+        self.research = research;
+        self.productivity = productivity;
+        self.terrain_params.water_well_rate = water_well_rate;
+        self.terrain_params.water_well_depletion = water_well_depletion;
+        self.belt_spacing = belt_spacing;
+        self.unlocked = unlocked;
+        self.objectives = objectives;
+        self.blueprints = blueprints;
+        self.drop_items = drop_items;
+        self.item_last_moved.clear();
+        self.tool_belt = tool_belt;
+        self.tool_row = tool_row;
+        self.game_speed = 1.;
+        self.game_speed_accum = 0.;
+
+        self.structures = structures;
+        self.rebuild_structure_chunks();
+
+        // We need to collect the positions into a temporary Vec to allow passing &mut self to update_fluid_connections
+        for pos in self
+            .structures
+            .iter()
+            .filter_map(|s| Some(*s.dynamic.as_deref()?.position()))
+            .collect::<Vec<_>>()
+        {
+            self.update_fluid_connections(&pos);
+        }
+
+        let s_d_iter = StructureDynIter::new_all(&mut self.structures);
+        self.power_networks = build_power_networks(&s_d_iter, &self.power_wires);
+        self.rebuild_signal_networks();
 
-        self.tool_belt = from_value(json_take(&mut json, "tool_belt")?)?;
+        self.drop_items_index = build_index(&self.drop_items);
 
         // Redraw minimap
-        self.render_minimap_data()?;
+        self.render_minimap_data();
 
         Ok(())
     }
 
-    pub fn load_game(&mut self) -> Result<(), JsValue> {
-        if let Some(storage) = window().local_storage()? {
-            let data = storage
-                .get_item("FactorishWasmGameSave")?
-                .ok_or_else(|| js_str!("save data not found!"))?;
-            self.deserialize_game(&data)
-        } else {
-            js_err!("The subsystem does not support localStorage")
-        }
-    }
-
     #[allow(dead_code)]
     fn proc_structures_mutual(
         &mut self,
@@ -1106,7 +1697,10 @@ impl FactorishState {
             .flatten()
     }
 
-    fn update_fluid_connections(&mut self, position: &Position) -> Result<(), JsValue> {
+    /// Connect the fluid boxes of the structure at the position to its neighbors, or disconnect
+    /// the neighbors if the structure is gone.
+    fn update_fluid_connections(&mut self, position: &Position) {
+        self.fluid_networks_dirty = true;
         if let Some(i) = self
             .structures
             .iter()
@@ -1122,18 +1716,7 @@ impl FactorishState {
             for j in 0..self.structures.len() {
                 if i != j {
                     if let (Some(a), Some(b)) = self.get_pair_mut(i, j) {
-                        let (aid, bid) = (a.0, b.0);
-                        if let Some(((idx, mut av), mut bv)) =
-                            a.1.position()
-                                .neighbor_index(b.1.position())
-                                .zip(a.1.fluid_box_mut())
-                                .zip(b.1.fluid_box_mut())
-                        {
-                            av.iter_mut()
-                                .for_each(|fb| fb.connect_to[(idx as usize + 2) % 4] = Some(bid));
-                            bv.iter_mut()
-                                .for_each(|fb| fb.connect_to[idx as usize] = Some(aid));
-                        }
+                        connect_fluid_boxes((a.0, a.1.as_mut()), (b.0, b.1.as_mut()));
                     }
                 }
             }
@@ -1154,46 +1737,115 @@ impl FactorishState {
                 }
             }
         }
-
-        Ok(())
     }
 
     pub fn simulate(&mut self, delta_time: f64) -> Result<js_sys::Array, JsValue> {
-        let start_simulate = performance().now();
         // console_log!("simulating delta_time {}, {}", delta_time, self.sim_time);
-        const SERIALIZE_PERIOD: f64 = 100.;
-        if (self.sim_time / SERIALIZE_PERIOD).floor()
-            < ((self.sim_time + delta_time) / SERIALIZE_PERIOD).floor()
-        {
-            self.save_game()?;
-        }
-
-        self.delta_time = delta_time;
-        self.sim_time += delta_time;
+        // The viewport moves in real time regardless of the game speed.
+        self.update_panning(delta_time);
 
-        // Since we cannot use callbacks to report events to the JavaScript environment,
-        // we need to accumulate events during simulation and return them as an array.
         let mut events = vec![];
+        if self.paused {
+            // Autosave counts the simulated frames, so it does not run while nothing changes.
+            self.update_popup_texts();
+        } else {
+            self.proc_autosave()?;
+            for _ in 0..game_speed_steps(self.game_speed, &mut self.game_speed_accum) {
+                events.extend(self.tick(delta_time)?);
+            }
+        }
+        self.process_tick_events(events)
+    }
 
-        let mut frame_proc_result_to_event = |result: Result<FrameProcResult, ()>| {
-            if let Ok(FrameProcResult::InventoryChanged(pos)) = result {
-                events.push(
-                    JsValue::from_serde(&JSEvent::UpdateStructureInventory(pos.x, pos.y)).unwrap(),
-                )
+    /// Advance the simulation by a step like `simulate`, but without the autosave, the player
+    /// inventory callback and the DOM updates, so that it can run without a browser, e.g. in tests.
+    /// The game speed and the viewport panning do not apply either.
+    pub fn tick_headless(&mut self, delta_time: f64) -> Result<(), JsValue> {
+        self.tick(delta_time).map(|_| ())
+    }
+}
+
+impl FactorishState {
+    /// Pass the player inventory to JavaScript after it changed, unless the game runs headless.
+    fn notify_player_update(&self) -> Result<JsValue, JsValue> {
+        match &self.on_player_update {
+            Some(on_player_update) => {
+                on_player_update.call1(&window(), &JsValue::from(self.get_player_inventory()?))
             }
-        };
+            None => Ok(JsValue::UNDEFINED),
+        }
+    }
 
-        self.ore_harvesting = (|| {
-            let mut ore_harvesting = self.ore_harvesting?;
-            let mut ret = true;
-            if (ore_harvesting.timer + 1) % ORE_HARVEST_TIME < ore_harvesting.timer {
-                console_log!("harvesting {:?}...", ore_harvesting.ore_type);
-                let tile = self.tile_at_mut(&ore_harvesting.pos)?;
-                let ore = tile.ore.as_mut()?;
-                let expected_ore = match ore_harvesting.ore_type {
-                    ItemType::IronOre => Ore::Iron,
-                    ItemType::CopperOre => Ore::Copper,
-                    ItemType::CoalOre => Ore::Coal,
+    /// Notify the player inventory changes and update the DOM after the simulation steps,
+    /// and return the rest of the events to JavaScript.
+    fn process_tick_events(&mut self, events: Vec<JSEvent>) -> Result<js_sys::Array, JsValue> {
+        if events
+            .iter()
+            .any(|event| matches!(event, JSEvent::UpdatePlayerInventory))
+        {
+            self.notify_player_update()
+                .unwrap_or_else(|_| JsValue::from(true));
+        }
+
+        self.update_info();
+        Ok(events
+            .iter()
+            .filter(|event| !matches!(event, JSEvent::UpdatePlayerInventory))
+            .map(|event| JsValue::from_serde(event).unwrap())
+            .collect())
+    }
+
+    /// Move the popup texts up and remove the expired ones.
+    fn update_popup_texts(&mut self) {
+        let mut delete_me = vec![];
+        for (i, item) in self.popup_texts.iter_mut().enumerate() {
+            if item.life <= 0 {
+                delete_me.push(i);
+            } else {
+                item.y -= 1.;
+                item.life -= 1;
+            }
+        }
+
+        for i in delete_me.iter().rev() {
+            self.popup_texts.remove(*i);
+        }
+    }
+
+    /// The core of the simulation, which does not touch the browser environment.
+    /// Changes to the player inventory are reported as `JSEvent::UpdatePlayerInventory`.
+    fn tick(&mut self, delta_time: f64) -> Result<Vec<JSEvent>, JsValue> {
+        let start_simulate = self.clock.now();
+
+        self.delta_time = delta_time;
+        self.sim_time += delta_time;
+
+        // Recorded after panning in `simulate`, so that the chunks generated by it precede this
+        // in the log.
+        self.record_input(ReplayInput::Simulate { delta_time });
+
+        // Since we cannot use callbacks to report events to the JavaScript environment,
+        // we need to accumulate events during simulation and return them as an array.
+        let mut events = vec![];
+        let mut player_updated = false;
+
+        let mut frame_proc_result_to_event = |result: Result<FrameProcResult, ()>| {
+            if let Ok(FrameProcResult::InventoryChanged(pos)) = result {
+                events.push(JSEvent::UpdateStructureInventory(pos.x, pos.y))
+            }
+        };
+
+        self.ore_harvesting = (|| {
+            let mut ore_harvesting = self.ore_harvesting?;
+            let mut ret = true;
+            if (ore_harvesting.timer + 1) % ORE_HARVEST_TIME < ore_harvesting.timer {
+                console_debug!(self, "harvesting {:?}...", ore_harvesting.ore_type);
+                let tile = self.tile_at_mut(&ore_harvesting.pos)?;
+                let ore = tile.ore.as_mut()?;
+                let expected_ore = match ore_harvesting.ore_type {
+                    ItemType::IronOre => Ore::Iron,
+                    ItemType::CopperOre => Ore::Copper,
+                    ItemType::CoalOre => Ore::Coal,
                     ItemType::StoneOre => Ore::Stone,
                     _ => return None,
                 };
@@ -1207,9 +1859,8 @@ impl FactorishState {
                         ret = false;
                     }
                     self.player.add_item(&ore_harvesting.ore_type, 1);
-                    self.on_player_update
-                        .call1(&window(), &JsValue::from(self.get_player_inventory().ok()?))
-                        .unwrap_or_else(|_| JsValue::from(true));
+                    self.on_ore_depleted(&ore_harvesting.pos, !ret);
+                    player_updated = true;
                     self.new_popup_text(
                         format!("+1 {:?}", ore_harvesting.ore_type),
                         ore_harvesting.pos.x as f64 * TILE_SIZE,
@@ -1227,27 +1878,48 @@ impl FactorishState {
             }
         })();
 
-        let mut delete_me = vec![];
-        for (i, item) in self.popup_texts.iter_mut().enumerate() {
-            if item.life <= 0 {
-                delete_me.push(i);
-            } else {
-                item.y -= 1.;
-                item.life -= 1;
+        if let Some(job) = self.crafting_queue.first_mut() {
+            job.progress += delta_time * HAND_CRAFT_TIME_SCALE;
+            if job.recipe.recipe_time <= job.progress {
+                let job = self.crafting_queue.remove(0);
+                for (item, count) in &job.recipe.output {
+                    self.player.add_item(item, *count);
+                }
+                player_updated = true;
             }
         }
 
-        for i in delete_me.iter().rev() {
-            self.popup_texts.remove(*i);
+        if self.build_ghosts()? {
+            player_updated = true;
         }
 
-        let start_structures = performance().now();
+        self.update_popup_texts();
+
+        let start_structures = self.clock.now();
         // This is silly way to avoid borrow checker that temporarily move the structures
         // away from self so that they do not claim mutable borrow twice, but it works.
         let mut structures = std::mem::take(&mut self.structures);
+        if self.fluid_networks_dirty {
+            self.fluid_networks = build_fluid_networks(&StructureDynIter::new_all(&mut structures));
+            self.fluid_networks_dirty = false;
+        }
+        simulate_fluid_networks(&self.fluid_networks, &mut structures)?;
+        update_power_ratios(
+            &mut self.power_networks,
+            &StructureDynIter::new_all(&mut structures),
+        );
+        update_signals(
+            &mut self.signal_networks,
+            &StructureDynIter::new_all(&mut structures),
+        );
+        let awake_area = self.awake_area();
         for i in 0..structures.len() {
             let (center, mut dyn_iter) = StructureDynIter::new(&mut structures, i)?;
             if let Some(dynamic) = center.dynamic.as_deref_mut() {
+                center.active = is_awake(&awake_area, &dynamic.bounding_box(), dynamic.idle());
+                if !center.active {
+                    continue;
+                }
                 frame_proc_result_to_event(
                     dynamic.frame_proc(
                         StructureId {
@@ -1260,11 +1932,17 @@ impl FactorishState {
                 );
             }
         }
+        charge_accumulators(
+            &self.power_networks,
+            &mut StructureDynIter::new_all(&mut structures),
+        );
+        self.production_stats.tick();
         self.perf_structures
-            .add(performance().now() - start_structures);
+            .add(self.clock.now() - start_structures);
 
-        let start_index = performance().now();
+        let start_index = self.clock.now();
         let index = &mut self.drop_items_index; //build_index(&self.drop_items);
+        let tile_index = &self.structure_tiles;
         for i in 0..self.drop_items.len() {
             // (id, item) in drop_item_id_iter_mut(&mut self.drop_items) {
             let entry = &self.drop_items[i];
@@ -1283,47 +1961,50 @@ impl FactorishState {
                     continue;
                 }
             }
-            if let Some(item_response_result) = structures
-                .iter_mut()
-                .filter_map(|s| s.dynamic.as_mut())
-                .find(|s| {
-                    s.contains(&Position {
-                        x: item.x.div_euclid(TILE_SIZE_I),
-                        y: item.y.div_euclid(TILE_SIZE_I),
-                    })
-                })
+            let position = item.tile();
+            if let Some(item_response_result) = tile_index
+                .get(&position)
+                .and_then(|i| structures[*i].dynamic.as_mut())
                 .and_then(|structure| structure.item_response(item).ok())
             {
                 match item_response_result.0 {
                     ItemResponse::Move(moved_x, moved_y) => {
-                        if hit_check_with_index(
+                        let (moved_x, moved_y) = match move_destination(
                             &self.drop_items,
                             &index,
-                            moved_x,
-                            moved_y,
-                            Some(id),
+                            &structures,
+                            tile_index,
+                            id,
+                            (moved_x, moved_y),
+                            self.belt_spacing,
                         ) {
-                            continue;
-                        }
-                        let position = Position {
-                            x: moved_x.div_euclid(TILE_SIZE_I),
-                            y: moved_y.div_euclid(TILE_SIZE_I),
-                        };
-                        if let Some(s) = structures
-                            .iter()
-                            .filter_map(|s| s.dynamic.as_deref())
-                            .find(|s| s.contains(&position))
-                        {
-                            if !s.movable() {
+                            Destination::Free(x, y) => (x, y),
+                            Destination::BlockedByItem(x, y) => {
+                                if self.merge_drop_items {
+                                    merge_blocked_item(
+                                        &mut self.drop_items,
+                                        index,
+                                        id,
+                                        x,
+                                        y,
+                                        self.belt_spacing,
+                                    );
+                                }
                                 continue;
                             }
-                        } else {
-                            continue;
-                        }
+                            Destination::Blocked => continue,
+                        };
                         update_index(index, id, item.x, item.y, moved_x, moved_y);
                         let item = self.drop_items[i].item.as_mut().unwrap();
                         item.x = moved_x;
                         item.y = moved_y;
+                        if self.debug_items {
+                            self.item_last_moved.insert(id, self.sim_time);
+                        }
+                    }
+                    // The structure takes one item of a stack at a time
+                    ItemResponse::Consume if 1 < item.count => {
+                        self.drop_items[i].item.as_mut().unwrap().count -= 1;
                     }
                     ItemResponse::Consume => {
                         remove_index(index, id, item.x, item.y);
@@ -1335,30 +2016,62 @@ impl FactorishState {
                 }
             }
         }
-        self.perf_drop_items.add(performance().now() - start_index);
+        self.perf_drop_items.add(self.clock.now() - start_index);
+
+        if self.debug_items {
+            // Forget the items that are consumed or picked up
+            let drop_items = &self.drop_items;
+            self.item_last_moved.retain(|id, _| {
+                drop_items
+                    .get(id.id as usize)
+                    .map_or(false, |entry| entry.gen == id.gen && entry.item.is_some())
+            });
+        }
 
         self.structures = structures;
 
+        self.update_alerts();
+        self.update_pollution();
+        self.update_enemies(delta_time)?;
+        for objective in self.update_objectives() {
+            events.push(JSEvent::ObjectiveCompleted(objective));
+        }
+        for tech in self.research.take_completed() {
+            events.push(JSEvent::ResearchCompleted(tech.to_string()));
+        }
+
         // Actually, taking away, filter and collect is easier than removing expied objects
         // one by one.
         self.temp_ents = std::mem::take(&mut self.temp_ents)
             .into_iter()
             .map(|mut ent| {
-                ent.position.0 += delta_time * 1.5;
-                ent.position.1 -= delta_time * 4.2;
+                match ent.kind {
+                    TempEntKind::Smoke => {
+                        ent.position.0 += delta_time * 1.5;
+                        ent.position.1 -= delta_time * 4.2;
+                    }
+                    TempEntKind::Projectile(velocity) => {
+                        ent.position.0 += velocity.0 * delta_time;
+                        ent.position.1 += velocity.1 * delta_time;
+                    }
+                }
                 ent.life -= delta_time;
                 ent
             })
             .filter(|ent| 0. < ent.life)
             .collect();
 
-        self.perf_simulate.add(performance().now() - start_simulate);
+        self.perf_simulate.add(self.clock.now() - start_simulate);
 
-        // self.drop_items = drop_items;
-        self.update_info();
-        Ok(events.iter().collect())
+        if player_updated {
+            events.push(JSEvent::UpdatePlayerInventory);
+        }
+        Ok(events)
     }
+}
 
+#[wasm_bindgen]
+impl FactorishState {
     fn tile_at(&self, tile: &Position) -> Option<Cell> {
         let (chunk_pos, mp) = tile.div_mod(CHUNK_SIZE as i32);
         let chunk = self.board.get(&chunk_pos)?;
@@ -1369,6 +2082,16 @@ impl FactorishState {
         }
     }
 
+    /// Same as `tile_at`, but the tiles out of the bounds of a limited world are not there.
+    fn tile_in_bounds(&self, tile: &Position) -> Option<Cell> {
+        if let Some(bounds) = self.bounds.as_ref() {
+            if !(0 <= tile.x && tile.x < bounds.width && 0 <= tile.y && tile.y < bounds.height) {
+                return None;
+            }
+        }
+        self.tile_at(tile)
+    }
+
     fn tile_at_mut(&mut self, tile: &Position) -> Option<&mut Cell> {
         let (chunk_pos, mp) = tile.div_mod(CHUNK_SIZE as i32);
         let chunk = self.board.get_mut(&chunk_pos)?;
@@ -1418,23 +2141,25 @@ impl FactorishState {
     // }
 
     fn find_item(&self, pos: &Position) -> Option<(DropItemId, &DropItem)> {
-        drop_item_id_iter(&self.drop_items).find(|(_, item)| {
-            item.x.div_euclid(TILE_SIZE_I) == pos.x && item.y.div_euclid(TILE_SIZE_I) == pos.y
-        })
+        find_item_at(&self.drop_items, pos)
     }
 
+    /// Remove an item of the drop item, which splits a stack of items.
     fn remove_item(&mut self, id: DropItemId) -> Option<DropItem> {
-        if let Some(entry) = self.drop_items.get_mut(id.id as usize) {
-            entry.item.take()
-        } else {
-            None
+        let entry = self.drop_items.get_mut(id.id as usize)?;
+        match entry.item.as_mut() {
+            Some(item) if 1 < item.count => {
+                item.count -= 1;
+                Some(DropItem { count: 1, ..*item })
+            }
+            _ => entry.item.take(),
         }
     }
 
     fn _remove_item_pos(&mut self, pos: &Position) -> Option<DropItem> {
         if let Some(entry) = self.drop_items.iter_mut().find(|item| {
             if let Some(item) = item.item.as_ref() {
-                item.x / 32 == pos.x && item.y / 32 == pos.y
+                item.tile() == *pos
             } else {
                 false
             }
@@ -1445,12 +2170,23 @@ impl FactorishState {
         }
     }
 
-    fn update_info(&self) {
+    fn update_info(&mut self) {
         if let Some(cursor) = self.cursor {
+            let patch_desc = self.ore_patch_desc(&Position::from(&cursor));
             if let Some(ref elem) = self.info_elem {
                 elem.set_inner_html(
                     &if let Some(structure) = self.find_structure_tile(&cursor) {
-                        format!(r#"Type: {}<br>{}"#, structure.name(), structure.desc(&self))
+                        let network_desc = self
+                            .structure_id_at(&cursor)
+                            .and_then(|id| self.power_network_desc(id))
+                            .map(|desc| format!("<br>{}", desc))
+                            .unwrap_or_default();
+                        format!(
+                            r#"Type: {}<br>{}{}"#,
+                            structure.name(),
+                            structure.desc(&self),
+                            network_desc
+                        )
                     } else {
                         let (chunk_pos, mp) =
                             Position::new(cursor[0], cursor[1]).div_mod(CHUNK_SIZE as i32);
@@ -1458,12 +2194,14 @@ impl FactorishState {
                             let cell = &chunk.cells[mp.x as usize + mp.y as usize * CHUNK_SIZE];
                             format!(
                                 r#"Empty tile<br>
-                                {}<br>"#,
+                                {}<br>
+                                {}"#,
                                 if let Some(ore) = cell.ore.as_ref() {
                                     format!("{:?}: {}", ore.0, ore.1)
                                 } else {
                                     "No ore".to_string()
-                                }
+                                },
+                                patch_desc.map(|desc| desc + "<br>").unwrap_or_default()
                             )
                         } else {
                             format!("Empty tile")
@@ -1486,10 +2224,39 @@ impl FactorishState {
                 if let Some(idx) = self.find_structure_tile_idx(cursor) {
                     let (s, others) = StructureDynIter::new(&mut self.structures, idx)
                         .map_err(|_| RotateErr::NotFound)?;
-                    s.dynamic
-                        .as_deref_mut()
-                        .ok_or(RotateErr::NotFound)?
-                        .rotate(&others)?;
+                    let s = s.dynamic.as_deref_mut().ok_or(RotateErr::NotFound)?;
+                    let old_bb = s.bounding_box();
+                    s.rotate(&others)?;
+                    // A structure longer than wide covers other tiles after turning.
+                    remove_structure_tiles(&mut self.structure_tiles, &old_bb, idx);
+                    add_structure_tiles(&mut self.structure_tiles, &s.bounding_box(), idx);
+
+                    // Let the neighbors reconnect, e.g. belts fed by the rotated belt may curve.
+                    let id = StructureId {
+                        id: idx as u32,
+                        gen: self.structures[idx].gen,
+                    };
+                    if let Some(rotated) = self.structures[idx].dynamic.take() {
+                        let res = self
+                            .structures
+                            .iter_mut()
+                            .filter_map(|entry| entry.dynamic.as_deref_mut())
+                            .try_for_each(|s| {
+                                s.on_construction(id, rotated.as_ref(), false)?;
+                                s.on_construction(id, rotated.as_ref(), true)
+                            });
+                        self.structures[idx].dynamic = Some(rotated);
+                        res.map_err(RotateErr::Other)?;
+                    }
+                    // A valve connects to other sides of pipes after turning.
+                    if let Some(position) = self.structures[idx]
+                        .dynamic
+                        .as_deref()
+                        .filter(|s| s.fluid_box().is_some())
+                        .map(|s| *s.position())
+                    {
+                        self.update_fluid_connections(&position);
+                    }
                 }
             }
             Err(RotateErr::NotFound)
@@ -1498,26 +2265,40 @@ impl FactorishState {
 
     /// Insert an object on the board.  It could fail if there's already some object at the position.
     fn new_object(&mut self, pos: &Position, type_: ItemType) -> Result<(), NewObjectErr> {
-        let cell = self.tile_at(pos).ok_or_else(|| NewObjectErr::OutOfMap)?;
-        if cell.water {
-            return Err(NewObjectErr::OnWater);
-        }
-        if let Some(bounds) = self.bounds.as_ref() {
-            if !(0 <= pos.x && pos.x < bounds.width && 0 <= pos.y && pos.y < bounds.height) {
-                return Err(NewObjectErr::OutOfMap);
-            }
-        }
-        if let Some(stru) = self.find_structure_tile(&[pos.x, pos.y]) {
+        self.new_object_pixel(
+            pos.x * TILE_SIZE_I + TILE_SIZE_I / 2,
+            pos.y * TILE_SIZE_I + TILE_SIZE_I / 2,
+            type_,
+        )
+    }
+
+    /// Same as `new_object`, but the item is placed at the given pixel coordinates,
+    /// e.g. on a specific lane of a transport belt.
+    fn new_object_pixel(&mut self, x: i32, y: i32, type_: ItemType) -> Result<(), NewObjectErr> {
+        let pos = &Position::from_pixel(x, y);
+        self.gen_chunk_for_tile(pos);
+        check_tile(self.tile_in_bounds(pos), false)?;
+        // The items on a belt are spaced by the belt density, while the ones on the ground only
+        // need to stay clear of each other.
+        let (x, y, spacing) = if let Some(stru) = self.find_structure_tile(&[pos.x, pos.y]) {
             if !stru.movable() {
                 return Err(NewObjectErr::BlockedByStructure);
             }
-        }
-        let item = DropItem::new(type_, pos.x, pos.y);
+            let (x, y) = stru.item_entry(x, y);
+            (x, y, self.belt_spacing)
+        } else {
+            (x, y, DROP_ITEM_SIZE_I)
+        };
+        let item = DropItem {
+            type_,
+            x,
+            y,
+            count: 1,
+        };
         // return board[c + r * ysize].structure.input(obj);
-        if hit_check(&self.drop_items, item.x, item.y, None) {
+        if hit_check(&self.drop_items, item.x, item.y, spacing, None) {
             return Err(NewObjectErr::BlockedByItem);
         }
-        let (x, y) = (item.x, item.y);
         let entry = self
             .drop_items
             .iter_mut()
@@ -1543,6 +2324,316 @@ impl FactorishState {
         return Ok(());
     }
 
+    /// Drop an item on a free tile around the bounding box if the inventory holds a full stack
+    /// of it, so that a structure without an inserter can keep producing.
+    /// The tiles on the `output_side` are tried first.
+    /// `structures` are needed since the structures are taken out of the state in `frame_proc`.
+    /// @returns true if an item was dropped.
+    pub(crate) fn spill_output(
+        &mut self,
+        bb: &BoundingBox,
+        output_side: Rotation,
+        inventory: &mut Inventory,
+        structures: &StructureDynIter,
+    ) -> bool {
+        let item = match inventory
+            .iter()
+            .find(|(item, count)| stack_size(item) <= **count)
+        {
+            Some((item, _)) => *item,
+            None => return false,
+        };
+        let mut tiles = (bb.y0 - 1..=bb.y1)
+            .flat_map(|y| (bb.x0 - 1..=bb.x1).map(move |x| Position { x, y }))
+            .filter(|pos| !(bb.x0 <= pos.x && pos.x < bb.x1 && bb.y0 <= pos.y && pos.y < bb.y1))
+            .collect::<Vec<_>>();
+        tiles.sort_by_key(|pos| !bb.is_beside(pos, output_side));
+        for pos in tiles {
+            if structures
+                .dyn_iter()
+                .any(|s| s.contains(&pos) && !s.movable())
+            {
+                continue;
+            }
+            if self.new_object(&pos, item).is_ok() {
+                inventory.remove_item(&item);
+                return true;
+            }
+        }
+        false
+    }
+
+    fn rebuild_structure_chunks(&mut self) {
+        self.structure_tiles = build_structure_tile_index(&self.structures);
+        self.structure_chunks.clear();
+        for (i, entry) in self.structures.iter().enumerate() {
+            if let Some(s) = entry.dynamic.as_deref() {
+                self.structure_chunks
+                    .entry(structure_chunk(s.position()))
+                    .or_default()
+                    .push(StructureId {
+                        id: i as u32,
+                        gen: entry.gen,
+                    });
+            }
+        }
+    }
+
+    /// Returns the structures whose bounding boxes overlap the rectangle from (left, top)
+    /// to (right, bottom), both ends inclusive, sorted by their positions in row-major order.
+    fn structures_overlapping(
+        &self,
+        left: i32,
+        top: i32,
+        right: i32,
+        bottom: i32,
+    ) -> Vec<&dyn Structure> {
+        let overlaps = |s: &&dyn Structure| {
+            let bb = s.bounding_box();
+            left < bb.x1 && bb.x0 <= right && top < bb.y1 && bb.y0 <= bottom
+        };
+        // Extend the range to find structures whose origin is in a neighboring chunk, which
+        // may be on either side since some structures extend to the left of or above it.
+        let (cx0, cy0) = (
+            (left - MAX_STRUCTURE_EXTENT).div_euclid(CHUNK_SIZE_I),
+            (top - MAX_STRUCTURE_EXTENT).div_euclid(CHUNK_SIZE_I),
+        );
+        let (cx1, cy1) = (
+            (right + MAX_STRUCTURE_EXTENT).div_euclid(CHUNK_SIZE_I),
+            (bottom + MAX_STRUCTURE_EXTENT).div_euclid(CHUNK_SIZE_I),
+        );
+        let chunks = (cx1 - cx0 + 1) as usize * (cy1 - cy0 + 1) as usize;
+        let mut ret: Vec<&dyn Structure> = if chunks < self.structure_chunks.len() {
+            (cx0..=cx1)
+                .flat_map(|cx| (cy0..=cy1).map(move |cy| Position::new(cx, cy)))
+                .filter_map(|chunk_pos| self.structure_chunks.get(&chunk_pos))
+                .flatten()
+                .filter_map(|id| {
+                    self.structures
+                        .get(id.id as usize)
+                        .filter(|entry| entry.gen == id.gen)?
+                        .dynamic
+                        .as_deref()
+                })
+                .filter(overlaps)
+                .collect()
+        } else {
+            // Scanning all structures is faster than visiting many chunks for a large rectangle.
+            self.structure_iter().filter(overlaps).collect()
+        };
+        ret.sort_by_key(|s| (s.position().y, s.position().x));
+        ret
+    }
+
+    /// Add a structure to the world, connecting power wires and notifying the neighbors
+    /// of the construction. The caller is responsible for clearing the tiles beforehand.
+    fn add_structure(&mut self, mut new_s: Box<dyn Structure>) -> Result<StructureId, JsValue> {
+        let position = *new_s.position();
+        // First, find an empty slot
+        let id = self
+            .structures
+            .iter()
+            .enumerate()
+            .find(|(_, s)| s.dynamic.is_none())
+            .map(|(i, slot)| StructureId {
+                id: i as u32,
+                gen: slot.gen,
+            })
+            .unwrap_or_else(|| StructureId {
+                id: self.structures.len() as u32,
+                gen: 0,
+            });
+
+        for (other_id, structure) in self.structures.iter().enumerate().filter_map(|(i, s)| {
+            Some((
+                StructureId {
+                    id: i as u32,
+                    gen: s.gen,
+                },
+                s.dynamic.as_deref()?,
+            ))
+        }) {
+            if would_connect(new_s.as_ref(), structure) {
+                let new_power_wire = PowerWire(id, other_id);
+                if self.power_wires.iter().any(|p| *p == new_power_wire) {
+                    continue;
+                }
+                console_debug!(self, "power_wires: {}", self.power_wires.len());
+                self.power_wires.push(new_power_wire);
+            }
+        }
+
+        new_s.on_construction_self(id, &StructureDynIter::new_all(&mut self.structures), true)?;
+
+        // Notify structures after a slot has been decided
+        for structure in &mut self.structures {
+            if let Some(s) = structure.dynamic.as_deref_mut() {
+                s.on_construction(id, new_s.as_mut(), true)?;
+            }
+        }
+
+        self.structure_chunks
+            .entry(structure_chunk(&position))
+            .or_default()
+            .push(id);
+        add_structure_tiles(
+            &mut self.structure_tiles,
+            &new_s.bounding_box(),
+            id.id as usize,
+        );
+
+        if id.id < self.structures.len() as u32 {
+            self.structures[id.id as usize].dynamic = Some(new_s);
+
+            console_debug!(
+                self,
+                "Inserted to an empty slot: {}/{}, id: {:?}",
+                self.structures
+                    .iter()
+                    .filter(|s| s.dynamic.is_none())
+                    .count(),
+                self.structures.len(),
+                id
+            );
+        } else {
+            self.structures.push(StructureEntry::new(new_s));
+            console_debug!(
+                self,
+                "Pushed to the end: {}/{}",
+                self.structures
+                    .iter()
+                    .filter(|s| s.dynamic.is_none())
+                    .count(),
+                self.structures.len()
+            );
+        }
+
+        self.power_networks = build_power_networks(
+            &StructureDynIter::new_all(&mut self.structures),
+            &self.power_wires,
+        );
+
+        self.update_fluid_connections(&position);
+
+        let mut chunks = std::mem::take(&mut self.board);
+        self.render_minimap_data_pixel(&mut chunks, &position);
+        self.board = chunks;
+
+        Ok(id)
+    }
+
+    /// Place a structure from the player's inventory, harvesting the structures in the way.
+    /// @returns true if the structure is placed.
+    fn place_structure(
+        &mut self,
+        tool: ItemType,
+        position: &Position,
+        rotation: Rotation,
+    ) -> Result<bool, JsValue> {
+        if let Some(count) = self.player.inventory.get(&tool) {
+            if 1 <= *count {
+                let rotation = match check_placement(&tool, position, rotation, |pos| {
+                    self.tile_in_bounds(pos)
+                }) {
+                    Ok(rotation) => rotation,
+                    // Tell why an offshore pump cannot be placed, since its rule is not obvious.
+                    Err(reason) if tool == ItemType::OffshorePump => return js_err!(reason),
+                    Err(_) => return Ok(false),
+                };
+                let mut new_s = self.new_structure(&tool, position)?;
+                new_s.set_rotation(&rotation).ok();
+                let bbox = new_s.bounding_box();
+                for y in bbox.y0..bbox.y1 {
+                    for x in bbox.x0..bbox.x1 {
+                        self.harvest(&Position { x, y }, !new_s.movable())?;
+                    }
+                }
+                self.add_structure(new_s)?;
+                self.push_undo(UndoAction::Placed {
+                    type_: tool,
+                    position: *position,
+                });
+
+                if let Some(count) = self.player.inventory.get_mut(&tool) {
+                    *count -= 1;
+                }
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Remove the structure at the index into the player's inventory along with its contents.
+    /// The caller is responsible for rebuilding the power networks and fluid connections.
+    /// @returns the position and item type of the removed structure, and the items reclaimed from
+    ///          its inventory.
+    fn take_structure(&mut self, i: usize) -> Result<(Position, ItemType, Inventory), JsValue> {
+        let (mut structure, undo_value) = self.detach_structure(i)?;
+        let item_type = str_to_item(&structure.name()).ok_or_else(|| {
+            JsValue::from_str(&format!("wrong structure name: {:?}", structure.name()))
+        })?;
+        self.player.inventory.add_item(&item_type);
+        let position = *structure.position();
+        let contents = structure.destroy_inventory();
+        for (item_type, count) in &contents {
+            self.player.add_item(item_type, *count)
+        }
+        let mut refund = contents.clone();
+        refund.add_item(&item_type);
+        self.push_undo(UndoAction::Removed {
+            type_: item_type,
+            value: undo_value,
+            refund,
+        });
+        Ok((position, item_type, contents))
+    }
+
+    /// Remove the structure at the index from the world, disconnecting its wires and notifying
+    /// the other structures. Its inventory is left to the caller.
+    /// @returns the removed structure and its serialized value for undo.
+    fn detach_structure(
+        &mut self,
+        i: usize,
+    ) -> Result<(Box<dyn Structure>, serde_json::Value), JsValue> {
+        let mut structure = self.structures[i]
+            .dynamic
+            .take()
+            .ok_or_else(|| js_str!("should be active entity"))?;
+        let gen = self.structures[i].gen;
+        self.structures[i].gen += 1;
+        self.structures[i].damage = 0.;
+        let undo_value = structure_to_json(structure.as_ref())?;
+        for notify_structure in &mut self.structures {
+            if let Some(s) = notify_structure.dynamic.as_deref_mut() {
+                s.on_construction(StructureId { id: i as u32, gen }, structure.as_mut(), false)?;
+            }
+        }
+        let position = *structure.position();
+        if let Some(ids) = self.structure_chunks.get_mut(&structure_chunk(&position)) {
+            ids.retain(|id| id.id != i as u32);
+        }
+        remove_structure_tiles(&mut self.structure_tiles, &structure.bounding_box(), i);
+        self.power_wires = std::mem::take(&mut self.power_wires)
+            .into_iter()
+            .filter(|power_wire| power_wire.0.id != i as u32 && power_wire.1.id != i as u32)
+            .collect();
+        let signal_wire_count = self.signal_wires.len();
+        self.signal_wires
+            .retain(|signal_wire| signal_wire.0.id != i as u32 && signal_wire.1.id != i as u32);
+        if self.signal_wires.len() != signal_wire_count {
+            self.rebuild_signal_networks();
+        }
+        structure.on_construction_self(
+            StructureId { id: i as u32, gen },
+            &StructureDynIter::new_all(&mut self.structures),
+            false,
+        )?;
+        let mut chunks = std::mem::take(&mut self.board);
+        self.render_minimap_data_pixel(&mut chunks, &position);
+        self.board = chunks;
+        Ok((structure, undo_value))
+    }
+
     fn harvest(&mut self, position: &Position, clear_item: bool) -> Result<bool, JsValue> {
         let mut harvested_structure = false;
         let mut popup_text = String::new();
@@ -1555,43 +2646,10 @@ impl FactorishState {
             {
                 continue;
             }
-            let mut structure = self.structures[i]
-                .dynamic
-                .take()
-                .expect("should be active entity");
-            let gen = self.structures[i].gen;
-            self.structures[i].gen += 1;
-            self.player
-                .inventory
-                .add_item(&str_to_item(&structure.name()).ok_or_else(|| {
-                    JsValue::from_str(&format!("wrong structure name: {:?}", structure.name()))
-                })?);
-            popup_text += &format!("+1 {}\n", structure.name());
-            for notify_structure in &mut self.structures {
-                if let Some(s) = notify_structure.dynamic.as_deref_mut() {
-                    s.on_construction(
-                        StructureId { id: i as u32, gen },
-                        structure.as_mut(),
-                        false,
-                    )?;
-                }
-            }
-            let position = *structure.position();
-            self.power_wires = std::mem::take(&mut self.power_wires)
-                .into_iter()
-                .filter(|power_wire| power_wire.0.id != i as u32 && power_wire.1.id != i as u32)
-                .collect();
-            structure.on_construction_self(
-                StructureId { id: i as u32, gen },
-                &StructureDynIter::new_all(&mut self.structures),
-                false,
-            )?;
-            let mut chunks = std::mem::take(&mut self.board);
-            self.render_minimap_data_pixel(&mut chunks, &position);
-            self.board = chunks;
-            for (item_type, count) in structure.destroy_inventory() {
-                popup_text += &format!("+{} {}\n", count, &item_to_str(&item_type));
-                self.player.add_item(&item_type, count)
+            let (position, item_type, refund) = self.take_structure(i)?;
+            popup_text += &format!("+1 {}\n", &item_to_str(&item_type));
+            for (item_type, count) in &refund {
+                popup_text += &format!("+{} {}\n", count, &item_to_str(item_type));
             }
 
             self.power_networks = build_power_networks(
@@ -1599,14 +2657,24 @@ impl FactorishState {
                 &self.power_wires,
             );
 
-            self.update_fluid_connections(&position)?;
+            self.update_fluid_connections(&position);
 
-            self.on_player_update
-                .call1(&window(), &JsValue::from(self.get_player_inventory()?))
+            self.notify_player_update()
                 .unwrap_or_else(|_| JsValue::from(true));
             harvested_structure = true;
         }
         let mut harvested_items = false;
+        if !harvested_structure {
+            if let Some(cell) = self.tile_at_mut(position).filter(|cell| cell.tree) {
+                cell.tree = false;
+                self.player.add_item(&ItemType::Wood, 1);
+                popup_text += &format!("+1 {}\n", &item_to_str(&ItemType::Wood));
+                let mut chunks = std::mem::take(&mut self.board);
+                self.render_minimap_data_pixel(&mut chunks, position);
+                self.board = chunks;
+                harvested_items = true;
+            }
+        }
         if !harvested_structure && clear_item {
             // Pick up dropped items in the cell
             let mut picked_items = Inventory::new();
@@ -1617,15 +2685,13 @@ impl FactorishState {
                     continue;
                 };
 
-                if !(item.x.div_euclid(TILE_SIZE_I) == position.x
-                    && item.y.div_euclid(TILE_SIZE_I) == position.y)
-                {
+                if item.tile() != *position {
                     continue;
                 }
-                let item_type = item.type_;
+                let (item_type, count) = (item.type_, item.count as usize);
                 entry.item = None;
-                picked_items.add_item(&item_type);
-                self.player.add_item(&item_type, 1);
+                picked_items.add_items(&item_type, count);
+                self.player.add_item(&item_type, count);
                 harvested_items = true;
             }
             for (item_type, count) in picked_items {
@@ -1653,11 +2719,12 @@ impl FactorishState {
         Ok(js_sys::Array::of2(
             &JsValue::from(
                 inventory
-                    .iter()
+                    .ordered_items()
+                    .into_iter()
                     .map(|pair| {
                         js_sys::Array::of2(
                             &JsValue::from_str(&item_to_str(&pair.0)),
-                            &JsValue::from_f64(*pair.1 as f64),
+                            &JsValue::from_f64(pair.1 as f64),
                         )
                     })
                     .collect::<js_sys::Array>(),
@@ -1686,6 +2753,9 @@ impl FactorishState {
     }
 
     pub fn select_player_inventory(&mut self, name: &str) -> Result<(), JsValue> {
+        self.record_input(ReplayInput::SelectPlayerInventory {
+            name: name.to_string(),
+        });
         self.selected_item = Some(SelectedItem::PlayerInventory(
             str_to_item(name).ok_or_else(|| JsValue::from_str("Item name not identified"))?,
         ));
@@ -1694,11 +2764,13 @@ impl FactorishState {
 
     /// Deselect is a separate function from select because wasm-bindgen cannot overload Option
     pub fn deselect_player_inventory(&mut self) -> Result<(), JsValue> {
+        self.record_input(ReplayInput::DeselectPlayerInventory);
         self.selected_item = None;
         Ok(())
     }
 
     pub fn open_structure_inventory(&mut self, c: i32, r: i32) -> Result<bool, JsValue> {
+        self.record_input(ReplayInput::OpenStructureInventory { c, r });
         let pos = Position { x: c, y: r };
         if let Some(s) = self.find_structure_tile(&[pos.x, pos.y]) {
             let recipe_enable = !s.get_recipes().is_empty();
@@ -1720,6 +2792,17 @@ impl FactorishState {
         Ok(JsValue::null())
     }
 
+    /// Returns 2-array of the tile coordinates under the mouse cursor, or null if it is not on the map.
+    pub fn get_cursor(&self) -> JsValue {
+        if let Some(cursor) = self.cursor {
+            return JsValue::from(js_sys::Array::of2(
+                &JsValue::from(cursor[0]),
+                &JsValue::from(cursor[1]),
+            ));
+        }
+        JsValue::null()
+    }
+
     /// Returns inventory items in selected tile.
     /// @param c column number.
     /// @param r row number.
@@ -1778,6 +2861,9 @@ impl FactorishState {
     }
 
     pub fn select_structure_inventory(&mut self, name: &str) -> Result<(), JsValue> {
+        self.record_input(ReplayInput::SelectStructureInventory {
+            name: name.to_string(),
+        });
         self.selected_item = Some(SelectedItem::StructInventory(
             self.selected_structure_inventory
                 .ok_or_else(|| js_str!("Structure not selected"))?,
@@ -1786,6 +2872,37 @@ impl FactorishState {
         Ok(())
     }
 
+    /// Returns an array of `{type, x, y, rotation}` of the structures overlapping the rectangle
+    /// from (x0, y0) to (x1, y1), both ends inclusive, sorted by their positions.
+    pub fn structures_in_rect(
+        &self,
+        x0: i32,
+        y0: i32,
+        x1: i32,
+        y1: i32,
+    ) -> Result<JsValue, JsValue> {
+        #[derive(Serialize)]
+        struct StructureSummary<'a> {
+            #[serde(rename = "type")]
+            type_: &'a str,
+            x: i32,
+            y: i32,
+            rotation: Option<Rotation>,
+        }
+
+        let structures = self
+            .structures_overlapping(x0.min(x1), y0.min(y1), x0.max(x1), y0.max(y1))
+            .into_iter()
+            .map(|s| StructureSummary {
+                type_: s.name(),
+                x: s.position().x,
+                y: s.position().y,
+                rotation: s.rotation(),
+            })
+            .collect::<Vec<_>>();
+        JsValue::from_serde(&structures).map_err(|e| js_str!("serialize error: {}", e))
+    }
+
     pub fn get_structure_recipes(&self, c: i32, r: i32) -> Result<JsValue, JsValue> {
         if let Some(structure) = self.find_structure_tile(&[c, r]) {
             // Ok(structure.get_recipes()
@@ -1807,38 +2924,361 @@ impl FactorishState {
             Ok(JsValue::from_serde(
                 &structure
                     .get_recipes()
-                    .into_owned()
                     .into_iter()
                     .map(RecipeSerial::from)
                     .collect::<Vec<_>>(),
             )
             .unwrap())
         } else {
-            Err(JsValue::from_str("structure is not found"))
+            Err(JsValue::from_str("structure is not found"))
+        }
+    }
+
+    pub fn select_recipe(&mut self, c: i32, r: i32, id: &str) -> Result<bool, JsValue> {
+        self.record_input(ReplayInput::SelectRecipe {
+            c,
+            r,
+            id: id.to_string(),
+        });
+        if let Some(structure) = self.find_structure_tile_mut(&[c, r]) {
+            structure.select_recipe(id)
+        } else {
+            Err(JsValue::from_str("Structure is not found"))
+        }
+    }
+
+    /// Returns the list of recipes that the player can craft by hand.
+    pub fn get_craft_recipes(&self) -> Result<JsValue, JsValue> {
+        JsValue::from_serde(
+            &RECIPES
+                .for_structure("Player")
+                .map(RecipeSerial::from)
+                .collect::<Vec<_>>(),
+        )
+        .map_err(|e| js_str!("serialize error: {}", e))
+    }
+
+    /// Try to enqueue a hand crafting job, consuming the ingredients from the player inventory.
+    /// @returns false if the player does not have enough ingredients.
+    pub fn queue_craft(&mut self, recipe_index: usize) -> Result<bool, JsValue> {
+        self.record_input(ReplayInput::QueueCraft { recipe_index });
+        let recipe = &RECIPES
+            .for_structure("Player")
+            .nth(recipe_index)
+            .ok_or_else(|| js_str!("recipes index out of bound {:?}", recipe_index))?
+            .recipe;
+        if !recipe
+            .input
+            .iter()
+            .all(|(item, count)| *count <= self.player.inventory.count_item(item))
+        {
+            return Ok(false);
+        }
+        for (item, count) in &recipe.input {
+            self.player.inventory.remove_items(item, *count);
+        }
+        self.crafting_queue.push(CraftJob {
+            recipe: recipe.clone(),
+            progress: 0.,
+        });
+        self.notify_player_update()?;
+        Ok(true)
+    }
+
+    /// Cancel a hand crafting job and refund its ingredients to the player inventory.
+    pub fn cancel_craft(&mut self, index: usize) -> Result<bool, JsValue> {
+        self.record_input(ReplayInput::CancelCraft { index });
+        if self.crafting_queue.len() <= index {
+            return Ok(false);
+        }
+        let job = self.crafting_queue.remove(index);
+        for (item, count) in &job.recipe.input {
+            self.player.add_item(item, *count);
+        }
+        self.notify_player_update()?;
+        Ok(true)
+    }
+
+    /// Returns an array of `{output, progress}` for each job in the crafting queue,
+    /// where progress is in the range [0, 1].
+    pub fn get_crafting_queue(&self) -> Result<JsValue, JsValue> {
+        JsValue::from_serde(
+            &self
+                .crafting_queue
+                .iter()
+                .map(CraftJobSerial::from)
+                .collect::<Vec<_>>(),
+        )
+        .map_err(|e| js_str!("serialize error: {}", e))
+    }
+
+    /// Configure a splitter at the given tile.
+    /// @param input_priority "left", "right" or "none"
+    /// @param output_priority "left", "right" or "none"
+    /// @param filter name of the item type to filter, or undefined to disable filtering
+    /// @param filter_side "left" or "right", the side that filtered items go
+    pub fn set_splitter_config(
+        &mut self,
+        c: i32,
+        r: i32,
+        input_priority: &str,
+        output_priority: &str,
+        filter: Option<String>,
+        filter_side: &str,
+    ) -> Result<(), JsValue> {
+        self.record_input(ReplayInput::SetSplitterConfig {
+            c,
+            r,
+            input_priority: input_priority.to_string(),
+            output_priority: output_priority.to_string(),
+            filter: filter.clone(),
+            filter_side: filter_side.to_string(),
+        });
+        let input_priority = SplitterSide::from_str(input_priority)?;
+        let output_priority = SplitterSide::from_str(output_priority)?;
+        let filter = if let Some(filter) = filter {
+            Some((
+                str_to_item(&filter).ok_or_else(|| js_str!("Item name not valid: {}", filter))?,
+                SplitterSide::from_str(filter_side)?
+                    .ok_or_else(|| js_str!("Filter side must be left or right"))?,
+            ))
+        } else {
+            None
+        };
+        let config = self
+            .find_structure_tile_mut(&[c, r])
+            .ok_or_else(|| js_str!("Structure is not found"))?
+            .splitter_config_mut()
+            .ok_or_else(|| js_str!("Structure is not a splitter"))?;
+        config.input_priority = input_priority;
+        config.output_priority = output_priority;
+        config.filter = filter;
+        Ok(())
+    }
+
+    pub fn set_inserter_stack_size(&mut self, c: i32, r: i32, n: usize) -> Result<(), JsValue> {
+        self.record_input(ReplayInput::SetInserterStackSize { c, r, n });
+        self.find_structure_tile_mut(&[c, r])
+            .ok_or_else(|| js_str!("Structure is not found"))?
+            .set_stack_size(n)
+    }
+
+    /// Let a furnace or an assembler drop its outputs on the ground around it, a stack at a
+    /// time, when they are not taken out. It is off by default.
+    pub fn set_output_to_ground(&mut self, c: i32, r: i32, enable: bool) -> Result<(), JsValue> {
+        self.record_input(ReplayInput::SetOutputToGround { c, r, enable });
+        self.find_structure_tile_mut(&[c, r])
+            .ok_or_else(|| js_str!("Structure is not found"))?
+            .set_output_to_ground(enable)
+    }
+
+    /// Returns the power in kilowatts that the steam engine at the tile generates.
+    pub fn get_steam_engine_output(&self, c: i32, r: i32) -> Result<f64, JsValue> {
+        self.find_structure_tile(&[c, r])
+            .ok_or_else(|| js_str!("Structure is not found"))?
+            .power_generation()
+            .ok_or_else(|| js_str!("Structure is not a steam engine"))
+    }
+
+    /// Make the inserter stop when the chest it inserts into has `max` or more of the item.
+    /// Passing an empty item name removes the limit.
+    pub fn set_inserter_chest_limit(
+        &mut self,
+        c: i32,
+        r: i32,
+        item: &str,
+        max: usize,
+    ) -> Result<(), JsValue> {
+        self.record_input(ReplayInput::SetInserterChestLimit {
+            c,
+            r,
+            item: item.to_string(),
+            max,
+        });
+        let limit = if item.is_empty() {
+            None
+        } else {
+            Some(ChestLimit {
+                item: str_to_item(item).ok_or_else(|| js_str!("Item name not valid: {}", item))?,
+                max,
+            })
+        };
+        self.find_structure_tile_mut(&[c, r])
+            .ok_or_else(|| js_str!("Structure is not found"))?
+            .set_chest_limit(limit)
+    }
+
+    /// Set the desired count of an item for a requester chest. Setting 0 removes the request.
+    pub fn set_chest_request(
+        &mut self,
+        c: i32,
+        r: i32,
+        item: &str,
+        count: usize,
+    ) -> Result<(), JsValue> {
+        self.record_input(ReplayInput::SetChestRequest {
+            c,
+            r,
+            item: item.to_string(),
+            count,
+        });
+        let item_type =
+            str_to_item(item).ok_or_else(|| js_str!("Item name not valid: {}", item))?;
+        let requests = self
+            .find_structure_tile_mut(&[c, r])
+            .ok_or_else(|| js_str!("Structure is not found"))?
+            .chest_requests_mut()
+            .ok_or_else(|| js_str!("Structure is not a requester chest"))?;
+        if count == 0 {
+            requests.remove(&item_type);
+        } else {
+            requests.insert(item_type, count);
         }
+        Ok(())
     }
 
-    pub fn select_recipe(&mut self, c: i32, r: i32, index: usize) -> Result<bool, JsValue> {
-        if let Some(structure) = self.find_structure_tile_mut(&[c, r]) {
-            structure.select_recipe(index)
+    /// Reserve a slot of a chest for an item type, so that inserters and belts put only
+    /// the item in it.
+    /// @param item_name the item type, or an empty string to free the slot for any item.
+    pub fn set_chest_slot_filter(
+        &mut self,
+        c: i32,
+        r: i32,
+        slot: usize,
+        item_name: &str,
+    ) -> Result<(), JsValue> {
+        self.record_input(ReplayInput::SetChestSlotFilter {
+            c,
+            r,
+            slot,
+            item_name: item_name.to_string(),
+        });
+        let item = if item_name.is_empty() {
+            None
         } else {
-            Err(JsValue::from_str("Structure is not found"))
+            Some(
+                str_to_item(item_name)
+                    .ok_or_else(|| js_str!("Item name not valid: {}", item_name))?,
+            )
+        };
+        self.find_structure_tile_mut(&[c, r])
+            .ok_or_else(|| js_str!("Structure is not found"))?
+            .set_slot_filter(slot, item)
+    }
+
+    /// Returns an array of `[slot, item name]` of the reserved slots of a chest.
+    pub fn get_chest_slot_filters(&self, c: i32, r: i32) -> Result<js_sys::Array, JsValue> {
+        let filters = self
+            .find_structure_tile(&[c, r])
+            .ok_or_else(|| js_str!("Structure is not found"))?
+            .slot_filters()
+            .ok_or_else(|| js_str!("Structure does not have slot filters"))?;
+        Ok(filters
+            .iter()
+            .map(|(slot, item)| {
+                js_sys::Array::of2(
+                    &JsValue::from_f64(*slot as f64),
+                    &JsValue::from_str(&item_to_str(item)),
+                )
+            })
+            .collect())
+    }
+
+    /// Dedicate the fluid boxes of a pipe-like structure to a fluid type, so that they reject
+    /// the other fluids from neighbors. Fluid boxes that are only inputs or only outputs,
+    /// e.g. the water input of a boiler, keep their own filters.
+    /// @param fluid_type "Water", "Steam", or an empty string to remove the filter.
+    pub fn set_fluid_filter(&mut self, c: i32, r: i32, fluid_type: &str) -> Result<(), JsValue> {
+        self.record_input(ReplayInput::SetFluidFilter {
+            c,
+            r,
+            fluid_type: fluid_type.to_string(),
+        });
+        let filter = if fluid_type.is_empty() {
+            None
+        } else {
+            Some(
+                fluid_type
+                    .parse::<FluidType>()
+                    .map_err(|e| JsValue::from_str(&e))?,
+            )
+        };
+        let fluid_boxes = self
+            .find_structure_tile_mut(&[c, r])
+            .ok_or_else(|| js_str!("Structure is not found"))?
+            .fluid_box_mut()
+            .ok_or_else(|| js_str!("Structure does not have a fluid box"))?;
+        let mut found = false;
+        for fluid_box in fluid_boxes {
+            if fluid_box.input_enable && fluid_box.output_enable {
+                fluid_box.filter = filter;
+                found = true;
+            }
         }
+        if !found {
+            return js_err!("Structure does not have a configurable fluid box");
+        }
+        Ok(())
+    }
+
+    /// Returns the name of the fluid type that the structure's fluid box accepts,
+    /// or null if it accepts any.
+    pub fn get_fluid_filter(&self, c: i32, r: i32) -> Result<JsValue, JsValue> {
+        let fluid_boxes = self
+            .find_structure_tile(&[c, r])
+            .ok_or_else(|| js_str!("Structure is not found"))?
+            .fluid_box()
+            .ok_or_else(|| js_str!("Structure does not have a fluid box"))?;
+        Ok(fluid_boxes
+            .into_iter()
+            .find(|fluid_box| fluid_box.input_enable && fluid_box.output_enable)
+            .and_then(|fluid_box| fluid_box.filter)
+            .map_or(JsValue::null(), |filter| js_str!("{:?}", filter)))
     }
 
     fn move_inventory_item(src: &mut Inventory, dst: &mut Inventory, item_type: &ItemType) -> bool {
         if let Some(src_item) = src.remove(item_type) {
-            dst.add_items(item_type, src_item);
-            true
+            // Leave the items that did not fit in the source inventory.
+            let overflow = dst.add_items(item_type, src_item);
+            if 0 < overflow {
+                src.add_items(item_type, overflow);
+            }
+            overflow < src_item
         } else {
             false
         }
     }
 
+    /// Returns the time of day in the range [0, 1), where 0 is midnight and 0.5 is noon.
+    /// It is always noon if the day/night cycle is disabled.
+    pub fn get_time_of_day(&self) -> f64 {
+        let day_length = self.terrain_params.day_length;
+        if 0. < day_length {
+            // Start the game in the morning
+            (self.sim_time / day_length + 0.3).fract()
+        } else {
+            0.5
+        }
+    }
+
+    /// Brightness of the world in the range [MIN_BRIGHTNESS, 1]. It stays at the maximum
+    /// for the most of the day and drops smoothly in the night.
+    fn brightness(&self) -> f64 {
+        let sun = 0.5 - 0.5 * (self.get_time_of_day() * 2. * std::f64::consts::PI).cos();
+        MIN_BRIGHTNESS + (1. - MIN_BRIGHTNESS) * (sun * 2.).min(1.)
+    }
+
     pub fn set_debug_bbox(&mut self, value: bool) {
         self.debug_bbox = value;
     }
 
+    /// Tint the items by how long they have been stuck, green for moving and red for jammed.
+    /// Items that have not moved since the overlay is turned on count as jammed.
+    pub fn set_debug_items(&mut self, value: bool) {
+        self.debug_items = value;
+        self.item_last_moved.clear();
+    }
+
     pub fn set_debug_fluidbox(&mut self, value: bool) {
         self.debug_fluidbox = value;
     }
@@ -1847,6 +3287,12 @@ impl FactorishState {
         self.debug_power_network = value;
     }
 
+    /// Tint the structures with a color for each power network, so that split grids stand out.
+    /// Structures without power connections are left as they are.
+    pub fn set_debug_power_tint(&mut self, value: bool) {
+        self.debug_power_tint = value;
+    }
+
     /// Move inventory items between structure and player
     /// @param to_player whether the movement happen towards player
     /// @param inventory_type a string indicating type of the inventory in the structure
@@ -1855,7 +3301,21 @@ impl FactorishState {
         to_player: bool,
         inventory_type: JsValue,
     ) -> Result<bool, JsValue> {
-        let inventory_type = InventoryType::try_from(inventory_type)?;
+        self.move_selected_items(to_player, InventoryType::try_from(inventory_type)?)
+    }
+}
+
+impl FactorishState {
+    /// Same as `move_selected_inventory_item` with the parsed inventory type, which can be replayed.
+    fn move_selected_items(
+        &mut self,
+        to_player: bool,
+        inventory_type: InventoryType,
+    ) -> Result<bool, JsValue> {
+        self.record_input(ReplayInput::MoveSelectedInventoryItem {
+            to_player,
+            inventory_type,
+        });
         let pos = if let Some(pos) = self.selected_structure_inventory {
             pos
         } else {
@@ -1920,8 +3380,7 @@ impl FactorishState {
                     // console_log!("moving {:?}", item_name);
                     if let Some(item_name) = item_name {
                         if FactorishState::move_inventory_item(src, dst, &item_name) {
-                            self.on_player_update
-                                .call1(&window(), &JsValue::from(self.get_player_inventory()?))?;
+                            self.notify_player_update()?;
                             return Ok(true);
                         }
                     }
@@ -1930,11 +3389,84 @@ impl FactorishState {
         }
         Ok(false)
     }
+}
+
+#[wasm_bindgen]
+impl FactorishState {
+    /// Sort the player inventory if is_player is true, otherwise the inventories
+    /// of the structure at (c, r).
+    pub fn sort_inventory(&mut self, is_player: bool, c: i32, r: i32) -> Result<(), JsValue> {
+        self.record_input(ReplayInput::SortInventory { is_player, c, r });
+        if is_player {
+            self.player.inventory.sort();
+        } else {
+            let structure = self
+                .find_structure_tile_mut(&[c, r])
+                .ok_or_else(|| js_str!("structure not found at position"))?;
+            for is_input in [true, false] {
+                if let Some(inventory) = structure.inventory_mut(is_input) {
+                    inventory.sort();
+                }
+            }
+        }
+        self.notify_player_update()?;
+        Ok(())
+    }
+
+    /// Move all the player's items of the types that the chest at (c, r) already has into it.
+    /// Other structures with inventories, e.g. furnaces, are not stacked into.
+    /// @returns the number of moved items.
+    pub fn quick_stack_to_chest(&mut self, c: i32, r: i32) -> Result<usize, JsValue> {
+        self.record_input(ReplayInput::QuickStackToChest { c, r });
+        let position = Position::new(c, r);
+        let chest = self
+            .structures
+            .iter_mut()
+            .filter_map(|entry| entry.dynamic.as_deref_mut())
+            .find(|d| d.contains(&position))
+            .filter(|d| d.inventory(true).is_some())
+            .ok_or_else(|| js_str!("chest not found at position"))?;
+        let items = chest
+            .inventory(true)
+            .map(|inventory| inventory.keys().copied().collect::<Vec<_>>())
+            .unwrap_or_default();
+        let mut moved = 0;
+        for item in items {
+            // Add one by one while the chest accepts them, which respects the slot filters
+            // and the full stacks.
+            while 0 < self.player.inventory.count_item(&item) && chest.can_input(&item) {
+                if let Some(inventory) = chest.inventory_mut(true) {
+                    inventory.add_item(&item);
+                } else {
+                    break;
+                }
+                self.player.inventory.remove_item(&item);
+                moved += 1;
+            }
+        }
+        if 0 < moved {
+            self.notify_player_update()?;
+        }
+        Ok(moved)
+    }
 
+    /// Creates a structure to be placed, which fails if the item is locked.
     fn new_structure(
         &self,
         tool: &ItemType,
         cursor: &Position,
+    ) -> Result<Box<dyn Structure>, JsValue> {
+        if !self.is_unlocked(tool) {
+            return js_err!("{} is locked", item_to_str(tool));
+        }
+        self.create_structure(tool, cursor)
+    }
+
+    /// Creates a structure regardless of the unlocked items, e.g. for rendering previews.
+    fn create_structure(
+        &self,
+        tool: &ItemType,
+        cursor: &Position,
     ) -> Result<Box<dyn Structure>, JsValue> {
         Ok(match tool {
             ItemType::TransportBelt => {
@@ -1942,16 +3474,27 @@ impl FactorishState {
             }
             ItemType::Inserter => Box::new(Inserter::new(cursor.x, cursor.y, self.tool_rotation)),
             ItemType::Splitter => Box::new(Splitter::new(cursor.x, cursor.y, self.tool_rotation)),
+            ItemType::Balancer => Box::new(Balancer::new(cursor.x, cursor.y, self.tool_rotation)),
             ItemType::OreMine => Box::new(OreMine::new(cursor.x, cursor.y, self.tool_rotation)),
+            ItemType::ElectricOreMine => {
+                Box::new(ElectricOreMine::new(cursor.x, cursor.y, self.tool_rotation))
+            }
             ItemType::Chest => Box::new(Chest::new(cursor)),
+            ItemType::RequesterChest => Box::new(RequesterChest::new(cursor)),
             ItemType::Furnace => Box::new(Furnace::new(cursor)),
+            ItemType::ElectricFurnace => Box::new(ElectricFurnace::new(cursor)),
             ItemType::Assembler => Box::new(Assembler::new(cursor)),
             ItemType::Boiler => Box::new(Boiler::new(cursor)),
             ItemType::WaterWell => Box::new(WaterWell::new(cursor)),
             ItemType::OffshorePump => Box::new(OffshorePump::new(cursor)),
             ItemType::Pipe => Box::new(Pipe::new(cursor)),
+            ItemType::Valve => Box::new(Valve::new(cursor, self.tool_rotation)),
             ItemType::SteamEngine => Box::new(SteamEngine::new(cursor)),
             ItemType::ElectPole => Box::new(ElectPole::new(cursor)),
+            ItemType::Accumulator => Box::new(Accumulator::new(cursor)),
+            ItemType::Lab => Box::new(Lab::new(cursor)),
+            ItemType::Lamp => Box::new(Lamp::new(cursor)),
+            ItemType::GunTurret => Box::new(GunTurret::new(cursor)),
             _ => return js_err!("Can't make a structure from {:?}", tool),
         })
     }
@@ -1986,9 +3529,19 @@ impl FactorishState {
             }
             ItemType::Inserter => Box::new(map_err(serde_json::from_value::<Inserter>(payload))?),
             ItemType::Splitter => Box::new(map_err(serde_json::from_value::<Splitter>(payload))?),
+            ItemType::Balancer => Box::new(map_err(serde_json::from_value::<Balancer>(payload))?),
             ItemType::OreMine => Box::new(map_err(serde_json::from_value::<OreMine>(payload))?),
+            ItemType::ElectricOreMine => {
+                Box::new(map_err(serde_json::from_value::<ElectricOreMine>(payload))?)
+            }
             ItemType::Chest => Box::new(map_err(serde_json::from_value::<Chest>(payload))?),
+            ItemType::RequesterChest => {
+                Box::new(map_err(serde_json::from_value::<RequesterChest>(payload))?)
+            }
             ItemType::Furnace => Box::new(map_err(serde_json::from_value::<Furnace>(payload))?),
+            ItemType::ElectricFurnace => {
+                Box::new(map_err(serde_json::from_value::<ElectricFurnace>(payload))?)
+            }
             ItemType::Assembler => Box::new(map_err(serde_json::from_value::<Assembler>(payload))?),
             ItemType::Boiler => Box::new(map_err(serde_json::from_value::<Boiler>(payload))?),
             ItemType::WaterWell => Box::new(map_err(serde_json::from_value::<WaterWell>(payload))?),
@@ -1996,31 +3549,46 @@ impl FactorishState {
                 Box::new(map_err(serde_json::from_value::<OffshorePump>(payload))?)
             }
             ItemType::Pipe => Box::new(map_err(serde_json::from_value::<Pipe>(payload))?),
+            ItemType::Valve => Box::new(map_err(serde_json::from_value::<Valve>(payload))?),
             ItemType::SteamEngine => {
                 Box::new(map_err(serde_json::from_value::<SteamEngine>(payload))?)
             }
             ItemType::ElectPole => Box::new(map_err(serde_json::from_value::<ElectPole>(payload))?),
+            ItemType::Accumulator => {
+                Box::new(map_err(serde_json::from_value::<Accumulator>(payload))?)
+            }
+            ItemType::Lab => Box::new(map_err(serde_json::from_value::<Lab>(payload))?),
+            ItemType::Lamp => Box::new(map_err(serde_json::from_value::<Lamp>(payload))?),
+            ItemType::GunTurret => Box::new(map_err(serde_json::from_value::<GunTurret>(payload))?),
             _ => return js_err!("Can't make a structure from {:?}", type_str),
         })
     }
 
     pub fn mouse_down(&mut self, pos: &[f64], button: i32) -> Result<JsValue, JsValue> {
+        self.record_input(ReplayInput::MouseDown {
+            pos: pos.to_vec(),
+            button,
+        });
         if pos.len() < 2 {
             return Err(JsValue::from_str("position must have 2 elements"));
         }
-        let cursor = Position {
-            x: (pos[0] / self.viewport.scale / TILE_SIZE - self.viewport.x).floor() as i32,
-            y: (pos[1] / self.viewport.scale / TILE_SIZE - self.viewport.y).floor() as i32,
-        };
-
-        console_log!("mouse_down: {}, {}, button: {}", cursor.x, cursor.y, button);
+        let cursor = self.viewport.screen_to_tile((pos[0], pos[1]));
+
+        console_debug!(
+            self,
+            "mouse_down: {}, {}, button: {}",
+            cursor.x,
+            cursor.y,
+            button
+        );
+        if button == 0 && self.get_selected_tool_or_item_opt() == Some(ItemType::TransportBelt) {
+            self.drag_path = Some(vec![cursor]);
+        }
         if button == 2
             && self.find_structure_tile(&[cursor.x, cursor.y]).is_none()
+            && self.ghost_at(&cursor).is_none()
             // Let the player pick up drop items before harvesting ore below.
-            && !drop_item_iter(&self.drop_items).any(|item| {
-                item.x / TILE_SIZE_I == pos[0] as i32 / TILE_SIZE_I
-                    && item.y / TILE_SIZE_I == pos[1] as i32 / TILE_SIZE_I
-            })
+            && find_item_at(&self.drop_items, &cursor).is_none()
         {
             if let Some(tile) = self.tile_at(&cursor) {
                 if let Some(ore_type) = tile.get_ore_type() {
@@ -2037,140 +3605,62 @@ impl FactorishState {
     }
 
     pub fn mouse_up(&mut self, pos: &[f64], button: i32) -> Result<JsValue, JsValue> {
+        self.record_input(ReplayInput::MouseUp {
+            pos: pos.to_vec(),
+            button,
+        });
         if pos.len() < 2 {
             return Err(JsValue::from_str("position must have 2 elements"));
         }
-        let cursor = Position {
-            x: (pos[0] / self.viewport.scale / TILE_SIZE - self.viewport.x).floor() as i32,
-            y: (pos[1] / self.viewport.scale / TILE_SIZE - self.viewport.y).floor() as i32,
-        };
+        let cursor = self.viewport.screen_to_tile((pos[0], pos[1]));
         let mut events = vec![];
 
+        let drag_path = self
+            .drag_path
+            .take()
+            .filter(|path| 1 < path.len())
+            .map(|path| drag_path_rotations(&path));
+
         if button == 0 {
             if let Some(selected_tool) = self.get_selected_tool_or_item_opt() {
-                let cell = self.tile_at(&cursor);
-                if let Some((count, cell)) =
-                    self.player.inventory.get(&selected_tool).zip(cell.as_ref())
-                {
-                    if 1 <= *count && cell.water ^ (selected_tool != ItemType::OffshorePump) {
-                        let mut new_s = self.new_structure(&selected_tool, &cursor)?;
-                        let bbox = new_s.bounding_box();
-                        for y in bbox.y0..bbox.y1 {
-                            for x in bbox.x0..bbox.x1 {
-                                self.harvest(&Position { x, y }, !new_s.movable())?;
-                            }
-                        }
-                        // let connections = new_s.connection(self, &Ref(&self.structures));
-                        // console_log!(
-                        //     "Connection recalculated for self {:?}: {:?}",
-                        //     new_s.position(),
-                        //     connections
-                        // );
-                        // if let Some(fluid_boxes) = new_s.fluid_box_mut() {
-                        //     for fbox in fluid_boxes {
-                        //         fbox.connect_to = connections;
-                        //     }
-                        // }
-
-                        // First, find an empty slot
-                        let id = self
-                            .structures
-                            .iter()
-                            .enumerate()
-                            .find(|(_, s)| s.dynamic.is_none())
-                            .map(|(i, slot)| StructureId {
-                                id: i as u32,
-                                gen: slot.gen,
-                            })
-                            .unwrap_or_else(|| StructureId {
-                                id: self.structures.len() as u32,
-                                gen: 0,
-                            });
-
-                        for (other_id, structure) in
-                            self.structures.iter().enumerate().filter_map(|(i, s)| {
-                                Some((
-                                    StructureId {
-                                        id: i as u32,
-                                        gen: s.gen,
-                                    },
-                                    s.dynamic.as_deref()?,
-                                ))
-                            })
-                        {
-                            if (new_s.power_sink() && structure.power_source()
-                                || new_s.power_source() && structure.power_sink())
-                                && new_s.position().distance(structure.position())
-                                    <= new_s.wire_reach().min(structure.wire_reach()) as i32
-                            {
-                                let new_power_wire = PowerWire(id, other_id);
-                                if self.power_wires.iter().any(|p| *p == new_power_wire) {
-                                    continue;
-                                }
-                                console_log!("power_wires: {}", self.power_wires.len());
-                                self.power_wires.push(new_power_wire);
-                            }
-                        }
-
-                        new_s.on_construction_self(
-                            id,
-                            &StructureDynIter::new_all(&mut self.structures),
-                            true,
-                        )?;
-
-                        // Notify structures after a slot has been decided
-                        for structure in &mut self.structures {
-                            if let Some(s) = structure.dynamic.as_deref_mut() {
-                                s.on_construction(id, new_s.as_mut(), true)?;
-                            }
-                        }
-
-                        if id.id < self.structures.len() as u32 {
-                            self.structures[id.id as usize].dynamic = Some(new_s);
-
-                            console_log!(
-                                "Inserted to an empty slot: {}/{}, id: {:?}",
-                                self.structures
-                                    .iter()
-                                    .filter(|s| s.dynamic.is_none())
-                                    .count(),
-                                self.structures.len(),
-                                id
-                            );
-                        } else {
-                            self.structures.push(StructureEntry {
-                                gen: 0,
-                                dynamic: Some(new_s),
-                            });
-                            console_log!(
-                                "Pushed to the end: {}/{}",
-                                self.structures
-                                    .iter()
-                                    .filter(|s| s.dynamic.is_none())
-                                    .count(),
-                                self.structures.len()
-                            );
+                if !self.is_unlocked(&selected_tool) {
+                    self.new_popup_text(
+                        format!("{} is locked", item_to_str(&selected_tool)),
+                        cursor.x as f64 * TILE_SIZE,
+                        cursor.y as f64 * TILE_SIZE,
+                    );
+                } else if let Some(drag_path) = drag_path {
+                    let mut placed = false;
+                    for (position, rotation) in drag_path {
+                        if self.player.inventory.count_item(&selected_tool) == 0 {
+                            break;
                         }
-
-                        self.power_networks = build_power_networks(
-                            &StructureDynIter::new_all(&mut self.structures),
-                            &self.power_wires,
-                        );
-
-                        self.update_fluid_connections(&cursor)?;
-
-                        let mut chunks = std::mem::take(&mut self.board);
-                        self.render_minimap_data_pixel(&mut chunks, &cursor);
-                        self.board = chunks;
-
-                        if let Some(count) = self.player.inventory.get_mut(&selected_tool) {
-                            *count -= 1;
+                        let mut new_s = self.new_structure(&selected_tool, &position)?;
+                        new_s.set_rotation(&rotation).ok();
+                        if self.is_placeable(new_s.as_ref(), &selected_tool).is_err() {
+                            continue;
                         }
-                        self.on_player_update
-                            .call1(&window(), &JsValue::from(self.get_player_inventory()?))
+                        placed |= self.place_structure(selected_tool, &position, rotation)?;
+                    }
+                    if placed {
+                        self.notify_player_update()
                             .unwrap_or_else(|_| JsValue::from(true));
                         events.push(JsValue::from_serde(&JSEvent::UpdatePlayerInventory).unwrap());
                     }
+                } else if self.player.inventory.count_item(&selected_tool) == 0 {
+                    // Plan the construction to build it later when the player gets the item.
+                    if let Err(reason) = self.add_ghost(Ghost {
+                        type_: selected_tool,
+                        position: cursor,
+                        rotation: Some(self.tool_rotation),
+                        recipe: None,
+                    })? {
+                        console_log!(self, "Cannot place a ghost: {}", reason);
+                    }
+                } else if self.place_structure(selected_tool, &cursor, self.tool_rotation)? {
+                    self.notify_player_update()
+                        .unwrap_or_else(|_| JsValue::from(true));
+                    events.push(JsValue::from_serde(&JSEvent::UpdatePlayerInventory).unwrap());
                 }
             } else if let Some(structure) = self.find_structure_tile(&[cursor.x, cursor.y]) {
                 if structure.inventory(true).is_some()
@@ -2178,8 +3668,10 @@ impl FactorishState {
                     || structure.burner_inventory().is_some()
                 {
                     // Select clicked structure
-                    console_log!("opening inventory at {:?}", cursor);
-                    if let Ok(recipe_enable) = self.open_structure_inventory(cursor.x, cursor.y) {
+                    console_debug!(self, "opening inventory at {:?}", cursor);
+                    if let Ok(recipe_enable) =
+                        self.unrecorded(|this| this.open_structure_inventory(cursor.x, cursor.y))
+                    {
                         // self.on_show_inventory.call0(&window()).unwrap();
                         events.push(
                             JsValue::from_serde(&JSEvent::ShowInventoryAt {
@@ -2196,6 +3688,8 @@ impl FactorishState {
         } else if button == 2 {
             if self.ore_harvesting.is_some() {
                 self.ore_harvesting = None;
+            } else if let Some(i) = self.ghost_at(&cursor) {
+                self.ghosts.remove(i);
             } else {
                 // Right click means explicit cleanup, so we pick up items no matter what.
                 self.harvest(&cursor, true)?;
@@ -2203,19 +3697,18 @@ impl FactorishState {
             }
         }
 
-        console_log!("mouse_up: {}, {}", cursor.x, cursor.y);
+        console_debug!(self, "mouse_up: {}, {}", cursor.x, cursor.y);
         self.update_info();
         Ok(JsValue::from(events.iter().collect::<js_sys::Array>()))
     }
 
     pub fn mouse_move(&mut self, pos: &[f64]) -> Result<(), JsValue> {
+        self.record_input(ReplayInput::MouseMove { pos: pos.to_vec() });
         if pos.len() < 2 {
             return Err(JsValue::from_str("position must have 2 elements"));
         }
-        let cursor = [
-            (pos[0] / self.viewport.scale / TILE_SIZE - self.viewport.x).floor() as i32,
-            (pos[1] / self.viewport.scale / TILE_SIZE - self.viewport.y).floor() as i32,
-        ];
+        let cursor = self.viewport.screen_to_tile((pos[0], pos[1]));
+        let cursor = [cursor.x, cursor.y];
         if let Some(bounds) = self.bounds.as_ref() {
             if cursor[0] < 0
                 || bounds.width as i32 <= cursor[0]
@@ -2228,44 +3721,54 @@ impl FactorishState {
             }
         }
         self.cursor = Some(cursor);
+        if let Some(path) = self.drag_path.as_mut() {
+            // Fill the gap between the tiles with a 4-connected line, since the mouse can
+            // skip tiles if it moves fast.
+            let target = Position::from(&cursor);
+            while let Some(last) = path.last().copied() {
+                let (dx, dy) = (target.x - last.x, target.y - last.y);
+                if dx == 0 && dy == 0 {
+                    break;
+                }
+                path.push(if dy.abs() < dx.abs() {
+                    last.add((dx.signum(), 0))
+                } else {
+                    last.add((0, dy.signum()))
+                });
+            }
+        }
         // console_log!("mouse_move: cursor: {}, {}", cursor[0], cursor[1]);
         self.update_info();
         Ok(())
     }
 
     pub fn mouse_leave(&mut self) -> Result<(), JsValue> {
+        self.record_input(ReplayInput::MouseLeave);
         self.cursor = None;
+        self.drag_path = None;
         if let Some(ref elem) = self.info_elem {
             elem.set_inner_html("");
         }
         if self.ore_harvesting.is_some() {
             self.ore_harvesting = None;
         }
-        console_log!("mouse_leave");
+        console_debug!(self, "mouse_leave");
         Ok(())
     }
 
+    /// Start zooming at the cursor. The scale is animated in render().
     pub fn mouse_wheel(&mut self, delta: i32, x: f64, y: f64) -> Result<(), JsValue> {
-        let base = (2_f64).powf(1. / 5.);
-        let new_scale = if delta < 0 {
-            (self.viewport.scale * base).min(8.)
-        } else {
-            (self.viewport.scale / base).max(0.5)
-        };
-        self.viewport.x +=
-            (x as f64 / self.viewport.scale / 32.) * (1. - new_scale / self.viewport.scale);
-        self.viewport.y +=
-            (y as f64 / self.viewport.scale / 32.) * (1. - new_scale / self.viewport.scale);
-        self.viewport.scale = new_scale;
-
-        self.gen_chunks_in_viewport();
-
+        self.record_input(ReplayInput::MouseWheel { delta, x, y });
+        self.zoom_to(delta < 0, x, y);
         Ok(())
     }
 
     /// Keyboard event handler. Returns true if re-rendering is necessary to update internal state.
-    pub fn on_key_down(&mut self, key_code: i32) -> Result<JsValue, JsValue> {
+    pub fn on_key_down(&mut self, key_code: i32, ctrl: bool) -> Result<JsValue, JsValue> {
+        self.record_input(ReplayInput::KeyDown { key_code, ctrl });
         match key_code {
+            // Ctrl+'z'
+            90 if ctrl => Ok(JsValue::from_bool(self.unrecorded(Self::undo)?)),
             // 'r'
             82 => match self.rotate() {
                 Ok(b) => Ok(JsValue::from_bool(b)),
@@ -2277,27 +3780,7 @@ impl FactorishState {
             },
             // Detect keys through '0'..'9', that's a shame char literal cannot be used in place of i32
             code @ 48..=58 => {
-                self.select_tool((code - '0' as i32 + 9) % 10)?;
-                Ok(JsValue::from_bool(true))
-            }
-            37 => {
-                // Left
-                self.viewport.x = (self.viewport.x + 1.).min(0.);
-                Ok(JsValue::from_bool(true))
-            }
-            38 => {
-                // Up
-                self.viewport.y = (self.viewport.y + 1.).min(0.);
-                Ok(JsValue::from_bool(true))
-            }
-            39 => {
-                // Right
-                self.viewport.x = (self.viewport.x - 1.).max(-(self.width as f64));
-                Ok(JsValue::from_bool(true))
-            }
-            40 => {
-                // Down
-                self.viewport.y = (self.viewport.y - 1.).max(-(self.height as f64));
+                self.unrecorded(|this| this.select_tool((code - '0' as i32 + 9) % 10))?;
                 Ok(JsValue::from_bool(true))
             }
             69 => {
@@ -2321,13 +3804,13 @@ impl FactorishState {
                         } else {
                             None
                         };
-                        console_log!("q: selected_tool is {:?}", self.selected_item);
+                        console_debug!(self, "q: selected_tool is {:?}", self.selected_item);
                     }
                 }
                 Ok(JsValue::from_bool(true))
             }
             _ => {
-                console_log!("unrecognized key: {}", key_code);
+                console_debug!(self, "unrecognized key: {}", key_code);
                 Ok(JsValue::from_bool(false))
             }
         }
@@ -2336,6 +3819,10 @@ impl FactorishState {
     fn color_of_cell(cell: &Cell) -> [u8; 3] {
         if cell.water {
             [0x00, 0x00, 0xff]
+        } else if cell.cliff {
+            [0x5f, 0x4f, 0x3f]
+        } else if cell.tree {
+            [0x3f, 0x7f, 0x1f]
         } else {
             match cell.ore {
                 Some(OreValue(Ore::Iron, _)) => [0x3f, 0xaf, 0xff],
@@ -2362,7 +3849,7 @@ impl FactorishState {
         self.viewport_height = canvas.height() as f64;
         self.info_elem = Some(info_elem);
 
-        self.render_minimap_data()?;
+        self.render_minimap_data();
 
         let load_image = |path| -> Result<ImageBundle, JsValue> {
             if let Some(value) = image_assets.iter().find(|value| {
@@ -2398,6 +3885,8 @@ impl FactorishState {
             }
         };
         self.image_dirt = Some(load_image("dirt")?);
+        self.image_cliff = Some(load_image("cliff")?);
+        self.image_tree = Some(load_image("tree")?);
         self.image_back_tiles = Some(load_image("backTiles")?);
         self.image_weeds = Some(load_image("weeds")?);
         self.image_ore = Some(load_image("iron")?);
@@ -2405,9 +3894,16 @@ impl FactorishState {
         self.image_copper = Some(load_image("copper")?);
         self.image_stone = Some(load_image("stone")?);
         self.image_belt = Some(load_image("transport")?);
+        self.image_belt_curve = Some(load_image("transportCurve")?);
         self.image_chest = Some(load_image("chest")?);
+        self.image_requester_chest = Some(load_image("requesterChest")?);
+        self.image_lab = Some(load_image("lab")?);
+        self.image_lamp = Some(load_image("lamp")?);
+        self.image_gun_turret = Some(load_image("gunTurret")?);
         self.image_mine = Some(load_image("mine")?);
+        self.image_electric_mine = Some(load_image("electricMine")?);
         self.image_furnace = Some(load_image("furnace")?);
+        self.image_electric_furnace = Some(load_image("electricFurnace")?);
         self.image_assembler = Some(load_image("assembler")?);
         self.image_boiler = Some(load_image("boiler")?);
         self.image_steam_engine = Some(load_image("steamEngine")?);
@@ -2415,7 +3911,9 @@ impl FactorishState {
         self.image_offshore_pump = Some(load_image("offshorePump")?);
         self.image_pipe = Some(load_image("pipe")?);
         self.image_elect_pole = Some(load_image("electPole")?);
+        self.image_accumulator = Some(load_image("accumulator")?);
         self.image_splitter = Some(load_image("splitter")?);
+        self.image_balancer = Some(load_image("balancer")?);
         self.image_inserter = Some(load_image("inserter")?);
         self.image_direction = Some(load_image("direction")?);
         self.image_iron_ore = Some(load_image("ore")?);
@@ -2424,9 +3922,14 @@ impl FactorishState {
         self.image_stone_ore = Some(load_image("stoneOre")?);
         self.image_iron_plate = Some(load_image("ironPlate")?);
         self.image_copper_plate = Some(load_image("copperPlate")?);
+        self.image_steel_plate = Some(load_image("steelPlate")?);
         self.image_gear = Some(load_image("gear")?);
         self.image_copper_wire = Some(load_image("copperWire")?);
         self.image_circuit = Some(load_image("circuit")?);
+        self.image_wood = Some(load_image("wood")?);
+        self.image_science_pack = Some(load_image("sciencePack")?);
+        self.image_repair_pack = Some(load_image("repairPack")?);
+        self.image_firearm_magazine = Some(load_image("firearmMagazine")?);
         self.image_time = Some(load_image("time")?);
         self.image_smoke = Some(load_image("smoke")?);
         self.image_fuel_alarm = Some(load_image("fuelAlarm")?);
@@ -2434,25 +3937,47 @@ impl FactorishState {
         Ok(())
     }
 
+    /// Returns [item_name, desc, locked] arrays of the tools, where locked tools
+    /// cannot be placed until they are unlocked.
     pub fn tool_defs(&self) -> Result<js_sys::Array, JsValue> {
         Ok(tool_defs
             .iter()
             .map(|tool| {
-                js_sys::Array::of2(
+                js_sys::Array::of3(
                     &JsValue::from_str(&item_to_str(&tool.item_type)),
                     &JsValue::from_str(&tool.desc),
+                    &JsValue::from_bool(!self.is_unlocked(&tool.item_type)),
                 )
             })
             .collect::<js_sys::Array>())
     }
 
+    /// Locks or unlocks placing an item, so that scenarios can stage the content.
+    pub fn set_item_unlocked(&mut self, item_name: &str, value: bool) -> Result<(), JsValue> {
+        self.record_input(ReplayInput::SetItemUnlocked {
+            item_name: item_name.to_string(),
+            value,
+        });
+        let item =
+            str_to_item(item_name).ok_or_else(|| js_str!("Item name not valid: {}", item_name))?;
+        let unlocked = self
+            .unlocked
+            .get_or_insert_with(|| tool_defs.iter().map(|tool| tool.item_type).collect());
+        if value {
+            unlocked.insert(item);
+        } else {
+            unlocked.remove(&item);
+        }
+        Ok(())
+    }
+
     /// Returns 2-array with [selected_tool, inventory_count]
     pub fn selected_tool(&self) -> js_sys::Array {
         if let Some(SelectedItem::ToolBelt(selected_tool)) = self.selected_item {
             [
                 JsValue::from(selected_tool as f64),
                 JsValue::from(
-                    *self.tool_belt[selected_tool]
+                    *self.active_tool_row()[selected_tool]
                         .and_then(|item| self.player.inventory.get(&item))
                         .unwrap_or(&0) as f64,
                 ),
@@ -2488,12 +4013,18 @@ impl FactorishState {
         context: &CanvasRenderingContext2d,
     ) -> Result<(), JsValue> {
         context.clear_rect(0., 0., 32., 32.);
-        if let Some(item) = self.tool_belt.get(tool_index).unwrap_or(&None) {
-            let mut tool = self.new_structure(item, &Position { x: 0, y: 0 })?;
+        if let Some(item) = self.active_tool_row().get(tool_index).unwrap_or(&None) {
+            let mut tool = self.create_structure(item, &Position { x: 0, y: 0 })?;
             tool.set_rotation(&self.tool_rotation).ok();
+            context.save();
+            // Gray out locked tools
+            if !self.is_unlocked(item) {
+                context.set_global_alpha(0.3);
+            }
             for depth in 0..3 {
                 tool.draw(self, context, depth, true)?;
             }
+            context.restore();
         }
         Ok(())
     }
@@ -2502,7 +4033,7 @@ impl FactorishState {
     /// otherwise null.
     pub fn get_tool_desc(&self, index: usize) -> Result<JsValue, JsValue> {
         Ok(self
-            .tool_belt
+            .active_tool_row()
             .get(index)
             .unwrap_or(&None)
             .and_then(|item| tool_defs.iter().find(|tool| tool.item_type == item))
@@ -2530,7 +4061,7 @@ impl FactorishState {
 
     fn get_selected_tool_or_item_opt(&self) -> Option<ItemType> {
         match self.selected_item {
-            Some(SelectedItem::ToolBelt(tool)) => self.tool_belt[tool],
+            Some(SelectedItem::ToolBelt(tool)) => self.active_tool_row()[tool],
             Some(SelectedItem::PlayerInventory(item)) => tool_defs
                 .iter()
                 .find(|def| def.item_type == item)
@@ -2550,16 +4081,22 @@ impl FactorishState {
     /// @param tool the index of the tool item, [0,9]
     /// @returns whether the tool bar item should be re-rendered
     pub fn select_tool(&mut self, tool: i32) -> Result<JsValue, JsValue> {
+        self.record_input(ReplayInput::SelectTool { tool });
         if let Some(SelectedItem::PlayerInventory(item)) = self.selected_item {
             // We allow only items in tool_defs to present on the tool belt
             // This behavior is different from Factorio, maybe we can allow it
+            if !self.is_unlocked(&item) {
+                console_log!(self, "select_tool: {:?} is locked", item);
+                return Ok(JsValue::from_bool(false));
+            }
             if tool_defs.iter().any(|i| i.item_type == item) {
-                self.tool_belt[tool as usize] = Some(item);
+                self.active_tool_row_mut()[tool as usize] = Some(item);
                 // Deselect the item for the player to let him select from tool belt.
                 self.selected_item = None;
                 return Ok(JsValue::from_bool(true));
             } else {
-                console_log!(
+                console_warn!(
+                    self,
                     "select_tool could not find tool_def with item type: {:?}",
                     item
                 );
@@ -2573,7 +4110,7 @@ impl FactorishState {
                 None
             };
         if let Some(SelectedItem::ToolBelt(sel)) = self.selected_item {
-            if self.tool_belt[sel].is_none() {
+            if self.active_tool_row()[sel].is_none() {
                 return Ok(JsValue::from_serde(&JSEvent::ShowInventory).unwrap());
             }
         }
@@ -2581,13 +4118,14 @@ impl FactorishState {
     }
 
     pub fn rotate_tool(&mut self) -> i32 {
+        self.record_input(ReplayInput::RotateTool);
         self.tool_rotation = self.tool_rotation.next();
         self.tool_rotation.angle_4()
     }
 
     /// Returns an array of item count for tool bar items
     pub fn tool_inventory(&self) -> js_sys::Array {
-        self.tool_belt
+        self.active_tool_row()
             .iter()
             .map(|item| {
                 JsValue::from(
@@ -2610,14 +4148,45 @@ impl FactorishState {
         self.viewport.scale
     }
 
+    /// Convert a position in the world in tiles to the canvas in pixels with the current
+    /// viewport, so that the overlays in JavaScript line up with the rendered world.
+    /// @returns [px, py]
+    pub fn world_to_screen(&self, x: f64, y: f64) -> js_sys::Array {
+        let (px, py) = self.viewport.world_to_screen((x, y));
+        js_sys::Array::of2(&JsValue::from_f64(px), &JsValue::from_f64(py))
+    }
+
+    /// Convert a position on the canvas in pixels to the world in tiles, which is fractional.
+    /// @returns [x, y]
+    pub fn screen_to_world(&self, px: f64, py: f64) -> js_sys::Array {
+        let (x, y) = self.viewport.screen_to_world((px, py));
+        js_sys::Array::of2(&JsValue::from_f64(x), &JsValue::from_f64(y))
+    }
+
+    /// Returns the tile under a position on the canvas in pixels, which is where the mouse
+    /// handlers place structures.
+    /// @returns [c, r]
+    pub fn screen_to_tile(&self, px: f64, py: f64) -> js_sys::Array {
+        let tile = self.viewport.screen_to_tile((px, py));
+        js_sys::Array::of2(&JsValue::from(tile.x), &JsValue::from(tile.y))
+    }
+
     pub fn set_viewport_pos(&mut self, x: f64, y: f64) -> Result<js_sys::Array, JsValue> {
         let viewport = self.get_viewport();
-        self.viewport.x = -(x - viewport.0 / TILE_SIZE / 2.)
-            .max(0.)
-            .min(self.width as f64 - viewport.0 / TILE_SIZE - 1.);
-        self.viewport.y = -(y - viewport.1 / TILE_SIZE / 2.)
-            .max(0.)
-            .min(self.height as f64 - viewport.1 / TILE_SIZE - 1.);
+        let x = x - viewport.0 / TILE_SIZE / 2.;
+        let y = y - viewport.1 / TILE_SIZE / 2.;
+        // Unlimited worlds can scroll anywhere, so clamp only within bounds
+        if let Some(bounds) = self.bounds.as_ref() {
+            self.viewport.x = -x
+                .max(0.)
+                .min(bounds.width as f64 - viewport.0 / TILE_SIZE - 1.);
+            self.viewport.y = -y
+                .max(0.)
+                .min(bounds.height as f64 - viewport.1 / TILE_SIZE - 1.);
+        } else {
+            self.viewport.x = -x;
+            self.viewport.y = -y;
+        }
 
         self.gen_chunks_in_viewport();
 
@@ -2661,28 +4230,58 @@ impl FactorishState {
             for cy in top.div_euclid(CHUNK_SIZE_I)..=bottom.div_euclid(CHUNK_SIZE_I) {
                 let chunk_pos = Position::new(cx, cy);
                 if !self.board.contains_key(&chunk_pos) {
-                    console_log!(
-                        "Generating chunk_pos {:?}, {} chunks total",
-                        chunk_pos,
-                        self.board.len()
-                    );
-                    let mut chunk = gen_chunk(chunk_pos, &self.terrain_params);
-                    calculate_back_image(&mut self.board, &chunk_pos, &mut chunk.cells);
-                    self.render_minimap_chunk(&chunk_pos, &mut chunk);
-                    self.board.insert(chunk_pos, chunk);
+                    self.record_input(ReplayInput::GenerateChunk {
+                        position: chunk_pos,
+                    });
+                    self.gen_chunk_at(chunk_pos);
                 }
             }
         }
     }
 
+    /// Generate the chunk containing the tile on demand in an unlimited world, so that things
+    /// can be put on the tiles that have not been seen yet.
+    fn gen_chunk_for_tile(&mut self, tile: &Position) {
+        if self.bounds.is_some() {
+            return;
+        }
+        if let Some(chunk_pos) = self.board.missing_chunk(*tile) {
+            self.record_input(ReplayInput::GenerateChunk {
+                position: chunk_pos,
+            });
+            self.gen_chunk_at(chunk_pos);
+        }
+    }
+
+    fn gen_chunk_at(&mut self, chunk_pos: Position) {
+        if self.board.contains_key(&chunk_pos) {
+            return;
+        }
+        console_debug!(
+            self,
+            "Generating chunk_pos {:?}, {} chunks total",
+            chunk_pos,
+            self.board.len()
+        );
+        let mut chunk = gen_chunk(chunk_pos, &self.terrain_params);
+        calculate_back_image(&mut self.board, &chunk_pos, &mut chunk.cells);
+        self.render_minimap_chunk(&chunk_pos, &mut chunk);
+        self.board.insert(chunk_pos, chunk);
+        // A patch may extend into the new chunk.
+        self.ore_patches.clear();
+    }
+
     /// Add a new popup text that will show for a moment and automatically disappears
     ///
     /// @param text Is given as owned string because the text is most likely dynamic.
     fn new_popup_text(&mut self, text: String, x: f64, y: f64) {
+        let (x, y) = self
+            .viewport
+            .world_to_screen((x / TILE_SIZE, y / TILE_SIZE));
         let pop = PopupText {
             text: text.to_string(),
-            x: (x + self.viewport.x * TILE_SIZE) * self.viewport.scale,
-            y: (y + self.viewport.y * TILE_SIZE) * self.viewport.scale,
+            x,
+            y,
             life: POPUP_TEXT_LIFE,
         };
         self.popup_texts.push(pop);
@@ -2696,7 +4295,9 @@ impl FactorishState {
     pub fn render(&mut self, context: CanvasRenderingContext2d) -> Result<(), JsValue> {
         use std::f64;
 
-        let start_render = performance().now();
+        let start_render = self.clock.now();
+
+        self.update_zoom();
 
         context.clear_rect(0., 0., self.viewport_width, self.viewport_height);
 
@@ -2710,6 +4311,7 @@ impl FactorishState {
             }
             let img = unwrap_img(&self.image_dirt)?;
             let back_tiles = unwrap_img(&self.image_back_tiles)?;
+            let img_cliff = unwrap_img(&self.image_cliff)?;
             let img_ore = unwrap_img(&self.image_ore)?;
             let img_coal = unwrap_img(&self.image_coal)?;
             let img_copper = unwrap_img(&self.image_copper)?;
@@ -2729,7 +4331,9 @@ impl FactorishState {
                     let (mx, my) = (x as usize % CHUNK_SIZE, y as usize % CHUNK_SIZE);
                     let cell = &chunk.cells[(mx + my * CHUNK_SIZE) as usize];
                     let (dx, dy) = (x as f64 * 32., y as f64 * 32.);
-                    if cell.water || cell.image != 0 {
+                    if cell.cliff {
+                        context.draw_image_with_image_bitmap(&img_cliff.bitmap, dx, dy)?;
+                    } else if cell.water || cell.image != 0 {
                         let srcx = cell.image % 4;
                         let srcy = cell.image / 4;
                         context.draw_image_with_image_bitmap_and_sw_and_sh_and_dx_and_dy_and_dw_and_dh(
@@ -2743,7 +4347,7 @@ impl FactorishState {
                                     (cell.grass_image * 32) as f64, 0., 32., 32., dx, dy, 32., 32.)?;
                             }
                         } else {
-                            console_log!("Weed image not found");
+                            console_warn!(self, "Weed image not found");
                         }
                     }
                     let draw_ore = |ore: u32, img: &ImageBitmap| -> Result<(), JsValue> {
@@ -2775,8 +4379,45 @@ impl FactorishState {
             Ok(())
         })().map_err(|e: JsValue| js_str!("image not available: {:?}", e))?;
 
+        // Structures can extend beyond their chunks, e.g. multi-tile structures or tall sprites,
+        // so we draw one more chunk around the viewport.
+        let (left, top, right, bottom) = apply_bounds(
+            &self.bounds,
+            &self.viewport,
+            self.viewport_width,
+            self.viewport_height,
+        );
+
+        // Trees are drawn above the terrain, but below structures.
+        if let Some(img_tree) = self.image_tree.as_ref() {
+            for y in top..=bottom {
+                for x in left..=right {
+                    if self
+                        .tile_at(&Position::new(x, y))
+                        .map(|cell| cell.tree)
+                        .unwrap_or(false)
+                    {
+                        context.draw_image_with_image_bitmap(
+                            &img_tree.bitmap,
+                            x as f64 * TILE_SIZE,
+                            y as f64 * TILE_SIZE,
+                        )?;
+                    }
+                }
+            }
+        }
+
+        let visible_ids = (left.div_euclid(CHUNK_SIZE_I) - 1..=right.div_euclid(CHUNK_SIZE_I) + 1)
+            .flat_map(|cx| {
+                (top.div_euclid(CHUNK_SIZE_I) - 1..=bottom.div_euclid(CHUNK_SIZE_I) + 1)
+                    .map(move |cy| Position::new(cx, cy))
+            })
+            .filter_map(|chunk_pos| self.structure_chunks.get(&chunk_pos))
+            .flatten()
+            .copied()
+            .collect::<Vec<_>>();
         let draw_structures = |depth| -> Result<(), JsValue> {
-            for structure in self.structure_iter() {
+            for structure in visible_ids.iter().filter_map(|id| self.get_structure(*id)) {
                 structure.draw(&self, &context, depth, false)?;
             }
             Ok(())
@@ -2784,18 +4425,23 @@ impl FactorishState {
 
         draw_structures(0)?;
 
+        if self.debug_power_tint {
+            self.render_power_tint(&context, &visible_ids);
+        }
+
         for item in drop_item_iter(&self.drop_items) {
             render_drop_item(self, &context, &item.type_, item.x, item.y)?;
         }
+        self.render_drop_item_counts(&context)?;
 
         const WIRE_ATTACH_X: f64 = 28.;
         const WIRE_ATTACH_Y: f64 = 8.;
         const WIRE_HANG: f64 = 0.15;
 
-        let draw_wires = |wires: &[PowerWire]| {
-            for PowerWire(first, second) in wires {
+        let draw_wires = |wires: &mut dyn Iterator<Item = (StructureId, StructureId)>| {
+            for (first, second) in wires {
                 context.begin_path();
-                let first = if let Some(d) = self.get_structure(*first) {
+                let first = if let Some(d) = self.get_structure(first) {
                     d.position()
                 } else {
                     continue;
@@ -2804,7 +4450,7 @@ impl FactorishState {
                     first.x as f64 * TILE_SIZE + WIRE_ATTACH_X,
                     first.y as f64 * TILE_SIZE + WIRE_ATTACH_Y,
                 );
-                let second = if let Some(d) = self.get_structure(*second) {
+                let second = if let Some(d) = self.get_structure(second) {
                     d.position()
                 } else {
                     continue;
@@ -2829,17 +4475,64 @@ impl FactorishState {
                     ["rgb(255,0,0)", "rgb(0,0,255)", "rgb(0,255,0)"][i % 3]
                 ));
                 context.set_line_width(3.);
-                draw_wires(&nw.wires);
+                draw_wires(&mut nw.wires.iter().map(|w| (w.0, w.1)));
             }
         }
 
         context.set_stroke_style(&js_str!("rgb(191,127,0)"));
         context.set_line_width(1.);
-        draw_wires(&self.power_wires);
+        draw_wires(&mut self.power_wires.iter().map(|w| (w.0, w.1)));
+
+        context.set_stroke_style(&js_str!("rgb(0,191,0)"));
+        draw_wires(&mut self.signal_wires.iter().map(|w| (w.0, w.1)));
 
         draw_structures(1)?;
         draw_structures(2)?;
 
+        self.render_enemies(&context, (left, top, right, bottom))?;
+        self.render_crafting_progress(&context, &visible_ids)?;
+        self.render_health_bars(&context, &visible_ids);
+
+        // Blink a warning sign on fluid boxes where different fluids meet
+        if self.sim_time % 1. < 0.5 {
+            context.save();
+            context.set_fill_style(&js_str!("yellow"));
+            context.set_stroke_style(&js_str!("black"));
+            context.set_line_width(1.);
+            for structure in visible_ids.iter().filter_map(|id| self.get_structure(*id)) {
+                if !structure
+                    .fluid_box()
+                    .map_or(false, |fluid_boxes| fluid_boxes.iter().any(|fb| fb.blocked))
+                {
+                    continue;
+                }
+                let (x, y) = (
+                    structure.position().x as f64 * TILE_SIZE,
+                    structure.position().y as f64 * TILE_SIZE,
+                );
+                context.begin_path();
+                context.move_to(x + 24., y + 2.);
+                context.line_to(x + 31., y + 14.);
+                context.line_to(x + 17., y + 14.);
+                context.close_path();
+                context.fill();
+                context.stroke();
+                context.begin_path();
+                context.move_to(x + 24., y + 5.);
+                context.line_to(x + 24., y + 10.);
+                context.move_to(x + 24., y + 12.);
+                context.line_to(x + 24., y + 13.);
+                context.stroke();
+            }
+            context.restore();
+        }
+
+        if self.pollution_overlay {
+            self.render_pollution(&context, (left, top, right, bottom));
+        }
+
+        self.draw_ghosts(&context)?;
+
         if self.debug_bbox {
             context.save();
             context.set_stroke_style(&js_str!("red"));
@@ -2874,9 +4567,32 @@ impl FactorishState {
             context.restore();
         }
 
+        if self.debug_items {
+            context.save();
+            for (id, item) in drop_item_id_iter(&self.drop_items) {
+                let stuck = self
+                    .item_last_moved
+                    .get(&id)
+                    .map_or(JAMMED_ITEM_TIME, |t| self.sim_time - t);
+                let jam = (stuck / JAMMED_ITEM_TIME).min(1.);
+                context.set_fill_style(&js_str!(
+                    "rgba({:.0},{:.0},0,0.6)",
+                    jam * 255.,
+                    (1. - jam) * 255.
+                ));
+                context.fill_rect(
+                    item.x as f64 - DROP_ITEM_SIZE / 2.,
+                    item.y as f64 - DROP_ITEM_SIZE / 2.,
+                    DROP_ITEM_SIZE,
+                    DROP_ITEM_SIZE,
+                );
+            }
+            context.restore();
+        }
+
         if self.debug_fluidbox {
             context.save();
-            for structure in self.structure_iter() {
+            for structure in visible_ids.iter().filter_map(|id| self.get_structure(*id)) {
                 if let Some(fluid_boxes) = structure.fluid_box() {
                     let bb = structure.bounding_box();
                     for (i, fb) in fluid_boxes.iter().enumerate() {
@@ -2915,8 +4631,23 @@ impl FactorishState {
             context.restore();
         }
 
+        if self.fluid_flow_overlay || self.debug_fluidbox {
+            self.render_fluid_flow(&context);
+        }
+
         for ent in &self.temp_ents {
-            if let Some(img) = &self.image_smoke {
+            if let TempEntKind::Projectile(velocity) = ent.kind {
+                // Draw a short tracer behind the bullet
+                context.set_stroke_style(&js_str!("#ffdf3f"));
+                context.set_line_width(2.);
+                context.begin_path();
+                context.move_to(ent.position.0, ent.position.1);
+                context.line_to(
+                    ent.position.0 - velocity.0 * 0.02,
+                    ent.position.1 - velocity.1 * 0.02,
+                );
+                context.stroke();
+            } else if let Some(img) = &self.image_smoke {
                 let (x, y) = (ent.position.0 - 24., ent.position.1 - 24.);
                 context.save();
                 context
@@ -2934,21 +4665,54 @@ impl FactorishState {
             }
         }
 
+        self.render_night(&context)?;
+
         if let Some(ref cursor) = self.cursor {
             let (x, y) = ((cursor[0] * 32) as f64, (cursor[1] * 32) as f64);
+            let mut cursor_rect = (x, y, 32., 32.);
             if let Some(selected_tool) = self.get_selected_tool_or_item_opt() {
-                context.save();
-                context.set_global_alpha(0.5);
-                let mut tool = self.new_structure(&selected_tool, &Position::from(cursor))?;
-                tool.set_rotation(&self.tool_rotation).ok();
-                for depth in 0..3 {
-                    tool.draw(self, &context, depth, false)?;
+                let unlocked = self.is_unlocked(&selected_tool);
+                match &self.drag_path {
+                    Some(path) if 1 < path.len() => {
+                        for (position, rotation) in drag_path_rotations(path) {
+                            let mut tool = self.create_structure(&selected_tool, &position)?;
+                            tool.set_rotation(&rotation).ok();
+                            // The tiles in the way are skipped rather than harvested on dragging.
+                            let placeable = unlocked
+                                && self.is_placeable(tool.as_ref(), &selected_tool).is_ok();
+                            self.draw_placement_preview(&context, tool.as_ref(), placeable)?;
+                        }
+                    }
+                    _ => {
+                        let position = Position::from(cursor);
+                        let mut tool = self.create_structure(&selected_tool, &position)?;
+                        let placement =
+                            check_placement(&selected_tool, &position, self.tool_rotation, |pos| {
+                                self.tile_in_bounds(pos)
+                            });
+                        // Show an offshore pump turned to the land as it will be placed.
+                        tool.set_rotation(placement.as_ref().unwrap_or(&self.tool_rotation))
+                            .ok();
+                        self.draw_placement_preview(
+                            &context,
+                            tool.as_ref(),
+                            unlocked && placement.is_ok(),
+                        )?;
+                        self.render_wire_reach(&context, tool.as_ref())?;
+                        // Outline the whole footprint of the rotated tool
+                        let bb = tool.bounding_box();
+                        cursor_rect = (
+                            bb.x0 as f64 * TILE_SIZE,
+                            bb.y0 as f64 * TILE_SIZE,
+                            (bb.x1 - bb.x0) as f64 * TILE_SIZE,
+                            (bb.y1 - bb.y0) as f64 * TILE_SIZE,
+                        );
+                    }
                 }
-                context.restore();
             }
             context.set_stroke_style(&JsValue::from_str("blue"));
             context.set_line_width(2.);
-            context.stroke_rect(x, y, 32., 32.);
+            context.stroke_rect(cursor_rect.0, cursor_rect.1, cursor_rect.2, cursor_rect.3);
         }
 
         if let Some(ore_harvesting) = &self.ore_harvesting {
@@ -2976,7 +4740,7 @@ impl FactorishState {
             context.fill_text(&item.text, item.x, item.y)?;
         }
 
-        self.perf_render.add(performance().now() - start_render);
+        self.perf_render.add(self.clock.now() - start_render);
         Ok(())
     }
 }
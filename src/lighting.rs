@@ -0,0 +1,114 @@
+use super::{window, FactorishState, TILE_SIZE};
+use wasm_bindgen::{prelude::*, JsCast};
+use web_sys::{CanvasRenderingContext2d, HtmlCanvasElement};
+
+/// Number of the concentric circles that make the edge of a light soft
+const LIGHT_RINGS: usize = 4;
+/// The fraction of the darkness that each circle removes
+const LIGHT_RING_ALPHA: f64 = 0.5;
+
+impl FactorishState {
+    /// The centers and the radii of the lights in tiles, which are cast by structures in the night.
+    fn light_sources(&self) -> Vec<(f64, f64, f64)> {
+        self.structure_iter()
+            .filter_map(|s| {
+                let radius = s.light_radius();
+                if radius <= 0. {
+                    return None;
+                }
+                let bb = s.bounding_box();
+                Some((
+                    (bb.x0 + bb.x1) as f64 / 2.,
+                    (bb.y0 + bb.y1) as f64 / 2.,
+                    radius,
+                ))
+            })
+            .collect()
+    }
+
+    /// The offscreen canvas of the size of the viewport, where the night is drawn before it is
+    /// laid over the world.
+    fn light_canvas(&mut self) -> Result<HtmlCanvasElement, JsValue> {
+        let canvas = if let Some(canvas) = self.light_canvas.as_ref() {
+            canvas.clone()
+        } else {
+            let canvas = window()
+                .document()
+                .ok_or_else(|| js_str!("document not available"))?
+                .create_element("canvas")?
+                .dyn_into::<HtmlCanvasElement>()
+                .map_err(|_| js_str!("canvas element not available"))?;
+            self.light_canvas = Some(canvas.clone());
+            canvas
+        };
+        let (width, height) = (self.viewport_width as u32, self.viewport_height as u32);
+        if canvas.width() != width || canvas.height() != height {
+            canvas.set_width(width);
+            canvas.set_height(height);
+        }
+        Ok(canvas)
+    }
+
+    /// Darken the viewport for the night, except the soft circles around the light sources.
+    pub(crate) fn render_night(
+        &mut self,
+        context: &CanvasRenderingContext2d,
+    ) -> Result<(), JsValue> {
+        let darkness = 1. - self.brightness();
+        if darkness <= 0. {
+            return Ok(());
+        }
+        let (width, height) = (self.viewport_width, self.viewport_height);
+        let scale = self.viewport.scale;
+        let lights = self
+            .light_sources()
+            .into_iter()
+            .map(|(x, y, radius)| {
+                let (x, y) = self.viewport.world_to_screen((x, y));
+                (x, y, radius * TILE_SIZE * scale)
+            })
+            .filter(|(x, y, radius)| {
+                -radius < *x && *x < width + radius && -radius < *y && *y < height + radius
+            })
+            .collect::<Vec<_>>();
+
+        // Cover the whole viewport in screen coordinates, regardless of the scale.
+        context.save();
+        context.set_transform(1., 0., 0., 1., 0., 0.)?;
+        if lights.is_empty() {
+            context.set_fill_style(&js_str!("rgba(0,0,31,{})", darkness));
+            context.fill_rect(0., 0., width, height);
+            context.restore();
+            return Ok(());
+        }
+
+        let canvas = self.light_canvas()?;
+        let night = canvas
+            .get_context("2d")?
+            .ok_or_else(|| js_str!("canvas context not available"))?
+            .dyn_into::<CanvasRenderingContext2d>()?;
+        night.set_global_composite_operation("source-over")?;
+        night.clear_rect(0., 0., width, height);
+        night.set_fill_style(&js_str!("rgba(0,0,31,{})", darkness));
+        night.fill_rect(0., 0., width, height);
+        // Erase the darkness more toward the center of each light
+        night.set_global_composite_operation("destination-out")?;
+        night.set_fill_style(&js_str!("rgba(0,0,0,{})", LIGHT_RING_ALPHA));
+        for (x, y, radius) in lights {
+            for ring in 0..LIGHT_RINGS {
+                night.begin_path();
+                night.arc(
+                    x,
+                    y,
+                    radius * (LIGHT_RINGS - ring) as f64 / LIGHT_RINGS as f64,
+                    0.,
+                    std::f64::consts::PI * 2.,
+                )?;
+                night.fill();
+            }
+        }
+        context.draw_image_with_html_canvas_element(&canvas, 0., 0.)?;
+        context.restore();
+        Ok(())
+    }
+}
@@ -0,0 +1,78 @@
+use super::FactorishState;
+use wasm_bindgen::prelude::*;
+
+/// The verbosity of the console output. A message is shown if its level is at or below the
+/// current one, e.g. `Warn` shows warnings and errors.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum LogLevel {
+    Error = 1,
+    Warn,
+    Info,
+    Debug,
+}
+
+impl std::str::FromStr for LogLevel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "error" => Self::Error,
+            "warn" => Self::Warn,
+            "info" => Self::Info,
+            "debug" => Self::Debug,
+            _ => return Err(format!("Log level not valid: {}", s)),
+        })
+    }
+}
+
+impl LogLevel {
+    fn to_str(self) -> &'static str {
+        match self {
+            Self::Error => "error",
+            Self::Warn => "warn",
+            Self::Info => "info",
+            Self::Debug => "debug",
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl FactorishState {
+    /// Set the verbosity of the console output, which is one of "error", "warn", "info" or
+    /// "debug". The default "warn" hides the informational messages.
+    pub fn set_log_level(&mut self, level: &str) -> Result<(), JsValue> {
+        self.log_level = level.parse().map_err(|e: String| JsValue::from_str(&e))?;
+        Ok(())
+    }
+
+    pub fn get_log_level(&self) -> String {
+        self.log_level.to_str().to_string()
+    }
+}
+
+#[test]
+fn test_log_level_order() {
+    assert!(LogLevel::Error < LogLevel::Warn);
+    assert!(LogLevel::Info < LogLevel::Debug);
+    for level in [
+        LogLevel::Error,
+        LogLevel::Warn,
+        LogLevel::Info,
+        LogLevel::Debug,
+    ] {
+        assert_eq!(level.to_str().parse(), Ok(level));
+    }
+    assert!("verbose".parse::<LogLevel>().is_err());
+}
+
+#[test]
+fn test_log_level_per_state() {
+    use super::scenarios::empty_state;
+    let mut state = empty_state(16, 16);
+    // Only warnings and errors are shown by default.
+    assert_eq!(state.get_log_level(), "warn");
+    state.set_log_level("debug").unwrap();
+    assert_eq!(state.log_level, LogLevel::Debug);
+    // A new game starts quiet again.
+    assert_eq!(empty_state(16, 16).get_log_level(), "warn");
+}
@@ -1,11 +1,47 @@
+/// format-like macro that writes to console.log at the given `LogLevel`, if it is enabled
+/// by `set_log_level` of the `FactorishState` given as the first argument
+#[macro_export]
+macro_rules! console_log_at {
+    ($state:expr, $level:ident, $($arg:tt)+) => {
+        if $crate::logging::LogLevel::$level <= $state.log_level {
+            let text = format!($($arg)+);
+            match $crate::logging::LogLevel::$level {
+                $crate::logging::LogLevel::Error => $crate::error(&text),
+                $crate::logging::LogLevel::Warn => $crate::warn(&text),
+                _ => $crate::log(&text),
+            }
+        }
+    };
+}
+
+/// Informational message, hidden by default
 #[macro_export]
 macro_rules! console_log {
-    ($fmt:expr, $($arg1:expr),*) => {
-        crate::log(&format!($fmt, $($arg1),+))
+    ($state:expr, $($arg:tt)+) => {
+        $crate::console_log_at!($state, Info, $($arg)+)
+    };
+}
+
+/// Detailed message to trace the internals, like mouse events and chunk generation
+#[macro_export]
+macro_rules! console_debug {
+    ($state:expr, $($arg:tt)+) => {
+        $crate::console_log_at!($state, Debug, $($arg)+)
+    };
+}
+
+#[macro_export]
+macro_rules! console_warn {
+    ($state:expr, $($arg:tt)+) => {
+        $crate::console_log_at!($state, Warn, $($arg)+)
+    };
+}
+
+#[macro_export]
+macro_rules! console_error {
+    ($state:expr, $($arg:tt)+) => {
+        $crate::console_log_at!($state, Error, $($arg)+)
     };
-    ($fmt:expr) => {
-        crate::log($fmt)
-    }
 }
 
 /// format-like macro that returns js_sys::String
@@ -1,72 +1,208 @@
 use super::{
-    performance,
-    structure::Position,
-    terrain::{Chunk, Chunks, CHUNK_SIZE, CHUNK_SIZE_F, CHUNK_SIZE_I},
+    apply_bounds,
+    structure::{Position, Structure},
+    terrain::{Chunk, Chunks, CHUNK_SIZE_F, CHUNK_SIZE_I},
     FactorishState,
 };
 use wasm_bindgen::prelude::*;
 use wasm_bindgen::Clamped;
 use web_sys::ImageData;
 
+/// The range of `set_minimap_scale` in tiles per pixel
+const MIN_MINIMAP_SCALE: f64 = 0.25;
+const MAX_MINIMAP_SCALE: f64 = 16.;
+
 struct ImageBuffer<'a> {
     buf: &'a mut [u8],
     width: usize,
     height: usize,
 }
 
-fn copy_rect(dest: &mut ImageBuffer, src: &ImageBuffer, x: i32, y: i32) {
-    let x0 = x.min(dest.width as i32).max(0) as usize;
-    let x1 = (x + src.width as i32).min(dest.width as i32).max(0) as usize;
-    let sx0 = (x0 as i32 - x).min(src.width as i32).max(0) as usize;
-    if x0 == x1 {
-        return;
-    }
-    let sx1 = (sx0 + (x1 - x0)).min(src.width).max(0) as usize;
-    let y0 = y.min(dest.height as i32).max(0);
-    let sy0 = (y0 - y) as usize;
-    let y0 = y0 as usize;
-    let y1 = (y + src.height as i32).min(dest.height as i32).max(0) as usize;
-    if y0 == y1 {
-        return;
-    }
-    // let dblen = dest.buf.len();
-    // let sblen = src.buf.len();
-    // console_log!("src: {} y: {} y1: {} sx0 {}, sx1 {}", src.height, y, y1, sx0, sx1);
-    for y in y0..y1 {
-        let sy = y - y0 + sy0;
-        let d0 = (x0 + y * dest.width) * 4;
-        let d1 = (x1 + y * dest.width) * 4;
-        let s0 = (sx0 + sy * src.width) * 4;
-        let s1 = (sx1 + sy * src.width) * 4;
-        // console_log!("y {}: d [{}] {}..{}/{} s [{}] {}..{}/{}", y,
-        //     d1 - d0, d0, d1, dblen,
-        //     s1 - s0, s0, s1, sblen);
-        let d = &mut dest.buf[d0..d1];
-        let s = &src.buf[s0..s1];
-        if d.len() != s.len() {
-            panic!("d {} s {}", d.len(), s.len());
+/// The region of the world shown on the minimap, whose top left corner is at the tile
+/// (`x0`, `y0`) and whose pixel spans `scale` tiles.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct MinimapRegion {
+    x0: f64,
+    y0: f64,
+    scale: f64,
+    width: u32,
+    height: u32,
+}
+
+impl Default for MinimapRegion {
+    fn default() -> Self {
+        Self {
+            x0: 0.,
+            y0: 0.,
+            scale: 1.,
+            width: 0,
+            height: 0,
         }
-        d.copy_from_slice(s);
+    }
+}
+
+impl MinimapRegion {
+    /// The region of the given size in pixels centered on the given tile coordinates.
+    /// The corner is snapped to whole pixels so that the tiles do not flicker as the view moves.
+    fn new((cx, cy): (f64, f64), scale: f64, width: u32, height: u32) -> Self {
+        let snap = |v: f64| (v / scale).floor() * scale;
+        Self {
+            x0: snap(cx - width as f64 / 2. * scale),
+            y0: snap(cy - height as f64 / 2. * scale),
+            scale,
+            width,
+            height,
+        }
+    }
+
+    /// Returns the tile coordinates at the center of the pixel.
+    fn tile_at_pixel(&self, px: f64, py: f64) -> (f64, f64) {
+        (
+            self.x0 + (px.floor() + 0.5) * self.scale,
+            self.y0 + (py.floor() + 0.5) * self.scale,
+        )
+    }
+
+    fn pixel_of_tile(&self, x: i32, y: i32) -> (i32, i32) {
+        (
+            ((x as f64 - self.x0) / self.scale).floor() as i32,
+            ((y as f64 - self.y0) / self.scale).floor() as i32,
+        )
+    }
+
+    /// Returns (left, top, right, bottom) in tiles.
+    fn bounds(&self) -> (f64, f64, f64, f64) {
+        (
+            self.x0,
+            self.y0,
+            self.x0 + self.width as f64 * self.scale,
+            self.y0 + self.height as f64 * self.scale,
+        )
+    }
+
+    fn contains_chunk(&self, chunk_pos: &Position) -> bool {
+        let (left, top, right, bottom) = self.bounds();
+        let (x, y) = (
+            (chunk_pos.x * CHUNK_SIZE_I) as f64,
+            (chunk_pos.y * CHUNK_SIZE_I) as f64,
+        );
+        left < x + CHUNK_SIZE_F && x < right && top < y + CHUNK_SIZE_F && y < bottom
+    }
+}
+
+/// Fill the buffer with the minimap buffers of the chunks in the region, picking the tile at
+/// the center of each pixel. The pixels without a chunk are left untouched.
+fn sample_chunks(dest: &mut ImageBuffer, chunks: &Chunks, region: &MinimapRegion) {
+    for py in 0..dest.height {
+        // Consecutive pixels mostly fall in the same chunk, so remember the last one looked up.
+        let mut last_chunk: Option<(Position, Option<&Chunk>)> = None;
+        for px in 0..dest.width {
+            let (tx, ty) = region.tile_at_pixel(px as f64, py as f64);
+            let (chunk_pos, cell_pos) =
+                Position::new(tx.floor() as i32, ty.floor() as i32).div_mod(CHUNK_SIZE_I);
+            let chunk = match last_chunk {
+                Some((pos, chunk)) if pos == chunk_pos => chunk,
+                _ => {
+                    let chunk = chunks.get(&chunk_pos);
+                    last_chunk = Some((chunk_pos, chunk));
+                    chunk
+                }
+            };
+            if let Some(chunk) = chunk {
+                let s = ((cell_pos.x + cell_pos.y * CHUNK_SIZE_I) * 4) as usize;
+                let d = (px + py * dest.width) * 4;
+                dest.buf[d..d + 4].copy_from_slice(&chunk.minimap_buffer[s..s + 4]);
+            }
+        }
+    }
+}
+
+/// Draws the outline of a rectangle with inclusive corners, clipped to the buffer.
+fn stroke_rect(dest: &mut ImageBuffer, x0: i32, y0: i32, x1: i32, y1: i32, color: [u8; 3]) {
+    let (width, height) = (dest.width as i32, dest.height as i32);
+    let mut plot = |x: i32, y: i32| {
+        if 0 <= x && x < width && 0 <= y && y < height {
+            let start = ((x + y * width) * 4) as usize;
+            dest.buf[start..start + 3].copy_from_slice(&color);
+            dest.buf[start + 3] = 255;
+        }
+    };
+    for x in x0.max(-1)..=x1.min(width) {
+        plot(x, y0);
+        plot(x, y1);
+    }
+    for y in y0.max(-1)..=y1.min(height) {
+        plot(x0, y);
+        plot(x1, y);
     }
 }
 
 #[wasm_bindgen]
 impl FactorishState {
-    pub(crate) fn render_minimap_data(&mut self) -> Result<(), JsValue> {
+    /// The tile coordinates at the center of the minimap, which is the center of the viewport
+    /// unless the minimap is panned.
+    fn minimap_center(&self) -> (f64, f64) {
+        self.minimap_center.unwrap_or_else(|| {
+            self.viewport
+                .screen_to_world((self.viewport_width / 2., self.viewport_height / 2.))
+        })
+    }
+
+    fn minimap_region(&self, minimap_width: u32, minimap_height: u32) -> MinimapRegion {
+        MinimapRegion::new(
+            self.minimap_center(),
+            self.minimap_scale,
+            minimap_width,
+            minimap_height,
+        )
+    }
+
+    /// Returns the marker color of the structure on the minimap, or None if it is filtered out.
+    fn minimap_color_of_structure(&self, structure: &dyn Structure) -> Option<[u8; 3]> {
+        let name = structure.name();
+        if let Some(filter) = &self.minimap_structure_filter {
+            if !filter.contains(name) {
+                return None;
+            }
+        }
+        Some(match name {
+            "Steam Engine" | "Boiler" | "Offshore Pump" => [0xff, 0x7f, 0x00],
+            "Assembler" => [0x00, 0xff, 0xff],
+            "Chest" | "Requester Chest" => [0xff, 0xff, 0x00],
+            "Furnace" | "Electric Furnace" => [0xff, 0x3f, 0x3f],
+            "Ore Mine" | "Electric Ore Mine" => [0xff, 0x00, 0xff],
+            _ => [0x00, 0xff, 0x7f],
+        })
+    }
+
+    /// Repaint the minimap buffers of the chunks in the region of the last `render_minimap`.
+    /// The other chunks are repainted when they come into the minimap, which saves painting
+    /// the whole world on a large map.
+    pub(crate) fn render_minimap_data(&mut self) {
+        for chunk in self.board.values_mut() {
+            chunk.minimap_dirty = true;
+        }
+        let painted = self.render_minimap_region(&self.minimap_region.clone());
+
+        console_debug!(self, "painted {}", painted);
+    }
+
+    /// Repaint the outdated minimap buffers of the chunks in the region.
+    fn render_minimap_region(&mut self, region: &MinimapRegion) -> usize {
         let mut chunks = std::mem::take(&mut self.board);
         let mut painted = 0;
         for (chunk_pos, chunk) in &mut chunks {
-            painted += self.render_minimap_chunk(chunk_pos, chunk);
+            if chunk.minimap_dirty && region.contains_chunk(chunk_pos) {
+                painted += self.render_minimap_chunk(chunk_pos, chunk);
+            }
         }
         self.board = chunks;
-
-        console_log!("painted {}", painted);
-
-        Ok(())
+        painted
     }
 
     pub(crate) fn render_minimap_chunk(&self, chunk_pos: &Position, chunk: &mut Chunk) -> usize {
         let mut painted = 0;
+        chunk.minimap_dirty = false;
         let data = &mut chunk.minimap_buffer;
 
         for y in 0..CHUNK_SIZE_I {
@@ -82,9 +218,12 @@ impl FactorishState {
             }
         }
 
-        // context.set_fill_style(&JsValue::from_str("#00ff7f"));
-        let color = [0x00, 0xff, 0x7f];
         for structure in self.structure_iter() {
+            let color = if let Some(color) = self.minimap_color_of_structure(structure) {
+                color
+            } else {
+                continue;
+            };
             let Position { x, y } = *structure.position();
             if chunk_pos.x * CHUNK_SIZE_I <= x
                 && x < (chunk_pos.x + 1) * CHUNK_SIZE_I
@@ -103,16 +242,9 @@ impl FactorishState {
 
     pub(crate) fn render_minimap_data_pixel(&self, chunks: &mut Chunks, position: &Position) {
         let color = self
-            .structures
-            .iter()
-            .find(|structure| {
-                structure
-                    .dynamic
-                    .as_deref()
-                    .map(|s| *s.position() == *position)
-                    .unwrap_or(false)
-            })
-            .map(|_| [0x00, 0xff, 0x7f])
+            .structure_iter()
+            .find(|structure| *structure.position() == *position)
+            .and_then(|structure| self.minimap_color_of_structure(structure))
             .or_else(|| {
                 self.tile_at(position)
                     .map(|cell| Self::color_of_cell(&cell))
@@ -135,9 +267,11 @@ impl FactorishState {
         minimap_width: u32,
         minimap_height: u32,
     ) -> Result<ImageData, JsValue> {
-        let start_render = performance().now();
+        let start_render = self.clock.now();
 
-        let vp = self.get_viewport();
+        let region = self.minimap_region(minimap_width, minimap_height);
+        self.render_minimap_region(&region);
+        self.minimap_region = region;
         let data = &mut self.minimap_buffer;
         if data.len() != (minimap_width * minimap_height * 4) as usize {
             *data = vec![0u8; (minimap_width * minimap_height * 4) as usize];
@@ -149,27 +283,163 @@ impl FactorishState {
             width: minimap_width as usize,
             height: minimap_height as usize,
         };
-        for (pos, chunk) in &mut self.board {
-            let src = ImageBuffer {
-                buf: &mut chunk.minimap_buffer,
-                width: CHUNK_SIZE,
-                height: CHUNK_SIZE,
-            };
-            copy_rect(
-                &mut data_buf,
-                &src,
-                pos.x * CHUNK_SIZE_I + self.viewport.x as i32 + minimap_width as i32 / 2
-                    - (vp.0 / CHUNK_SIZE_F) as i32 / 4,
-                pos.y * CHUNK_SIZE_I + self.viewport.y as i32 + minimap_height as i32 / 2
-                    - (vp.1 / CHUNK_SIZE_F) as i32 / 4,
-            );
-        }
+        sample_chunks(&mut data_buf, &self.board, &region);
+
+        // Outline the region visible in the main view
+        let (left, top, right, bottom) = apply_bounds(
+            &self.bounds,
+            &self.viewport,
+            self.viewport_width,
+            self.viewport_height,
+        );
+        let (left, top) = region.pixel_of_tile(left, top);
+        let (right, bottom) = region.pixel_of_tile(right, bottom);
+        stroke_rect(&mut data_buf, left, top, right, bottom, [0xff, 0xff, 0xff]);
+
         let image_data = ImageData::new_with_u8_clamped_array_and_sh(
             Clamped::<_>(&mut *data),
             minimap_width as u32,
             minimap_height as u32,
         )?;
-        self.perf_minimap.add(performance().now() - start_render);
+        self.perf_minimap.add(self.clock.now() - start_render);
         return Ok(image_data);
     }
+
+    /// Choose which structure types are marked on the minimap.
+    /// @param types Array of structure names like "Steam Engine", or null to mark all structures.
+    pub fn set_minimap_structure_filter(&mut self, types: JsValue) -> Result<(), JsValue> {
+        self.minimap_structure_filter = if types.is_null() || types.is_undefined() {
+            None
+        } else {
+            Some(
+                js_sys::Array::from(&types)
+                    .iter()
+                    .map(|name| {
+                        name.as_string()
+                            .ok_or_else(|| js_str!("Structure type must be a string"))
+                    })
+                    .collect::<Result<_, _>>()?,
+            )
+        };
+        self.render_minimap_data();
+        Ok(())
+    }
+
+    /// Recenter the viewport on the tile under the given pixel of the minimap.
+    /// Returns the same value as `set_viewport_pos`.
+    pub fn minimap_click(
+        &mut self,
+        mx: f64,
+        my: f64,
+        minimap_width: u32,
+        minimap_height: u32,
+    ) -> Result<js_sys::Array, JsValue> {
+        let (x, y) = self
+            .minimap_region(minimap_width, minimap_height)
+            .tile_at_pixel(mx, my);
+        self.set_viewport_pos(x, y)
+    }
+
+    /// Set how many tiles a pixel of the minimap spans, which is clamped between
+    /// `MIN_MINIMAP_SCALE` and `MAX_MINIMAP_SCALE`. Only the region around the center is
+    /// rendered, so a smaller scale is cheaper on a large map.
+    /// @returns the scale actually applied.
+    pub fn set_minimap_scale(&mut self, tiles_per_pixel: f64) -> Result<f64, JsValue> {
+        if !(0. < tiles_per_pixel && tiles_per_pixel.is_finite()) {
+            return js_err!(
+                "Minimap scale must be a positive number: {}",
+                tiles_per_pixel
+            );
+        }
+        self.minimap_scale = tiles_per_pixel
+            .max(MIN_MINIMAP_SCALE)
+            .min(MAX_MINIMAP_SCALE);
+        Ok(self.minimap_scale)
+    }
+
+    pub fn get_minimap_scale(&self) -> f64 {
+        self.minimap_scale
+    }
+
+    /// Scroll the minimap by the given minimap pixels without moving the viewport.
+    /// The minimap stays there until `reset_minimap_pan` is called.
+    pub fn pan_minimap(&mut self, dx: f64, dy: f64) {
+        let (x, y) = self.minimap_center();
+        self.minimap_center = Some((x + dx * self.minimap_scale, y + dy * self.minimap_scale));
+    }
+
+    /// Make the minimap follow the viewport again.
+    pub fn reset_minimap_pan(&mut self) {
+        self.minimap_center = None;
+    }
+
+    /// Returns [left, top, right, bottom] in tiles of the region of the world that the last
+    /// `render_minimap` rendered, to align overlays with the minimap.
+    pub fn get_minimap_region(&self) -> js_sys::Array {
+        let (left, top, right, bottom) = self.minimap_region.bounds();
+        [left, top, right, bottom]
+            .iter()
+            .map(|v| JsValue::from_f64(*v))
+            .collect()
+    }
+}
+
+#[test]
+fn test_sample_chunks_scale() {
+    use super::terrain::CHUNK_SIZE2;
+
+    // A chunk at the origin whose pixels encode their tile coordinates in red and green
+    let mut chunk = Chunk::new(vec![]);
+    for i in 0..CHUNK_SIZE2 {
+        chunk.minimap_buffer[i * 4..i * 4 + 4].copy_from_slice(&[
+            (i % CHUNK_SIZE_I as usize) as u8,
+            (i / CHUNK_SIZE_I as usize) as u8,
+            0,
+            255,
+        ]);
+    }
+    let mut chunks = Chunks::new();
+    chunks.insert(Position::new(0, 0), chunk);
+
+    let pixel = |buf: &[u8], width: usize, x: usize, y: usize| {
+        let start = (x + y * width) * 4;
+        [buf[start], buf[start + 1], buf[start + 3]]
+    };
+
+    // 4 tiles per pixel centered on the middle of the chunk covers it in 4x4 pixels.
+    let region = MinimapRegion::new((8., 8.), 4., 8, 8);
+    assert_eq!(region.bounds(), (-8., -8., 24., 24.));
+    assert!(region.contains_chunk(&Position::new(0, 0)));
+    assert!(region.contains_chunk(&Position::new(1, 1)));
+    assert!(!region.contains_chunk(&Position::new(2, 0)));
+    let mut buf = vec![0u8; 8 * 8 * 4];
+    sample_chunks(
+        &mut ImageBuffer {
+            buf: &mut buf,
+            width: 8,
+            height: 8,
+        },
+        &chunks,
+        &region,
+    );
+    assert_eq!(pixel(&buf, 8, 1, 1), [0, 0, 0]);
+    assert_eq!(pixel(&buf, 8, 2, 2), [2, 2, 255]);
+    assert_eq!(pixel(&buf, 8, 5, 3), [14, 6, 255]);
+    assert_eq!(pixel(&buf, 8, 6, 6), [0, 0, 0]);
+    assert_eq!(region.pixel_of_tile(8, 8), (4, 4));
+    assert_eq!(region.tile_at_pixel(4.5, 4.), (10., 10.));
+
+    // At a tile per pixel, the chunk is copied as is.
+    let region = MinimapRegion::new((8., 8.), 1., 16, 16);
+    let mut buf = vec![0u8; 16 * 16 * 4];
+    sample_chunks(
+        &mut ImageBuffer {
+            buf: &mut buf,
+            width: 16,
+            height: 16,
+        },
+        &chunks,
+        &region,
+    );
+    assert_eq!(&buf[..], &chunks[&Position::new(0, 0)].minimap_buffer[..]);
 }
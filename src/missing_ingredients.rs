@@ -0,0 +1,97 @@
+use super::{
+    inventory::{Inventory, InventoryTrait},
+    items::item_to_str,
+    water_well::FluidBox,
+    FactorishState, Recipe,
+};
+use wasm_bindgen::prelude::*;
+
+/// The ingredients of the recipe that the input inventory and fluid boxes lack, as pairs of
+/// the item or fluid name and the amount short, sorted by the name.
+/// The fluid is consumed as the recipe progresses, but the whole amount is reported here.
+fn missing_ingredients(
+    recipe: &Recipe,
+    inventory: Option<&Inventory>,
+    fluid_boxes: &[&FluidBox],
+) -> Vec<(String, f64)> {
+    let mut ret = recipe
+        .input
+        .iter()
+        .filter_map(|(item, count)| {
+            let have = inventory.map_or(0, |inventory| inventory.count_item(item));
+            if have < *count {
+                Some((item_to_str(item), (*count - have) as f64))
+            } else {
+                None
+            }
+        })
+        .collect::<Vec<_>>();
+    if let Some(fluid) = recipe.input_fluid {
+        let have: f64 = fluid_boxes
+            .iter()
+            .filter(|fb| fb.input_enable && fb.type_ == Some(fluid))
+            .map(|fb| fb.amount)
+            .sum();
+        if have < recipe.input_fluid_amount {
+            ret.push((format!("{:?}", fluid), recipe.input_fluid_amount - have));
+        }
+    }
+    ret.sort_by(|a, b| a.0.cmp(&b.0));
+    ret
+}
+
+#[wasm_bindgen]
+impl FactorishState {
+    /// Returns [[name, count]] of the ingredients that the structure at the tile needs to start
+    /// its selected recipe, which is empty if no recipe is selected or nothing is missing.
+    pub fn get_missing_ingredients(&self, c: i32, r: i32) -> Result<js_sys::Array, JsValue> {
+        let structure = self
+            .find_structure_tile(&[c, r])
+            .ok_or_else(|| js_str!("Structure is not found"))?;
+        let recipe = if let Some(recipe) = structure.get_selected_recipe() {
+            recipe
+        } else {
+            return Ok(js_sys::Array::new());
+        };
+        let fluid_boxes = structure.fluid_box().unwrap_or_default();
+        Ok(
+            missing_ingredients(recipe, structure.inventory(true), &fluid_boxes)
+                .into_iter()
+                .map(|(name, count)| {
+                    js_sys::Array::of2(&JsValue::from_str(&name), &JsValue::from_f64(count))
+                })
+                .collect(),
+        )
+    }
+}
+
+#[test]
+fn test_missing_ingredients() {
+    use super::{items::ItemType, water_well::FluidType};
+
+    let recipe = Recipe::new(
+        hash_map!(ItemType::IronPlate => 2, ItemType::Gear => 1),
+        hash_map!(ItemType::Circuit => 1),
+        20.,
+        50.,
+    )
+    .with_input_fluid(FluidType::Water, 50.);
+
+    let mut inventory = Inventory::new();
+    inventory.add_item(&ItemType::IronPlate);
+    let mut fluid_box = FluidBox::new(true, false);
+    assert_eq!(
+        missing_ingredients(&recipe, Some(&inventory), &[&fluid_box]),
+        [
+            ("Gear".to_string(), 1.),
+            ("Iron Plate".to_string(), 1.),
+            ("Water".to_string(), 50.)
+        ]
+    );
+
+    inventory.add_items(&ItemType::IronPlate, 2);
+    inventory.add_item(&ItemType::Gear);
+    fluid_box.type_ = Some(FluidType::Water);
+    fluid_box.amount = 60.;
+    assert!(missing_ingredients(&recipe, Some(&inventory), &[&fluid_box]).is_empty());
+}
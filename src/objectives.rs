@@ -0,0 +1,120 @@
+use super::{
+    items::{item_to_str, ItemType},
+    FactorishState, InventoryTrait,
+};
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) enum Goal {
+    /// Produce the number of items in total since the objective is given
+    Produce(ItemType, usize),
+    /// Have the number of structures built at the same time
+    Build(ItemType, usize),
+}
+
+impl Goal {
+    fn count(&self) -> usize {
+        match self {
+            Goal::Produce(_, count) | Goal::Build(_, count) => *count,
+        }
+    }
+
+    fn desc(&self) -> String {
+        match self {
+            Goal::Produce(item, count) => format!("Produce {} {}", count, item_to_str(item)),
+            Goal::Build(item, count) => format!("Build {} {}", count, item_to_str(item)),
+        }
+    }
+}
+
+/// A goal declared by a scenario. The progress is saved with the game.
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct Objective {
+    goal: Goal,
+    progress: usize,
+    completed: bool,
+}
+
+impl Objective {
+    pub(crate) fn new(goal: Goal) -> Self {
+        Self {
+            goal,
+            progress: 0,
+            completed: false,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ObjectiveState {
+    description: String,
+    progress: usize,
+    count: usize,
+    completed: bool,
+}
+
+impl FactorishState {
+    /// Update the progress of the objectives. Called every frame from `simulate`.
+    /// @returns descriptions of the objectives that have just been completed.
+    pub(crate) fn update_objectives(&mut self) -> Vec<String> {
+        let mut completed = vec![];
+        for i in 0..self.objectives.len() {
+            if self.objectives[i].completed {
+                continue;
+            }
+            let progress = match &self.objectives[i].goal {
+                Goal::Produce(item, _) => {
+                    self.objectives[i].progress
+                        + self
+                            .production_stats
+                            .last_produced()
+                            .map_or(0, |produced| produced.count_item(item))
+                }
+                Goal::Build(item, _) => {
+                    let name = item_to_str(item);
+                    self.structure_iter().filter(|s| s.name() == name).count()
+                }
+            };
+            let objective = &mut self.objectives[i];
+            objective.progress = progress.min(objective.goal.count());
+            if objective.goal.count() <= objective.progress {
+                objective.completed = true;
+                completed.push(objective.goal.desc());
+            }
+        }
+        completed
+    }
+}
+
+#[wasm_bindgen]
+impl FactorishState {
+    /// Returns an array of `{description, progress, count, completed}` for the objectives
+    /// of the scenario.
+    pub fn get_objectives(&self) -> Result<JsValue, JsValue> {
+        JsValue::from_serde(
+            &self
+                .objectives
+                .iter()
+                .map(|objective| ObjectiveState {
+                    description: objective.goal.desc(),
+                    progress: objective.progress,
+                    count: objective.goal.count(),
+                    completed: objective.completed,
+                })
+                .collect::<Vec<_>>(),
+        )
+        .map_err(|e| js_str!("serialize error: {}", e))
+    }
+}
+
+#[test]
+fn test_objective_progress_serialized() {
+    let mut objective = Objective::new(Goal::Produce(ItemType::IronPlate, 50));
+    objective.progress = 20;
+    let loaded: Objective =
+        serde_json::from_value(serde_json::to_value(&objective).unwrap()).unwrap();
+    assert_eq!(loaded.progress, 20);
+    assert!(!loaded.completed);
+    assert_eq!(loaded.goal.desc(), "Produce 50 Iron Plate");
+}
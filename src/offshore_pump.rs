@@ -2,16 +2,35 @@ use super::{
     pipe::Pipe,
     structure::{Structure, StructureDynIter, StructureId},
     water_well::{FluidBox, FluidType},
-    FactorishState, FrameProcResult, Position,
+    FactorishState, FrameProcResult, Position, Rotation,
 };
 use serde::{Deserialize, Serialize};
 use wasm_bindgen::prelude::*;
 use web_sys::CanvasRenderingContext2d;
 
+/// Returns the direction of the land that a pump on the water tile at `position` outputs to,
+/// or None if the pump is not on a coast. The land in the direction of `rotation` is preferred,
+/// then the other sides clockwise.
+pub(crate) fn coast_direction(
+    position: &Position,
+    rotation: Rotation,
+    is_water: impl Fn(&Position) -> bool,
+) -> Option<Rotation> {
+    if !is_water(position) {
+        return None;
+    }
+    std::iter::successors(Some(rotation), |r| Some(r.next()))
+        .take(4)
+        .find(|r| !is_water(&position.add(r.delta())))
+}
+
 #[derive(Serialize, Deserialize)]
 pub(crate) struct OffshorePump {
     position: Position,
     output_fluid_box: FluidBox,
+    /// Direction of the land that the pump outputs to. Pumps from older saves output to all sides.
+    #[serde(default)]
+    rotation: Option<Rotation>,
 }
 
 impl OffshorePump {
@@ -19,6 +38,7 @@ impl OffshorePump {
         OffshorePump {
             position: *position,
             output_fluid_box: FluidBox::new(false, true).set_type(&FluidType::Water),
+            rotation: None,
         }
     }
 }
@@ -66,11 +86,10 @@ impl Structure for OffshorePump {
         &mut self,
         _me: StructureId,
         _state: &mut FactorishState,
-        structures: &mut StructureDynIter,
+        _structures: &mut StructureDynIter,
     ) -> Result<FrameProcResult, ()> {
         self.output_fluid_box.amount =
             (self.output_fluid_box.amount + 1.).min(self.output_fluid_box.max_amount);
-        self.output_fluid_box.simulate(structures);
         Ok(FrameProcResult::None)
     }
 
@@ -82,5 +101,43 @@ impl Structure for OffshorePump {
         Some(vec![&mut self.output_fluid_box])
     }
 
+    fn fluid_connects(&self, direction: Rotation) -> bool {
+        self.rotation.map_or(true, |rotation| rotation == direction)
+    }
+
+    fn rotation(&self) -> Option<Rotation> {
+        self.rotation
+    }
+
+    fn set_rotation(&mut self, rotation: &Rotation) -> Result<(), ()> {
+        self.rotation = Some(*rotation);
+        Ok(())
+    }
+
     crate::serialize_impl!();
 }
+
+#[test]
+fn test_coast_direction() {
+    // Water on the left half of the map, where x < 0
+    let is_water = |pos: &Position| pos.x < 0;
+    assert!(coast_direction(&Position::new(-5, 0), Rotation::Right, is_water).is_none());
+    assert!(coast_direction(&Position::new(1, 0), Rotation::Right, is_water).is_none());
+    // Turns to the land even if the tool is rotated toward the water
+    for rotation in &[
+        Rotation::Left,
+        Rotation::Top,
+        Rotation::Right,
+        Rotation::Bottom,
+    ] {
+        assert!(
+            coast_direction(&Position::new(-1, 3), *rotation, is_water) == Some(Rotation::Right)
+        );
+    }
+
+    // On a corner of a lake, the tool rotation picks one of the land sides
+    let is_water = |pos: &Position| pos.x < 0 && pos.y < 0;
+    let corner = Position::new(-1, -1);
+    assert!(coast_direction(&corner, Rotation::Bottom, is_water) == Some(Rotation::Bottom));
+    assert!(coast_direction(&corner, Rotation::Left, is_water) == Some(Rotation::Right));
+}
@@ -1,11 +1,12 @@
 use super::{
+    alerts::AlertType,
     draw_direction_arrow,
-    drop_items::hit_check,
     inventory::{Inventory, InventoryTrait},
     items::ItemType,
+    productivity::describe_productivity,
     structure::{RotateErr, Structure, StructureDynIter, StructureId},
-    DropItem, FactorishState, FrameProcResult, Position, Recipe, Rotation, TempEnt, COAL_POWER,
-    TILE_SIZE, TILE_SIZE_I,
+    unburnt_fuel, DropItem, FactorishState, FrameProcResult, Position, Recipe, Rotation, TempEnt,
+    COAL_POWER, TILE_SIZE,
 };
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
@@ -13,6 +14,127 @@ use wasm_bindgen::prelude::*;
 use web_sys::CanvasRenderingContext2d;
 
 const FUEL_CAPACITY: usize = 10;
+/// The number of mined ores that a mine holds while its output is blocked, before it stalls.
+pub(crate) const OUTPUT_BUFFER_CAPACITY: usize = 4;
+
+/// Take away a unit of ore from the cell at `position`.
+/// @returns the amount of ore left in the cell, or Err if there was none.
+pub(crate) fn deplete_ore(state: &mut FactorishState, position: &Position) -> Result<u32, ()> {
+    let tile = state.tile_at_mut(position).ok_or(())?;
+    let item_type = tile.get_ore_type().ok_or(())?;
+    let ore = tile.ore.as_mut().ok_or(())?;
+    let val = &mut ore.1;
+    if 0 < *val {
+        *val -= 1;
+        let ret = *val;
+        if ret == 0 {
+            tile.ore = None;
+        }
+        state.production_stats.add_produced(&item_type, 1);
+        state.on_ore_depleted(position, ret == 0);
+        Ok(ret)
+    } else {
+        Err(())
+    }
+}
+
+/// The mined ores on their way out of a mine, which is the same whether the mine burns fuel or
+/// draws electricity.
+#[derive(Default, Serialize, Deserialize)]
+pub(crate) struct MineOutput {
+    /// Bonus operations accumulated from the productivity. The whole part is the number of
+    /// operations that yield an item without depleting the ore.
+    #[serde(default)]
+    productivity_accum: f64,
+    /// Mined ores waiting for the output tile or structure to accept them.
+    #[serde(default)]
+    output_buffer: Inventory,
+}
+
+impl MineOutput {
+    pub(crate) fn buffered_count(&self) -> usize {
+        self.output_buffer.values().sum()
+    }
+
+    /// Whether the output buffer is full, in which case the mine does not mine any more.
+    pub(crate) fn is_stalled(&self) -> bool {
+        OUTPUT_BUFFER_CAPACITY <= self.buffered_count()
+    }
+
+    /// Pass the buffered ores one at a time to `output` until it refuses one.
+    /// @returns whether any ore was output.
+    fn flush_output(&mut self, mut output: impl FnMut(ItemType) -> bool) -> bool {
+        let mut flushed = false;
+        while let Some((item, _)) = self.output_buffer.ordered_items().first().copied() {
+            if !output(item) {
+                break;
+            }
+            self.output_buffer.remove_item(&item);
+            flushed = true;
+        }
+        flushed
+    }
+
+    /// Put the buffered ores into the structure in front of the mine, or on the tile.
+    /// @returns whether any ore was output.
+    pub(crate) fn output(
+        &mut self,
+        state: &mut FactorishState,
+        structures: &mut StructureDynIter,
+        output_structure: Option<StructureId>,
+        output_position: &Position,
+    ) -> bool {
+        self.flush_output(|type_| {
+            output_ore(state, structures, output_structure, output_position, type_)
+        })
+    }
+
+    /// Finish an operation of the `recipe` by moving its output to the buffer, which stays
+    /// complete while the buffer is full.
+    /// The ore at `position` is taken away, unless a bonus operation pays for it. If the
+    /// productivity adds up to a bonus operation, it starts complete so that the bonus item
+    /// comes out next without spending energy.
+    /// The recipe is cleared when the ore runs out.
+    pub(crate) fn complete_operation(
+        &mut self,
+        state: &mut FactorishState,
+        position: &Position,
+        recipe: &mut Option<Recipe>,
+        progress: &mut f64,
+    ) -> Result<(), ()> {
+        if self.is_stalled() {
+            return Ok(());
+        }
+        let type_ = *recipe
+            .as_ref()
+            .and_then(|recipe| recipe.output.keys().next())
+            .ok_or(())?;
+        if 1. <= self.productivity_accum {
+            self.productivity_accum -= 1.;
+        } else {
+            match deplete_ore(state, position) {
+                Ok(0) => *recipe = None,
+                Ok(_) => (),
+                Err(()) => {
+                    *recipe = None;
+                    return Err(());
+                }
+            }
+            self.productivity_accum += state.productivity_bonus();
+        }
+        self.output_buffer.add_item(&type_);
+        *progress = if 1. <= self.productivity_accum {
+            1.
+        } else {
+            0.
+        };
+        Ok(())
+    }
+
+    pub(crate) fn take_inventory(&mut self) -> Inventory {
+        std::mem::take(&mut self.output_buffer)
+    }
+}
 
 #[derive(Serialize, Deserialize)]
 pub(crate) struct OreMine {
@@ -26,6 +148,8 @@ pub(crate) struct OreMine {
     output_structure: Option<StructureId>,
     #[serde(skip)]
     digging: bool,
+    #[serde(flatten)]
+    output: MineOutput,
 }
 
 impl OreMine {
@@ -40,6 +164,7 @@ impl OreMine {
             input_inventory: Inventory::new(),
             output_structure: None,
             digging: false,
+            output: MineOutput::default(),
         }
     }
 
@@ -52,22 +177,54 @@ impl OreMine {
         let output_position = self.position.add(self.rotation.delta());
         if *other.position() == output_position {
             self.output_structure = if construct { Some(other_id) } else { None };
-            console_log!(
-                "OreMine{:?}: {} output_structure {:?}",
-                self.position,
-                if construct { "set" } else { "unset" },
-                other_id
-            );
         }
         Ok(())
     }
 }
 
+/// Put an ore into the structure in front of the mine, or drop it on the tile if there is room,
+/// which `new_object` checks.
+/// @returns whether the ore is output.
+fn output_ore(
+    state: &mut FactorishState,
+    structures: &mut StructureDynIter,
+    output_structure: Option<StructureId>,
+    output_position: &Position,
+    type_: ItemType,
+) -> bool {
+    if let Some(structure) = output_structure.and_then(|id| structures.get_mut(id)) {
+        if structure.can_input(&type_) {
+            return structure
+                .input(&DropItem {
+                    type_,
+                    x: output_position.x,
+                    y: output_position.y,
+                    count: 1,
+                })
+                .is_ok();
+        }
+        if !structure.movable() {
+            return false;
+        }
+    }
+    state.new_object(output_position, type_).is_ok()
+}
+
 impl Structure for OreMine {
     fn name(&self) -> &str {
         "Ore Mine"
     }
 
+    fn alert(&self) -> Option<AlertType> {
+        if self.recipe.is_some() && self.power == 0. {
+            Some(AlertType::NoFuel)
+        } else if self.output.is_stalled() {
+            Some(AlertType::OutputBlocked)
+        } else {
+            None
+        }
+    }
+
     fn position(&self) -> &Position {
         &self.position
     }
@@ -126,7 +283,8 @@ impl Structure for OreMine {
         };
         if let Some(_recipe) = &self.recipe {
             // Progress bar
-            format!("{}{}{}{}{}",
+            format!("{}{}{}{}{}{}{}",
+                if self.output.is_stalled() { "<b>Output blocked</b><br>" } else { "" },
                 format!("Progress: {:.0}%<br>", self.progress * 100.),
                 "<div style='position: relative; width: 100px; height: 10px; background-color: #001f1f; margin: 2px; border: 1px solid #3f3f3f'>",
                 format!("<div style='position: absolute; width: {}px; height: 10px; background-color: #ff00ff'></div></div>",
@@ -135,7 +293,11 @@ impl Structure for OreMine {
                  <div style='position: absolute; width: {}px; height: 10px; background-color: #ff00ff'></div></div>"#,
                     self.power,
                     if 0. < self.max_power { (self.power) / self.max_power * 100. } else { 0. }),
-                format!("Expected output: {}", tile.ore.map(|ore| ore.1).unwrap_or(0)))
+                describe_productivity(state.productivity_bonus()),
+                format!("Expected output: {}<br>Output buffer: {}/{}",
+                    tile.ore.map(|ore| ore.1).unwrap_or(0),
+                    self.output.buffered_count(),
+                    OUTPUT_BUFFER_CAPACITY))
         // getHTML(generateItemImage("time", true, this.recipe.time), true) + "<br>" +
         // "Outputs: <br>" +
         // getHTML(generateItemImage(this.recipe.output, true, 1), true) + "<br>";
@@ -188,93 +350,23 @@ impl Structure for OreMine {
                 }
             }
 
-            let output = |state: &mut FactorishState, _item, position: &Position| {
-                let tile = state.tile_at_mut(&position).ok_or(())?;
-                let ore = tile.ore.as_mut().ok_or(())?;
-                let val = &mut ore.1;
-                if 0 < *val {
-                    *val -= 1;
-                    let ret = *val;
-                    if ret == 0 {
-                        tile.ore = None;
-                    }
-                    Ok(ret)
-                } else {
-                    Err(())
-                }
-            };
-
             // Proceed only if we have sufficient energy in the buffer.
             let progress = (self.power / recipe.power_cost)
                 .min(1. / recipe.recipe_time)
                 .min(1. - self.progress);
             if 1. <= self.progress + progress {
-                let output_position = self.position.add(self.rotation.delta());
-                if let Some(structure) = self
-                    .output_structure
-                    .map(|id| structures.get_mut(id))
-                    .flatten()
-                {
-                    let mut it = recipe.output.iter();
-                    if let Some(item) = it.next() {
-                        // Check whether we can input first
-                        if structure.can_input(item.0) {
-                            if let Ok(val) = output(state, *item.0, &self.position) {
-                                structure
-                                    .input(&DropItem {
-                                        type_: *item.0,
-                                        x: output_position.x,
-                                        y: output_position.y,
-                                    })
-                                    .map_err(|_| ())?;
-                                if val == 0 {
-                                    self.recipe = None;
-                                }
-                                self.progress = 0.;
-                                return Ok(FrameProcResult::InventoryChanged(output_position));
-                            } else {
-                                self.recipe = None;
-                                return Err(());
-                            };
-                        }
-                    }
-                    if !structure.movable() {
-                        self.digging = false;
-                        return Ok(FrameProcResult::None);
-                    }
-                }
-                let drop_x = output_position.x * TILE_SIZE_I + TILE_SIZE_I / 2;
-                let drop_y = output_position.y * TILE_SIZE_I + TILE_SIZE_I / 2;
-                if !hit_check(&state.drop_items, drop_x, drop_y, None)
-                    && state
-                        .tile_at(&output_position)
-                        .map(|cell| !cell.water)
-                        .unwrap_or(false)
-                {
-                    // let dest_tile = state.board[dx as usize + dy as usize * state.width as usize];
-                    let mut it = recipe.output.iter();
-                    if let Some(item) = it.next() {
-                        assert!(it.next().is_none());
-                        if let Err(_code) = state.new_object(&output_position, *item.0) {
-                            // console_log!("Failed to create object: {:?}", code);
-                        } else if let Ok(val) = output(state, *item.0, &self.position) {
-                            if val == 0 {
-                                self.recipe = None;
-                            }
-                            self.progress = 0.;
-                        }
-                    } else {
-                        return Err(());
-                    }
-                    self.progress = 0.;
-                } else {
-                    // Output is blocked
-                    self.digging = false;
-                    return Ok(FrameProcResult::None);
-                }
+                // The mined ore goes to the output buffer, so it is not lost even if the output
+                // is blocked.
+                self.output.complete_operation(
+                    state,
+                    &self.position,
+                    &mut self.recipe,
+                    &mut self.progress,
+                )?;
             } else {
                 self.progress += progress;
                 self.power -= progress * recipe.power_cost;
+                state.emit_pollution(&self.position, progress * recipe.power_cost);
                 self.digging = 0. < progress;
             }
 
@@ -287,6 +379,17 @@ impl Structure for OreMine {
         } else {
             self.digging = false;
         }
+
+        let output_position = self.position.add(self.rotation.delta());
+        if self
+            .output
+            .output(state, structures, self.output_structure, &output_position)
+        {
+            ret = FrameProcResult::InventoryChanged(output_position);
+        }
+        if self.output.is_stalled() {
+            self.digging = false;
+        }
         Ok(ret)
     }
 
@@ -300,6 +403,10 @@ impl Structure for OreMine {
         Ok(())
     }
 
+    fn rotation(&self) -> Option<Rotation> {
+        Some(self.rotation)
+    }
+
     fn set_rotation(&mut self, rotation: &Rotation) -> Result<(), ()> {
         self.rotation = *rotation;
         Ok(())
@@ -369,16 +476,125 @@ impl Structure for OreMine {
     }
 
     fn destroy_inventory(&mut self) -> Inventory {
+        let mut ret = std::mem::take(&mut self.input_inventory);
+        // The ores already mined are returned as well.
+        ret.merge(self.output.take_inventory());
+        ret.merge(unburnt_fuel(std::mem::take(&mut self.power)));
         // Return the ingredients if it was in the middle of processing a recipe.
         if let Some(recipe) = self.recipe.take() {
             if 0. < self.progress {
-                let mut ret = std::mem::take(&mut self.input_inventory);
                 ret.merge(recipe.input);
-                return ret;
             }
         }
-        std::mem::take(&mut self.input_inventory)
+        ret
     }
 
     crate::serialize_impl!();
 }
+
+#[test]
+fn test_output_buffer_stalls() {
+    let mut mine = OreMine::new(0, 0, Rotation::Right);
+    for _ in 0..OUTPUT_BUFFER_CAPACITY {
+        assert!(!mine.output.is_stalled());
+        mine.output.output_buffer.add_item(&ItemType::IronOre);
+        // The output tile is blocked
+        assert!(!mine.output.flush_output(|_| false));
+    }
+    assert!(mine.output.is_stalled());
+    assert_eq!(mine.alert(), Some(AlertType::OutputBlocked));
+
+    // Only a single ore fits on the tile once it is cleared, and the rest wait in the buffer.
+    let mut dropped = 0;
+    assert!(mine.output.flush_output(|_| {
+        dropped += 1;
+        dropped <= 1
+    }));
+    assert_eq!(mine.output.buffered_count(), OUTPUT_BUFFER_CAPACITY - 1);
+    assert!(!mine.output.is_stalled());
+
+    let mut output = vec![];
+    assert!(mine.output.flush_output(|item| {
+        output.push(item);
+        true
+    }));
+    assert_eq!(output, vec![ItemType::IronOre; OUTPUT_BUFFER_CAPACITY - 1]);
+    assert_eq!(mine.alert(), None);
+}
+
+#[test]
+fn test_blocked_output_pauses_mining() {
+    use super::{scenarios::empty_state, Ore, OreValue};
+    const ORE_AMOUNT: u32 = 100;
+    let mut state = empty_state(16, 16);
+    let position = Position::new(1, 1);
+    state.tile_at_mut(&position).unwrap().ore = Some(OreValue(Ore::Iron, ORE_AMOUNT));
+    // A stone on the output tile blocks the ore from being dropped.
+    let output = Position::new(2, 1);
+    state.new_object(&output, ItemType::StoneOre).unwrap();
+
+    fn mine(state: &FactorishState, id: StructureId) -> &dyn Structure {
+        state.structures[id.id as usize].dynamic.as_deref().unwrap()
+    }
+    let mut new_mine = OreMine::new(position.x, position.y, Rotation::Right);
+    new_mine
+        .input_inventory
+        .add_items(&ItemType::CoalOre, FUEL_CAPACITY);
+    let id = state.add_structure(Box::new(new_mine)).unwrap();
+    let ore_left = |state: &FactorishState| state.tile_at(&position).unwrap().ore.unwrap().1;
+
+    for _ in 0..1000 {
+        if mine(&state, id).alert() == Some(AlertType::OutputBlocked) {
+            break;
+        }
+        state.tick_headless(0.05).unwrap();
+    }
+    assert_eq!(mine(&state, id).alert(), Some(AlertType::OutputBlocked));
+    assert!(mine(&state, id).desc(&state).contains("Output blocked"));
+
+    // The drill pauses without taking more ore out of the ground.
+    let mined = ORE_AMOUNT - ore_left(&state);
+    for _ in 0..100 {
+        state.tick_headless(0.05).unwrap();
+    }
+    assert_eq!(ORE_AMOUNT - ore_left(&state), mined);
+
+    // Every mined ore is kept in the mine.
+    let contents = state.structures[id.id as usize]
+        .dynamic
+        .as_deref_mut()
+        .unwrap()
+        .destroy_inventory();
+    assert_eq!(contents.count_item(&ItemType::IronOre), mined as usize);
+    assert_eq!(mined as usize, OUTPUT_BUFFER_CAPACITY);
+}
+
+#[test]
+fn test_complete_operation_productivity() {
+    use super::{scenarios::empty_state, Ore, OreValue};
+    const ORE_AMOUNT: u32 = 100;
+    let mut state = empty_state(16, 16);
+    state.productivity = 0.5;
+    let position = Position::new(1, 1);
+    state.tile_at_mut(&position).unwrap().ore = Some(OreValue(Ore::Iron, ORE_AMOUNT));
+    let mut recipe = Some(Recipe::new(
+        HashMap::new(),
+        hash_map!(ItemType::IronOre => 1usize),
+        8.,
+        80.,
+    ));
+    let mut output = MineOutput::default();
+    let mut progress = 1.;
+    let mut progresses = vec![];
+    for _ in 0..4 {
+        output
+            .complete_operation(&mut state, &position, &mut recipe, &mut progress)
+            .unwrap();
+        progresses.push(progress);
+    }
+    // Every other operation gives a bonus ore, which starts complete.
+    assert_eq!(progresses, [0., 1., 0., 0.]);
+    assert_eq!(output.take_inventory().count_item(&ItemType::IronOre), 4);
+    let ore_left = state.tile_at(&position).unwrap().ore.unwrap().1;
+    assert_eq!(ORE_AMOUNT - ore_left, 3);
+}
@@ -0,0 +1,102 @@
+use super::{structure::Position, FactorishState, Ore, OreValue};
+use std::collections::{HashSet, VecDeque};
+use wasm_bindgen::prelude::*;
+
+/// Maximum number of tiles to flood fill, since a patch can be arbitrarily large in an unlimited world.
+/// Larger patches are truncated, so their totals are underestimated.
+const MAX_PATCH_TILES: usize = 4096;
+
+/// A set of contiguous tiles with the same type of ore.
+pub(crate) struct OrePatch {
+    ore: Ore,
+    tiles: HashSet<Position>,
+    /// Sum of the remaining ore in the tiles.
+    total: u32,
+}
+
+impl FactorishState {
+    fn flood_fill_ore_patch(&self, start: &Position) -> Option<OrePatch> {
+        let ore = self.tile_at(start)?.ore?.0;
+        let mut tiles = HashSet::new();
+        let mut queue = VecDeque::new();
+        let mut total = 0;
+        tiles.insert(*start);
+        queue.push_back(*start);
+        while let Some(position) = queue.pop_front() {
+            if let Some(OreValue(_, amount)) = self.tile_at(&position).and_then(|cell| cell.ore) {
+                total += amount;
+            }
+            for delta in &[(-1, 0), (0, -1), (1, 0), (0, 1)] {
+                let next = position.add(*delta);
+                if MAX_PATCH_TILES <= tiles.len() || tiles.contains(&next) {
+                    continue;
+                }
+                if let Some(OreValue(next_ore, _)) = self.tile_at(&next).and_then(|cell| cell.ore) {
+                    if next_ore == ore {
+                        tiles.insert(next);
+                        queue.push_back(next);
+                    }
+                }
+            }
+        }
+        Some(OrePatch { ore, tiles, total })
+    }
+
+    /// Returns the ore patch containing the tile, flood filling it only if it is not cached yet.
+    pub(crate) fn ore_patch_at(&mut self, position: &Position) -> Option<&OrePatch> {
+        let idx = if let Some(idx) = self
+            .ore_patches
+            .iter()
+            .position(|patch| patch.tiles.contains(position))
+        {
+            idx
+        } else {
+            let patch = self.flood_fill_ore_patch(position)?;
+            self.ore_patches.push(patch);
+            self.ore_patches.len() - 1
+        };
+        self.ore_patches.get(idx)
+    }
+
+    /// Keep the cached patches in sync when a unit of ore is taken from a tile.
+    /// @param emptied whether the tile has run out of ore.
+    pub(crate) fn on_ore_depleted(&mut self, position: &Position, emptied: bool) {
+        if let Some(idx) = self
+            .ore_patches
+            .iter()
+            .position(|patch| patch.tiles.contains(position))
+        {
+            if emptied {
+                // The patch may be split into pieces, so flood fill it again on next query.
+                self.ore_patches.swap_remove(idx);
+            } else {
+                let patch = &mut self.ore_patches[idx];
+                patch.total = patch.total.saturating_sub(1);
+            }
+        }
+    }
+
+    /// Returns a line of the info panel describing the ore patch at the tile.
+    pub(crate) fn ore_patch_desc(&mut self, position: &Position) -> Option<String> {
+        let patch = self.ore_patch_at(position)?;
+        Some(format!(
+            "{:?} patch: {} in {} tiles",
+            patch.ore,
+            patch.total,
+            patch.tiles.len()
+        ))
+    }
+}
+
+#[wasm_bindgen]
+impl FactorishState {
+    /// Returns 2-array of [total remaining ore, number of tiles] in the ore patch
+    /// containing the tile, or null if there is no ore.
+    pub fn get_ore_patch_total(&mut self, c: i32, r: i32) -> Option<js_sys::Array> {
+        let patch = self.ore_patch_at(&Position::new(c, r))?;
+        Some(js_sys::Array::of2(
+            &JsValue::from(patch.total),
+            &JsValue::from(patch.tiles.len() as u32),
+        ))
+    }
+}
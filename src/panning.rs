@@ -0,0 +1,77 @@
+use super::{FactorishState, Viewport};
+use wasm_bindgen::prelude::*;
+
+/// Rate per second to reach the requested velocity while a key is held.
+const PAN_ACCELERATION: f64 = 6.;
+/// Rate per second to slow down after the keys are released.
+const PAN_FRICTION: f64 = 4.;
+/// Below this speed in tiles per second, the viewport stops.
+const PAN_MIN_SPEED: f64 = 0.05;
+
+/// Approach the velocity toward the input and return the distance to move in this step.
+fn update_velocity(viewport: &mut Viewport, delta_time: f64) -> (f64, f64) {
+    let (ix, iy) = viewport.pan_input;
+    let rate = if ix == 0. && iy == 0. {
+        PAN_FRICTION
+    } else {
+        PAN_ACCELERATION
+    };
+    let t = (rate * delta_time).min(1.);
+    let (vx, vy) = viewport.velocity;
+    let (mut vx, mut vy) = (vx + (ix - vx) * t, vy + (iy - vy) * t);
+    if ix == 0. && iy == 0. && vx.hypot(vy) < PAN_MIN_SPEED {
+        vx = 0.;
+        vy = 0.;
+    }
+    viewport.velocity = (vx, vy);
+    (vx * delta_time, vy * delta_time)
+}
+
+impl FactorishState {
+    /// Move the viewport by its velocity, called every simulation step.
+    pub(crate) fn update_panning(&mut self, delta_time: f64) {
+        let (dx, dy) = update_velocity(&mut self.viewport, delta_time);
+        if dx == 0. && dy == 0. {
+            return;
+        }
+        // The velocity is in screen space, so it looks the same speed at any zoom level.
+        self.viewport.x -= dx / self.viewport.scale;
+        self.viewport.y -= dy / self.viewport.scale;
+        if let Some(bounds) = self.bounds.as_ref() {
+            self.viewport.x = self.viewport.x.min(0.).max(-(bounds.width as f64));
+            self.viewport.y = self.viewport.y.min(0.).max(-(bounds.height as f64));
+        }
+        self.gen_chunks_in_viewport();
+    }
+}
+
+#[wasm_bindgen]
+impl FactorishState {
+    /// Set the velocity to pan the viewport in tiles per second, e.g. while arrow keys are held.
+    /// The viewport accelerates toward it and keeps moving a little after it is set to zero.
+    pub fn set_viewport_velocity(&mut self, vx: f64, vy: f64) {
+        self.viewport.pan_input = (vx, vy);
+    }
+}
+
+#[test]
+fn test_panning_inertia() {
+    let mut viewport = Viewport::default();
+    viewport.pan_input = (10., 0.);
+    let mut moved = 0.;
+    let mut last_speed = 0.;
+    for _ in 0..10 {
+        moved += update_velocity(&mut viewport, 0.05).0;
+        // Accelerating
+        assert!(last_speed < viewport.velocity.0);
+        last_speed = viewport.velocity.0;
+    }
+    viewport.pan_input = (0., 0.);
+    let before_release = moved;
+    for _ in 0..100 {
+        moved += update_velocity(&mut viewport, 0.05).0;
+    }
+    // Keeps going a little after the release and eventually stops
+    assert!(before_release < moved);
+    assert_eq!(viewport.velocity, (0., 0.));
+}
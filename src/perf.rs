@@ -1,12 +1,46 @@
-use std::collections::VecDeque;
+use serde::Serialize;
+use std::{cell::Cell, collections::VecDeque, rc::Rc};
 use wasm_bindgen::prelude::*;
 use web_sys::CanvasRenderingContext2d;
 
-use super::FactorishState;
+use super::{performance, FactorishState};
 
 const MOVING_AVERAGE: usize = 10;
 const PERF_HISTORY: usize = 200;
 
+/// Source of the time in milliseconds for the performance measurements.
+/// The simulation itself never reads the clock, so that it does not depend on the browser.
+pub(crate) trait Clock {
+    fn now(&self) -> f64;
+}
+
+/// The high resolution timer of the browser
+pub(crate) struct PerformanceClock;
+
+impl Clock for PerformanceClock {
+    fn now(&self) -> f64 {
+        performance().now()
+    }
+}
+
+/// A clock that advances only when told to, for tests and headless runs.
+/// Clones share the time, so a test can keep a handle to the clock it gave to the game.
+#[derive(Default, Clone)]
+pub(crate) struct ManualClock(Rc<Cell<f64>>);
+
+impl ManualClock {
+    #[cfg(test)]
+    pub(crate) fn advance(&self, millis: f64) {
+        self.0.set(self.0.get() + millis);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> f64 {
+        self.0.get()
+    }
+}
+
 #[derive(Default)]
 pub(crate) struct PerfStats {
     values: VecDeque<f64>,
@@ -30,10 +64,91 @@ impl PerfStats {
         self.total += sample;
         self.count += 1;
     }
+
+    /// Statistics of the samples in the history window.
+    fn summary(&self) -> PerfSummary {
+        if self.values.is_empty() {
+            return PerfSummary::default();
+        }
+        PerfSummary {
+            avg: self.values.iter().sum::<f64>() / self.values.len() as f64,
+            min: self.values.iter().copied().fold(f64::INFINITY, f64::min),
+            max: self
+                .values
+                .iter()
+                .copied()
+                .fold(f64::NEG_INFINITY, f64::max),
+            samples: self.values.len(),
+        }
+    }
+}
+
+/// Frame times in milliseconds, which are all zero if there are no samples.
+#[derive(Serialize, Default)]
+struct PerfSummary {
+    avg: f64,
+    min: f64,
+    max: f64,
+    samples: usize,
+}
+
+#[derive(Serialize)]
+struct PerfReport {
+    structures: PerfSummary,
+    drop_items: PerfSummary,
+    simulate: PerfSummary,
+    minimap: PerfSummary,
+    render: PerfSummary,
+    structure_count: usize,
+    drop_item_count: usize,
+    chunk_count: usize,
+    /// Structures that skipped the last tick because they are idle and out of the view
+    sleeping_count: usize,
+}
+
+impl FactorishState {
+    /// Replace the time source, e.g. with a `ManualClock` where the browser is not available.
+    pub(crate) fn set_clock(&mut self, clock: Box<dyn Clock>) {
+        self.clock = clock;
+    }
 }
 
 #[wasm_bindgen]
 impl FactorishState {
+    /// Returns the average, minimum and maximum frame times of each subsystem over the last
+    /// frames, along with the numbers of structures, items and chunks for context.
+    pub fn get_perf_stats(&self) -> Result<JsValue, JsValue> {
+        let report = PerfReport {
+            structures: self.perf_structures.summary(),
+            drop_items: self.perf_drop_items.summary(),
+            simulate: self.perf_simulate.summary(),
+            minimap: self.perf_minimap.summary(),
+            render: self.perf_render.summary(),
+            structure_count: self.structure_iter().count(),
+            drop_item_count: self
+                .drop_items
+                .iter()
+                .filter(|entry| entry.item.is_some())
+                .count(),
+            chunk_count: self.board.len(),
+            sleeping_count: self
+                .structures
+                .iter()
+                .filter(|entry| entry.dynamic.is_some() && !entry.active)
+                .count(),
+        };
+        JsValue::from_serde(&report).map_err(|e| js_str!("serialize error: {}", e))
+    }
+
+    /// Clear the history of the performance measurements.
+    pub fn reset_perf_stats(&mut self) {
+        self.perf_structures = PerfStats::default();
+        self.perf_drop_items = PerfStats::default();
+        self.perf_simulate = PerfStats::default();
+        self.perf_minimap = PerfStats::default();
+        self.perf_render = PerfStats::default();
+    }
+
     pub fn render_perf(&self, context: CanvasRenderingContext2d) -> js_sys::Array {
         let canvas = context.canvas().unwrap();
         let (width, height) = (canvas.width(), canvas.height());
@@ -111,3 +226,28 @@ impl FactorishState {
         .collect()
     }
 }
+
+#[test]
+fn test_manual_clock() {
+    let clock = ManualClock::default();
+    let mut stats = PerfStats::default();
+    let start = clock.now();
+    clock.advance(2.5);
+    stats.add(clock.now() - start);
+    assert_eq!(stats.total, 2.5);
+    assert_eq!(stats.count, 1);
+}
+
+#[test]
+fn test_perf_summary() {
+    let mut stats = PerfStats::default();
+    assert_eq!(stats.summary().samples, 0);
+    for sample in &[2., 1., 3.] {
+        stats.add(*sample);
+    }
+    let summary = stats.summary();
+    assert_eq!(
+        (summary.avg, summary.min, summary.max, summary.samples),
+        (2., 1., 3., 3)
+    );
+}
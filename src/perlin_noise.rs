@@ -1,5 +1,7 @@
 //! Perlin noise implementation from Wikipedia https://en.wikipedia.org/wiki/Perlin_noise
 
+use serde::{Deserialize, Serialize};
+
 pub(crate) fn perlin_noise_pixel(x: f64, y: f64, bit: u32, terms: &[[f64; 6]]) -> f64 {
     let mut sum = 0.;
     let [mut maxv, mut f] = [0., 1.];
@@ -60,6 +62,7 @@ fn noise_pixel(ix: f64, iy: f64, x: f64, y: f64, terms: &[f64; 6]) -> f64 {
     dx * gradient[0] + dy * gradient[1]
 }
 
+#[derive(Clone, Serialize, Deserialize)]
 pub(crate) struct Xor128 {
     x: u32,
     y: u32,
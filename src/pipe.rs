@@ -35,21 +35,14 @@ impl Pipe {
         let (x, y) = (position.x as f64 * 32., position.y as f64 * 32.);
         match state.image_pipe.as_ref() {
             Some(img) => {
+                // Fluid boxes may face different sides, e.g. the input and output of a valve.
                 let connections = structure
                     .fluid_box()
-                    .map(|fluid_boxes| {
-                        Some(
-                            fluid_boxes
-                                .first()?
-                                .connect_to
-                                .iter()
-                                .enumerate()
-                                .filter(|(_, b)| b.is_some())
-                                .fold(0, |acc, (i, _)| acc | (1 << i)),
-                        )
-                    })
+                    .into_iter()
                     .flatten()
-                    .unwrap_or(0);
+                    .flat_map(|fluid_box| fluid_box.connect_to.iter().enumerate())
+                    .filter(|(_, b)| b.is_some())
+                    .fold(0, |acc, (i, _)| acc | (1 << i));
                 // Skip drawing center dot? if there are no connections
                 if !draw_center && connections == 0 {
                     return Ok(());
@@ -84,6 +77,10 @@ impl Structure for Pipe {
         &self.position
     }
 
+    fn walkable(&self) -> bool {
+        true
+    }
+
     fn draw(
         &self,
         state: &FactorishState,
@@ -105,9 +102,8 @@ impl Structure for Pipe {
         &mut self,
         _me: StructureId,
         _state: &mut FactorishState,
-        structures: &mut StructureDynIter,
+        _structures: &mut StructureDynIter,
     ) -> Result<FrameProcResult, ()> {
-        self.fluid_box.simulate(structures);
         Ok(FrameProcResult::None)
     }
 
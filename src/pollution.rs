@@ -0,0 +1,102 @@
+use super::{
+    structure::Position,
+    terrain::{CHUNK_SIZE_F, CHUNK_SIZE_I},
+    FactorishState, TILE_SIZE,
+};
+use wasm_bindgen::prelude::*;
+use web_sys::CanvasRenderingContext2d;
+
+/// Pollution emitted per kilojoule of burned fuel.
+const POLLUTION_PER_KJ: f64 = 0.01;
+/// Fraction of the pollution in a chunk that spreads to the neighbors in a frame.
+const DIFFUSION_RATE: f64 = 0.02;
+/// Fraction of the pollution that is absorbed in a frame.
+const DECAY_RATE: f64 = 0.001;
+/// Pollution below this value is cleared, so that the chunks do not diffuse forever.
+const POLLUTION_EPSILON: f64 = 1e-3;
+/// Pollution at which the overlay is drawn with full opacity.
+const OVERLAY_MAX: f64 = 100.;
+
+impl FactorishState {
+    /// Add pollution to the chunk containing the tile, in proportion to the energy burned.
+    pub(crate) fn emit_pollution(&mut self, position: &Position, energy: f64) {
+        let (chunk_pos, _) = position.div_mod(CHUNK_SIZE_I);
+        if let Some(chunk) = self.board.get_mut(&chunk_pos) {
+            chunk.pollution += energy * POLLUTION_PER_KJ;
+        }
+    }
+
+    /// Diffuse the pollution to the neighbor chunks and let it decay.
+    /// It works at chunk granularity, since doing it per tile would be too expensive.
+    pub(crate) fn update_pollution(&mut self) {
+        let mut deltas = vec![];
+        for (chunk_pos, chunk) in &self.board {
+            if chunk.pollution <= 0. {
+                continue;
+            }
+            let spread = chunk.pollution * DIFFUSION_RATE / 4.;
+            for delta in &[(-1, 0), (0, -1), (1, 0), (0, 1)] {
+                let neighbor = chunk_pos.add(*delta);
+                // Pollution stays in the generated area
+                if self.board.contains_key(&neighbor) {
+                    deltas.push((neighbor, spread));
+                    deltas.push((*chunk_pos, -spread));
+                }
+            }
+        }
+        for (chunk_pos, delta) in deltas {
+            if let Some(chunk) = self.board.get_mut(&chunk_pos) {
+                chunk.pollution += delta;
+            }
+        }
+        for chunk in self.board.values_mut() {
+            chunk.pollution *= 1. - DECAY_RATE;
+            if chunk.pollution < POLLUTION_EPSILON {
+                chunk.pollution = 0.;
+            }
+        }
+    }
+
+    pub(crate) fn render_pollution(
+        &self,
+        context: &CanvasRenderingContext2d,
+        (left, top, right, bottom): (i32, i32, i32, i32),
+    ) {
+        for cy in top.div_euclid(CHUNK_SIZE_I)..=bottom.div_euclid(CHUNK_SIZE_I) {
+            for cx in left.div_euclid(CHUNK_SIZE_I)..=right.div_euclid(CHUNK_SIZE_I) {
+                let pollution = self
+                    .board
+                    .get(&Position::new(cx, cy))
+                    .map_or(0., |chunk| chunk.pollution);
+                if pollution <= 0. {
+                    continue;
+                }
+                context.set_fill_style(&js_str!(
+                    "rgba(255,0,0,{})",
+                    (pollution / OVERLAY_MAX).min(1.) * 0.5
+                ));
+                context.fill_rect(
+                    cx as f64 * CHUNK_SIZE_F * TILE_SIZE,
+                    cy as f64 * CHUNK_SIZE_F * TILE_SIZE,
+                    CHUNK_SIZE_F * TILE_SIZE,
+                    CHUNK_SIZE_F * TILE_SIZE,
+                );
+            }
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl FactorishState {
+    pub fn set_pollution_overlay(&mut self, value: bool) {
+        self.pollution_overlay = value;
+    }
+
+    /// Returns the pollution of the chunk containing the tile.
+    pub fn get_pollution_at(&self, c: i32, r: i32) -> f64 {
+        let (chunk_pos, _) = Position::new(c, r).div_mod(CHUNK_SIZE_I);
+        self.board
+            .get(&chunk_pos)
+            .map_or(0., |chunk| chunk.pollution)
+    }
+}
@@ -1,14 +1,304 @@
 use super::{
-    structure::{StructureDynIter, StructureId},
-    PowerWire,
+    replay::ReplayInput,
+    structure::{Structure, StructureDynIter, StructureEntry, StructureId},
+    FactorishState, PowerWire, TILE_SIZE,
 };
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use wasm_bindgen::prelude::*;
+use web_sys::CanvasRenderingContext2d;
 
 #[derive(Debug)]
 pub(crate) struct PowerNetwork {
     pub wires: Vec<PowerWire>,
     pub sources: HashSet<StructureId>,
     pub sinks: HashSet<StructureId>,
+    /// Structures that store surplus energy and give it back on deficit.
+    /// They are not included in `sources` nor `sinks`.
+    pub accumulators: HashSet<StructureId>,
+    /// Ratio of the demand that can be satisfied in this frame, in the range [0, 1].
+    /// Consumers are supplied proportionally when demand exceeds supply.
+    pub power_ratio: f64,
+    /// Energy in kJ available from generators and accumulators in this frame.
+    pub supply: f64,
+    /// Energy in kJ requested by consumers in this frame.
+    pub demand: f64,
+}
+
+/// Default value for structures' power ratio, e.g. right after deserialization.
+pub(crate) fn full_power_ratio() -> f64 {
+    1.
+}
+
+/// Whether a power wire is made between the two structures when one of them is placed.
+pub(crate) fn would_connect(a: &dyn Structure, b: &dyn Structure) -> bool {
+    (a.power_sink() && b.power_source() || a.power_source() && b.power_sink())
+        && a.position().distance(b.position()) <= a.wire_reach().min(b.wire_reach()) as i32
+}
+
+/// Map the structures in the networks to the indices of their networks.
+fn structure_networks(networks: &[PowerNetwork]) -> HashMap<StructureId, usize> {
+    networks
+        .iter()
+        .enumerate()
+        .flat_map(|(i, network)| {
+            network
+                .sources
+                .iter()
+                .chain(&network.sinks)
+                .chain(&network.accumulators)
+                .map(move |id| (*id, i))
+        })
+        .collect()
+}
+
+/// A translucent color for the network index. The hue advances by the golden angle, so that
+/// the networks with close indices get distinct colors.
+fn network_tint(index: usize) -> String {
+    format!("hsla({:.0},100%,50%,0.4)", (index as f64 * 137.5) % 360.)
+}
+
+impl FactorishState {
+    /// Tint the structures among the given ones by the power network they belong to,
+    /// to reveal which of them share a grid.
+    pub(crate) fn render_power_tint(
+        &self,
+        context: &CanvasRenderingContext2d,
+        ids: &[StructureId],
+    ) {
+        let networks = structure_networks(&self.power_networks);
+        for id in ids {
+            let (network, structure) =
+                if let Some(pair) = networks.get(id).zip(self.get_structure(*id)) {
+                    pair
+                } else {
+                    continue;
+                };
+            let bb = structure.bounding_box();
+            context.set_fill_style(&JsValue::from_str(&network_tint(*network)));
+            context.fill_rect(
+                bb.x0 as f64 * TILE_SIZE,
+                bb.y0 as f64 * TILE_SIZE,
+                (bb.x1 - bb.x0) as f64 * TILE_SIZE,
+                (bb.y1 - bb.y0) as f64 * TILE_SIZE,
+            );
+        }
+    }
+
+    /// Preview the wire reach of a structure to be placed and the structures it would connect to.
+    pub(crate) fn render_wire_reach(
+        &self,
+        context: &CanvasRenderingContext2d,
+        tool: &dyn Structure,
+    ) -> Result<(), JsValue> {
+        if !tool.power_source() && !tool.power_sink() {
+            return Ok(());
+        }
+        let position = tool.position();
+        let reach = tool.wire_reach() as f64;
+        let (cx, cy) = (
+            (position.x as f64 + 0.5) * TILE_SIZE,
+            (position.y as f64 + 0.5) * TILE_SIZE,
+        );
+        context.save();
+        // The distance is measured in the max norm, so the reach is a square.
+        context.set_fill_style(&JsValue::from_str("rgba(255,255,0,0.1)"));
+        context.fill_rect(
+            (position.x as f64 - reach) * TILE_SIZE,
+            (position.y as f64 - reach) * TILE_SIZE,
+            (reach * 2. + 1.) * TILE_SIZE,
+            (reach * 2. + 1.) * TILE_SIZE,
+        );
+        context.set_stroke_style(&JsValue::from_str("rgba(255,255,0,0.8)"));
+        context.set_line_width(2.);
+        for structure in self
+            .structures
+            .iter()
+            .filter_map(|s| s.dynamic.as_deref())
+            .filter(|s| would_connect(tool, *s))
+        {
+            let bb = structure.bounding_box();
+            context.stroke_rect(
+                bb.x0 as f64 * TILE_SIZE,
+                bb.y0 as f64 * TILE_SIZE,
+                (bb.x1 - bb.x0) as f64 * TILE_SIZE,
+                (bb.y1 - bb.y0) as f64 * TILE_SIZE,
+            );
+            let other = structure.position();
+            context.begin_path();
+            context.move_to(cx, cy);
+            context.line_to(
+                (other.x as f64 + 0.5) * TILE_SIZE,
+                (other.y as f64 + 0.5) * TILE_SIZE,
+            );
+            context.stroke();
+        }
+        context.restore();
+        Ok(())
+    }
+
+    /// Find the structures at both tiles and check if they can be wired.
+    fn power_wire_at(&self, a: &[i32], b: &[i32]) -> Result<PowerWire, JsValue> {
+        let find = |tile: &[i32]| {
+            self.structure_id_at(tile)
+                .ok_or_else(|| js_str!("Structure is not found at {:?}", tile))
+        };
+        let (first, second) = (find(a)?, find(b)?);
+        if first == second {
+            return js_err!("Cannot connect a structure to itself");
+        }
+        Ok(PowerWire(first, second))
+    }
+
+    /// Returns the index of the power network that the structure belongs to.
+    pub(crate) fn network_of(&self, id: StructureId) -> Option<usize> {
+        self.power_networks.iter().position(|network| {
+            network.sources.contains(&id)
+                || network.sinks.contains(&id)
+                || network.accumulators.contains(&id)
+        })
+    }
+
+    /// Describe the load of the power network that the structure belongs to.
+    pub(crate) fn power_network_desc(&self, id: StructureId) -> Option<String> {
+        let network = &self.power_networks[self.network_of(id)?];
+        Some(format!(
+            "Power network:<br>Demand: {:.1}kJ<br>Supply: {:.1}kJ<br>Satisfaction: {:.0}%",
+            network.demand,
+            network.supply,
+            network.power_ratio * 100.
+        ))
+    }
+
+    fn rebuild_power_networks(&mut self) {
+        self.power_networks = build_power_networks(
+            &StructureDynIter::new_all(&mut self.structures),
+            &self.power_wires,
+        );
+    }
+}
+
+#[wasm_bindgen]
+impl FactorishState {
+    /// Connect the structures at the tiles with a power wire, in addition to the ones
+    /// connected automatically on construction.
+    /// @returns false if the wire already exists.
+    pub fn connect_power_wire(
+        &mut self,
+        a_c: i32,
+        a_r: i32,
+        b_c: i32,
+        b_r: i32,
+    ) -> Result<bool, JsValue> {
+        self.record_input(ReplayInput::ConnectPowerWire { a_c, a_r, b_c, b_r });
+        let wire = self.power_wire_at(&[a_c, a_r], &[b_c, b_r])?;
+        let get = |id: StructureId| {
+            self.structures
+                .get(id.id as usize)
+                .and_then(|s| s.dynamic.as_deref())
+                .unwrap()
+        };
+        if !would_connect(get(wire.0), get(wire.1)) {
+            return js_err!("The structures cannot be connected with a power wire");
+        }
+        if self
+            .power_wires
+            .iter()
+            .any(|w| *w == wire || *w == PowerWire(wire.1, wire.0))
+        {
+            return Ok(false);
+        }
+        self.power_wires.push(wire);
+        self.rebuild_power_networks();
+        Ok(true)
+    }
+
+    /// Remove the power wire between the structures at the tiles, which may split a power network.
+    /// @returns false if there was no wire.
+    pub fn disconnect_power_wire(
+        &mut self,
+        a_c: i32,
+        a_r: i32,
+        b_c: i32,
+        b_r: i32,
+    ) -> Result<bool, JsValue> {
+        self.record_input(ReplayInput::DisconnectPowerWire { a_c, a_r, b_c, b_r });
+        let wire = self.power_wire_at(&[a_c, a_r], &[b_c, b_r])?;
+        let len = self.power_wires.len();
+        self.power_wires
+            .retain(|w| *w != wire && *w != PowerWire(wire.1, wire.0));
+        if self.power_wires.len() == len {
+            return Ok(false);
+        }
+        self.rebuild_power_networks();
+        Ok(true)
+    }
+}
+
+/// The indices of the live structures in the saved structure list, which skips the removed ones.
+/// Wires are saved with these indices, since the structures are loaded with them as ids.
+pub(crate) fn saved_structure_indices(
+    structures: &[StructureEntry],
+) -> HashMap<StructureId, usize> {
+    structures
+        .iter()
+        .enumerate()
+        .filter(|(_, s)| s.dynamic.is_some())
+        .enumerate()
+        .map(|(idx, (id, s))| {
+            (
+                StructureId {
+                    id: id as u32,
+                    gen: s.gen,
+                },
+                idx,
+            )
+        })
+        .collect()
+}
+
+/// Power wires in the saved form, as pairs of indices from `saved_structure_indices`.
+/// Wires to the structures that are gone are not saved.
+pub(crate) fn save_power_wires(
+    power_wires: &[PowerWire],
+    indices: &HashMap<StructureId, usize>,
+) -> Vec<(usize, usize)> {
+    power_wires
+        .iter()
+        .filter_map(|w| Some((*indices.get(&w.0)?, *indices.get(&w.1)?)))
+        .collect()
+}
+
+/// Restore the power wires saved by `save_power_wires` with the ids of the loaded structures,
+/// whose gens are all 0. Wires that do not connect two distinct power structures would never
+/// be found by `get_structure`, so they are dropped along with the duplicates.
+/// @returns the restored wires and the dropped ones.
+pub(crate) fn load_power_wires(
+    saved: &[(u32, u32)],
+    structures: &[StructureEntry],
+) -> (Vec<PowerWire>, Vec<(u32, u32)>) {
+    let is_power_structure = |idx: u32| {
+        structures
+            .get(idx as usize)
+            .and_then(|s| s.dynamic.as_deref())
+            .map_or(false, |s| s.power_sink() || s.power_source())
+    };
+    let id = |idx| StructureId { id: idx, gen: 0 };
+    let mut wires = vec![];
+    let mut dropped = vec![];
+    for &(a, b) in saved {
+        let wire = PowerWire(id(a), id(b));
+        if a == b
+            || !is_power_structure(a)
+            || !is_power_structure(b)
+            || wires.contains(&wire)
+            || wires.contains(&PowerWire(id(b), id(a)))
+        {
+            dropped.push((a, b));
+        } else {
+            wires.push(wire);
+        }
+    }
+    (wires, dropped)
 }
 
 pub(crate) fn build_power_networks(
@@ -16,22 +306,21 @@ pub(crate) fn build_power_networks(
     power_wires: &[PowerWire],
 ) -> Vec<PowerNetwork> {
     let mut left_wires = power_wires.iter().collect::<HashSet<_>>();
+    let mut visited = HashSet::<StructureId>::new();
     let mut ret = vec![];
 
     for (id, s) in structures.dyn_iter_id() {
         if !s.power_sink() && !s.power_source() {
             continue;
         }
+        if visited.contains(&id) {
+            continue;
+        }
         let mut expand_list = HashSet::<StructureId>::new();
         let mut wires = vec![];
         let mut sources = HashSet::new();
         let mut sinks = HashSet::new();
-        if s.power_source() {
-            sources.insert(id);
-        }
-        if s.power_sink() {
-            sinks.insert(id);
-        }
+        let mut accumulators = HashSet::new();
 
         expand_list.insert(id);
 
@@ -39,12 +328,17 @@ pub(crate) fn build_power_networks(
         while !expand_list.is_empty() {
             let mut next_expand = HashSet::<StructureId>::new();
             for id in expand_list {
+                visited.insert(id);
                 if let Some(s) = structures.get(id) {
-                    if s.power_source() {
-                        sources.insert(id);
-                    }
-                    if s.power_sink() {
-                        sinks.insert(id);
+                    if s.power_storage().is_some() {
+                        accumulators.insert(id);
+                    } else {
+                        if s.power_source() {
+                            sources.insert(id);
+                        }
+                        if s.power_sink() {
+                            sinks.insert(id);
+                        }
                     }
                 }
                 while let Some(wire) = left_wires.iter().find(|w| w.0 == id || w.1 == id).copied() {
@@ -56,13 +350,259 @@ pub(crate) fn build_power_networks(
             expand_list = next_expand;
         }
 
-        if !sources.is_empty() && !sinks.is_empty() {
+        if (!sources.is_empty() || !accumulators.is_empty())
+            && (!sinks.is_empty() || !accumulators.is_empty())
+        {
             ret.push(PowerNetwork {
                 wires,
                 sources,
                 sinks,
+                accumulators,
+                power_ratio: full_power_ratio(),
+                supply: 0.,
+                demand: 0.,
             });
         }
     }
     ret
 }
+
+/// Compare the total demand of consumers with the energy available in generators and
+/// accumulators to compute the satisfaction ratio of each network for this frame.
+pub(crate) fn update_power_ratios(
+    power_networks: &mut [PowerNetwork],
+    structures: &StructureDynIter,
+) {
+    for network in power_networks {
+        let demand: f64 = network
+            .sinks
+            .iter()
+            .filter_map(|id| structures.get(*id))
+            .map(|s| s.power_demand())
+            .sum();
+        let supply: f64 = network
+            .sources
+            .iter()
+            .filter_map(|id| structures.get(*id))
+            .map(|s| s.power_available())
+            .chain(
+                network
+                    .accumulators
+                    .iter()
+                    .filter_map(|id| structures.get(*id))
+                    .filter_map(|s| s.power_storage())
+                    .map(|(stored, _)| stored),
+            )
+            .sum();
+        network.power_ratio = if 0. < demand {
+            (supply / demand).min(1.)
+        } else {
+            1.
+        };
+        network.supply = supply;
+        network.demand = demand;
+    }
+}
+
+/// Returns the satisfaction ratio of the network that `sink` belongs to, or 0 if it is
+/// not connected to any network.
+pub(crate) fn power_ratio(power_networks: &[PowerNetwork], sink: StructureId) -> f64 {
+    power_networks
+        .iter()
+        .find(|network| network.sinks.contains(&sink))
+        .map_or(0., |network| network.power_ratio)
+}
+
+/// Drain up to `demand` kilojoules for `sink` from the network it belongs to.
+/// The demand is scaled by the network's power ratio, so that all consumers get
+/// a fair share in a brownout instead of the first ones taking everything.
+/// Generators are drained first, and accumulators cover the remaining deficit.
+pub(crate) fn drain_power(
+    power_networks: &[PowerNetwork],
+    structures: &mut StructureDynIter,
+    sink: StructureId,
+    demand: f64,
+) -> f64 {
+    let mut accumulated = 0.;
+    if let Some(network) = power_networks
+        .iter()
+        .find(|network| network.sinks.contains(&sink))
+    {
+        let demand = demand * network.power_ratio;
+        for id in network.sources.iter().chain(network.accumulators.iter()) {
+            if demand <= accumulated {
+                break;
+            }
+            if let Some(source) = structures.get_mut(*id) {
+                if let Some(energy) = source.power_outlet(demand - accumulated) {
+                    accumulated += energy;
+                }
+            }
+        }
+    }
+    accumulated
+}
+
+/// Charge accumulators with the energy left in the generators after all the consumers
+/// took what they needed in this frame.
+pub(crate) fn charge_accumulators(
+    power_networks: &[PowerNetwork],
+    structures: &mut StructureDynIter,
+) {
+    for network in power_networks {
+        for acc_id in &network.accumulators {
+            let demand = if let Some((stored, max)) =
+                structures.get(*acc_id).and_then(|s| s.power_storage())
+            {
+                max - stored
+            } else {
+                continue;
+            };
+            let mut accumulated = 0.;
+            for id in &network.sources {
+                if demand <= accumulated {
+                    break;
+                }
+                if let Some(source) = structures.get_mut(*id) {
+                    if let Some(energy) = source.power_outlet(demand - accumulated) {
+                        accumulated += energy;
+                    }
+                }
+            }
+            if let Some(acc) = structures.get_mut(*acc_id) {
+                acc.power_inlet(accumulated);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_would_connect() {
+    use super::{assembler::Assembler, chest::Chest, elect_pole::ElectPole, Position};
+    let pole = ElectPole::new(&Position::new(0, 0));
+    let reach = pole.wire_reach() as i32;
+    assert!(would_connect(
+        &pole,
+        &ElectPole::new(&Position::new(reach, -reach))
+    ));
+    assert!(!would_connect(
+        &pole,
+        &ElectPole::new(&Position::new(reach + 1, 0))
+    ));
+    assert!(would_connect(&pole, &Assembler::new(&Position::new(1, 1))));
+    assert!(!would_connect(&pole, &Chest::new(&Position::new(1, 1))));
+}
+
+#[test]
+fn test_disconnect_splits_network() {
+    use super::{elect_pole::ElectPole, scenarios::empty_state, Position};
+    let mut state = empty_state(16, 16);
+    for x in 0..4 {
+        state
+            .add_structure(Box::new(ElectPole::new(&Position::new(x * 3, 0))))
+            .unwrap();
+    }
+    for x in 0..3 {
+        state.connect_power_wire(x * 3, 0, x * 3 + 3, 0).unwrap();
+    }
+    assert_eq!(state.power_wires.len(), 3);
+    assert_eq!(state.power_networks.len(), 1);
+
+    assert!(state.disconnect_power_wire(6, 0, 3, 0).unwrap());
+    assert_eq!(state.power_wires.len(), 2);
+    assert_eq!(state.power_networks.len(), 2);
+    assert!(state
+        .power_networks
+        .iter()
+        .all(|network| network.sources.len() == 2));
+    // Nothing is left to remove between the poles.
+    assert!(!state.disconnect_power_wire(3, 0, 6, 0).unwrap());
+
+    // The poles on either side of the cut are tinted differently.
+    let id = |id| StructureId { id, gen: 0 };
+    let tints = structure_networks(&state.power_networks);
+    assert_eq!(tints.len(), 4);
+    assert_eq!(tints[&id(0)], tints[&id(1)]);
+    assert_eq!(tints[&id(2)], tints[&id(3)]);
+    assert_ne!(network_tint(tints[&id(1)]), network_tint(tints[&id(2)]));
+}
+
+#[test]
+fn test_power_wires_round_trip() {
+    use super::{elect_pole::ElectPole, lab::Lab, steam_engine::SteamEngine, Position};
+    let entry = |gen, s: Box<dyn Structure>| StructureEntry {
+        gen,
+        ..StructureEntry::new(s)
+    };
+    // A powered base with the gaps and gens left by removed structures
+    let mut structures = vec![
+        entry(2, Box::new(SteamEngine::new(&Position::new(0, 0)))),
+        StructureEntry {
+            gen: 1,
+            dynamic: None,
+            damage: 0.,
+            active: true,
+        },
+        entry(0, Box::new(ElectPole::new(&Position::new(3, 0)))),
+        entry(3, Box::new(Lab::new(&Position::new(5, 0)))),
+        entry(1, Box::new(SteamEngine::new(&Position::new(10, 10)))),
+        entry(4, Box::new(ElectPole::new(&Position::new(12, 10)))),
+    ];
+    let id = |id, gen| StructureId { id, gen };
+    let wires = vec![
+        PowerWire(id(0, 2), id(2, 0)),
+        PowerWire(id(2, 0), id(3, 3)),
+        PowerWire(id(4, 1), id(5, 4)),
+        // A stale wire to the removed structure
+        PowerWire(id(1, 0), id(2, 0)),
+    ];
+    let networks = build_power_networks(&StructureDynIter::new_all(&mut structures), &wires);
+
+    let indices = saved_structure_indices(&structures);
+    let mut saved = save_power_wires(&wires, &indices)
+        .into_iter()
+        .map(|(a, b)| (a as u32, b as u32))
+        .collect::<Vec<_>>();
+    assert_eq!(saved, [(0, 1), (1, 2), (3, 4)]);
+    // Broken wires in a save are dropped
+    saved.extend_from_slice(&[(1, 0), (2, 2), (0, 9)]);
+
+    let mut loaded = structures
+        .iter()
+        .filter_map(|s| s.dynamic.as_deref())
+        .map(|s| {
+            let mut value = serde_json::json!({
+                "type": s.name(),
+                "payload": s.serialize().unwrap(),
+            });
+            StructureEntry::new(FactorishState::structure_from_json(&mut value).unwrap())
+        })
+        .collect::<Vec<_>>();
+    let (loaded_wires, dropped) = load_power_wires(&saved, &loaded);
+    assert_eq!(dropped, [(1, 0), (2, 2), (0, 9)]);
+    let loaded_networks =
+        build_power_networks(&StructureDynIter::new_all(&mut loaded), &loaded_wires);
+
+    let members = |networks: &[PowerNetwork], map: &dyn Fn(&StructureId) -> usize| {
+        networks
+            .iter()
+            .map(|nw| {
+                let sorted = |set: &HashSet<StructureId>| {
+                    let mut v = set.iter().map(map).collect::<Vec<_>>();
+                    v.sort_unstable();
+                    v
+                };
+                (
+                    sorted(&nw.sources),
+                    sorted(&nw.sinks),
+                    sorted(&nw.accumulators),
+                )
+            })
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(networks.len(), 2);
+    assert_eq!(
+        members(&networks, &|id| indices[id]),
+        members(&loaded_networks, &|id| id.id as usize)
+    );
+}
@@ -0,0 +1,147 @@
+use super::{
+    items::{item_to_str, ItemType},
+    FactorishState, Inventory, InventoryTrait,
+};
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use wasm_bindgen::prelude::*;
+
+/// Lengths of the rolling windows in ticks.
+const STATS_WINDOWS: [usize; 3] = [60, 600, 3600];
+
+/// Items produced and consumed in a tick. Ticks without any production nor consumption
+/// do not create samples, so idle factories cost nothing.
+struct Sample {
+    tick: usize,
+    produced: Inventory,
+    consumed: Inventory,
+}
+
+/// Sum of the samples within the last `length` ticks. The totals are updated incrementally
+/// when a sample enters or leaves the window, so that we never scan all the samples.
+struct StatsWindow {
+    length: usize,
+    samples: VecDeque<Sample>,
+    produced: Inventory,
+    consumed: Inventory,
+}
+
+impl StatsWindow {
+    fn new(length: usize) -> Self {
+        Self {
+            length,
+            samples: VecDeque::new(),
+            produced: Inventory::new(),
+            consumed: Inventory::new(),
+        }
+    }
+}
+
+pub(crate) struct ProductionStats {
+    tick: usize,
+    produced: Inventory,
+    consumed: Inventory,
+    windows: Vec<StatsWindow>,
+}
+
+impl Default for ProductionStats {
+    fn default() -> Self {
+        Self {
+            tick: 0,
+            produced: Inventory::new(),
+            consumed: Inventory::new(),
+            windows: STATS_WINDOWS.iter().map(|l| StatsWindow::new(*l)).collect(),
+        }
+    }
+}
+
+impl ProductionStats {
+    pub(crate) fn add_produced(&mut self, item: &ItemType, count: usize) {
+        self.produced.add_items(item, count);
+    }
+
+    pub(crate) fn add_consumed(&mut self, item: &ItemType, count: usize) {
+        self.consumed.add_items(item, count);
+    }
+
+    /// Commit the counts of the current tick into the windows and evict expired samples.
+    pub(crate) fn tick(&mut self) {
+        self.tick += 1;
+        let produced = std::mem::take(&mut self.produced);
+        let consumed = std::mem::take(&mut self.consumed);
+        for window in &mut self.windows {
+            if !produced.is_empty() || !consumed.is_empty() {
+                for (item, count) in &produced {
+                    window.produced.add_items(item, *count);
+                }
+                for (item, count) in &consumed {
+                    window.consumed.add_items(item, *count);
+                }
+                window.samples.push_back(Sample {
+                    tick: self.tick,
+                    produced: produced.clone(),
+                    consumed: consumed.clone(),
+                });
+            }
+            while let Some(front) = window.samples.front() {
+                if self.tick < front.tick + window.length {
+                    break;
+                }
+                if let Some(sample) = window.samples.pop_front() {
+                    for (item, count) in &sample.produced {
+                        window.produced.remove_items(item, *count);
+                    }
+                    for (item, count) in &sample.consumed {
+                        window.consumed.remove_items(item, *count);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Items produced in the tick that was committed last by `tick`.
+    pub(crate) fn last_produced(&self) -> Option<&Inventory> {
+        self.windows
+            .first()?
+            .samples
+            .back()
+            .filter(|sample| sample.tick == self.tick)
+            .map(|sample| &sample.produced)
+    }
+}
+
+#[derive(Serialize)]
+struct ItemRates {
+    produced: f64,
+    consumed: f64,
+}
+
+#[wasm_bindgen]
+impl FactorishState {
+    /// Returns an object mapping item names to `{produced, consumed}` rates in items per second,
+    /// averaged over the last `window` ticks. `window` must be one of 60, 600 or 3600.
+    pub fn get_production_stats(&self, window: usize) -> Result<JsValue, JsValue> {
+        let stats = &self.production_stats;
+        let window = stats
+            .windows
+            .iter()
+            .find(|w| w.length == window)
+            .ok_or_else(|| js_str!("Window must be one of {:?}", STATS_WINDOWS))?;
+        let seconds = window.length.min(stats.tick).max(1) as f64 * self.delta_time;
+        let rate = |count: usize| {
+            if 0. < seconds {
+                count as f64 / seconds
+            } else {
+                0.
+            }
+        };
+        let mut ret = HashMap::<String, ItemRates>::new();
+        for item in window.produced.keys().chain(window.consumed.keys()) {
+            ret.entry(item_to_str(item)).or_insert_with(|| ItemRates {
+                produced: rate(window.produced.count_item(item)),
+                consumed: rate(window.consumed.count_item(item)),
+            });
+        }
+        JsValue::from_serde(&ret).map_err(|e| js_str!("serialize error: {}", e))
+    }
+}
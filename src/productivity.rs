@@ -0,0 +1,56 @@
+use super::{replay::ReplayInput, FactorishState};
+use wasm_bindgen::prelude::*;
+
+/// Add the productivity bonus of a completed operation to the fractional counter.
+/// @returns the number of extra items to produce, which is the whole part taken from the counter.
+pub(crate) fn take_bonus_items(accum: &mut f64, bonus: f64) -> usize {
+    *accum += bonus;
+    let items = accum.floor().max(0.);
+    *accum -= items;
+    items as usize
+}
+
+pub(crate) fn describe_productivity(bonus: f64) -> String {
+    format!("Productivity: +{:.0}%<br>", bonus * 100.)
+}
+
+impl FactorishState {
+    /// The fraction of extra output of ore mines and furnaces, from the scenario and the research.
+    pub(crate) fn productivity_bonus(&self) -> f64 {
+        self.productivity + self.research.productivity_bonus()
+    }
+}
+
+#[wasm_bindgen]
+impl FactorishState {
+    /// Set the base productivity bonus, e.g. 0.1 for 10% extra output, which is saved with the game.
+    /// The bonus from the research is added on top of it.
+    pub fn set_productivity(&mut self, bonus: f64) -> Result<(), JsValue> {
+        self.record_input(ReplayInput::SetProductivity { bonus });
+        if !(0. <= bonus && bonus.is_finite()) {
+            return js_err!(
+                "Productivity bonus must be a non-negative number: {}",
+                bonus
+            );
+        }
+        self.productivity = bonus;
+        Ok(())
+    }
+
+    /// Returns the total productivity bonus including the research.
+    pub fn get_productivity(&self) -> f64 {
+        self.productivity_bonus()
+    }
+}
+
+#[test]
+fn test_take_bonus_items() {
+    let mut accum = 0.;
+    let items = (0..10)
+        .map(|_| take_bonus_items(&mut accum, 0.25))
+        .collect::<Vec<_>>();
+    assert_eq!(items, [0, 0, 0, 1, 0, 0, 0, 1, 0, 0]);
+    assert_eq!(accum, 0.5);
+    assert_eq!(take_bonus_items(&mut accum, 1.5), 2);
+    assert_eq!(accum, 0.);
+}
@@ -0,0 +1,388 @@
+use super::{
+    items::{item_to_str, str_to_item, ItemType},
+    water_well::FluidType,
+    FactorishState, Recipe, RecipeSerial,
+};
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use std::collections::HashMap;
+use wasm_bindgen::prelude::*;
+
+/// Assembler recipes can also be crafted by the player's hands.
+const ASSEMBLER: &[&str] = &["Assembler", "Player"];
+/// The player cannot hold fluids, so the recipes with a fluid need an assembler.
+const FLUID_ASSEMBLER: &[&str] = &["Assembler"];
+const FURNACE: &[&str] = &["Furnace", "Electric Furnace"];
+
+pub(crate) struct RecipeEntry {
+    pub id: String,
+    pub recipe: Recipe,
+    /// Names of the structures that can run the recipe, where "Player" means hand crafting.
+    pub structures: &'static [&'static str],
+}
+
+/// The registry of all the recipes in the game, so that we can query and balance them in one place.
+pub(crate) struct Recipes {
+    entries: Vec<RecipeEntry>,
+    index: HashMap<String, usize>,
+}
+
+/// The id of a recipe is derived from its output, e.g. "iron-plate".
+fn recipe_id(recipe: &Recipe) -> String {
+    recipe
+        .output
+        .keys()
+        .map(|item| item_to_str(item).to_lowercase().replace(' ', "-"))
+        .collect::<Vec<_>>()
+        .join("+")
+}
+
+impl Recipes {
+    fn new() -> Self {
+        let assembler_recipes = vec![
+            Recipe::new(
+                hash_map!(ItemType::IronPlate => 2usize),
+                hash_map!(ItemType::Gear => 1usize),
+                20.,
+                50.,
+            ),
+            Recipe::new(
+                hash_map!(ItemType::IronPlate => 1usize, ItemType::Gear => 1usize),
+                hash_map!(ItemType::TransportBelt => 1usize),
+                20.,
+                50.,
+            ),
+            Recipe::new(
+                hash_map!(ItemType::TransportBelt => 2, ItemType::Gear => 2),
+                hash_map!(ItemType::Splitter => 1),
+                25.,
+                40.,
+            ),
+            Recipe::new(
+                hash_map!(ItemType::Splitter => 2, ItemType::Circuit => 5),
+                hash_map!(ItemType::Balancer => 1),
+                50.,
+                60.,
+            ),
+            Recipe::new(
+                hash_map!(ItemType::IronPlate => 5usize),
+                hash_map!(ItemType::Chest => 1usize),
+                20.,
+                50.,
+            ),
+            Recipe::new(
+                hash_map!(ItemType::Chest => 1, ItemType::Circuit => 3),
+                hash_map!(ItemType::RequesterChest => 1),
+                20.,
+                50.,
+            ),
+            Recipe::new(
+                hash_map!(ItemType::StoneOre => 5usize),
+                hash_map!(ItemType::Furnace => 1usize),
+                20.,
+                20.,
+            ),
+            Recipe::new(
+                hash_map!(ItemType::StoneOre => 5, ItemType::IronPlate => 5, ItemType::Circuit => 3),
+                hash_map!(ItemType::ElectricFurnace => 1),
+                50.,
+                100.,
+            ),
+            // Quench the iron plates in water
+            Recipe::new(
+                hash_map!(ItemType::IronPlate => 5),
+                hash_map!(ItemType::SteelPlate => 1),
+                50.,
+                80.,
+            )
+            .with_input_fluid(FluidType::Water, 50.),
+            Recipe::new(
+                hash_map!(ItemType::CopperPlate => 1usize),
+                hash_map!(ItemType::CopperWire => 2usize),
+                20.,
+                20.,
+            ),
+            Recipe::new(
+                hash_map!(ItemType::IronPlate => 1, ItemType::CopperWire => 3usize),
+                hash_map!(ItemType::Circuit => 1usize),
+                20.,
+                50.,
+            ),
+            Recipe::new(
+                hash_map!(ItemType::IronPlate => 5, ItemType::Gear => 5, ItemType::Circuit => 3),
+                hash_map!(ItemType::Assembler => 1),
+                20.,
+                120.,
+            ),
+            Recipe::new(
+                hash_map!(ItemType::IronPlate => 1, ItemType::Gear => 1, ItemType::Circuit => 1),
+                hash_map!(ItemType::Inserter => 1),
+                20.,
+                20.,
+            ),
+            Recipe::new(
+                hash_map!(ItemType::IronPlate => 1, ItemType::Gear => 5, ItemType::Circuit => 3),
+                hash_map!(ItemType::OreMine => 1),
+                100.,
+                100.,
+            ),
+            Recipe::new(
+                hash_map!(ItemType::IronPlate => 5, ItemType::Gear => 5, ItemType::Circuit => 5),
+                hash_map!(ItemType::ElectricOreMine => 1),
+                100.,
+                100.,
+            ),
+            Recipe::new(
+                hash_map!(ItemType::IronPlate => 2),
+                hash_map!(ItemType::Pipe => 1),
+                20.,
+                20.,
+            ),
+            Recipe::new(
+                hash_map!(ItemType::Pipe => 1, ItemType::Gear => 1),
+                hash_map!(ItemType::Valve => 1),
+                20.,
+                30.,
+            ),
+            Recipe::new(
+                hash_map!(ItemType::IronPlate => 5, ItemType::Gear => 5),
+                hash_map!(ItemType::OffshorePump => 1),
+                150.,
+                150.,
+            ),
+            Recipe::new(
+                hash_map!(ItemType::IronPlate => 5, ItemType::CopperPlate => 5),
+                hash_map!(ItemType::Boiler => 1),
+                100.,
+                100.,
+            ),
+            Recipe::new(
+                hash_map!(ItemType::IronPlate => 5, ItemType::Gear => 5, ItemType::CopperPlate => 5),
+                hash_map!(ItemType::SteamEngine => 1),
+                200.,
+                200.,
+            ),
+            Recipe::new(
+                hash_map!(ItemType::IronPlate => 2, ItemType::CopperWire => 2),
+                hash_map!(ItemType::ElectPole => 1),
+                20.,
+                20.,
+            ),
+            Recipe::new(
+                hash_map!(ItemType::IronPlate => 2, ItemType::Circuit => 5),
+                hash_map!(ItemType::Accumulator => 1),
+                100.,
+                100.,
+            ),
+            Recipe::new(
+                hash_map!(ItemType::IronPlate => 2, ItemType::Gear => 2),
+                hash_map!(ItemType::RepairPack => 1),
+                20.,
+                50.,
+            ),
+            Recipe::new(
+                hash_map!(ItemType::IronPlate => 4),
+                hash_map!(ItemType::FirearmMagazine => 1),
+                20.,
+                50.,
+            ),
+            Recipe::new(
+                hash_map!(ItemType::IronPlate => 10, ItemType::Gear => 10, ItemType::CopperPlate => 10),
+                hash_map!(ItemType::GunTurret => 1),
+                100.,
+                100.,
+            ),
+            Recipe::new(
+                hash_map!(ItemType::CopperPlate => 1, ItemType::Gear => 1),
+                hash_map!(ItemType::SciencePack => 1),
+                50.,
+                50.,
+            ),
+            Recipe::new(
+                hash_map!(ItemType::IronPlate => 10, ItemType::Gear => 10, ItemType::Circuit => 10),
+                hash_map!(ItemType::Lab => 1),
+                100.,
+                100.,
+            ),
+            Recipe::new(
+                hash_map!(ItemType::IronPlate => 1, ItemType::CopperWire => 3, ItemType::Circuit => 1),
+                hash_map!(ItemType::Lamp => 1),
+                20.,
+                30.,
+            ),
+        ];
+        let furnace_recipes = vec![
+            Recipe::new(
+                hash_map!(ItemType::IronOre => 1usize),
+                hash_map!(ItemType::IronPlate => 1usize),
+                20.,
+                50.,
+            ),
+            Recipe::new(
+                hash_map!(ItemType::CopperOre => 1usize),
+                hash_map!(ItemType::CopperPlate => 1usize),
+                20.,
+                50.,
+            ),
+        ];
+        let entries = assembler_recipes
+            .into_iter()
+            .map(|recipe| {
+                if recipe.input_fluid.is_some() {
+                    (recipe, FLUID_ASSEMBLER)
+                } else {
+                    (recipe, ASSEMBLER)
+                }
+            })
+            .chain(furnace_recipes.into_iter().map(|recipe| (recipe, FURNACE)))
+            .map(|(recipe, structures)| RecipeEntry {
+                id: recipe_id(&recipe),
+                recipe,
+                structures,
+            })
+            .collect::<Vec<_>>();
+        let index = entries
+            .iter()
+            .enumerate()
+            .map(|(i, entry)| (entry.id.clone(), i))
+            .collect();
+        Self { entries, index }
+    }
+
+    pub(crate) fn get(&self, id: &str) -> Option<&RecipeEntry> {
+        self.index.get(id).map(|i| &self.entries[*i])
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &RecipeEntry> {
+        self.entries.iter()
+    }
+
+    /// Returns the recipes that the structure with the given name can run.
+    pub(crate) fn for_structure<'a, 'b>(
+        &'a self,
+        name: &'b str,
+    ) -> impl Iterator<Item = &'a RecipeEntry> + 'b
+    where
+        'a: 'b,
+    {
+        self.entries
+            .iter()
+            .filter(move |entry| entry.structures.contains(&name))
+    }
+
+    /// Find the entry of a recipe stored in a structure or a saved game.
+    pub(crate) fn find(&self, recipe: &Recipe) -> Option<&RecipeEntry> {
+        self.entries.iter().find(|entry| {
+            entry.recipe.input == recipe.input && entry.recipe.output == recipe.output
+        })
+    }
+}
+
+pub(crate) static RECIPES: Lazy<Recipes> = Lazy::new(Recipes::new);
+
+/// Items that are mined or harvested rather than crafted.
+fn is_raw(item: &ItemType) -> bool {
+    matches!(
+        item,
+        ItemType::IronOre
+            | ItemType::CoalOre
+            | ItemType::CopperOre
+            | ItemType::StoneOre
+            | ItemType::Wood
+    )
+}
+
+#[derive(Serialize)]
+struct RecipeInfo {
+    structures: &'static [&'static str],
+    #[serde(flatten)]
+    recipe: RecipeSerial,
+}
+
+impl RecipeInfo {
+    fn new(entry: &RecipeEntry) -> Self {
+        Self {
+            structures: entry.structures,
+            recipe: RecipeSerial::from(entry),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ItemRecipeInfo {
+    item: String,
+    /// Raw resources are the leaves of a crafting tree, which no recipe produces.
+    raw: bool,
+    produced_by: Vec<RecipeInfo>,
+    used_in: Vec<RecipeInfo>,
+}
+
+#[wasm_bindgen]
+impl FactorishState {
+    /// Returns `{item, raw, produced_by, used_in}` where `produced_by` and `used_in` are arrays
+    /// of recipes with the structures that can run them and the ingredient names and counts.
+    pub fn get_item_recipe_info(&self, item_name: &str) -> Result<JsValue, JsValue> {
+        let item =
+            str_to_item(item_name).ok_or_else(|| js_str!("Item name not valid: {}", item_name))?;
+        let info = ItemRecipeInfo {
+            item: item_to_str(&item),
+            raw: is_raw(&item),
+            produced_by: RECIPES
+                .iter()
+                .filter(|entry| entry.recipe.output.contains_key(&item))
+                .map(RecipeInfo::new)
+                .collect(),
+            used_in: RECIPES
+                .iter()
+                .filter(|entry| entry.recipe.input.contains_key(&item))
+                .map(RecipeInfo::new)
+                .collect(),
+        };
+        JsValue::from_serde(&info).map_err(|e| js_str!("serialize error: {}", e))
+    }
+}
+
+#[test]
+fn test_raw_items_have_no_recipe() {
+    for entry in RECIPES.iter() {
+        assert!(entry.recipe.output.keys().all(|item| !is_raw(item)));
+    }
+}
+
+#[test]
+fn test_recipes_resolve() {
+    assert_eq!(
+        RECIPES.index.len(),
+        RECIPES.entries.len(),
+        "recipe ids must be unique"
+    );
+    for entry in RECIPES.iter() {
+        assert!(RECIPES.get(&entry.id).map(|e| &e.recipe) == Some(&entry.recipe));
+    }
+    for name in &["Assembler", "Player", "Furnace", "Electric Furnace"] {
+        assert!(RECIPES.for_structure(name).next().is_some());
+    }
+    assert_eq!(
+        RECIPES.get("iron-plate").map(|entry| entry.structures),
+        Some(FURNACE)
+    );
+    assert_eq!(
+        RECIPES.get("gear").map(|entry| entry.structures),
+        Some(ASSEMBLER)
+    );
+    assert_eq!(
+        RECIPES.get("steel-plate").map(|entry| entry.structures),
+        Some(FLUID_ASSEMBLER)
+    );
+    // Furnaces build their recipe from the input, which should be found in the registry.
+    let recipe = Recipe::new(
+        hash_map!(ItemType::CopperOre => 1usize),
+        hash_map!(ItemType::CopperPlate => 1usize),
+        20.,
+        50.,
+    );
+    assert_eq!(
+        RECIPES.find(&recipe).map(|entry| entry.id.as_str()),
+        Some("copper-plate")
+    );
+}
@@ -0,0 +1,620 @@
+use super::{
+    blueprint::Blueprint, inventory::InventoryType, items::ItemType, perlin_noise::Xor128,
+    structure::Position, FactorishState, OreHarvesting, Rotation, SelectedItem,
+};
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+
+/// An input to the simulation that changes the game state. Every method exported to JavaScript
+/// that changes the game records its arguments here, so that the replay calls it again.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub(crate) enum ReplayInput {
+    MouseDown {
+        pos: Vec<f64>,
+        button: i32,
+    },
+    MouseUp {
+        pos: Vec<f64>,
+        button: i32,
+    },
+    MouseMove {
+        pos: Vec<f64>,
+    },
+    KeyDown {
+        key_code: i32,
+        ctrl: bool,
+    },
+    SelectTool {
+        tool: i32,
+    },
+    SwitchToolRow {
+        row: usize,
+    },
+    Simulate {
+        delta_time: f64,
+    },
+    /// Chunks are generated as the viewport moves, which happens in rendering, so we record
+    /// them as inputs rather than replaying the camera.
+    GenerateChunk {
+        position: Position,
+    },
+    MouseLeave,
+    MouseWheel {
+        delta: i32,
+        x: f64,
+        y: f64,
+    },
+    RotateTool,
+    Undo,
+    SelectPlayerInventory {
+        name: String,
+    },
+    DeselectPlayerInventory,
+    OpenStructureInventory {
+        c: i32,
+        r: i32,
+    },
+    SelectStructureInventory {
+        name: String,
+    },
+    MoveSelectedInventoryItem {
+        to_player: bool,
+        inventory_type: InventoryType,
+    },
+    SortInventory {
+        is_player: bool,
+        c: i32,
+        r: i32,
+    },
+    QuickStackToChest {
+        c: i32,
+        r: i32,
+    },
+    SelectRecipe {
+        c: i32,
+        r: i32,
+        id: String,
+    },
+    QueueCraft {
+        recipe_index: usize,
+    },
+    CancelCraft {
+        index: usize,
+    },
+    StartResearch {
+        tech_id: String,
+    },
+    SetItemUnlocked {
+        item_name: String,
+        value: bool,
+    },
+    CaptureBlueprint {
+        x0: i32,
+        y0: i32,
+        x1: i32,
+        y1: i32,
+    },
+    SaveBlueprint {
+        name: String,
+    },
+    DeleteBlueprint {
+        name: String,
+    },
+    PasteBlueprint {
+        x: i32,
+        y: i32,
+        data: String,
+        rotation: u32,
+        flip: bool,
+    },
+    DeconstructArea {
+        x0: i32,
+        y0: i32,
+        x1: i32,
+        y1: i32,
+    },
+    CopySettings {
+        c: i32,
+        r: i32,
+    },
+    PasteSettings {
+        c: i32,
+        r: i32,
+    },
+    DamageStructure {
+        c: i32,
+        r: i32,
+        amount: f64,
+    },
+    RepairStructure {
+        c: i32,
+        r: i32,
+    },
+    ConnectPowerWire {
+        a_c: i32,
+        a_r: i32,
+        b_c: i32,
+        b_r: i32,
+    },
+    DisconnectPowerWire {
+        a_c: i32,
+        a_r: i32,
+        b_c: i32,
+        b_r: i32,
+    },
+    ConnectSignal {
+        a: Vec<i32>,
+        b: Vec<i32>,
+    },
+    SetInserterCondition {
+        c: i32,
+        r: i32,
+        item: String,
+        op: String,
+        value: usize,
+    },
+    SetInserterFilter {
+        c: i32,
+        r: i32,
+        items: Vec<ItemType>,
+    },
+    SetInserterFilterMode {
+        c: i32,
+        r: i32,
+        is_blacklist: bool,
+    },
+    SetInserterStackSize {
+        c: i32,
+        r: i32,
+        n: usize,
+    },
+    SetInserterChestLimit {
+        c: i32,
+        r: i32,
+        item: String,
+        max: usize,
+    },
+    SetOutputToGround {
+        c: i32,
+        r: i32,
+        enable: bool,
+    },
+    SetSplitterConfig {
+        c: i32,
+        r: i32,
+        input_priority: String,
+        output_priority: String,
+        filter: Option<String>,
+        filter_side: String,
+    },
+    SetChestRequest {
+        c: i32,
+        r: i32,
+        item: String,
+        count: usize,
+    },
+    SetChestSlotFilter {
+        c: i32,
+        r: i32,
+        slot: usize,
+        item_name: String,
+    },
+    SetFluidFilter {
+        c: i32,
+        r: i32,
+        fluid_type: String,
+    },
+    SetValveDirection {
+        c: i32,
+        r: i32,
+        rotation: String,
+    },
+    SetValveMaxFlow {
+        c: i32,
+        r: i32,
+        max_flow: Option<f64>,
+    },
+    SetMergeDropItems {
+        enable: bool,
+    },
+    SetBeltDensity {
+        factor: f64,
+    },
+    SetProductivity {
+        bonus: f64,
+    },
+    SetSleepIdleStructures {
+        enable: bool,
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+struct ReplayEvent {
+    /// `sim_time` when the input was applied
+    time: f64,
+    /// The viewport `(x, y, scale)` to convert the mouse positions in screen coordinates.
+    viewport: (f64, f64, f64),
+    #[serde(flatten)]
+    input: ReplayInput,
+}
+
+/// A snapshot of the game at the start of recording and the inputs after it.
+/// The selection states are not saved with the game, so they are captured separately.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct ReplayLog {
+    game: serde_json::Value,
+    rng: Xor128,
+    selected_item: Option<SelectedItem>,
+    tool_rotation: Rotation,
+    ore_harvesting: Option<OreHarvesting>,
+    /// The blueprint captured before recording, which `save_blueprint` may save during it.
+    #[serde(default)]
+    blueprint_clipboard: Option<Blueprint>,
+    events: Vec<ReplayEvent>,
+}
+
+impl FactorishState {
+    pub(crate) fn record_input(&mut self, input: ReplayInput) {
+        if let Some(log) = self.recording.as_mut() {
+            log.events.push(ReplayEvent {
+                time: self.sim_time,
+                viewport: (self.viewport.x, self.viewport.y, self.viewport.scale),
+                input,
+            });
+        }
+    }
+
+    /// Call an input handler from another one without recording it, since replaying the outer
+    /// handler calls it again.
+    pub(crate) fn unrecorded<T>(&mut self, f: impl FnOnce(&mut Self) -> T) -> T {
+        let recording = self.recording.take();
+        let ret = f(self);
+        self.recording = recording;
+        ret
+    }
+
+    /// Apply a recorded input by calling the method that recorded it.
+    pub(crate) fn apply_input(&mut self, input: ReplayInput) -> Result<(), JsValue> {
+        match input {
+            ReplayInput::MouseDown { pos, button } => self.mouse_down(&pos, button).map(drop),
+            ReplayInput::MouseUp { pos, button } => self.mouse_up(&pos, button).map(drop),
+            ReplayInput::MouseMove { pos } => self.mouse_move(&pos),
+            ReplayInput::KeyDown { key_code, ctrl } => self.on_key_down(key_code, ctrl).map(drop),
+            ReplayInput::SelectTool { tool } => self.select_tool(tool).map(drop),
+            ReplayInput::SwitchToolRow { row } => self.switch_tool_row(row),
+            // A step of the simulation, so that the game speed does not affect the replay.
+            ReplayInput::Simulate { delta_time } => self.tick(delta_time).map(drop),
+            ReplayInput::GenerateChunk { position } => {
+                self.gen_chunk_at(position);
+                Ok(())
+            }
+            ReplayInput::MouseLeave => self.mouse_leave(),
+            ReplayInput::MouseWheel { delta, x, y } => self.mouse_wheel(delta, x, y),
+            ReplayInput::RotateTool => {
+                self.rotate_tool();
+                Ok(())
+            }
+            ReplayInput::Undo => self.undo().map(drop),
+            ReplayInput::SelectPlayerInventory { name } => self.select_player_inventory(&name),
+            ReplayInput::DeselectPlayerInventory => self.deselect_player_inventory(),
+            ReplayInput::OpenStructureInventory { c, r } => {
+                self.open_structure_inventory(c, r).map(drop)
+            }
+            ReplayInput::SelectStructureInventory { name } => {
+                self.select_structure_inventory(&name)
+            }
+            ReplayInput::MoveSelectedInventoryItem {
+                to_player,
+                inventory_type,
+            } => self
+                .move_selected_items(to_player, inventory_type)
+                .map(drop),
+            ReplayInput::SortInventory { is_player, c, r } => self.sort_inventory(is_player, c, r),
+            ReplayInput::QuickStackToChest { c, r } => self.quick_stack_to_chest(c, r).map(drop),
+            ReplayInput::SelectRecipe { c, r, id } => self.select_recipe(c, r, &id).map(drop),
+            ReplayInput::QueueCraft { recipe_index } => self.queue_craft(recipe_index).map(drop),
+            ReplayInput::CancelCraft { index } => self.cancel_craft(index).map(drop),
+            ReplayInput::StartResearch { tech_id } => self.start_research(&tech_id),
+            ReplayInput::SetItemUnlocked { item_name, value } => {
+                self.set_item_unlocked(&item_name, value)
+            }
+            ReplayInput::CaptureBlueprint { x0, y0, x1, y1 } => {
+                self.capture_blueprint(x0, y0, x1, y1).map(drop)
+            }
+            ReplayInput::SaveBlueprint { name } => self.save_blueprint(&name),
+            ReplayInput::DeleteBlueprint { name } => {
+                self.delete_blueprint(&name);
+                Ok(())
+            }
+            ReplayInput::PasteBlueprint {
+                x,
+                y,
+                data,
+                rotation,
+                flip,
+            } => self.place_blueprint(x, y, &data, rotation, flip).map(drop),
+            ReplayInput::DeconstructArea { x0, y0, x1, y1 } => {
+                self.deconstruct_area(x0, y0, x1, y1).map(drop)
+            }
+            ReplayInput::CopySettings { c, r } => {
+                self.copy_settings(c, r);
+                Ok(())
+            }
+            ReplayInput::PasteSettings { c, r } => self.paste_settings(c, r).map(drop),
+            ReplayInput::DamageStructure { c, r, amount } => {
+                self.damage_structure(c, r, amount).map(drop)
+            }
+            ReplayInput::RepairStructure { c, r } => self.repair_structure(c, r).map(drop),
+            ReplayInput::ConnectPowerWire { a_c, a_r, b_c, b_r } => {
+                self.connect_power_wire(a_c, a_r, b_c, b_r).map(drop)
+            }
+            ReplayInput::DisconnectPowerWire { a_c, a_r, b_c, b_r } => {
+                self.disconnect_power_wire(a_c, a_r, b_c, b_r).map(drop)
+            }
+            ReplayInput::ConnectSignal { a, b } => self.connect_signal(&a, &b).map(drop),
+            ReplayInput::SetInserterCondition {
+                c,
+                r,
+                item,
+                op,
+                value,
+            } => self.set_inserter_condition(c, r, &item, &op, value),
+            ReplayInput::SetInserterFilter { c, r, items } => {
+                self.set_inserter_filter_items(c, r, items)
+            }
+            ReplayInput::SetInserterFilterMode { c, r, is_blacklist } => {
+                self.set_inserter_filter_mode(c, r, is_blacklist)
+            }
+            ReplayInput::SetInserterStackSize { c, r, n } => self.set_inserter_stack_size(c, r, n),
+            ReplayInput::SetInserterChestLimit { c, r, item, max } => {
+                self.set_inserter_chest_limit(c, r, &item, max)
+            }
+            ReplayInput::SetOutputToGround { c, r, enable } => {
+                self.set_output_to_ground(c, r, enable)
+            }
+            ReplayInput::SetSplitterConfig {
+                c,
+                r,
+                input_priority,
+                output_priority,
+                filter,
+                filter_side,
+            } => self.set_splitter_config(
+                c,
+                r,
+                &input_priority,
+                &output_priority,
+                filter,
+                &filter_side,
+            ),
+            ReplayInput::SetChestRequest { c, r, item, count } => {
+                self.set_chest_request(c, r, &item, count)
+            }
+            ReplayInput::SetChestSlotFilter {
+                c,
+                r,
+                slot,
+                item_name,
+            } => self.set_chest_slot_filter(c, r, slot, &item_name),
+            ReplayInput::SetFluidFilter { c, r, fluid_type } => {
+                self.set_fluid_filter(c, r, &fluid_type)
+            }
+            ReplayInput::SetValveDirection { c, r, rotation } => {
+                self.set_valve_direction(c, r, &rotation)
+            }
+            ReplayInput::SetValveMaxFlow { c, r, max_flow } => {
+                self.set_valve_max_flow(c, r, max_flow)
+            }
+            ReplayInput::SetMergeDropItems { enable } => {
+                self.set_merge_drop_items(enable);
+                Ok(())
+            }
+            ReplayInput::SetBeltDensity { factor } => self.set_belt_density(factor).map(drop),
+            ReplayInput::SetProductivity { bonus } => self.set_productivity(bonus),
+            ReplayInput::SetSleepIdleStructures { enable } => {
+                self.set_sleep_idle_structures(enable);
+                Ok(())
+            }
+        }
+    }
+
+    /// Cancel the states in the middle of a gesture, which cannot be reproduced from the log.
+    fn reset_transient_input(&mut self) {
+        self.drag_path = None;
+        self.viewport.velocity = (0., 0.);
+        self.viewport.pan_input = (0., 0.);
+        self.zoom.cancel();
+    }
+}
+
+#[wasm_bindgen]
+impl FactorishState {
+    /// Start recording the inputs from the current state of the game.
+    /// Any recording in progress is discarded.
+    pub fn start_recording(&mut self) -> Result<(), JsValue> {
+        self.reset_transient_input();
+        self.recording = Some(ReplayLog {
+            game: self.serialize_game_value()?,
+            rng: self.rng.clone(),
+            selected_item: self.selected_item,
+            tool_rotation: self.tool_rotation,
+            ore_harvesting: self.ore_harvesting,
+            blueprint_clipboard: self.blueprint_clipboard.clone(),
+            events: vec![],
+        });
+        Ok(())
+    }
+
+    /// Stop recording and return the log as a JSON string, which can be passed to `replay`.
+    pub fn stop_recording(&mut self) -> Result<String, JsValue> {
+        let log = self
+            .recording
+            .take()
+            .ok_or_else(|| js_str!("Not recording"))?;
+        serde_json::to_string(&log).map_err(|e| js_str!("Serialize error: {}", e))
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    /// Restore the game at the start of the log and apply the recorded inputs in order.
+    /// Errors from the individual inputs are ignored, since they were returned to the caller
+    /// in the recorded session as well.
+    pub fn replay(&mut self, log: &str) -> Result<(), JsValue> {
+        if self.recording.is_some() {
+            return js_err!("Cannot replay while recording");
+        }
+        let log: ReplayLog =
+            serde_json::from_str(log).map_err(|e| js_str!("Replay log error: {}", e))?;
+        self.deserialize_game_value(log.game)?;
+        self.rng = log.rng;
+        self.selected_item = log.selected_item;
+        self.tool_rotation = log.tool_rotation;
+        self.ore_harvesting = log.ore_harvesting;
+        self.blueprint_clipboard = log.blueprint_clipboard;
+        self.reset_transient_input();
+
+        for event in log.events {
+            self.viewport.x = event.viewport.0;
+            self.viewport.y = event.viewport.1;
+            self.viewport.scale = event.viewport.2;
+            // Only the simulation steps stop the replay, since the errors of the other inputs
+            // were returned to JavaScript in the recorded session as well.
+            if let ReplayInput::Simulate { delta_time } = event.input {
+                self.tick(delta_time)?;
+            } else {
+                self.apply_input(event.input).ok();
+            }
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_replay_log_roundtrip() {
+    let log = ReplayLog {
+        game: serde_json::json!({}),
+        rng: Xor128::new(42),
+        selected_item: Some(SelectedItem::ToolBelt(3)),
+        tool_rotation: Rotation::Top,
+        ore_harvesting: None,
+        blueprint_clipboard: None,
+        events: vec![ReplayEvent {
+            time: 1.5,
+            viewport: (0., 0., 1.),
+            input: ReplayInput::KeyDown {
+                key_code: 82,
+                ctrl: false,
+            },
+        }],
+    };
+    let mut restored: ReplayLog =
+        serde_json::from_str(&serde_json::to_string(&log).unwrap()).unwrap();
+    // The random sequence continues from the recorded state.
+    assert_eq!(restored.rng.nexti(), log.rng.clone().nexti());
+    assert!(restored.selected_item == log.selected_item);
+    assert!(matches!(
+        restored.events[0].input,
+        ReplayInput::KeyDown {
+            key_code: 82,
+            ctrl: false
+        }
+    ));
+}
+
+#[test]
+fn test_replay_reproduces_state() {
+    use super::{
+        assembler::Assembler, items::ItemType, recipes::RECIPES, scenarios::empty_state,
+        InventoryTrait,
+    };
+
+    let mut state = empty_state(16, 16);
+    state
+        .add_structure(Box::new(Assembler::new(&Position::new(3, 3))))
+        .unwrap();
+    state.player.inventory.add_items(&ItemType::IronPlate, 50);
+    state.start_recording().unwrap();
+
+    let recipe_id = &RECIPES.for_structure("Assembler").next().unwrap().id;
+    state.select_recipe(3, 3, recipe_id).unwrap();
+    state.queue_craft(0).unwrap();
+    state.tick_headless(0.05).unwrap();
+    state.start_research("logistics").unwrap();
+    state.set_item_unlocked("Splitter", false).unwrap();
+    state.rotate_tool();
+    state.sort_inventory(true, 0, 0).unwrap();
+    state.on_key_down(49, false).unwrap();
+    for _ in 0..10 {
+        state.tick_headless(0.05).unwrap();
+    }
+    let log = state.stop_recording().unwrap();
+
+    // A game in a different state is restored to the start of the log and follows the inputs
+    let mut replayed = empty_state(16, 16);
+    replayed.replay(&log).unwrap();
+    // The unlocked items are saved from a set, so they are compared regardless of the order
+    let split_unlocks = |state: &FactorishState| {
+        let mut save = state.serialize_game_value().unwrap();
+        let unlocks: std::collections::HashSet<ItemType> =
+            serde_json::from_value(save["scenario_unlocks"].take()).unwrap();
+        (save, unlocks)
+    };
+    let (save, unlocks) = split_unlocks(&state);
+    assert!(!unlocks.contains(&ItemType::Splitter));
+    assert!(save["crafting_queue"].as_array().unwrap().len() == 1);
+    assert_eq!(split_unlocks(&replayed), (save, unlocks));
+    assert!(matches!(replayed.tool_rotation, Rotation::Top));
+    assert!(replayed.selected_item == state.selected_item);
+    // The tool selected by the key press is recorded once, as the key press
+    let events: serde_json::Value = serde_json::from_str(&log).unwrap();
+    assert!(events["events"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .all(|event| event["type"] != "SelectTool"));
+}
+
+#[test]
+fn test_replay_blueprint() {
+    use super::{
+        items::ItemType, scenarios::empty_state, transport_belt::TransportBelt, InventoryTrait,
+    };
+
+    let mut state = empty_state(16, 16);
+    for x in 0..3 {
+        state
+            .add_structure(Box::new(TransportBelt::new(x, 0, Rotation::Right)))
+            .unwrap();
+    }
+    state
+        .player
+        .inventory
+        .add_items(&ItemType::TransportBelt, 3);
+    state.start_recording().unwrap();
+
+    let data = state.capture_blueprint(0, 0, 2, 0).unwrap();
+    state.save_blueprint("line").unwrap();
+    assert!(state
+        .place_blueprint(0, 2, &data, 0, false)
+        .unwrap()
+        .is_empty());
+    let log = state.stop_recording().unwrap();
+
+    let mut replayed = empty_state(16, 16);
+    replayed.replay(&log).unwrap();
+    assert!(replayed.blueprints.contains_key("line"));
+    let belts = |state: &FactorishState| {
+        (0..3)
+            .filter(|x| state.find_structure_tile(&[*x, 2]).is_some())
+            .count()
+    };
+    assert_eq!(belts(&state), 3);
+    assert_eq!(belts(&replayed), 3);
+    assert_eq!(
+        replayed.serialize_game_value().unwrap(),
+        state.serialize_game_value().unwrap()
+    );
+}
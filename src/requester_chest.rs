@@ -0,0 +1,186 @@
+use super::{
+    chest::{deserialize_chest_inventory, CHEST_CAPACITY},
+    drop_items::DropItem,
+    items::{item_to_str, ItemType},
+    structure::{ItemResponse, ItemResponseResult, Structure, StructureDynIter, StructureId},
+    FactorishState, FrameProcResult, Inventory, InventoryTrait, Position,
+};
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+use web_sys::CanvasRenderingContext2d;
+
+/// Provider chests within this distance in tiles belong to the requester's logistic network.
+pub(crate) const LOGISTIC_RANGE: i32 = 10;
+
+/// A chest that pulls requested items from provider chests nearby.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct RequesterChest {
+    position: Position,
+    #[serde(deserialize_with = "deserialize_chest_inventory")]
+    inventory: Inventory,
+    #[serde(default)]
+    requests: Inventory,
+}
+
+impl RequesterChest {
+    pub(crate) fn new(position: &Position) -> Self {
+        RequesterChest {
+            position: *position,
+            inventory: Inventory::with_capacity(CHEST_CAPACITY),
+            requests: Inventory::new(),
+        }
+    }
+
+    /// Find a provider chest in the logistic network that has the given item.
+    fn find_provider(
+        &self,
+        structures: &StructureDynIter,
+        item_type: &ItemType,
+    ) -> Option<StructureId> {
+        structures
+            .dyn_iter_id()
+            .find(|(_, s)| {
+                s.logistic_provider()
+                    && s.position().distance(&self.position) <= LOGISTIC_RANGE
+                    && s.inventory(true)
+                        .map_or(false, |inventory| 0 < inventory.count_item(item_type))
+            })
+            .map(|(id, _)| id)
+    }
+}
+
+impl Structure for RequesterChest {
+    fn name(&self) -> &'static str {
+        "Requester Chest"
+    }
+
+    fn position(&self) -> &Position {
+        &self.position
+    }
+
+    fn draw(
+        &self,
+        state: &FactorishState,
+        context: &CanvasRenderingContext2d,
+        depth: i32,
+        _is_toolbar: bool,
+    ) -> Result<(), JsValue> {
+        if depth != 0 {
+            return Ok(());
+        };
+        let (x, y) = (self.position.x as f64 * 32., self.position.y as f64 * 32.);
+        match state.image_requester_chest.as_ref() {
+            Some(img) => {
+                context.draw_image_with_image_bitmap(&img.bitmap, x, y)?;
+                Ok(())
+            }
+            None => js_err!("requester chest image not available"),
+        }
+    }
+
+    fn desc(&self, _state: &FactorishState) -> String {
+        format!(
+            "Requests: <br>{}Items: <br>{}",
+            self.requests
+                .iter()
+                .map(|(item, count)| format!(
+                    "{}: {}/{}<br>",
+                    item_to_str(item),
+                    self.inventory.count_item(item),
+                    count
+                ))
+                .fold(String::from(""), |accum, item| accum + &item),
+            self.inventory
+                .iter()
+                .map(|item| format!("{:?}: {}<br>", item.0, item.1))
+                .fold(String::from(""), |accum, item| accum + &item)
+        )
+    }
+
+    /// Pull at most one item per tick from the logistic network. We go through `output()`
+    /// of the provider, the same path as inserters, so that the provider's inventory never
+    /// gets out of sync even if an inserter is taking from the same chest.
+    fn frame_proc(
+        &mut self,
+        _me: StructureId,
+        state: &mut FactorishState,
+        structures: &mut StructureDynIter,
+    ) -> Result<FrameProcResult, ()> {
+        for (item_type, count) in &self.requests {
+            if *count <= self.inventory.count_item(item_type)
+                || self.inventory.is_full_for(item_type)
+            {
+                continue;
+            }
+            if let Some(provider) = self
+                .find_provider(structures, item_type)
+                .and_then(|id| structures.get_mut(id))
+            {
+                if provider.output(state, item_type).is_ok() {
+                    self.inventory.add_item(item_type);
+                    return Ok(FrameProcResult::InventoryChanged(self.position));
+                }
+            }
+        }
+        Ok(FrameProcResult::None)
+    }
+
+    fn item_response(&mut self, item: &DropItem) -> Result<ItemResponseResult, ()> {
+        if self.inventory.add_item(&item.type_) {
+            Ok((
+                ItemResponse::Consume,
+                Some(FrameProcResult::InventoryChanged(self.position)),
+            ))
+        } else {
+            Err(())
+        }
+    }
+
+    fn input(&mut self, o: &DropItem) -> Result<(), JsValue> {
+        self.item_response(o)
+            .map(|_| ())
+            .map_err(|_| js_str!("ItemResponse failed"))
+    }
+
+    fn can_input(&self, o: &ItemType) -> bool {
+        !self.inventory.is_full_for(o)
+    }
+
+    fn can_output(&self) -> Inventory {
+        self.inventory.clone()
+    }
+
+    fn output(&mut self, _state: &mut FactorishState, item_type: &ItemType) -> Result<(), ()> {
+        if self.inventory.remove_item(item_type) {
+            Ok(())
+        } else {
+            Err(())
+        }
+    }
+
+    fn inventory(&self, is_input: bool) -> Option<&Inventory> {
+        if is_input {
+            Some(&self.inventory)
+        } else {
+            None
+        }
+    }
+
+    fn inventory_mut(&mut self, is_input: bool) -> Option<&mut Inventory> {
+        if is_input {
+            Some(&mut self.inventory)
+        } else {
+            None
+        }
+    }
+
+    fn chest_requests(&self) -> Option<&Inventory> {
+        Some(&self.requests)
+    }
+
+    fn chest_requests_mut(&mut self) -> Option<&mut Inventory> {
+        Some(&mut self.requests)
+    }
+
+    crate::serialize_impl!();
+}
@@ -0,0 +1,266 @@
+use super::{
+    items::{item_to_str, ItemType},
+    replay::ReplayInput,
+    tool_defs, FactorishState,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use wasm_bindgen::prelude::*;
+
+pub(crate) struct Technology {
+    pub id: &'static str,
+    pub name: &'static str,
+    /// The number of science packs to finish the research
+    pub cost: usize,
+    pub prerequisites: &'static [&'static str],
+    pub unlocks: &'static [ItemType],
+    /// Productivity bonus of ore mines and furnaces granted by the research
+    pub productivity: f64,
+}
+
+pub(crate) const TECHNOLOGIES: [Technology; 5] = [
+    Technology {
+        id: "logistics",
+        name: "Logistics",
+        cost: 10,
+        prerequisites: &[],
+        unlocks: &[ItemType::Splitter],
+        productivity: 0.,
+    },
+    Technology {
+        id: "electric-smelting",
+        name: "Electric Smelting",
+        cost: 20,
+        prerequisites: &[],
+        unlocks: &[ItemType::ElectricFurnace],
+        productivity: 0.,
+    },
+    Technology {
+        id: "energy-storage",
+        name: "Energy Storage",
+        cost: 30,
+        prerequisites: &["electric-smelting"],
+        unlocks: &[ItemType::Accumulator],
+        productivity: 0.,
+    },
+    Technology {
+        id: "logistic-network",
+        name: "Logistic Network",
+        cost: 50,
+        prerequisites: &["logistics"],
+        unlocks: &[ItemType::RequesterChest],
+        productivity: 0.,
+    },
+    Technology {
+        id: "mining-productivity",
+        name: "Mining Productivity",
+        cost: 40,
+        prerequisites: &[],
+        unlocks: &[],
+        productivity: 0.1,
+    },
+];
+
+fn find_technology(id: &str) -> Option<&'static Technology> {
+    TECHNOLOGIES.iter().find(|tech| tech.id == id)
+}
+
+/// The tools that are available without research, which is what staged scenarios start with.
+pub(crate) fn default_unlocked() -> HashSet<ItemType> {
+    tool_defs
+        .iter()
+        .map(|tool| tool.item_type)
+        .filter(|item| !TECHNOLOGIES.iter().any(|tech| tech.unlocks.contains(item)))
+        .collect()
+}
+
+/// The state of the research, which is saved with the game.
+#[derive(Default, Serialize, Deserialize)]
+pub(crate) struct TechTree {
+    researched: HashSet<String>,
+    current: Option<String>,
+    /// Science packs consumed for each technology. It is kept when the player switches the research.
+    progress: HashMap<String, usize>,
+    /// Names of the technologies researched since the last `take_completed`, which are reported
+    /// to JavaScript.
+    #[serde(skip)]
+    completed: Vec<&'static str>,
+}
+
+impl TechTree {
+    pub(crate) fn is_researched(&self, id: &str) -> bool {
+        self.researched.contains(id)
+    }
+
+    /// Returns the items unlocked by the researched technologies, which staged scenarios
+    /// allow in addition to their own unlocked items.
+    pub(crate) fn unlocked_items(&self) -> impl Iterator<Item = ItemType> + '_ {
+        TECHNOLOGIES
+            .iter()
+            .filter(move |tech| self.is_researched(tech.id))
+            .flat_map(|tech| tech.unlocks.iter().copied())
+    }
+
+    pub(crate) fn productivity_bonus(&self) -> f64 {
+        TECHNOLOGIES
+            .iter()
+            .filter(|tech| self.is_researched(tech.id))
+            .map(|tech| tech.productivity)
+            .sum()
+    }
+
+    pub(crate) fn is_active(&self) -> bool {
+        self.current.is_some()
+    }
+
+    pub(crate) fn current_technology(&self) -> Option<&'static Technology> {
+        find_technology(self.current.as_ref()?)
+    }
+
+    pub(crate) fn take_completed(&mut self) -> Vec<&'static str> {
+        std::mem::take(&mut self.completed)
+    }
+
+    /// Add a consumed science pack to the active research.
+    /// @returns the technology if it has just been researched.
+    pub(crate) fn add_progress(&mut self) -> Option<&'static Technology> {
+        let tech = find_technology(self.current.as_ref()?)?;
+        let progress = self.progress.entry(tech.id.to_string()).or_default();
+        *progress += 1;
+        if tech.cost <= *progress {
+            self.progress.remove(tech.id);
+            self.researched.insert(tech.id.to_string());
+            self.current = None;
+            self.completed.push(tech.name);
+            Some(tech)
+        } else {
+            None
+        }
+    }
+}
+
+impl FactorishState {
+    /// Returns whether the player may place the item, which is always the case in the sandbox.
+    /// Staged scenarios allow the items they unlocked and the ones unlocked by research.
+    pub(crate) fn is_unlocked(&self, item: &ItemType) -> bool {
+        self.unlocked.as_ref().map_or(true, |unlocked| {
+            unlocked.contains(item) || self.research.unlocked_items().any(|i| i == *item)
+        })
+    }
+}
+
+#[derive(Serialize)]
+struct TechnologyState {
+    id: &'static str,
+    name: &'static str,
+    cost: usize,
+    progress: usize,
+    researched: bool,
+    available: bool,
+    unlocks: Vec<String>,
+    productivity: f64,
+}
+
+#[wasm_bindgen]
+impl FactorishState {
+    pub fn start_research(&mut self, tech_id: &str) -> Result<(), JsValue> {
+        self.record_input(ReplayInput::StartResearch {
+            tech_id: tech_id.to_string(),
+        });
+        let tech =
+            find_technology(tech_id).ok_or_else(|| js_str!("Unknown technology: {}", tech_id))?;
+        if self.research.is_researched(tech.id) {
+            return js_err!("{} is already researched", tech.name);
+        }
+        if let Some(missing) = tech
+            .prerequisites
+            .iter()
+            .find(|id| !self.research.is_researched(id))
+        {
+            return js_err!("{} requires {}", tech.name, missing);
+        }
+        self.research.current = Some(tech.id.to_string());
+        Ok(())
+    }
+
+    /// Returns an object with the active research and a list of all technologies
+    /// with their progress.
+    pub fn get_research_progress(&self) -> Result<JsValue, JsValue> {
+        let technologies = TECHNOLOGIES
+            .iter()
+            .map(|tech| TechnologyState {
+                id: tech.id,
+                name: tech.name,
+                cost: tech.cost,
+                progress: *self.research.progress.get(tech.id).unwrap_or(&0),
+                researched: self.research.is_researched(tech.id),
+                available: tech
+                    .prerequisites
+                    .iter()
+                    .all(|id| self.research.is_researched(id)),
+                unlocks: tech.unlocks.iter().map(item_to_str).collect(),
+                productivity: tech.productivity,
+            })
+            .collect::<Vec<_>>();
+        JsValue::from_serde(&serde_json::json!({
+            "current": self.research.current,
+            "technologies": technologies,
+        }))
+        .map_err(|e| js_str!("serialize error: {}", e))
+    }
+}
+
+#[test]
+fn test_research_unlocks_item() {
+    let unlocked = default_unlocked();
+    assert!(unlocked.contains(&ItemType::TransportBelt));
+    assert!(!unlocked.contains(&ItemType::Splitter));
+
+    let mut tree = TechTree::default();
+
+    tree.current = Some("logistics".to_string());
+    for _ in 0..9 {
+        assert!(tree.add_progress().is_none());
+    }
+    assert_eq!(tree.add_progress().map(|tech| tech.id), Some("logistics"));
+    assert_eq!(
+        tree.unlocked_items().collect::<Vec<_>>(),
+        [ItemType::Splitter]
+    );
+    assert!(!tree.is_active());
+}
+
+#[test]
+fn test_staged_unlocks() {
+    use super::scenarios::empty_state;
+
+    // The sandbox has every tool, including the ones that research unlocks in staged scenarios
+    let mut state = empty_state(16, 16);
+    assert!(tool_defs
+        .iter()
+        .all(|tool| state.is_unlocked(&tool.item_type)));
+    let save = state.serialize_game_value().unwrap();
+    assert!(save.get("scenario_unlocks").is_none());
+
+    state.unlocked = Some(default_unlocked());
+    assert!(state.is_unlocked(&ItemType::TransportBelt));
+    assert!(!state.is_unlocked(&ItemType::Splitter));
+    state.research.researched.insert("logistics".to_string());
+    assert!(state.is_unlocked(&ItemType::Splitter));
+
+    // Only the scenario's own unlocks are saved, and the research is restored separately
+    let save = state.serialize_game_value().unwrap();
+    let saved: HashSet<ItemType> =
+        serde_json::from_value(save["scenario_unlocks"].clone()).unwrap();
+    assert_eq!(saved, default_unlocked());
+    let mut loaded = empty_state(16, 16);
+    loaded.deserialize_game_value(save).unwrap();
+    assert!(loaded.is_unlocked(&ItemType::Splitter));
+    assert!(!loaded.is_unlocked(&ItemType::Accumulator));
+
+    // Saves without the field, like the ones before staged scenarios, get every tool back
+    let mut save = state.serialize_game_value().unwrap();
+    save.as_object_mut().unwrap().remove("scenario_unlocks");
+    loaded.deserialize_game_value(save).unwrap();
+    assert!(loaded.is_unlocked(&ItemType::Accumulator));
+}
@@ -0,0 +1,202 @@
+use super::{FactorishState, SAVE_VERSION};
+use serde_json::{json, Value};
+use wasm_bindgen::prelude::*;
+
+/// The oldest save data version that can be migrated to the current version.
+const MIN_SAVE_VERSION: i64 = 4;
+
+/// Fields that every save data must have. They cannot be defaulted since the game is meaningless
+/// without them.
+const REQUIRED_FIELDS: [&str; 6] = [
+    "sim_time",
+    "player",
+    "width",
+    "height",
+    "board",
+    "structures",
+];
+
+/// Compression level for deflate, from 0 (none) to 10 (best).
+const COMPRESSION_LEVEL: u8 = 6;
+
+/// Header of exported save files, followed by the checksum of the payload in hex and a newline.
+const EXPORT_MAGIC: &str = "FactorishWasmSave";
+
+/// 32 bit FNV-1a hash, which is enough to detect truncated or edited files.
+fn checksum(data: &str) -> u32 {
+    data.bytes().fold(0x811c_9dc5, |hash, byte| {
+        (hash ^ byte as u32).wrapping_mul(0x0100_0193)
+    })
+}
+
+/// Split an exported save file into the payload, checking the header and the checksum.
+fn parse_export(data: &str) -> Result<&str, JsValue> {
+    let (header, payload) = data
+        .split_once('\n')
+        .ok_or_else(|| js_str!("The file is not a FactorishWasm save: no header"))?;
+    let sum = header
+        .strip_prefix(EXPORT_MAGIC)
+        .and_then(|rest| rest.strip_prefix(' '))
+        .ok_or_else(|| js_str!("The file is not a FactorishWasm save: wrong header"))?;
+    let sum = u32::from_str_radix(sum.trim(), 16)
+        .map_err(|_| js_str!("The save file has a malformed checksum"))?;
+    if sum != checksum(payload) {
+        return js_err!("The save file is corrupt: checksum mismatch");
+    }
+    Ok(payload)
+}
+
+/// Upgrade version 4 save data to version 5 by filling the fields that version 4 may lack.
+fn migrate_4_to_5(json: &mut serde_json::Map<String, Value>) -> Result<(), String> {
+    for field in &REQUIRED_FIELDS {
+        if !json.contains_key(*field) {
+            return Err(field.to_string());
+        }
+    }
+    json.entry("power_wires").or_insert_with(|| json!([]));
+    json.entry("items").or_insert_with(|| json!([]));
+    json.entry("tool_belt")
+        .or_insert_with(|| json!([null, null, null, null, null, null, null, null, null, null]));
+    Ok(())
+}
+
+/// Upgrade version 5 save data to version 6, where the tool belt has multiple rows.
+/// The single row of the older versions becomes the first row.
+fn migrate_5_to_6(json: &mut serde_json::Map<String, Value>) -> Result<(), String> {
+    let tool_belt = json
+        .get_mut("tool_belt")
+        .ok_or_else(|| "tool_belt".to_string())?;
+    *tool_belt = json!([tool_belt.take()]);
+    Ok(())
+}
+
+/// Upgrade version 6 save data to version 7, which has the blueprint library.
+fn migrate_6_to_7(json: &mut serde_json::Map<String, Value>) -> Result<(), String> {
+    json.entry("blueprints").or_insert_with(|| json!({}));
+    Ok(())
+}
+
+/// Migrate save data to `SAVE_VERSION` in place, one version at a time.
+pub(crate) fn migrate_save(json: &mut Value) -> Result<(), JsValue> {
+    let map = json
+        .as_object_mut()
+        .ok_or_else(|| js_str!("Save data is not an object"))?;
+
+    let version = if let Some(version) = map.get("version") {
+        version
+            .as_i64()
+            .ok_or_else(|| js_str!("Version string cannot be parsed as int"))?
+    } else {
+        0
+    };
+
+    if version < MIN_SAVE_VERSION {
+        return js_err!("Save data version is too old. Please start a new game.");
+    }
+
+    for from in version..SAVE_VERSION {
+        let result = match from {
+            4 => migrate_4_to_5(map),
+            5 => migrate_5_to_6(map),
+            6 => migrate_6_to_7(map),
+            _ => return js_err!("No migration from save data version {}", from),
+        };
+        result.map_err(|field| {
+            js_str!(
+                "Failed to migrate save data from version {} to {}: field \"{}\" is missing",
+                from,
+                from + 1,
+                field
+            )
+        })?;
+        map.insert("version".to_string(), Value::from(from + 1));
+    }
+
+    Ok(())
+}
+
+#[wasm_bindgen]
+impl FactorishState {
+    /// Serialize the game in MessagePack compressed with deflate, which is much smaller than
+    /// the JSON returned by `serialize_game`.
+    pub fn serialize_game_binary(&self) -> Result<Vec<u8>, JsValue> {
+        let packed = rmp_serde::to_vec(&self.serialize_game_value()?)
+            .map_err(|e| js_str!("Serialize error: {}", e))?;
+        Ok(miniz_oxide::deflate::compress_to_vec(
+            &packed,
+            COMPRESSION_LEVEL,
+        ))
+    }
+
+    pub fn deserialize_game_binary(&mut self, data: &[u8]) -> Result<(), JsValue> {
+        let packed = miniz_oxide::inflate::decompress_to_vec(data)
+            .map_err(|e| js_str!("Decompress error: {:?}", e))?;
+        let json =
+            rmp_serde::from_slice(&packed).map_err(|e| js_str!("Deserialize error: {}", e))?;
+        self.deserialize_game_value(json)
+    }
+
+    /// Serialize the game with a header and a checksum, for the JS side to offer as a file download.
+    pub fn export_save(&self) -> Result<String, JsValue> {
+        let payload = self.serialize_game()?;
+        Ok(format!(
+            "{} {:08x}\n{}",
+            EXPORT_MAGIC,
+            checksum(&payload),
+            payload
+        ))
+    }
+
+    /// Load a save exported by `export_save`. The current game is kept intact if the data
+    /// cannot be loaded.
+    pub fn import_save(&mut self, data: &str) -> Result<(), JsValue> {
+        let json: Value = serde_json::from_str(parse_export(data)?)
+            .map_err(|e| js_str!("The save file is not valid JSON: {}", e))?;
+        self.deserialize_game_value(json)
+    }
+}
+
+#[test]
+fn test_export_checksum() {
+    let payload = "{\"version\":5}";
+    let data = format!("{} {:08x}\n{}", EXPORT_MAGIC, checksum(payload), payload);
+    assert_eq!(parse_export(&data).ok(), Some(payload));
+}
+
+#[test]
+fn test_migrate_4_to_5() {
+    let mut json = json!({
+        "version": 4,
+        "sim_time": 0.,
+        "player": {},
+        "width": 64,
+        "height": 64,
+        "board": [],
+        "structures": [],
+    });
+    assert!(migrate_save(&mut json).is_ok());
+    assert_eq!(json["version"], SAVE_VERSION);
+    assert_eq!(json["power_wires"], json!([]));
+    assert_eq!(json["items"], json!([]));
+}
+
+#[test]
+fn test_migrate_5_to_6() {
+    let mut json = json!({
+        "version": 5,
+        "tool_belt": ["Transport Belt", null],
+    });
+    assert!(migrate_save(&mut json).is_ok());
+    assert_eq!(json["version"], SAVE_VERSION);
+    assert_eq!(json["tool_belt"], json!([["Transport Belt", null]]));
+}
+
+#[test]
+fn test_migrate_6_to_7() {
+    let mut json = json!({
+        "version": 6,
+    });
+    assert!(migrate_save(&mut json).is_ok());
+    assert_eq!(json["version"], SAVE_VERSION);
+    assert_eq!(json["blueprints"], json!({}));
+}
@@ -0,0 +1,146 @@
+use super::{window, FactorishState};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use wasm_bindgen::prelude::*;
+
+/// The slot that is saved on autosave and on leaving the page, and loaded on start.
+pub(crate) const AUTOSAVE_SLOT: &str = "autosave";
+/// Autosave interval in ticks, which is 100 seconds with the default time step.
+pub(crate) const DEFAULT_AUTOSAVE_INTERVAL: u32 = 2000;
+/// The key of the index of the saved slots and their timestamps
+const SAVE_INDEX_KEY: &str = "FactorishWasmGameSaveIndex";
+
+/// The key of the save data of a slot. The autosave slot uses the key from before the slots
+/// were introduced, so that the existing saves are kept.
+fn slot_key(slot: &str) -> String {
+    if slot == AUTOSAVE_SLOT {
+        "FactorishWasmGameSave".to_string()
+    } else {
+        format!("FactorishWasmGameSave.{}", slot)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct SaveInfo {
+    /// Milliseconds since the epoch when the slot was saved
+    timestamp: f64,
+}
+
+#[derive(Serialize)]
+struct SaveListEntry<'a> {
+    name: &'a str,
+    timestamp: f64,
+}
+
+fn storage() -> Result<web_sys::Storage, JsValue> {
+    window()
+        .local_storage()?
+        .ok_or_else(|| js_str!("The subsystem does not support localStorage"))
+}
+
+fn read_index(storage: &web_sys::Storage) -> Result<BTreeMap<String, SaveInfo>, JsValue> {
+    Ok(storage
+        .get_item(SAVE_INDEX_KEY)?
+        .and_then(|data| serde_json::from_str(&data).ok())
+        .unwrap_or_default())
+}
+
+impl FactorishState {
+    /// Called every tick from `simulate` to save the game periodically.
+    pub(crate) fn proc_autosave(&mut self) -> Result<(), JsValue> {
+        if self.autosave_interval == 0 {
+            return Ok(());
+        }
+        self.autosave_ticks += 1;
+        if self.autosave_interval <= self.autosave_ticks {
+            self.autosave_ticks = 0;
+            self.save_game_to(&self.autosave_slot.clone())?;
+        }
+        Ok(())
+    }
+}
+
+#[wasm_bindgen]
+impl FactorishState {
+    /// Set the number of ticks between autosaves, or 0 to disable autosaving.
+    pub fn set_autosave_interval(&mut self, ticks: u32) {
+        self.autosave_interval = ticks;
+        self.autosave_ticks = 0;
+    }
+
+    /// Set the slot that autosave, `save_game` and `load_game` use.
+    pub fn set_autosave_slot(&mut self, slot: &str) -> Result<(), JsValue> {
+        if slot.is_empty() {
+            return js_err!("Slot name cannot be empty");
+        }
+        self.autosave_slot = slot.to_string();
+        Ok(())
+    }
+
+    pub fn save_game(&self) -> Result<(), JsValue> {
+        self.save_game_to(&self.autosave_slot)
+    }
+
+    pub fn load_game(&mut self) -> Result<(), JsValue> {
+        self.load_game_from(&self.autosave_slot.clone())
+    }
+
+    pub fn save_game_to(&self, slot: &str) -> Result<(), JsValue> {
+        if slot.is_empty() {
+            return js_err!("Slot name cannot be empty");
+        }
+        let storage = storage()?;
+        storage.set_item(&slot_key(slot), &self.serialize_game()?)?;
+        let mut index = read_index(&storage)?;
+        index.insert(
+            slot.to_string(),
+            SaveInfo {
+                timestamp: js_sys::Date::now(),
+            },
+        );
+        storage.set_item(
+            SAVE_INDEX_KEY,
+            &serde_json::to_string(&index).map_err(|e| js_str!("Serialize error: {}", e))?,
+        )?;
+        Ok(())
+    }
+
+    pub fn load_game_from(&mut self, slot: &str) -> Result<(), JsValue> {
+        let data = storage()?
+            .get_item(&slot_key(slot))?
+            .ok_or_else(|| js_str!("save data not found in slot {}!", slot))?;
+        self.deserialize_game(&data)
+    }
+
+    /// Returns an array of `{name, timestamp}` of the saved slots, the newest first.
+    /// The timestamp is in milliseconds since the epoch, which can be passed to `Date`.
+    pub fn list_saves(&self) -> Result<JsValue, JsValue> {
+        let storage = storage()?;
+        let index = read_index(&storage)?;
+        let mut saves = index
+            .iter()
+            .map(|(name, info)| SaveListEntry {
+                name,
+                timestamp: info.timestamp,
+            })
+            .collect::<Vec<_>>();
+        // Saves from before the index was introduced have no timestamp
+        if !index.contains_key(AUTOSAVE_SLOT)
+            && storage.get_item(&slot_key(AUTOSAVE_SLOT))?.is_some()
+        {
+            saves.push(SaveListEntry {
+                name: AUTOSAVE_SLOT,
+                timestamp: 0.,
+            });
+        }
+        saves.sort_by(|a, b| b.timestamp.partial_cmp(&a.timestamp).unwrap());
+        JsValue::from_serde(&saves).map_err(|e| js_str!("serialize error: {}", e))
+    }
+}
+
+#[test]
+fn test_slot_keys() {
+    assert_eq!(slot_key(AUTOSAVE_SLOT), "FactorishWasmGameSave");
+    assert_eq!(slot_key("base"), "FactorishWasmGameSave.base");
+    assert_ne!(slot_key("base"), SAVE_INDEX_KEY);
+}
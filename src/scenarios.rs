@@ -7,28 +7,23 @@ use super::{
     furnace::Furnace,
     inserter::Inserter,
     items::ItemType,
+    objectives::{Goal, Objective},
     ore_mine::OreMine,
     pipe::Pipe,
-    power_network::build_power_networks,
+    power_network::{build_power_networks, would_connect},
+    research::default_unlocked,
     steam_engine::SteamEngine,
-    structure::{Structure, StructureBoxed, StructureDynIter, StructureEntry, StructureId},
+    structure::{Structure, StructureDynIter, StructureEntry, StructureId},
     terrain::{
         calculate_back_image, gen_terrain, Chunks, ChunksExt, TerrainParameters, CHUNK_SIZE_I,
     },
     transport_belt::TransportBelt,
-    FactorishState, InventoryTrait, Position, PowerWire, Rotation,
+    FactorishState, InventoryTrait, Ore, OreValue, Position, PowerWire, Rotation,
 };
 use std::collections::HashSet;
 use wasm_bindgen::prelude::*;
 
-fn wrap_structure(s: StructureBoxed) -> StructureEntry {
-    StructureEntry {
-        gen: 0,
-        dynamic: Some(s),
-    }
-}
-
-/// Avoid having water beneath a structure by filling water cells
+/// Avoid having water, cliffs or trees beneath a structure by clearing the cells
 fn update_water(
     structures: &[StructureEntry],
     terrain: &mut Chunks,
@@ -38,9 +33,11 @@ fn update_water(
     for structure in structures {
         if let Some(dynamic) = structure.dynamic.as_deref() {
             let position = *dynamic.position();
-            terrain
-                .get_tile_mut(position)
-                .map(|cell| cell.water = false);
+            terrain.get_tile_mut(position).map(|cell| {
+                cell.water = false;
+                cell.cliff = false;
+                cell.tree = false;
+            });
             to_update.insert(Position::new(
                 position.x.div_euclid(CHUNK_SIZE_I),
                 position.y.div_euclid(CHUNK_SIZE_I),
@@ -48,27 +45,59 @@ fn update_water(
         }
     }
 
-    // Update back image in only touched chunks
-    for chunk_pos in &to_update {
+    update_back_image(terrain, &to_update);
+}
+
+/// Update back image in only touched chunks
+fn update_back_image(terrain: &mut Chunks, to_update: &HashSet<Position>) {
+    for chunk_pos in to_update {
         let mut cells = std::mem::take(&mut terrain.get_mut(chunk_pos).unwrap().cells);
         calculate_back_image(terrain, &chunk_pos, &mut cells);
         terrain.get_mut(chunk_pos).map(|c| c.cells = cells);
     }
 }
 
+/// Overwrite a rectangle of tiles with an ore patch, so that a scenario can rely on having
+/// ores regardless of the terrain seed.
+fn place_ore_patch(
+    terrain: &mut Chunks,
+    (x0, y0): (i32, i32),
+    (x1, y1): (i32, i32),
+    ore: Ore,
+    amount: u32,
+) {
+    let mut to_update = HashSet::new();
+    for y in y0..y1 {
+        for x in x0..x1 {
+            let position = Position::new(x, y);
+            if let Some(cell) = terrain.get_tile_mut(position) {
+                cell.water = false;
+                cell.cliff = false;
+                cell.tree = false;
+                cell.ore = Some(OreValue(ore, amount));
+                to_update.insert(Position::new(
+                    x.div_euclid(CHUNK_SIZE_I),
+                    y.div_euclid(CHUNK_SIZE_I),
+                ));
+            }
+        }
+    }
+    update_back_image(terrain, &to_update);
+}
+
 fn default_scenario(
     terrain_params: &TerrainParameters,
 ) -> (Vec<StructureEntry>, Chunks, Vec<DropItemEntry>) {
     let structures = vec![
-        wrap_structure(Box::new(TransportBelt::new(10, 3, Rotation::Left))),
-        wrap_structure(Box::new(TransportBelt::new(11, 3, Rotation::Left))),
-        wrap_structure(Box::new(TransportBelt::new(12, 3, Rotation::Left))),
-        wrap_structure(Box::new(OreMine::new(12, 2, Rotation::Bottom))),
-        wrap_structure(Box::new(Furnace::new(&Position::new(8, 3)))),
-        wrap_structure(Box::new(Assembler::new(&Position::new(6, 3)))),
-        wrap_structure(Box::new(Boiler::new(&Position::new(13, 5)))),
-        wrap_structure(Box::new(Pipe::new(&Position::new(12, 5)))),
-        wrap_structure(Box::new(SteamEngine::new(&Position::new(11, 5)))),
+        StructureEntry::new(Box::new(TransportBelt::new(10, 3, Rotation::Left))),
+        StructureEntry::new(Box::new(TransportBelt::new(11, 3, Rotation::Left))),
+        StructureEntry::new(Box::new(TransportBelt::new(12, 3, Rotation::Left))),
+        StructureEntry::new(Box::new(OreMine::new(12, 2, Rotation::Bottom))),
+        StructureEntry::new(Box::new(Furnace::new(&Position::new(8, 3)))),
+        StructureEntry::new(Box::new(Assembler::new(&Position::new(6, 3)))),
+        StructureEntry::new(Box::new(Boiler::new(&Position::new(13, 5)))),
+        StructureEntry::new(Box::new(Pipe::new(&Position::new(12, 5)))),
+        StructureEntry::new(Box::new(SteamEngine::new(&Position::new(11, 5)))),
     ];
     let mut terrain = gen_terrain(terrain_params);
 
@@ -82,14 +111,17 @@ fn pipe_bench(
 ) -> (Vec<StructureEntry>, Chunks, Vec<DropItemEntry>) {
     let (mut structures, mut terrain, items) = default_scenario(terrain_params);
 
+    structures.extend(
+        (11..=100).map(|x| StructureEntry::new(Box::new(Pipe::new(&Position::new(x, 10))))),
+    );
+    structures.extend(
+        (10..=99).map(|x| StructureEntry::new(Box::new(Pipe::new(&Position::new(x, 100))))),
+    );
     structures
-        .extend((11..=100).map(|x| wrap_structure(Box::new(Pipe::new(&Position::new(x, 10))))));
-    structures
-        .extend((10..=99).map(|x| wrap_structure(Box::new(Pipe::new(&Position::new(x, 100))))));
-    structures
-        .extend((10..=99).map(|x| wrap_structure(Box::new(Pipe::new(&Position::new(10, x))))));
-    structures
-        .extend((11..=100).map(|x| wrap_structure(Box::new(Pipe::new(&Position::new(100, x))))));
+        .extend((10..=99).map(|x| StructureEntry::new(Box::new(Pipe::new(&Position::new(10, x))))));
+    structures.extend(
+        (11..=100).map(|x| StructureEntry::new(Box::new(Pipe::new(&Position::new(100, x))))),
+    );
 
     update_water(&structures, &mut terrain, &terrain_params);
 
@@ -103,7 +135,7 @@ fn inserter_bench(
 
     structures.extend((10..=100).map(|x| {
         if x % 2 == 0 {
-            wrap_structure(Box::new({
+            StructureEntry::new(Box::new({
                 let mut chest = Chest::new(&Position::new(x, 10));
                 chest
                     .inventory_mut(true)
@@ -111,11 +143,11 @@ fn inserter_bench(
                 chest
             }))
         } else {
-            wrap_structure(Box::new(Inserter::new(x, 10, Rotation::Right)))
+            StructureEntry::new(Box::new(Inserter::new(x, 10, Rotation::Right)))
         }
     }));
     structures.extend((10..=100).map(|x| {
-        wrap_structure(if x % 2 == 0 {
+        StructureEntry::new(if x % 2 == 0 {
             Box::new({
                 let mut chest = Chest::new(&Position::new(x, 100));
                 chest
@@ -129,13 +161,13 @@ fn inserter_bench(
     }));
     structures.extend((11..=99).map(|x| {
         if x % 2 == 0 {
-            wrap_structure(Box::new(Chest::new(&Position::new(10, x))) as Box<dyn Structure>)
+            StructureEntry::new(Box::new(Chest::new(&Position::new(10, x))) as Box<dyn Structure>)
         } else {
-            wrap_structure(Box::new(Inserter::new(10, x, Rotation::Top)) as Box<dyn Structure>)
+            StructureEntry::new(Box::new(Inserter::new(10, x, Rotation::Top)) as Box<dyn Structure>)
         }
     }));
     structures.extend((11..=99).map(|x| {
-        wrap_structure(if x % 2 == 0 {
+        StructureEntry::new(if x % 2 == 0 {
             Box::new(Chest::new(&Position::new(100, x))) as Box<dyn Structure>
         } else {
             Box::new(Inserter::new(100, x, Rotation::Bottom)) as Box<dyn Structure>
@@ -153,19 +185,23 @@ fn transport_bench(
     let (mut structures, mut terrain, mut items) = default_scenario(terrain_params);
 
     structures.extend(
-        (11..=100).map(|x| wrap_structure(Box::new(TransportBelt::new(x, 10, Rotation::Left)))),
+        (11..=100)
+            .map(|x| StructureEntry::new(Box::new(TransportBelt::new(x, 10, Rotation::Left)))),
     );
     items.extend((11..=100).map(|x| DropItemEntry::new(ItemType::CoalOre, &Position::new(x, 10))));
     structures.extend(
-        (10..=99).map(|x| wrap_structure(Box::new(TransportBelt::new(x, 100, Rotation::Right)))),
+        (10..=99)
+            .map(|x| StructureEntry::new(Box::new(TransportBelt::new(x, 100, Rotation::Right)))),
     );
     items.extend((10..=99).map(|x| DropItemEntry::new(ItemType::IronOre, &Position::new(x, 100))));
     structures.extend(
-        (10..=99).map(|x| wrap_structure(Box::new(TransportBelt::new(10, x, Rotation::Bottom)))),
+        (10..=99)
+            .map(|x| StructureEntry::new(Box::new(TransportBelt::new(10, x, Rotation::Bottom)))),
     );
     items.extend((10..=99).map(|x| DropItemEntry::new(ItemType::CopperOre, &Position::new(10, x))));
     structures.extend(
-        (11..=100).map(|x| wrap_structure(Box::new(TransportBelt::new(100, x, Rotation::Top)))),
+        (11..=100)
+            .map(|x| StructureEntry::new(Box::new(TransportBelt::new(100, x, Rotation::Top)))),
     );
     items
         .extend((11..=100).map(|x| DropItemEntry::new(ItemType::StoneOre, &Position::new(100, x))));
@@ -175,6 +211,37 @@ fn transport_bench(
     (structures, terrain, items)
 }
 
+/// Closed loops of transport belts full of items, 10,000 of each in total, to measure the
+/// drop item updates on dense belts.
+fn dense_transport_bench(
+    terrain_params: &TerrainParameters,
+) -> (Vec<StructureEntry>, Chunks, Vec<DropItemEntry>) {
+    const LOOP_SIZE: i32 = 26;
+    const LOOPS: i32 = 10;
+    let (mut structures, mut terrain, mut items) = default_scenario(terrain_params);
+
+    for i in 0..LOOPS * LOOPS {
+        let x0 = 10 + (i % LOOPS) * (LOOP_SIZE + 2);
+        let y0 = 10 + (i / LOOPS) * (LOOP_SIZE + 2);
+        let (x1, y1) = (x0 + LOOP_SIZE - 1, y0 + LOOP_SIZE - 1);
+        let belts = (x0..x1)
+            .map(|x| (x, y0, Rotation::Right))
+            .chain((y0..y1).map(|y| (x1, y, Rotation::Bottom)))
+            .chain((x0 + 1..=x1).map(|x| (x, y1, Rotation::Left)))
+            .chain((y0 + 1..=y1).map(|y| (x0, y, Rotation::Top)));
+        for (x, y, rotation) in belts {
+            structures.push(StructureEntry::new(Box::new(TransportBelt::new(
+                x, y, rotation,
+            ))));
+            items.push(DropItemEntry::new(ItemType::IronOre, &Position::new(x, y)));
+        }
+    }
+
+    update_water(&structures, &mut terrain, &terrain_params);
+
+    (structures, terrain, items)
+}
+
 fn electric_bench(
     terrain_params: &TerrainParameters,
 ) -> (Vec<StructureEntry>, Chunks, Vec<DropItemEntry>) {
@@ -183,14 +250,14 @@ fn electric_bench(
     structures.extend((10..=100).filter_map(|x| {
         if x % 2 == 0 {
             let p = Box::new(Assembler::new(&Position::new(x, 10)));
-            Some(wrap_structure(p as Box<dyn Structure>))
+            Some(StructureEntry::new(p as Box<dyn Structure>))
         } else {
             let p = Box::new(ElectPole::new(&Position::new(x, 10)));
-            Some(wrap_structure(p as Box<dyn Structure>))
+            Some(StructureEntry::new(p as Box<dyn Structure>))
         }
     }));
     structures.extend((10..=100).map(|x| {
-        wrap_structure(if x % 2 == 0 {
+        StructureEntry::new(if x % 2 == 0 {
             Box::new(Assembler::new(&Position::new(x, 100))) as Box<dyn Structure>
         } else {
             Box::new(ElectPole::new(&Position::new(x, 100))) as Box<dyn Structure>
@@ -198,13 +265,17 @@ fn electric_bench(
     }));
     structures.extend((11..=99).map(|x| {
         if x % 2 == 0 {
-            wrap_structure(Box::new(Assembler::new(&Position::new(10, x))) as Box<dyn Structure>)
+            StructureEntry::new(
+                Box::new(Assembler::new(&Position::new(10, x))) as Box<dyn Structure>
+            )
         } else {
-            wrap_structure(Box::new(ElectPole::new(&Position::new(10, x))) as Box<dyn Structure>)
+            StructureEntry::new(
+                Box::new(ElectPole::new(&Position::new(10, x))) as Box<dyn Structure>
+            )
         }
     }));
     structures.extend((11..=99).map(|x| {
-        wrap_structure(if x % 2 == 0 {
+        StructureEntry::new(if x % 2 == 0 {
             Box::new(Assembler::new(&Position::new(100, x))) as Box<dyn Structure>
         } else {
             Box::new(ElectPole::new(&Position::new(100, x))) as Box<dyn Structure>
@@ -216,20 +287,131 @@ fn electric_bench(
     (structures, terrain, items)
 }
 
+/// Start from scratch with objectives guiding the player through the basic production chain
+fn tutorial(
+    terrain_params: &TerrainParameters,
+) -> (Vec<StructureEntry>, Chunks, Vec<DropItemEntry>) {
+    (vec![], gen_terrain(terrain_params), vec![])
+}
+
+/// A small working base to learn from: ore mines put coal and iron ore on a belt,
+/// which an inserter feeds to a furnace, and another inserter stores the plates in a chest.
+fn peaceful(
+    terrain_params: &TerrainParameters,
+) -> (Vec<StructureEntry>, Chunks, Vec<DropItemEntry>) {
+    let mut terrain = gen_terrain(terrain_params);
+    place_ore_patch(&mut terrain, (3, 3), (7, 6), Ore::Coal, 2000);
+    place_ore_patch(&mut terrain, (6, 7), (11, 10), Ore::Iron, 2000);
+
+    // Mines start with some fuel to get going
+    let fueled_mine = |x, y, rotation| {
+        let mut mine = OreMine::new(x, y, rotation);
+        mine.add_burner_inventory(&ItemType::CoalOre, 10);
+        StructureEntry::new(Box::new(mine))
+    };
+
+    let mut structures = vec![
+        fueled_mine(5, 5, Rotation::Bottom),
+        fueled_mine(7, 7, Rotation::Top),
+        fueled_mine(9, 7, Rotation::Top),
+    ];
+    structures.extend(
+        (4..=10).map(|x| StructureEntry::new(Box::new(TransportBelt::new(x, 6, Rotation::Right)))),
+    );
+    structures.push(StructureEntry::new(Box::new(Inserter::new(
+        11,
+        6,
+        Rotation::Right,
+    ))));
+    structures.push(StructureEntry::new(Box::new({
+        let mut furnace = Furnace::new(&Position::new(12, 6));
+        furnace
+            .inventory_mut(true)
+            .map(|inv| inv.add_items(&ItemType::CoalOre, 5));
+        furnace
+    })));
+    structures.push(StructureEntry::new(Box::new(Inserter::new(
+        13,
+        6,
+        Rotation::Right,
+    ))));
+    structures.push(StructureEntry::new(Box::new(Chest::new(&Position::new(
+        14, 6,
+    )))));
+
+    update_water(&structures, &mut terrain, &terrain_params);
+
+    (structures, terrain, vec![])
+}
+
 pub(crate) fn select_scenario(
     name: &str,
     terrain_params: &TerrainParameters,
 ) -> Result<(Vec<StructureEntry>, Chunks, Vec<DropItemEntry>), JsValue> {
     match name {
         "default" => Ok(default_scenario(terrain_params)),
+        "tutorial" => Ok(tutorial(terrain_params)),
+        "peaceful" => Ok(peaceful(terrain_params)),
         "pipe_bench" => Ok(pipe_bench(terrain_params)),
         "inserter_bench" => Ok(inserter_bench(terrain_params)),
         "transport_bench" => Ok(transport_bench(terrain_params)),
+        "dense_transport_bench" => Ok(dense_transport_bench(terrain_params)),
         "electric_bench" => Ok(electric_bench(terrain_params)),
+        #[cfg(test)]
+        "empty" => Ok((vec![], gen_terrain(terrain_params), vec![])),
         _ => js_err!("Scenario name not valid: {}", name),
     }
 }
 
+/// A game on a flat map of the given size without water, ore, cliffs, trees or structures,
+/// for tests that build their own factory.
+#[cfg(test)]
+pub(crate) fn empty_state(width: u32, height: u32) -> FactorishState {
+    let terrain_params = serde_json::from_value(serde_json::json!({
+        "width": width,
+        "height": height,
+        "unlimited": false,
+        "terrain_seed": 1,
+        "water_noise_threshold": 10.,
+        "resource_amount": 0.,
+        "noise_scale": 5.,
+        "noise_threshold": 10.,
+        "noise_octaves": 3,
+        "tree_density": 0.,
+    }))
+    .unwrap();
+    FactorishState::new_headless(terrain_params, "empty").unwrap()
+}
+
+/// Items that a staged scenario lets the player place at the start, or `None` for the sandbox
+/// scenarios where every tool is available.
+pub(crate) fn scenario_unlocks(name: &str) -> Option<HashSet<ItemType>> {
+    match name {
+        "tutorial" => Some(default_unlocked()),
+        _ => None,
+    }
+}
+
+/// Override the terrain parameters that the scenario defines regardless of the game options.
+pub(crate) fn apply_scenario_params(name: &str, terrain_params: &mut TerrainParameters) {
+    if name == "peaceful" {
+        // Faster water wells for a relaxed game
+        terrain_params.water_well_rate = 4.;
+    }
+}
+
+pub(crate) fn scenario_objectives(name: &str) -> Vec<Objective> {
+    match name {
+        "tutorial" => vec![
+            Objective::new(Goal::Build(ItemType::OreMine, 2)),
+            Objective::new(Goal::Build(ItemType::Furnace, 1)),
+            Objective::new(Goal::Build(ItemType::TransportBelt, 10)),
+            Objective::new(Goal::Produce(ItemType::IronPlate, 50)),
+        ],
+        _ => vec![],
+    }
+}
+
 impl FactorishState {
     pub(super) fn update_cache(&mut self) -> Result<(), JsValue> {
         let positions = self
@@ -239,7 +421,7 @@ impl FactorishState {
             .flatten()
             .collect::<Vec<_>>();
         for position in positions {
-            self.update_fluid_connections(&position).unwrap();
+            self.update_fluid_connections(&position);
         }
 
         for s in self
@@ -247,7 +429,23 @@ impl FactorishState {
             .iter_mut()
             .filter_map(|s| s.dynamic.as_deref_mut())
         {
-            s.select_recipe(0).ok();
+            if let Some(entry) = s.get_recipes().first() {
+                s.select_recipe(&entry.id).ok();
+            }
+        }
+
+        // Let the structures find their neighbors, e.g. the input and output of inserters,
+        // in the same way as loading a saved game.
+        for i in 0..self.structures.len() {
+            let (s, others) = StructureDynIter::new(&mut self.structures, i)?;
+            let id = StructureId {
+                id: i as u32,
+                gen: s.gen,
+            };
+            s.dynamic
+                .as_deref_mut()
+                .map(|d| d.on_construction_self(id, &others, true))
+                .unwrap_or(Ok(()))?;
         }
 
         let structures = std::mem::take(&mut self.structures);
@@ -255,11 +453,7 @@ impl FactorishState {
             for j in i + 1..structures.len() {
                 let structure1 = structures[i].dynamic.as_deref().unwrap();
                 let structure2 = structures[j].dynamic.as_deref().unwrap();
-                if (structure1.power_sink() && structure2.power_source()
-                    || structure1.power_source() && structure2.power_sink())
-                    && structure1.position().distance(structure2.position())
-                        <= structure1.wire_reach().min(structure2.wire_reach()) as i32
-                {
+                if would_connect(structure1, structure2) {
                     let add = PowerWire(
                         StructureId {
                             id: i as u32,
@@ -282,7 +476,8 @@ impl FactorishState {
             &StructureDynIter::new_all(&mut self.structures),
             &self.power_wires,
         );
-        console_log!(
+        console_debug!(
+            self,
             "power: {:?}",
             self.power_networks
                 .iter()
@@ -294,13 +489,15 @@ impl FactorishState {
                 ))
                 .collect::<Vec<_>>()
         );
-        console_log!(
+        console_debug!(
+            self,
             "Assemblers: {}",
             self.structure_iter()
                 .filter(|s| s.name() == "Assembler")
                 .count()
         );
-        console_log!(
+        console_debug!(
+            self,
             "ElectPole: {}",
             self.structure_iter()
                 .filter(|s| s.name() == "Electric Pole")
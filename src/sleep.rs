@@ -0,0 +1,81 @@
+use super::{
+    apply_bounds, replay::ReplayInput, structure::BoundingBox, terrain::CHUNK_SIZE_I,
+    FactorishState,
+};
+use wasm_bindgen::prelude::*;
+
+/// Chunks within this many chunks from the ones in the viewport are simulated in full.
+const AWAKE_MARGIN_CHUNKS: i32 = 1;
+
+/// The tiles of the chunks covering the viewport rectangle `(left, top, right, bottom)`
+/// and the margin around them. The bottom right is inclusive like `apply_bounds`.
+fn awake_chunks((left, top, right, bottom): (i32, i32, i32, i32)) -> BoundingBox {
+    let chunk_range = |from: i32, to: i32| {
+        (
+            (from.div_euclid(CHUNK_SIZE_I) - AWAKE_MARGIN_CHUNKS) * CHUNK_SIZE_I,
+            (to.div_euclid(CHUNK_SIZE_I) + 1 + AWAKE_MARGIN_CHUNKS) * CHUNK_SIZE_I,
+        )
+    };
+    let (x0, x1) = chunk_range(left, right);
+    let (y0, y1) = chunk_range(top, bottom);
+    BoundingBox { x0, y0, x1, y1 }
+}
+
+fn intersects(a: &BoundingBox, b: &BoundingBox) -> bool {
+    a.x0 < b.x1 && b.x0 < a.x1 && a.y0 < b.y1 && b.y0 < a.y1
+}
+
+impl FactorishState {
+    /// The area where the structures run every tick, or None if every structure should.
+    /// Without a canvas, e.g. in tests, there is no viewport to measure from.
+    pub(crate) fn awake_area(&self) -> Option<BoundingBox> {
+        if !self.sleep_idle_structures || self.viewport_width <= 0. {
+            return None;
+        }
+        Some(awake_chunks(apply_bounds(
+            &self.bounds,
+            &self.viewport,
+            self.viewport_width,
+            self.viewport_height,
+        )))
+    }
+}
+
+/// Whether a structure should run `frame_proc` this tick. Structures out of the awake area
+/// sleep while they are `idle()`, and wake up as soon as an input gives them work.
+pub(crate) fn is_awake(area: &Option<BoundingBox>, bb: &BoundingBox, idle: bool) -> bool {
+    !idle || area.as_ref().map_or(true, |area| intersects(area, bb))
+}
+
+#[wasm_bindgen]
+impl FactorishState {
+    /// Let idle structures far from the viewport skip their updates. It is enabled by default
+    /// and can be disabled to compare the behavior or the performance.
+    pub fn set_sleep_idle_structures(&mut self, enable: bool) {
+        self.record_input(ReplayInput::SetSleepIdleStructures { enable });
+        self.sleep_idle_structures = enable;
+    }
+
+    pub fn get_sleep_idle_structures(&self) -> bool {
+        self.sleep_idle_structures
+    }
+}
+
+#[test]
+fn test_awake_area() {
+    let area = awake_chunks((CHUNK_SIZE_I + 1, -1, CHUNK_SIZE_I + 2, CHUNK_SIZE_I - 1));
+    assert_eq!(
+        (area.x0, area.y0, area.x1, area.y1),
+        (0, -2 * CHUNK_SIZE_I, 3 * CHUNK_SIZE_I, 2 * CHUNK_SIZE_I)
+    );
+    let far = BoundingBox {
+        x0: 3 * CHUNK_SIZE_I,
+        y0: 0,
+        x1: 3 * CHUNK_SIZE_I + 2,
+        y1: 2,
+    };
+    let area = Some(area);
+    assert!(!is_awake(&area, &far, true));
+    assert!(is_awake(&area, &far, false));
+    assert!(is_awake(&None, &far, true));
+}
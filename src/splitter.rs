@@ -1,19 +1,71 @@
 use super::{
     drop_items::DropItem,
+    items::{item_to_str, ItemType},
     structure::{
-        BoundingBox, ItemResponse, ItemResponseResult, RotateErr, Size, Structure, StructureDynIter,
+        BoundingBox, ItemResponse, ItemResponseResult, RotateErr, Size, Structure,
+        StructureDynIter, StructureId,
     },
-    FactorishState, Position, Rotation, TILE_SIZE,
+    transport_belt::snap_to_lane,
+    FactorishState, FrameProcResult, Position, Rotation, TILE_SIZE,
 };
 use serde::{Deserialize, Serialize};
 use wasm_bindgen::prelude::*;
 use web_sys::CanvasRenderingContext2d;
 
+/// A side of the splitter, seen from the direction of the item flow.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub(crate) enum SplitterSide {
+    Left,
+    Right,
+}
+
+impl SplitterSide {
+    pub(crate) fn from_str(s: &str) -> Result<Option<Self>, JsValue> {
+        match s {
+            "" | "none" => Ok(None),
+            "left" => Ok(Some(SplitterSide::Left)),
+            "right" => Ok(Some(SplitterSide::Right)),
+            _ => js_err!("Unknown splitter side: {}", s),
+        }
+    }
+
+    /// Index of the tile in the splitter, which is 0 for the splitter's position and 1 for the
+    /// other tile that is shifted to the right of the flow.
+    fn lane(&self) -> i8 {
+        match self {
+            SplitterSide::Left => 0,
+            SplitterSide::Right => 1,
+        }
+    }
+}
+
+#[derive(Default, Clone, Serialize, Deserialize)]
+pub(crate) struct SplitterConfig {
+    pub input_priority: Option<SplitterSide>,
+    pub output_priority: Option<SplitterSide>,
+    /// Items of this type always go to the given side and wait if it is blocked.
+    /// The other items go to the other side.
+    pub filter: Option<(ItemType, SplitterSide)>,
+}
+
 #[derive(Serialize, Deserialize)]
 pub(crate) struct Splitter {
     position: Position,
     rotation: Rotation,
     direction: i8,
+    #[serde(default)]
+    config: SplitterConfig,
+    /// Whether an item was coming on the priority input lane in the last frame.
+    #[serde(skip)]
+    priority_input_busy: bool,
+    #[serde(skip)]
+    priority_input_seen: bool,
+    /// Position of the item that was routed to the priority output in the last frame.
+    /// If the item is still there in this frame, the priority output is blocked.
+    #[serde(skip)]
+    prev_output_attempt: Option<(i32, i32)>,
+    #[serde(skip)]
+    output_attempt: Option<(i32, i32)>,
 }
 
 impl Splitter {
@@ -22,8 +74,36 @@ impl Splitter {
             position: Position { x, y },
             rotation,
             direction: 0,
+            config: SplitterConfig::default(),
+            priority_input_busy: false,
+            priority_input_seen: false,
+            prev_output_attempt: None,
+            output_attempt: None,
         }
     }
+
+    /// Decide the output lane for an item that passes the mid point.
+    fn output_lane(&mut self, item: &DropItem) -> i8 {
+        if let Some((filter_type, side)) = self.config.filter {
+            // Filtered items never spill to the other side. If the lane is blocked, the item
+            // fails to move and tries the same lane again in the next frame.
+            return if item.type_ == filter_type {
+                side.lane()
+            } else {
+                1 - side.lane()
+            };
+        }
+        if let Some(side) = self.config.output_priority {
+            if self.prev_output_attempt == Some((item.x, item.y)) {
+                return 1 - side.lane();
+            }
+            self.output_attempt = Some((item.x, item.y));
+            return side.lane();
+        }
+        let lane = self.direction;
+        self.direction = (self.direction + 1) % 2;
+        lane
+    }
 }
 
 impl Structure for Splitter {
@@ -136,15 +216,52 @@ impl Structure for Splitter {
         ret
     }
 
+    fn desc(&self, _state: &FactorishState) -> String {
+        let side_str = |side: Option<SplitterSide>| match side {
+            Some(SplitterSide::Left) => "Left",
+            Some(SplitterSide::Right) => "Right",
+            None => "None",
+        };
+        format!(
+            "Input priority: {}<br>Output priority: {}<br>Filter: {}",
+            side_str(self.config.input_priority),
+            side_str(self.config.output_priority),
+            if let Some((item, side)) = self.config.filter {
+                format!("{} to {}", item_to_str(&item), side_str(Some(side)))
+            } else {
+                "None".to_string()
+            }
+        )
+    }
+
+    fn frame_proc(
+        &mut self,
+        _me: StructureId,
+        _state: &mut FactorishState,
+        _structures: &mut StructureDynIter,
+    ) -> Result<FrameProcResult, ()> {
+        self.priority_input_busy = std::mem::take(&mut self.priority_input_seen);
+        self.prev_output_attempt = self.output_attempt.take();
+        Ok(FrameProcResult::None)
+    }
+
     fn movable(&self) -> bool {
         true
     }
 
+    fn walkable(&self) -> bool {
+        true
+    }
+
     fn rotate(&mut self, _others: &StructureDynIter) -> Result<(), RotateErr> {
         self.rotation = self.rotation.next();
         Ok(())
     }
 
+    fn rotation(&self) -> Option<Rotation> {
+        Some(self.rotation)
+    }
+
     fn set_rotation(&mut self, rotation: &Rotation) -> Result<(), ()> {
         self.rotation = *rotation;
         Ok(())
@@ -153,39 +270,57 @@ impl Structure for Splitter {
     fn item_response(&mut self, item: &DropItem) -> Result<ItemResponseResult, ()> {
         let vx = self.rotation.delta().0;
         let vy = self.rotation.delta().1;
+        // Keep the belt lane of the item, see TransportBelt::item_response.
         let mut ax = if self.rotation.is_vertial() {
-            (item.x as f64 / TILE_SIZE).floor() * TILE_SIZE + TILE_SIZE / 2.
+            snap_to_lane(item.x) as f64
         } else {
             item.x as f64
         };
         let mut ay = if self.rotation.is_horizontal() {
-            (item.y as f64 / TILE_SIZE).floor() * TILE_SIZE + TILE_SIZE / 2.
+            snap_to_lane(item.y) as f64
         } else {
             item.y as f64
         };
         let Position { x: tx, y: ty } = self.position;
         let halftilesize = TILE_SIZE / 2.;
-        let mut postdirection = false;
         let shift_direction = self.rotation.clone().next().delta();
-        if self.rotation.is_horizontal() {
-            // Detect the point where the item passes over the mid point of this entity.
-            if ((ax + halftilesize) / TILE_SIZE).floor()
-                != ((ax + vx as f64 + halftilesize) / TILE_SIZE).floor()
-            {
-                ay = (ty + self.direction as i32 * shift_direction.1) as f64 * TILE_SIZE
-                    + TILE_SIZE / 2.;
-                postdirection = true; // Signal to switch direction
+
+        // Detect the point where the item passes over the mid point of this entity.
+        let (along, velocity) = if self.rotation.is_horizontal() {
+            (ax, vx)
+        } else {
+            (ay, vy)
+        };
+        let crossing = ((along + halftilesize) / TILE_SIZE).floor()
+            != ((along + velocity as f64 + halftilesize) / TILE_SIZE).floor();
+
+        if let Some(side) = self.config.input_priority {
+            let input_lane = if self.rotation.is_horizontal() {
+                item.y.div_euclid(TILE_SIZE as i32) != ty
+            } else {
+                item.x.div_euclid(TILE_SIZE as i32) != tx
+            } as i8;
+            let center = (along / TILE_SIZE).floor() * TILE_SIZE + halftilesize;
+            let before_center = (along - center) * velocity as f64 <= 0.;
+            if input_lane == side.lane() {
+                if before_center {
+                    self.priority_input_seen = true;
+                }
+            } else if crossing && self.priority_input_busy {
+                // Let the items on the priority lane go first.
+                return Ok((ItemResponse::Move(item.x, item.y), None));
             }
-        } else if ((ay + halftilesize) / TILE_SIZE).floor()
-            != ((ay + vy as f64 + halftilesize) / TILE_SIZE).floor()
-        {
-            ax = (tx + self.direction as i32 * shift_direction.0) as f64 * TILE_SIZE
-                + TILE_SIZE / 2.;
-            postdirection = true; // Signal to switch direction
         }
 
-        if postdirection {
-            self.direction = (self.direction + 1) % 2;
+        if crossing {
+            let lane = self.output_lane(item);
+            if self.rotation.is_horizontal() {
+                ay = (ty + lane as i32 * shift_direction.1) as f64 * TILE_SIZE
+                    + ay.rem_euclid(TILE_SIZE);
+            } else {
+                ax = (tx + lane as i32 * shift_direction.0) as f64 * TILE_SIZE
+                    + ax.rem_euclid(TILE_SIZE);
+            }
         }
 
         let moved_x = ax as i32 + vx;
@@ -193,5 +328,39 @@ impl Structure for Splitter {
         Ok((ItemResponse::Move(moved_x, moved_y), None))
     }
 
+    fn splitter_config(&self) -> Option<&SplitterConfig> {
+        Some(&self.config)
+    }
+
+    fn splitter_config_mut(&mut self) -> Option<&mut SplitterConfig> {
+        Some(&mut self.config)
+    }
+
     crate::serialize_impl!();
 }
+
+#[test]
+fn test_find_left_splitter_across_chunk_boundary() {
+    use super::{scenarios::empty_state, terrain::CHUNK_SIZE_I, transport_belt::TransportBelt};
+
+    let mut state = empty_state(128, 128);
+    // A splitter facing left whose position is in the chunk below the tile it also occupies
+    state
+        .add_structure(Box::new(Splitter::new(5, CHUNK_SIZE_I, Rotation::Left)))
+        .unwrap();
+    // Structures in other chunks, so that the query visits the chunks rather than all structures
+    for i in 1..4 {
+        state
+            .add_structure(Box::new(TransportBelt::new(
+                i * CHUNK_SIZE_I,
+                2 * CHUNK_SIZE_I,
+                Rotation::Right,
+            )))
+            .unwrap();
+    }
+
+    let found = state.structures_overlapping(5, CHUNK_SIZE_I - 1, 5, CHUNK_SIZE_I - 1);
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].name(), "Splitter");
+    assert_eq!(*found[0].position(), Position::new(5, CHUNK_SIZE_I));
+}
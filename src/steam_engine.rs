@@ -19,6 +19,12 @@ pub(crate) struct SteamEngine {
     max_power: f64,
     recipe: Option<Recipe>,
     input_fluid_box: FluidBox,
+    /// Power generated in the last frame in kilowatts.
+    #[serde(skip)]
+    output: f64,
+    /// Rotation of the flywheel in radians, which spins in proportion to the output.
+    #[serde(skip)]
+    flywheel_angle: f64,
 }
 
 impl SteamEngine {
@@ -31,22 +37,40 @@ impl SteamEngine {
             recipe: Some(Recipe {
                 input: HashMap::new(),
                 input_fluid: Some(FluidType::Steam),
+                input_fluid_amount: 0.,
                 output: HashMap::new(),
                 output_fluid: None,
                 power_cost: -100.,
                 recipe_time: 100.,
             }),
             input_fluid_box: FluidBox::new(true, false),
+            output: 0.,
+            flywheel_angle: 0.,
         }
     }
 
     const FLUID_PER_PROGRESS: f64 = 100.;
     const COMBUSTION_EPSILON: f64 = 1e-6;
+    /// Fill ratio of the input fluid box at which the engine runs at full speed.
+    const FULL_STEAM_RATIO: f64 = 0.5;
+    /// Flywheel rotation per frame in radians at full speed.
+    const FLYWHEEL_SPEED: f64 = 0.5;
+
+    /// Efficiency in the range [0, 1] depending on the steam pressure. The engine slows down
+    /// linearly when it is starved, down to zero with no steam.
+    fn steam_efficiency(&self) -> f64 {
+        if self.input_fluid_box.type_ != Some(FluidType::Steam) {
+            return 0.;
+        }
+        (self.input_fluid_box.amount / self.input_fluid_box.max_amount / Self::FULL_STEAM_RATIO)
+            .min(1.)
+            .max(0.)
+    }
 
     fn combustion_rate(&self) -> f64 {
         if let Some(ref recipe) = self.recipe {
             ((self.max_power - self.power) / recipe.power_cost.abs())
-                .min(1. / recipe.recipe_time)
+                .min(self.steam_efficiency() / recipe.recipe_time)
                 .min(self.input_fluid_box.amount / Self::FLUID_PER_PROGRESS)
                 .min(1.)
                 .max(0.)
@@ -101,6 +125,21 @@ impl Structure for SteamEngine {
             None => return Err(JsValue::from_str("furnace image not available")),
         }
 
+        // Flywheel
+        context.save();
+        context.set_stroke_style(&JsValue::from_str("#3f3f3f"));
+        context.set_line_width(2.);
+        let (cx, cy, radius) = (x + 24., y + 24., 6.);
+        context.begin_path();
+        context.arc(cx, cy, radius, 0., std::f64::consts::PI * 2.)?;
+        for i in 0..3 {
+            let angle = self.flywheel_angle + i as f64 * std::f64::consts::PI * 2. / 3.;
+            context.move_to(cx, cy);
+            context.line_to(cx + radius * angle.cos(), cy + radius * angle.sin());
+        }
+        context.stroke();
+        context.restore();
+
         Ok(())
     }
 
@@ -116,7 +155,7 @@ impl Structure for SteamEngine {
                 <div style='position: absolute; width: {}px; height: 10px; background-color: #ff00ff'></div></div>"#,
                 self.power,
                 if 0. < self.max_power { (self.power) / self.max_power * 100. } else { 0. }),
-                format!("<div>Combustion rate: {:.1}</div>", self.combustion_rate()),
+                format!("<div>Combustion rate: {:.1}</div><div>Output: {:.1}kW</div>", self.combustion_rate(), self.output),
                 self.input_fluid_box.desc())
         // getHTML(generateItemImage("time", true, this.recipe.time), true) + "<br>" +
         // "Outputs: <br>" +
@@ -129,10 +168,10 @@ impl Structure for SteamEngine {
     fn frame_proc(
         &mut self,
         _me: StructureId,
-        _state: &mut FactorishState,
-        structures: &mut StructureDynIter,
+        state: &mut FactorishState,
+        _structures: &mut StructureDynIter,
     ) -> Result<FrameProcResult, ()> {
-        self.input_fluid_box.simulate(structures);
+        self.output = 0.;
         if let Some(recipe) = &self.recipe {
             if self.input_fluid_box.type_ == recipe.input_fluid {
                 self.progress = Some(0.);
@@ -142,6 +181,12 @@ impl Structure for SteamEngine {
             if let Some(prev_progress) = self.progress {
                 // Proceed only if we have sufficient energy in the buffer.
                 let progress = self.combustion_rate();
+                if Self::COMBUSTION_EPSILON < progress && 0. < state.delta_time {
+                    self.output = progress * recipe.power_cost.abs() / state.delta_time;
+                    self.flywheel_angle = (self.flywheel_angle
+                        + progress * recipe.recipe_time * Self::FLYWHEEL_SPEED)
+                        % (std::f64::consts::PI * 2.);
+                }
                 if 1. <= prev_progress + progress {
                     self.progress = None;
                     return Ok(FrameProcResult::InventoryChanged(self.position));
@@ -172,6 +217,14 @@ impl Structure for SteamEngine {
         true
     }
 
+    fn power_available(&self) -> f64 {
+        self.power
+    }
+
+    fn power_generation(&self) -> Option<f64> {
+        Some(self.output)
+    }
+
     fn power_outlet(&mut self, demand: f64) -> Option<f64> {
         let energy = demand.min(self.power);
         self.power -= energy;
@@ -180,3 +233,43 @@ impl Structure for SteamEngine {
 
     serialize_impl!();
 }
+
+#[test]
+fn test_boiler_feeds_engine_placed_next_to_it() {
+    use super::{boiler::Boiler, scenarios::empty_state, structure::StructureBoxed};
+
+    let boiler = || Box::new(Boiler::new(&Position::new(0, 0))) as StructureBoxed;
+    let engine = || Box::new(SteamEngine::new(&Position::new(1, 0))) as StructureBoxed;
+    for boiler_first in [true, false] {
+        let mut state = empty_state(16, 16);
+        // Placing connects the fluid boxes whichever comes first.
+        let (boiler_id, engine_id) = if boiler_first {
+            let boiler_id = state.add_structure(boiler()).unwrap();
+            (boiler_id, state.add_structure(engine()).unwrap())
+        } else {
+            let engine_id = state.add_structure(engine()).unwrap();
+            (state.add_structure(boiler()).unwrap(), engine_id)
+        };
+
+        for _ in 0..5 {
+            // Fill the boiler with steam, as if it burned fuel with water.
+            let mut boxes = state.structures[boiler_id.id as usize]
+                .dynamic
+                .as_deref_mut()
+                .unwrap()
+                .fluid_box_mut()
+                .unwrap();
+            boxes[1].type_ = Some(FluidType::Steam);
+            boxes[1].amount = boxes[1].max_amount;
+            state.tick_headless(0.05).unwrap();
+        }
+        let engine_box = state.structures[engine_id.id as usize]
+            .dynamic
+            .as_ref()
+            .unwrap()
+            .fluid_box()
+            .unwrap()[0];
+        assert_eq!(engine_box.type_, Some(FluidType::Steam));
+        assert!(0. < engine_box.amount);
+    }
+}
@@ -1,13 +1,18 @@
 use super::{
+    alerts::AlertType,
+    circuit_network::SignalCondition,
     drop_items::DropItem,
     dyn_iter::{DynIter, DynIterMut},
+    inserter::{ChestLimit, InserterFilter},
     items::ItemType,
+    recipes::{RecipeEntry, RECIPES},
+    splitter::SplitterConfig,
     water_well::FluidBox,
-    FactorishState, Inventory, InventoryTrait, Recipe,
+    FactorishState, Inventory, InventoryTrait, Recipe, TILE_SIZE_I,
 };
 use rotate_enum::RotateEnum;
 use serde::{Deserialize, Serialize};
-use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
 use wasm_bindgen::prelude::*;
 use web_sys::CanvasRenderingContext2d;
 
@@ -230,6 +235,13 @@ impl Position {
         Self { x, y }
     }
 
+    /// The tile that contains the pixel in the world. Negative pixels round toward negative
+    /// infinity, so that the tile -1 covers the pixels from -32 to -1.
+    /// All conversions from pixels to tiles should go through this function.
+    pub(crate) fn from_pixel(x: i32, y: i32) -> Self {
+        Self::new(x.div_euclid(TILE_SIZE_I), y.div_euclid(TILE_SIZE_I))
+    }
+
     pub(crate) fn div_mod(&self, size: i32) -> (Position, Position) {
         let div = Position::new(self.x.div_euclid(size), self.y.div_euclid(size));
         let mod_ = Position::new(self.x.rem_euclid(size), self.y.rem_euclid(size));
@@ -290,7 +302,21 @@ pub(crate) struct BoundingBox {
     pub y1: i32,
 }
 
-#[derive(Copy, Clone, Serialize, Deserialize, RotateEnum)]
+impl BoundingBox {
+    /// Whether the tile is right next to the edge of the box facing `side`, excluding corners.
+    pub(crate) fn is_beside(&self, pos: &Position, side: Rotation) -> bool {
+        let within_x = self.x0 <= pos.x && pos.x < self.x1;
+        let within_y = self.y0 <= pos.y && pos.y < self.y1;
+        match side {
+            Rotation::Left => pos.x == self.x0 - 1 && within_y,
+            Rotation::Top => pos.y == self.y0 - 1 && within_x,
+            Rotation::Right => pos.x == self.x1 && within_y,
+            Rotation::Bottom => pos.y == self.y1 && within_x,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Serialize, Deserialize, RotateEnum)]
 pub(crate) enum Rotation {
     Left,
     Top,
@@ -298,6 +324,25 @@ pub(crate) enum Rotation {
     Bottom,
 }
 
+/// The output side of the structures saved before they had one
+pub(crate) fn default_output_side() -> Rotation {
+    Rotation::Bottom
+}
+
+impl std::str::FromStr for Rotation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "Left" => Ok(Rotation::Left),
+            "Top" => Ok(Rotation::Top),
+            "Right" => Ok(Rotation::Right),
+            "Bottom" => Ok(Rotation::Bottom),
+            _ => Err(format!("Rotation name not valid: {}", s)),
+        }
+    }
+}
+
 impl Rotation {
     pub fn delta(&self) -> (i32, i32) {
         match self {
@@ -308,6 +353,17 @@ impl Rotation {
         }
     }
 
+    /// Returns the rotation pointing to the given unit vector, or None if it is not axis-aligned.
+    pub fn from_delta(delta: (i32, i32)) -> Option<Self> {
+        match delta {
+            (-1, 0) => Some(Rotation::Left),
+            (0, -1) => Some(Rotation::Top),
+            (1, 0) => Some(Rotation::Right),
+            (0, 1) => Some(Rotation::Bottom),
+            _ => None,
+        }
+    }
+
     pub fn delta_inv(&self) -> (i32, i32) {
         let delta = self.delta();
         (-delta.0, -delta.1)
@@ -367,13 +423,19 @@ pub(crate) trait Structure {
             height: 1,
         }
     }
+    /// `size` is the footprint facing left or right, so the width and height are swapped
+    /// if the structure faces top or bottom.
     fn bounding_box(&self) -> BoundingBox {
         let (position, size) = (self.position(), self.size());
+        let (width, height) = match self.rotation() {
+            Some(Rotation::Top) | Some(Rotation::Bottom) => (size.height, size.width),
+            _ => (size.width, size.height),
+        };
         BoundingBox {
             x0: position.x,
             y0: position.y,
-            x1: position.x + size.width,
-            y1: position.y + size.height,
+            x1: position.x + width,
+            y1: position.y + height,
         }
     }
     fn contains(&self, pos: &Position) -> bool {
@@ -416,12 +478,33 @@ pub(crate) trait Structure {
     ) -> Result<(), JsValue> {
         Ok(())
     }
+    /// Whether items on the ground can sit on and be conveyed over this structure, e.g. belts.
+    /// Items cannot be dropped on the tiles of a structure that is not movable, and placing one
+    /// clears the items under it.
     fn movable(&self) -> bool {
         false
     }
+    /// Where an item moving into this structure from a neighboring tile to the pixel `(x, y)`
+    /// is placed, e.g. on the near lane of a belt fed from the side.
+    fn item_entry(&self, x: i32, y: i32) -> (i32, i32) {
+        (x, y)
+    }
+    /// Whether creatures, such as the player or enemies, can walk over this structure.
+    /// It is independent of `movable`; a pipe is walkable but does not carry items.
+    fn walkable(&self) -> bool {
+        false
+    }
+    /// Whether the structure has nothing to do until an item is put in it, so that it can sleep
+    /// while it is far from the viewport. Structures that work on their own must return false.
+    fn idle(&self) -> bool {
+        false
+    }
     fn rotate(&mut self, _others: &StructureDynIter) -> Result<(), RotateErr> {
         Err(RotateErr::NotSupported)
     }
+    fn rotation(&self) -> Option<Rotation> {
+        None
+    }
     fn set_rotation(&mut self, _rotation: &Rotation) -> Result<(), ()> {
         Err(())
     }
@@ -460,6 +543,10 @@ pub(crate) trait Structure {
     fn burner_energy(&self) -> Option<(f64, f64)> {
         None
     }
+    /// Returns the kind of alert if this structure is stalled by lack of fuel or power.
+    fn alert(&self) -> Option<AlertType> {
+        None
+    }
     fn inventory(&self, _is_input: bool) -> Option<&Inventory> {
         None
     }
@@ -479,24 +566,96 @@ pub(crate) trait Structure {
         );
         ret
     }
-    /// Returns a list of recipes. The return value is wrapped in a Cow because some
-    /// structures can return dynamically configured list of recipes, while some others
-    /// have static fixed list of recipes. In reality, all our structures return a fixed list though.
-    fn get_recipes(&self) -> Cow<[Recipe]> {
-        Cow::from(&[][..])
+    /// Returns the recipes in the registry that this structure can run.
+    fn get_recipes(&self) -> Vec<&'static RecipeEntry> {
+        RECIPES.for_structure(self.name()).collect()
     }
-    fn select_recipe(&mut self, _index: usize) -> Result<bool, JsValue> {
+    /// Select a recipe by its id, which has to be one of get_recipes().
+    fn select_recipe(&mut self, _id: &str) -> Result<bool, JsValue> {
         Err(JsValue::from_str("recipes not available"))
     }
+    /// Set the number of items that an inserter moves in a swing.
+    fn set_stack_size(&mut self, _stack_size: usize) -> Result<(), JsValue> {
+        Err(JsValue::from_str("stack size not available"))
+    }
+    fn stack_size(&self) -> Option<usize> {
+        None
+    }
+    /// Set the condition on the signal network to enable this structure.
+    fn set_signal_condition(&mut self, _condition: Option<SignalCondition>) -> Result<(), JsValue> {
+        Err(JsValue::from_str("signal condition not available"))
+    }
+    /// Set the item count in the output chest at which this structure stops.
+    fn set_chest_limit(&mut self, _limit: Option<ChestLimit>) -> Result<(), JsValue> {
+        Err(JsValue::from_str("chest limit not available"))
+    }
+    /// Slots of the storage reserved for an item type, keyed by the slot index.
+    fn slot_filters(&self) -> Option<&BTreeMap<usize, ItemType>> {
+        None
+    }
+    /// Reserve a slot for an item type, or free it for any item with None.
+    fn set_slot_filter(&mut self, _slot: usize, _item: Option<ItemType>) -> Result<(), JsValue> {
+        Err(JsValue::from_str("slot filters not available"))
+    }
+    /// Set whether full stacks in the output inventory are dropped on the ground.
+    fn set_output_to_ground(&mut self, _enable: bool) -> Result<(), JsValue> {
+        Err(JsValue::from_str("output to ground not available"))
+    }
+    /// Signals that this structure emits to the signal network it is connected to.
+    fn signal_output(&self) -> Option<&Inventory> {
+        None
+    }
     fn get_selected_recipe(&self) -> Option<&Recipe> {
         None
     }
+    /// Completion ratio in [0, 1] of the recipe being crafted, or None if it is not crafting.
+    fn crafting_progress(&self) -> Option<f64> {
+        None
+    }
     fn fluid_box(&self) -> Option<Vec<&FluidBox>> {
         None
     }
+    fn splitter_config(&self) -> Option<&SplitterConfig> {
+        None
+    }
+    fn splitter_config_mut(&mut self) -> Option<&mut SplitterConfig> {
+        None
+    }
+    /// The items that an inserter picks up.
+    fn inserter_filter(&self) -> Option<&InserterFilter> {
+        None
+    }
+    fn inserter_filter_mut(&mut self) -> Option<&mut InserterFilter> {
+        None
+    }
+    /// Whether requester chests in the logistic network can pull items out of this structure.
+    fn logistic_provider(&self) -> bool {
+        false
+    }
+    /// Desired item counts of a requester chest, keyed by item type.
+    fn chest_requests(&self) -> Option<&Inventory> {
+        None
+    }
+    fn chest_requests_mut(&mut self) -> Option<&mut Inventory> {
+        None
+    }
     fn fluid_box_mut(&mut self) -> Option<Vec<&mut FluidBox>> {
         None
     }
+    /// Whether the fluid boxes connect to a neighbor in the direction. Both of the neighbors
+    /// need to agree to connect.
+    fn fluid_connects(&self, _direction: Rotation) -> bool {
+        true
+    }
+    /// Whether the fluid box at `index` of `fluid_box` connects to the neighbor in the direction,
+    /// for structures whose fluid boxes face different sides.
+    fn fluid_box_connects(&self, _index: usize, _direction: Rotation) -> bool {
+        true
+    }
+    /// The limit of the fluid that a valve passes in a tick, where the inner None is no limit.
+    fn valve_max_flow_mut(&mut self) -> Option<&mut Option<f64>> {
+        None
+    }
     fn connection(
         &self,
         state: &FactorishState,
@@ -539,9 +698,40 @@ pub(crate) trait Structure {
     fn power_outlet(&mut self, _demand: f64) -> Option<f64> {
         None
     }
+    /// Energy in kilojoules that this power source can supply in this frame.
+    fn power_available(&self) -> f64 {
+        0.
+    }
+    /// Power in kilowatts that this generator produced in the last frame.
+    fn power_generation(&self) -> Option<f64> {
+        None
+    }
+    /// Energy in kilojoules that this power sink wants to draw in this frame.
+    fn power_demand(&self) -> f64 {
+        0.
+    }
+    /// Returns (stored, max) energy if this structure buffers electricity for the grid.
+    /// Such structures are treated as accumulators by the power network.
+    fn power_storage(&self) -> Option<(f64, f64)> {
+        None
+    }
+    /// Try to store surplus power into this structure.
+    /// @param energy in kilojoules.
+    /// @returns the amount of energy actually stored.
+    fn power_inlet(&mut self, _energy: f64) -> f64 {
+        0.
+    }
     fn wire_reach(&self) -> u32 {
         3
     }
+    /// Radius in tiles of the light that this structure casts in the night, or 0 for none.
+    fn light_radius(&self) -> f64 {
+        0.
+    }
+    /// Health points when undamaged
+    fn max_health(&self) -> f64 {
+        100.
+    }
     fn serialize(&self) -> serde_json::Result<serde_json::Value>;
 }
 
@@ -550,4 +740,210 @@ pub(crate) type StructureBoxed = Box<dyn Structure>;
 pub(crate) struct StructureEntry {
     pub gen: u32,
     pub dynamic: Option<StructureBoxed>,
+    /// Health points lost from `max_health()`. It is reset when the slot is reused.
+    pub damage: f64,
+    /// Whether `frame_proc` ran in the last tick, or false if the structure was sleeping.
+    pub active: bool,
+}
+
+impl StructureEntry {
+    /// An entry for a newly built structure in a fresh slot.
+    pub(crate) fn new(dynamic: StructureBoxed) -> Self {
+        Self {
+            gen: 0,
+            dynamic: Some(dynamic),
+            damage: 0.,
+            active: true,
+        }
+    }
+}
+
+/// Map from each tile covered by a structure to its index in the structures array,
+/// to look up the structure under a drop item without scanning all the structures.
+pub(crate) type StructureTileIndex = HashMap<Position, usize>;
+
+/// If structures overlap, the first one in the array wins like a linear search.
+pub(crate) fn build_structure_tile_index(structures: &[StructureEntry]) -> StructureTileIndex {
+    let mut ret = StructureTileIndex::new();
+    for (i, structure) in structures
+        .iter()
+        .enumerate()
+        .filter_map(|(i, entry)| Some((i, entry.dynamic.as_deref()?)))
+    {
+        add_structure_tiles(&mut ret, &structure.bounding_box(), i);
+    }
+    ret
+}
+
+/// Register the tiles of the structure at index `i`, keeping the tiles that are already taken.
+pub(crate) fn add_structure_tiles(index: &mut StructureTileIndex, bb: &BoundingBox, i: usize) {
+    for y in bb.y0..bb.y1 {
+        for x in bb.x0..bb.x1 {
+            index.entry(Position { x, y }).or_insert(i);
+        }
+    }
+}
+
+/// Forget the tiles of the structure at index `i`, when it is removed or rotated.
+pub(crate) fn remove_structure_tiles(index: &mut StructureTileIndex, bb: &BoundingBox, i: usize) {
+    for y in bb.y0..bb.y1 {
+        for x in bb.x0..bb.x1 {
+            let position = Position { x, y };
+            if index.get(&position) == Some(&i) {
+                index.remove(&position);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_rotated_bounding_box() {
+    struct Wide {
+        position: Position,
+        rotation: Rotation,
+    }
+
+    impl Structure for Wide {
+        fn name(&self) -> &str {
+            "Wide"
+        }
+        fn position(&self) -> &Position {
+            &self.position
+        }
+        fn size(&self) -> Size {
+            Size {
+                width: 2,
+                height: 1,
+            }
+        }
+        fn draw(
+            &self,
+            _state: &FactorishState,
+            _context: &CanvasRenderingContext2d,
+            _depth: i32,
+            _is_tooltip: bool,
+        ) -> Result<(), JsValue> {
+            Ok(())
+        }
+        fn rotation(&self) -> Option<Rotation> {
+            Some(self.rotation)
+        }
+        fn set_rotation(&mut self, rotation: &Rotation) -> Result<(), ()> {
+            self.rotation = *rotation;
+            Ok(())
+        }
+        fn serialize(&self) -> serde_json::Result<serde_json::Value> {
+            Ok(serde_json::Value::Null)
+        }
+    }
+
+    let mut wide = Wide {
+        position: Position::new(3, 5),
+        rotation: Rotation::Right,
+    };
+    assert!(wide.contains(&Position::new(3, 5)));
+    assert!(wide.contains(&Position::new(4, 5)));
+    assert!(!wide.contains(&Position::new(3, 6)));
+
+    wide.set_rotation(&Rotation::Top).unwrap();
+    let bb = wide.bounding_box();
+    assert_eq!((bb.x0, bb.y0, bb.x1, bb.y1), (3, 5, 4, 7));
+    assert!(wide.contains(&Position::new(3, 6)));
+    assert!(!wide.contains(&Position::new(4, 5)));
+}
+
+#[test]
+fn test_structure_tile_index() {
+    use super::{balancer::Balancer, transport_belt::TransportBelt};
+    let structures = vec![
+        StructureEntry::new(Box::new(TransportBelt::new(0, 0, Rotation::Right))),
+        StructureEntry {
+            gen: 1,
+            dynamic: None,
+            damage: 0.,
+            active: true,
+        },
+        StructureEntry::new(Box::new(Balancer::new(2, 1, Rotation::Right))),
+        StructureEntry::new(Box::new(TransportBelt::new(5, 1, Rotation::Top))),
+        // Overlaps the balancer, which is found first
+        StructureEntry::new(Box::new(TransportBelt::new(2, 3, Rotation::Top))),
+    ];
+    let index = build_structure_tile_index(&structures);
+
+    // The index agrees with the linear search that it replaces
+    for y in -1..5 {
+        for x in -1..7 {
+            let pos = Position::new(x, y);
+            let linear = structures
+                .iter()
+                .position(|s| s.dynamic.as_ref().map_or(false, |s| s.contains(&pos)));
+            assert_eq!(index.get(&pos).copied(), linear);
+        }
+    }
+    assert_eq!(index.len(), 2 + 4);
+    assert_eq!(index.get(&Position::new(2, 3)), Some(&2));
+}
+
+#[test]
+fn test_structure_tiles_follow_changes() {
+    use super::{balancer::Balancer, scenarios::empty_state, transport_belt::TransportBelt};
+    let mut state = empty_state(16, 16);
+    state
+        .add_structure(Box::new(TransportBelt::new(0, 0, Rotation::Right)))
+        .unwrap();
+    state
+        .add_structure(Box::new(Balancer::new(2, 1, Rotation::Right)))
+        .unwrap();
+    let rebuilt = |state: &FactorishState| build_structure_tile_index(&state.structures);
+    assert!(state.structure_tiles == rebuilt(&state));
+
+    // The balancer spans other tiles after turning.
+    let old_tiles = state.structure_tiles.clone();
+    state.cursor = Some([2, 1]);
+    state.rotate().ok();
+    assert!(matches!(
+        state.structures[1].dynamic.as_ref().unwrap().rotation(),
+        Some(Rotation::Bottom)
+    ));
+    assert!(state.structure_tiles != old_tiles);
+    assert!(state.structure_tiles == rebuilt(&state));
+
+    state.harvest(&Position::new(0, 0), true).unwrap();
+    assert!(state.structure_tiles == rebuilt(&state));
+    assert_eq!(state.structure_tiles.get(&Position::new(0, 0)), None);
+
+    // The freed slot is reused.
+    state
+        .add_structure(Box::new(TransportBelt::new(5, 5, Rotation::Right)))
+        .unwrap();
+    assert_eq!(state.structure_tiles.get(&Position::new(5, 5)), Some(&0));
+    assert!(state.structure_tiles == rebuilt(&state));
+}
+
+#[test]
+fn test_bounding_box_is_beside() {
+    let bb = BoundingBox {
+        x0: 0,
+        y0: 0,
+        x1: 2,
+        y1: 1,
+    };
+    assert!(bb.is_beside(&Position::new(2, 0), Rotation::Right));
+    assert!(bb.is_beside(&Position::new(1, 1), Rotation::Bottom));
+    assert!(bb.is_beside(&Position::new(0, -1), Rotation::Top));
+    assert!(!bb.is_beside(&Position::new(2, 1), Rotation::Right));
+    assert!(!bb.is_beside(&Position::new(-1, 0), Rotation::Right));
+}
+
+#[test]
+fn test_rotation_from_str() {
+    for (name, rotation) in [
+        ("Left", Rotation::Left),
+        ("Top", Rotation::Top),
+        ("Right", Rotation::Right),
+        ("Bottom", Rotation::Bottom),
+    ] {
+        assert!(name.parse::<Rotation>() == Ok(rotation));
+    }
+    assert!("Up".parse::<Rotation>().is_err());
 }
@@ -0,0 +1,94 @@
+use super::{
+    alerts::AlertType,
+    items::str_to_item,
+    structure::{Position, Structure},
+    FactorishState,
+};
+use wasm_bindgen::prelude::*;
+
+/// Sort the positions of the structures by the distance of their tiles' centers from the
+/// given point in tiles, so that the nearest one comes first.
+fn sort_by_distance(positions: &mut [Position], (cx, cy): (f64, f64)) {
+    let dist2 = |pos: &Position| {
+        let (dx, dy) = (pos.x as f64 + 0.5 - cx, pos.y as f64 + 0.5 - cy);
+        dx * dx + dy * dy
+    };
+    positions.sort_by(|a, b| {
+        dist2(a)
+            .partial_cmp(&dist2(b))
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+}
+
+impl FactorishState {
+    /// The positions of the structures that pass the filter, nearest to the viewport center first.
+    fn find_structures(&self, filter: impl Fn(&dyn Structure) -> bool) -> js_sys::Array {
+        let mut positions = self
+            .structure_iter()
+            .filter(|s| filter(*s))
+            .map(|s| *s.position())
+            .collect::<Vec<_>>();
+        let center = self
+            .viewport
+            .screen_to_world((self.viewport_width / 2., self.viewport_height / 2.));
+        sort_by_distance(&mut positions, center);
+        positions
+            .into_iter()
+            .map(|pos| js_sys::Array::of2(&JsValue::from(pos.x), &JsValue::from(pos.y)))
+            .collect()
+    }
+}
+
+#[wasm_bindgen]
+impl FactorishState {
+    /// Returns [[x, y]] of the structures with the given item name, e.g. "Assembler",
+    /// nearest to the center of the viewport first. Each position can be passed to
+    /// `set_viewport_pos` to jump to the structure.
+    pub fn find_structures_by_type(&self, item_name: &str) -> Result<js_sys::Array, JsValue> {
+        if str_to_item(item_name).is_none() {
+            return js_err!("Unknown item name: {}", item_name);
+        }
+        Ok(self.find_structures(|s| s.name() == item_name))
+    }
+
+    /// Returns [[x, y]] of the structures that raise an alert, in the same order as
+    /// `find_structures_by_type`. `alert_type` can be "NoFuel", "NoPower" or "OutputBlocked" to
+    /// narrow down the search, or undefined for any alert.
+    pub fn find_alerted_structures(
+        &self,
+        alert_type: Option<String>,
+    ) -> Result<js_sys::Array, JsValue> {
+        let alert_type = match alert_type.as_deref() {
+            None => None,
+            Some("NoFuel") => Some(AlertType::NoFuel),
+            Some("NoPower") => Some(AlertType::NoPower),
+            Some("OutputBlocked") => Some(AlertType::OutputBlocked),
+            Some(name) => return js_err!("Unknown alert type: {}", name),
+        };
+        Ok(self.find_structures(|s| match (s.alert(), alert_type) {
+            (Some(alert), Some(type_)) => alert == type_,
+            (alert, None) => alert.is_some(),
+            (None, Some(_)) => false,
+        }))
+    }
+}
+
+#[test]
+fn test_sort_by_distance() {
+    let mut positions = vec![
+        Position::new(10, 0),
+        Position::new(-3, 2),
+        Position::new(0, 0),
+        Position::new(4, 4),
+    ];
+    sort_by_distance(&mut positions, (0.5, 0.5));
+    assert_eq!(
+        positions,
+        [
+            Position::new(0, 0),
+            Position::new(-3, 2),
+            Position::new(4, 4),
+            Position::new(10, 0)
+        ]
+    );
+}
@@ -0,0 +1,107 @@
+use super::{
+    inserter::InserterFilter, replay::ReplayInput, splitter::SplitterConfig, structure::Structure,
+    FactorishState, Inventory, Recipe,
+};
+use wasm_bindgen::prelude::*;
+
+/// A snapshot of the configuration of a structure that can be pasted onto another
+/// structure of the same type.
+pub(crate) struct StructureSettings {
+    name: String,
+    recipe: Option<Recipe>,
+    stack_size: Option<usize>,
+    splitter_config: Option<SplitterConfig>,
+    chest_requests: Option<Inventory>,
+    inserter_filter: Option<InserterFilter>,
+}
+
+impl StructureSettings {
+    fn from_structure(structure: &dyn Structure) -> Self {
+        Self {
+            name: structure.name().to_string(),
+            recipe: structure.get_selected_recipe().cloned(),
+            stack_size: structure.stack_size(),
+            splitter_config: structure.splitter_config().cloned(),
+            chest_requests: structure.chest_requests().cloned(),
+            inserter_filter: structure.inserter_filter().cloned(),
+        }
+    }
+
+    fn apply(&self, structure: &mut dyn Structure) -> Result<(), JsValue> {
+        if let Some(recipe) = &self.recipe {
+            // The target may not be able to produce the recipe, e.g. a furnace recipe on an assembler.
+            let entry = structure
+                .get_recipes()
+                .into_iter()
+                .find(|entry| entry.recipe == *recipe)
+                .ok_or_else(|| js_str!("Recipe is not available in the target structure"))?;
+            if structure.get_selected_recipe() != Some(recipe) {
+                structure.select_recipe(&entry.id)?;
+            }
+        }
+        if let Some(stack_size) = self.stack_size {
+            structure.set_stack_size(stack_size)?;
+        }
+        if let Some((src, dst)) = self
+            .splitter_config
+            .as_ref()
+            .zip(structure.splitter_config_mut())
+        {
+            *dst = src.clone();
+        }
+        if let Some((src, dst)) = self
+            .chest_requests
+            .as_ref()
+            .zip(structure.chest_requests_mut())
+        {
+            *dst = src.clone();
+        }
+        if let Some((src, dst)) = self
+            .inserter_filter
+            .as_ref()
+            .zip(structure.inserter_filter_mut())
+        {
+            *dst = src.clone();
+        }
+        Ok(())
+    }
+}
+
+#[wasm_bindgen]
+impl FactorishState {
+    /// Remember the settings of the structure at the tile for `paste_settings`.
+    /// @returns false if there is no structure.
+    pub fn copy_settings(&mut self, c: i32, r: i32) -> bool {
+        self.record_input(ReplayInput::CopySettings { c, r });
+        self.copied_settings = self
+            .find_structure_tile(&[c, r])
+            .map(StructureSettings::from_structure);
+        self.copied_settings.is_some()
+    }
+
+    /// Apply the copied settings to the structure at the tile.
+    /// @returns false if nothing is copied or the structure is of a different type.
+    pub fn paste_settings(&mut self, c: i32, r: i32) -> Result<bool, JsValue> {
+        self.record_input(ReplayInput::PasteSettings { c, r });
+        let settings = if let Some(settings) = self.copied_settings.as_ref() {
+            settings
+        } else {
+            return Ok(false);
+        };
+        let structure = if let Some(structure) = self
+            .structures
+            .iter_mut()
+            .filter_map(|s| s.dynamic.as_deref_mut())
+            .find(|s| s.position().x == c && s.position().y == r)
+        {
+            structure
+        } else {
+            return Ok(false);
+        };
+        if structure.name() != settings.name {
+            return Ok(false);
+        }
+        settings.apply(structure)?;
+        Ok(true)
+    }
+}
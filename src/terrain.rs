@@ -18,8 +18,55 @@ pub(crate) struct TerrainParameters {
     pub noise_scale: f64,
     pub noise_threshold: f64,
     pub noise_octaves: u32,
+    /// Length of a day/night cycle in seconds of simulation time. 0 disables the cycle.
+    #[serde(default = "default_day_length")]
+    pub day_length: f64,
+    /// Multiplier to the amount of ore in a tile, in the range (0, 10]. Since tiles with
+    /// less than a unit of ore are left empty, richer ore also makes patches larger.
+    #[serde(default = "default_one")]
+    pub ore_richness: f64,
+    /// Multiplier to the spatial frequency of ore noise, in the range [0.25, 4].
+    /// Larger values give more patches of smaller size.
+    #[serde(default = "default_one")]
+    pub ore_frequency: f64,
+    /// Multiplier to the `noise_scale` of water, in the range [0.25, 4].
+    /// Larger values give larger lakes further apart.
+    #[serde(default = "default_one")]
+    pub water_scale: f64,
+    /// Whether to generate cliffs, which block structures like water.
+    #[serde(default)]
+    pub cliffs: bool,
+    /// Fraction of tiles in forests that have trees, in the range [0, 1]. 0 disables trees.
+    #[serde(default = "default_tree_density")]
+    pub tree_density: f64,
+    /// Units of water that a fresh water well pumps in a tick.
+    #[serde(default = "default_one")]
+    pub water_well_rate: f64,
+    /// Whether water wells slow down as they pump, unlike offshore pumps. Disabled for sandbox play.
+    #[serde(default)]
+    pub water_well_depletion: bool,
 }
 
+fn default_day_length() -> f64 {
+    300.
+}
+
+fn default_one() -> f64 {
+    1.
+}
+
+fn default_tree_density() -> f64 {
+    0.5
+}
+
+/// Cliffs are generated where the cliff noise is within this distance from zero,
+/// which makes thin ridges rather than blobs.
+const CLIFF_WIDTH: f64 = 0.03;
+/// Scale of the cliff noise relative to `noise_scale`.
+const CLIFF_SCALE: f64 = 2.;
+/// Forests are where the tree noise exceeds this value.
+const TREE_NOISE_THRESHOLD: f64 = 0.15;
+
 pub(crate) const CHUNK_SIZE: usize = 16;
 pub(crate) const CHUNK_SIZE_I: i32 = CHUNK_SIZE as i32;
 pub(crate) const CHUNK_SIZE_F: f64 = CHUNK_SIZE as f64;
@@ -29,6 +76,10 @@ pub(crate) struct Chunk {
     pub cells: Vec<Cell>,
     /// Maintain a buffer for rendering minimap for performance
     pub minimap_buffer: Vec<u8>,
+    /// Whether `minimap_buffer` needs repainting before it is shown on the minimap.
+    pub minimap_dirty: bool,
+    /// Pollution shared by the tiles in the chunk.
+    pub pollution: f64,
 }
 
 impl Chunk {
@@ -36,6 +87,8 @@ impl Chunk {
         Self {
             cells,
             minimap_buffer: vec![0u8; CHUNK_SIZE2 * 4],
+            minimap_dirty: true,
+            pollution: 0.,
         }
     }
 }
@@ -45,6 +98,8 @@ pub(crate) type Chunks = HashMap<Position, Chunk>;
 pub(crate) trait ChunksExt {
     fn get_tile(&self, position: Position) -> Option<&Cell>;
     fn get_tile_mut(&mut self, position: Position) -> Option<&mut Cell>;
+    /// Returns the position of the chunk containing the tile if it is not generated yet.
+    fn missing_chunk(&self, position: Position) -> Option<Position>;
 }
 
 impl ChunksExt for Chunks {
@@ -63,6 +118,18 @@ impl ChunksExt for Chunks {
         }
     }
 
+    fn missing_chunk(&self, position: Position) -> Option<Position> {
+        let chunk_pos = Position::new(
+            position.x.div_euclid(CHUNK_SIZE_I),
+            position.y.div_euclid(CHUNK_SIZE_I),
+        );
+        if self.contains_key(&chunk_pos) {
+            None
+        } else {
+            Some(chunk_pos)
+        }
+    }
+
     fn get_tile_mut(&mut self, position: Position) -> Option<&mut Cell> {
         let chunk = self.get_mut(&Position::new(
             position.x.div_euclid(CHUNK_SIZE_I),
@@ -90,6 +157,11 @@ pub(crate) fn gen_chunk(position: Position, terrain_params: &TerrainParameters)
         noise_scale,
         noise_threshold,
         noise_octaves,
+        ore_richness,
+        ore_frequency,
+        water_scale,
+        cliffs,
+        tree_density,
         ..
     } = *terrain_params;
 
@@ -101,17 +173,37 @@ pub(crate) fn gen_chunk(position: Position, terrain_params: &TerrainParameters)
     let copper_terms = gen_terms(&mut rng, bits);
     let coal_terms = gen_terms(&mut rng, bits);
     let stone_terms = gen_terms(&mut rng, bits);
+    // Generated after the other layers so that adding cliffs does not change them.
+    let cliff_terms = gen_terms(&mut rng, bits);
+    let tree_terms = gen_terms(&mut rng, bits);
     for y in 0..CHUNK_SIZE {
         for x in 0..CHUNK_SIZE {
+            let (gx, gy) = (
+                x as i32 + position.x * CHUNK_SIZE_I,
+                y as i32 + position.y * CHUNK_SIZE_I,
+            );
             let [fx, fy] = [
                 (x as f64 + position.x as f64 * CHUNK_SIZE as f64) / noise_scale,
                 (y as f64 + position.y as f64 * CHUNK_SIZE as f64) / noise_scale,
             ];
             let cell = &mut ret[(x + y * CHUNK_SIZE) as usize];
-            cell.water = water_noise_threshold < perlin_noise_pixel(fx, fy, bits, &ocean_terms);
+            cell.water = water_noise_threshold
+                < perlin_noise_pixel(fx / water_scale, fy / water_scale, bits, &ocean_terms);
             if cell.water {
                 continue; // No ores in water
             }
+            if cliffs {
+                let cliff_noise =
+                    perlin_noise_pixel(fx / CLIFF_SCALE, fy / CLIFF_SCALE, bits, &cliff_terms);
+                cell.cliff = cliff_noise.abs() < CLIFF_WIDTH;
+                if cell.cliff {
+                    continue; // No ores in cliffs either
+                }
+            }
+            let forest = 0. < tree_density
+                && TREE_NOISE_THRESHOLD < perlin_noise_pixel(fx, fy, bits, &tree_terms);
+            let (fx, fy) = (fx * ore_frequency, fy * ore_frequency);
+            let resource_amount = resource_amount * ore_richness;
             let iron = (perlin_noise_pixel(fx, fy, bits, &iron_terms) - noise_threshold)
                 * 4.
                 * resource_amount;
@@ -138,6 +230,17 @@ pub(crate) fn gen_chunk(position: Position, terrain_params: &TerrainParameters)
                 Some((ore, v)) if 0 < v => cell.ore = Some(OreValue(*ore, v)),
                 _ => (),
             }
+
+            if forest && cell.ore.is_none() {
+                // Scatter trees with a random number that depends only on the tile position,
+                // so that the result does not depend on the order of chunk generation.
+                let mut tile_rng = Xor128::new(
+                    terrain_seed
+                        ^ (gx as u32).wrapping_mul(73_856_093)
+                        ^ (gy as u32).wrapping_mul(19_349_663),
+                );
+                cell.tree = tile_rng.next() < tree_density;
+            }
         }
     }
     Chunk::new(ret)
@@ -225,3 +328,29 @@ pub(crate) fn calculate_back_image_all(terrain: &mut Chunks) {
         terrain.get_mut(chunk_pos).map(|c| c.cells = chunk);
     }
 }
+
+#[test]
+fn test_missing_chunk_far_from_origin() {
+    let params: TerrainParameters = serde_json::from_value(serde_json::json!({
+        "width": 64,
+        "height": 64,
+        "unlimited": true,
+        "terrain_seed": 1,
+        "water_noise_threshold": 0.35,
+        "resource_amount": 1000.,
+        "noise_scale": 5.,
+        "noise_threshold": 0.3,
+        "noise_octaves": 3,
+    }))
+    .unwrap();
+    let mut board = Chunks::new();
+    let tile = Position::new(-1000, 5000);
+    // The tile is in the chunk at (-62.5, 312.5) rounded down.
+    let chunk_pos = board.missing_chunk(tile).unwrap();
+    assert_eq!(chunk_pos, Position::new(-63, 312));
+    assert!(board.get_tile(tile).is_none());
+
+    board.insert(chunk_pos, gen_chunk(chunk_pos, &params));
+    assert!(board.missing_chunk(tile).is_none());
+    assert!(board.get_tile(tile).is_some());
+}
@@ -0,0 +1,61 @@
+use super::{items::ItemType, replay::ReplayInput, FactorishState, SelectedItem};
+use wasm_bindgen::prelude::*;
+
+/// The number of slots in a row of the tool belt, which are selected by the number keys.
+pub(crate) const TOOL_BELT_SIZE: usize = 10;
+/// The number of rows of the tool belt that the player can switch between.
+pub(crate) const TOOL_BELT_ROWS: usize = 4;
+
+pub(crate) type ToolBeltRow = [Option<ItemType>; TOOL_BELT_SIZE];
+
+/// Make the number of rows loaded from a save `TOOL_BELT_ROWS`, adding empty rows if needed.
+pub(crate) fn fill_tool_rows(rows: &mut Vec<ToolBeltRow>) {
+    rows.resize(TOOL_BELT_ROWS, [None; TOOL_BELT_SIZE]);
+}
+
+impl FactorishState {
+    /// The row of the tool belt that the tools are selected from.
+    pub(crate) fn active_tool_row(&self) -> &ToolBeltRow {
+        &self.tool_belt[self.tool_row]
+    }
+
+    pub(crate) fn active_tool_row_mut(&mut self) -> &mut ToolBeltRow {
+        &mut self.tool_belt[self.tool_row]
+    }
+}
+
+#[wasm_bindgen]
+impl FactorishState {
+    /// Switch the row of the tool belt that `select_tool` and the toolbar operate on.
+    /// A selected tool is deselected, since the slot holds another item in the new row.
+    pub fn switch_tool_row(&mut self, row: usize) -> Result<(), JsValue> {
+        if TOOL_BELT_ROWS <= row {
+            return js_err!("Tool belt row out of range: {}", row);
+        }
+        self.record_input(ReplayInput::SwitchToolRow { row });
+        self.tool_row = row;
+        if let Some(SelectedItem::ToolBelt(_)) = self.selected_item {
+            self.selected_item = None;
+        }
+        Ok(())
+    }
+
+    pub fn get_tool_row(&self) -> usize {
+        self.tool_row
+    }
+
+    pub fn get_tool_row_count(&self) -> usize {
+        TOOL_BELT_ROWS
+    }
+}
+
+#[test]
+fn test_fill_tool_rows() {
+    let mut row = [None; TOOL_BELT_SIZE];
+    row[2] = Some(ItemType::TransportBelt);
+    let mut rows = vec![row];
+    fill_tool_rows(&mut rows);
+    assert_eq!(rows.len(), TOOL_BELT_ROWS);
+    assert_eq!(rows[0][2], Some(ItemType::TransportBelt));
+    assert!(rows[1..].iter().flatten().all(|slot| slot.is_none()));
+}
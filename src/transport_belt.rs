@@ -1,16 +1,63 @@
 use super::{
-    drop_items::DropItem,
-    structure::{ItemResponse, ItemResponseResult, Structure, StructureDynIter},
-    FactorishState, Position, RotateErr, Rotation, TILE_SIZE,
+    drop_items::{DropItem, DROP_ITEM_SIZE_I},
+    structure::{ItemResponse, ItemResponseResult, Structure, StructureDynIter, StructureId},
+    FactorishState, Position, RotateErr, Rotation, TILE_SIZE_I,
 };
 use serde::{Deserialize, Serialize};
 use wasm_bindgen::prelude::*;
 use web_sys::CanvasRenderingContext2d;
 
+/// Distance of each of the two lanes from the center line of a belt, in pixels.
+/// It is equal to the item size, so that items on different lanes never hit each other.
+pub(crate) const LANE_OFFSET: i32 = DROP_ITEM_SIZE_I;
+
+/// Snaps a coordinate perpendicular to the flow to the center of the nearest lane.
+/// Items exactly at the center of the tile, e.g. the ones put by ore mines or saved before
+/// lanes were introduced, go to the lane on the positive side.
+pub(crate) fn snap_to_lane(v: i32) -> i32 {
+    let sub = v.rem_euclid(TILE_SIZE_I);
+    v.div_euclid(TILE_SIZE_I) * TILE_SIZE_I
+        + TILE_SIZE_I / 2
+        + if sub < TILE_SIZE_I / 2 {
+            -LANE_OFFSET
+        } else {
+            LANE_OFFSET
+        }
+}
+
+/// Returns the point on the lane of the tile at `position` that is nearest to `from`.
+/// Used by inserters to drop items on the near lane of a belt.
+pub(crate) fn near_lane_point(position: &Position, from: &Position) -> (i32, i32) {
+    let (dx, dy) = (from.x - position.x, from.y - position.y);
+    (
+        position.x * TILE_SIZE_I + TILE_SIZE_I / 2 + dx.signum() * LANE_OFFSET,
+        position.y * TILE_SIZE_I + TILE_SIZE_I / 2 + dy.signum() * LANE_OFFSET,
+    )
+}
+
+/// Number of animation frames in the curved belt sprite sheet.
+const CURVE_FRAMES: i32 = 8;
+/// Distance that an item travels along a curve in a frame, in pixels. It is a bit longer than
+/// on a straight belt so that rounding to the pixel grid always makes progress.
+const CURVE_STEP: f64 = 1.5;
+
+/// Direction of the turn that items take on a curved belt, seen from above.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) enum CurveDir {
+    Clockwise,
+    Counterclockwise,
+}
+
 #[derive(Serialize, Deserialize)]
 pub(crate) struct TransportBelt {
     position: Position,
     rotation: Rotation,
+    /// Rotations of the belts that feed into this one. A belt feeding in from a tile is always
+    /// facing this tile, so the rotation also tells which neighbor it is.
+    #[serde(skip)]
+    feeders: Vec<Rotation>,
+    #[serde(skip)]
+    curve: Option<CurveDir>,
 }
 
 impl TransportBelt {
@@ -18,10 +65,71 @@ impl TransportBelt {
         TransportBelt {
             position: Position { x, y },
             rotation,
+            feeders: vec![],
+            curve: None,
+        }
+    }
+
+    /// A belt curves if it is fed only from one side, like in Factorio. A belt fed from behind
+    /// or from both sides is a straight belt, or a junction of lanes.
+    fn update_curve(&mut self) {
+        self.curve = match self.feeders[..] {
+            [feeder] if feeder == self.rotation.prev() => Some(CurveDir::Clockwise),
+            [feeder] if feeder == self.rotation.next() => Some(CurveDir::Counterclockwise),
+            _ => None,
+        };
+    }
+
+    /// Update `feeders` for the structure at the neighboring tile, if any.
+    fn update_feeder(&mut self, other: &dyn Structure, construct: bool) {
+        let side = [
+            Rotation::Left,
+            Rotation::Top,
+            Rotation::Right,
+            Rotation::Bottom,
+        ]
+        .iter()
+        .copied()
+        .find(|side| self.position.add(side.delta_inv()) == *other.position());
+        if let Some(side) = side {
+            self.feeders.retain(|feeder| *feeder != side);
+            if construct && other.name() == "Transport Belt" && other.rotation() == Some(side) {
+                self.feeders.push(side);
+            }
+            self.update_curve();
         }
     }
 }
 
+/// Move an item by `CURVE_STEP` along a curved belt facing `rotation`.
+/// The pixel offset from the center of the tile is converted to a frame where the belt faces
+/// right and turns clockwise, i.e. around the bottom right corner, and back.
+fn curve_item_response(rotation: Rotation, curve: CurveDir, (x, y): (i32, i32)) -> (i32, i32) {
+    let half = (TILE_SIZE_I / 2) as f64;
+    let forward = rotation.delta();
+    let side = match curve {
+        CurveDir::Clockwise => (-forward.1, forward.0),
+        CurveDir::Counterclockwise => (forward.1, -forward.0),
+    };
+    let (u, v) = (
+        (x * forward.0 + y * forward.1) as f64,
+        (x * side.0 + y * side.1) as f64,
+    );
+    let (dx, dy) = (u - half, v - half);
+    // Lanes are concentric arcs around the corner
+    let radius = if dx.hypot(dy) < half {
+        half - LANE_OFFSET as f64
+    } else {
+        half + LANE_OFFSET as f64
+    };
+    let angle = (-dy).atan2(-dx).max(0.) + CURVE_STEP / radius;
+    let (u, v) = (
+        (half - radius * angle.cos()).round() as i32,
+        (half - radius * angle.sin()).round() as i32,
+    );
+    (u * forward.0 + v * side.0, u * forward.1 + v * side.1)
+}
+
 impl Structure for TransportBelt {
     fn name(&self) -> &str {
         "Transport Belt"
@@ -41,6 +149,33 @@ impl Structure for TransportBelt {
         if depth != 0 {
             return Ok(());
         };
+        if let Some(curve) = self.curve {
+            let img = state
+                .image_belt_curve
+                .as_ref()
+                .ok_or_else(|| js_str!("belt curve image not available"))?;
+            let (x, y) = (self.position.x as f64 * 32., self.position.y as f64 * 32.);
+            let frame = ((state.sim_time * 16.) % 32.) as i32 * CURVE_FRAMES / 32;
+            context.save();
+            context.translate(x + 16., y + 16.)?;
+            context.rotate(self.rotation.angle_rad())?;
+            if curve == CurveDir::Counterclockwise {
+                context.scale(1., -1.)?;
+            }
+            context.draw_image_with_image_bitmap_and_sw_and_sh_and_dx_and_dy_and_dw_and_dh(
+                &img.bitmap,
+                frame as f64 * 32.,
+                0.,
+                32.,
+                32.,
+                -16.,
+                -16.,
+                32.,
+                32.,
+            )?;
+            context.restore();
+            return Ok(());
+        }
         match state.image_belt.as_ref() {
             Some(img) => {
                 let (x, y) = (self.position.x as f64 * 32., self.position.y as f64 * 32.);
@@ -74,33 +209,252 @@ impl Structure for TransportBelt {
         true
     }
 
+    /// Items fed from the side or dropped by inserters go onto the near lane right away, so
+    /// that they are checked against the items on the lane before entering.
+    /// Curves carry the items along their arcs instead.
+    fn item_entry(&self, x: i32, y: i32) -> (i32, i32) {
+        if self.curve.is_some() {
+            (x, y)
+        } else if self.rotation.is_horizontal() {
+            (x, snap_to_lane(y))
+        } else {
+            (snap_to_lane(x), y)
+        }
+    }
+
+    fn walkable(&self) -> bool {
+        true
+    }
+
     fn rotate(&mut self, _others: &StructureDynIter) -> Result<(), RotateErr> {
         self.rotation = self.rotation.next();
+        self.update_curve();
         Ok(())
     }
 
+    fn rotation(&self) -> Option<Rotation> {
+        Some(self.rotation)
+    }
+
     fn set_rotation(&mut self, rotation: &Rotation) -> Result<(), ()> {
         self.rotation = *rotation;
+        self.update_curve();
+        Ok(())
+    }
+
+    fn on_construction(
+        &mut self,
+        _other_id: StructureId,
+        other: &dyn Structure,
+        construct: bool,
+    ) -> Result<(), JsValue> {
+        self.update_feeder(other, construct);
         Ok(())
     }
 
+    fn on_construction_self(
+        &mut self,
+        _id: StructureId,
+        others: &StructureDynIter,
+        construct: bool,
+    ) -> Result<(), JsValue> {
+        self.feeders.clear();
+        if construct {
+            for (_, other) in others.dyn_iter_id() {
+                self.update_feeder(other, true);
+            }
+        }
+        self.update_curve();
+        Ok(())
+    }
+
+    /// Items travel on either of the two lanes, which are kept by the offset perpendicular to the flow.
+    /// On a curve, the lanes are arcs, so that items do not cut across the corner.
     fn item_response(&mut self, item: &DropItem) -> Result<ItemResponseResult, ()> {
+        if let Some(curve) = self.curve {
+            let (x0, y0) = (
+                self.position.x * TILE_SIZE_I + TILE_SIZE_I / 2,
+                self.position.y * TILE_SIZE_I + TILE_SIZE_I / 2,
+            );
+            let (dx, dy) = curve_item_response(self.rotation, curve, (item.x - x0, item.y - y0));
+            return Ok((ItemResponse::Move(x0 + dx, y0 + dy), None));
+        }
         let vx = self.rotation.delta().0;
         let vy = self.rotation.delta().1;
         let ax = if self.rotation.is_vertial() {
-            (item.x as f64 / TILE_SIZE).floor() * TILE_SIZE + TILE_SIZE / 2.
+            snap_to_lane(item.x)
         } else {
-            item.x as f64
+            item.x
         };
         let ay = if self.rotation.is_horizontal() {
-            (item.y as f64 / TILE_SIZE).floor() * TILE_SIZE + TILE_SIZE / 2.
+            snap_to_lane(item.y)
         } else {
-            item.y as f64
+            item.y
         };
-        let moved_x = ax as i32 + vx;
-        let moved_y = ay as i32 + vy;
+        let moved_x = ax + vx;
+        let moved_y = ay + vy;
         Ok((ItemResponse::Move(moved_x, moved_y), None))
     }
 
     crate::serialize_impl!();
 }
+
+#[test]
+fn test_curve_item_response() {
+    // An item coming up from the bottom on the outer lane of a belt turning right
+    let mut pos = (-LANE_OFFSET, TILE_SIZE_I / 2 - 1);
+    let half = TILE_SIZE_I / 2;
+    for _ in 0..100 {
+        let next = curve_item_response(Rotation::Right, CurveDir::Clockwise, pos);
+        // Keeps to the lane and never moves backwards
+        let radius = ((half - next.0) as f64).hypot((half - next.1) as f64);
+        assert!((radius - (half + LANE_OFFSET) as f64).abs() < 1.);
+        assert!(pos.0 <= next.0 && next.1 <= pos.1);
+        pos = next;
+        if half <= pos.0 {
+            break;
+        }
+    }
+    // Leaves the tile at the right edge on the same lane as a straight belt
+    assert!(half <= pos.0 && pos.0 <= half + 2);
+    assert_eq!(pos.1, -LANE_OFFSET);
+
+    // The mirrored curve of a belt facing left
+    let next = curve_item_response(
+        Rotation::Left,
+        CurveDir::Counterclockwise,
+        (-LANE_OFFSET, 15),
+    );
+    assert!(next.0 <= -LANE_OFFSET && next.1 < 15);
+}
+
+#[test]
+fn test_sideload_merges_into_near_lane() {
+    use super::{
+        drop_items::{
+            build_index, move_destination, update_index, Destination, DropItemEntry, DropItemId,
+        },
+        items::ItemType,
+        structure::{build_structure_tile_index, StructureEntry},
+    };
+
+    // A line of belts to the right, fed from behind and from the side at (2, 0):
+    //       v
+    //       v
+    //   > > > > > >
+    let wrap = |belt: TransportBelt| StructureEntry::new(Box::new(belt));
+    let mut structures = (0..6)
+        .map(|x| wrap(TransportBelt::new(x, 0, Rotation::Right)))
+        .collect::<Vec<_>>();
+    structures.push(wrap(TransportBelt::new(2, -1, Rotation::Bottom)));
+    structures.push(wrap(TransportBelt::new(2, -2, Rotation::Bottom)));
+    let tile_index = build_structure_tile_index(&structures);
+
+    let lane = |tile: i32, offset: i32| tile * TILE_SIZE_I + TILE_SIZE_I / 2 + offset;
+    let near_lane = lane(0, -LANE_OFFSET);
+    // The near lane of the line is full at the start, and so are both lanes of the side feed.
+    let mut positions = (0..4)
+        .map(|i| (i * DROP_ITEM_SIZE_I, near_lane))
+        .collect::<Vec<_>>();
+    for y in [-56, -40, -24, -8].iter() {
+        positions.push((lane(2, -LANE_OFFSET), *y));
+        positions.push((lane(2, LANE_OFFSET), *y));
+    }
+    let mut items = positions
+        .iter()
+        .map(|(x, y)| DropItemEntry {
+            gen: 0,
+            item: Some(DropItem {
+                type_: ItemType::IronOre,
+                x: *x,
+                y: *y,
+                count: 1,
+            }),
+        })
+        .collect::<Vec<_>>();
+    let mut index = build_index(&items);
+
+    for _ in 0..400 {
+        for i in 0..items.len() {
+            let item = items[i].item.as_ref().unwrap();
+            let id = DropItemId::new(i as u32, 0);
+            let moved = match structures[tile_index[&item.tile()]]
+                .dynamic
+                .as_mut()
+                .unwrap()
+                .item_response(item)
+                .unwrap()
+                .0
+            {
+                ItemResponse::Move(x, y) => (x, y),
+                ItemResponse::Consume => unreachable!(),
+            };
+            if let Destination::Free(x, y) = move_destination(
+                &items,
+                &index,
+                &structures,
+                &tile_index,
+                id,
+                moved,
+                DROP_ITEM_SIZE_I,
+            ) {
+                update_index(&mut index, id, item.x, item.y, x, y);
+                let item = items[i].item.as_mut().unwrap();
+                item.x = x;
+                item.y = y;
+            }
+        }
+        // Items entering the line from the side go straight onto a lane, rather than sitting
+        // between the lanes at the edge of the tile.
+        for entry in &items {
+            let item = entry.item.as_ref().unwrap();
+            if item.tile().y == 0 {
+                assert!(item.y == near_lane || item.y == lane(0, LANE_OFFSET));
+            }
+        }
+        // No two items ever overlap.
+        for (i, a) in items.iter().enumerate() {
+            for b in &items[i + 1..] {
+                let (a, b) = (a.item.as_ref().unwrap(), b.item.as_ref().unwrap());
+                assert!(
+                    DROP_ITEM_SIZE_I <= (a.x - b.x).abs() || DROP_ITEM_SIZE_I <= (a.y - b.y).abs()
+                );
+            }
+        }
+    }
+
+    // Both feeds merged into the near lane of the line and piled up at its end.
+    for entry in &items {
+        let item = entry.item.as_ref().unwrap();
+        assert_eq!(item.y, near_lane);
+        assert!(3 * TILE_SIZE_I <= item.x);
+    }
+}
+
+#[test]
+fn test_headless_tick_carries_item() {
+    use super::{items::ItemType, scenarios::empty_state};
+
+    // The headless game times the ticks with a `ManualClock` instead of the browser.
+    let mut state = empty_state(16, 16);
+    for x in 0..5 {
+        state
+            .add_structure(Box::new(TransportBelt::new(x, 0, Rotation::Right)))
+            .unwrap();
+    }
+    state
+        .new_object(&Position::new(0, 0), ItemType::IronOre)
+        .unwrap();
+
+    for _ in 0..200 {
+        state.tick_headless(0.05).unwrap();
+    }
+
+    // The item stops at the end of the belts.
+    let item = state
+        .drop_items
+        .iter()
+        .find_map(|entry| entry.item.as_ref())
+        .unwrap();
+    assert_eq!(item.tile(), Position::new(4, 0));
+}
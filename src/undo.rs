@@ -0,0 +1,113 @@
+use super::{
+    items::{str_to_item, ItemType},
+    replay::ReplayInput,
+    structure::Structure,
+    FactorishState, Inventory, InventoryTrait, Position,
+};
+use std::collections::VecDeque;
+use wasm_bindgen::prelude::*;
+
+/// Maximum number of actions that can be undone.
+const UNDO_LIMIT: usize = 32;
+
+pub(crate) enum UndoAction {
+    /// A structure was placed by the player. Undoing removes it and refunds the item.
+    Placed { type_: ItemType, position: Position },
+    /// A structure was harvested. Undoing puts it back from the serialized state in the same
+    /// format as the save data, and takes back the items that were refunded.
+    Removed {
+        type_: ItemType,
+        value: serde_json::Value,
+        refund: Inventory,
+    },
+}
+
+pub(crate) type UndoStack = VecDeque<UndoAction>;
+
+/// Serialize a structure in the same format as the save data, which `structure_from_json` can read.
+pub(crate) fn structure_to_json(structure: &dyn Structure) -> Result<serde_json::Value, JsValue> {
+    let mut map = serde_json::Map::new();
+    map.insert(
+        "type".to_string(),
+        serde_json::Value::String(structure.name().to_string()),
+    );
+    map.insert(
+        "payload".to_string(),
+        structure
+            .serialize()
+            .map_err(|e| js_str!("Serialize error: {}", e))?,
+    );
+    Ok(serde_json::Value::Object(map))
+}
+
+impl FactorishState {
+    pub(crate) fn push_undo(&mut self, action: UndoAction) {
+        self.undo_stack.push_back(action);
+        while UNDO_LIMIT < self.undo_stack.len() {
+            self.undo_stack.pop_front();
+        }
+    }
+
+    fn undo_action(&mut self, action: &UndoAction) -> Result<bool, JsValue> {
+        match action {
+            UndoAction::Placed { type_, position } => {
+                if self
+                    .find_structure_tile(&[position.x, position.y])
+                    .and_then(|s| str_to_item(s.name()))
+                    != Some(*type_)
+                {
+                    return Ok(false);
+                }
+                // Harvesting would record another undo action, which we don't want.
+                let undo_stack = std::mem::take(&mut self.undo_stack);
+                let ret = self.harvest(position, false);
+                self.undo_stack = undo_stack;
+                ret
+            }
+            UndoAction::Removed {
+                type_,
+                value,
+                refund,
+            } => {
+                if refund
+                    .iter()
+                    .any(|(item, count)| self.player.inventory.count_item(item) < *count)
+                {
+                    return Ok(false);
+                }
+                let structure = Self::structure_from_json(&mut value.clone())?;
+                if self.is_placeable(structure.as_ref(), type_).is_err() {
+                    return Ok(false);
+                }
+                for (item, count) in refund {
+                    self.player.inventory.remove_items(item, *count);
+                }
+                // Power wires, power networks and fluid connections are rebuilt in add_structure.
+                self.add_structure(structure)?;
+                Ok(true)
+            }
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl FactorishState {
+    /// Undo the last structure placement or removal.
+    /// @returns true if it succeeded. If the action cannot be undone, e.g. because the player
+    ///          does not have the items anymore, it is kept in the stack.
+    pub fn undo(&mut self) -> Result<bool, JsValue> {
+        self.record_input(ReplayInput::Undo);
+        let action = if let Some(action) = self.undo_stack.pop_back() {
+            action
+        } else {
+            return Ok(false);
+        };
+        if !self.undo_action(&action)? {
+            self.undo_stack.push_back(action);
+            return Ok(false);
+        }
+        self.notify_player_update()
+            .unwrap_or_else(|_| JsValue::from(true));
+        Ok(true)
+    }
+}
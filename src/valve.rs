@@ -0,0 +1,291 @@
+use super::{
+    draw_direction_arrow,
+    pipe::Pipe,
+    replay::ReplayInput,
+    structure::{RotateErr, Structure, StructureDynIter, StructureId},
+    water_well::FluidBox,
+    FactorishState, FrameProcResult, Position, Rotation,
+};
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+use web_sys::CanvasRenderingContext2d;
+
+/// A pipe that lets the fluid flow only in the direction it points to, e.g. from a boiler to
+/// steam engines but not back. The fluid enters the input box from behind and leaves the output
+/// box in front, and the valve passes it from the input to the output every tick.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct Valve {
+    position: Position,
+    rotation: Rotation,
+    input_fluid_box: FluidBox,
+    output_fluid_box: FluidBox,
+    /// The maximum amount of the fluid passed in a tick, or None for no limit.
+    max_flow: Option<f64>,
+}
+
+impl Valve {
+    pub(crate) fn new(position: &Position, rotation: Rotation) -> Self {
+        Self {
+            position: *position,
+            rotation,
+            input_fluid_box: FluidBox::new(true, false),
+            output_fluid_box: FluidBox::new(false, true),
+            max_flow: None,
+        }
+    }
+}
+
+/// Move the fluid from the input box to the output box, as much as the output has room for
+/// up to `max_flow`. A different fluid left in the output blocks the flow.
+/// @returns the amount passed.
+fn pass_fluid(input: &mut FluidBox, output: &mut FluidBox, max_flow: Option<f64>) -> f64 {
+    if 0. < output.amount && output.type_ != input.type_ {
+        return 0.;
+    }
+    let amount = input
+        .amount
+        .min(output.max_amount - output.amount)
+        .min(max_flow.unwrap_or(f64::INFINITY))
+        .max(0.);
+    if 0. < amount {
+        input.amount -= amount;
+        output.amount += amount;
+        output.type_ = input.type_;
+    }
+    amount
+}
+
+impl Structure for Valve {
+    fn name(&self) -> &str {
+        "Valve"
+    }
+
+    fn position(&self) -> &Position {
+        &self.position
+    }
+
+    fn walkable(&self) -> bool {
+        true
+    }
+
+    fn draw(
+        &self,
+        state: &FactorishState,
+        context: &CanvasRenderingContext2d,
+        depth: i32,
+        _is_toolbar: bool,
+    ) -> Result<(), JsValue> {
+        if depth == 2 {
+            let (x, y) = (self.position.x as f64 * 32., self.position.y as f64 * 32.);
+            return draw_direction_arrow((x, y), &self.rotation, state, context);
+        }
+        Pipe::draw_int(self, state, context, depth, true)
+    }
+
+    fn desc(&self, _state: &FactorishState) -> String {
+        format!(
+            "Input: {}<br>Output: {}<br>Max flow: {}<br>",
+            self.input_fluid_box.desc(),
+            self.output_fluid_box.desc(),
+            self.max_flow
+                .map_or_else(|| "Unlimited".to_string(), |flow| format!("{:.1}", flow))
+        )
+    }
+
+    fn frame_proc(
+        &mut self,
+        _me: StructureId,
+        _state: &mut FactorishState,
+        _structures: &mut StructureDynIter,
+    ) -> Result<FrameProcResult, ()> {
+        pass_fluid(
+            &mut self.input_fluid_box,
+            &mut self.output_fluid_box,
+            self.max_flow,
+        );
+        Ok(FrameProcResult::None)
+    }
+
+    fn rotate(&mut self, _others: &StructureDynIter) -> Result<(), RotateErr> {
+        self.rotation = self.rotation.next();
+        Ok(())
+    }
+
+    fn rotation(&self) -> Option<Rotation> {
+        Some(self.rotation)
+    }
+
+    fn set_rotation(&mut self, rotation: &Rotation) -> Result<(), ()> {
+        self.rotation = *rotation;
+        Ok(())
+    }
+
+    fn fluid_box(&self) -> Option<Vec<&FluidBox>> {
+        Some(vec![&self.input_fluid_box, &self.output_fluid_box])
+    }
+
+    fn fluid_box_mut(&mut self) -> Option<Vec<&mut FluidBox>> {
+        Some(vec![&mut self.input_fluid_box, &mut self.output_fluid_box])
+    }
+
+    fn fluid_connects(&self, direction: Rotation) -> bool {
+        direction.is_horizontal() == self.rotation.is_horizontal()
+    }
+
+    fn fluid_box_connects(&self, index: usize, direction: Rotation) -> bool {
+        match index {
+            0 => direction == self.rotation.next().next(),
+            _ => direction == self.rotation,
+        }
+    }
+
+    fn valve_max_flow_mut(&mut self) -> Option<&mut Option<f64>> {
+        Some(&mut self.max_flow)
+    }
+
+    crate::serialize_impl!();
+}
+
+#[wasm_bindgen]
+impl FactorishState {
+    /// Turn the valve at the tile so that the fluid flows toward `rotation`, which is one of
+    /// "Left", "Top", "Right" or "Bottom".
+    pub fn set_valve_direction(&mut self, c: i32, r: i32, rotation: &str) -> Result<(), JsValue> {
+        self.record_input(ReplayInput::SetValveDirection {
+            c,
+            r,
+            rotation: rotation.to_string(),
+        });
+        let rotation: Rotation = rotation
+            .parse()
+            .map_err(|e: String| JsValue::from_str(&e))?;
+        let valve = self
+            .find_structure_tile_mut(&[c, r])
+            .ok_or_else(|| js_str!("Structure is not found"))?;
+        if valve.valve_max_flow_mut().is_none() {
+            return js_err!("Structure is not a valve");
+        }
+        valve
+            .set_rotation(&rotation)
+            .map_err(|()| js_str!("Structure cannot be rotated"))?;
+        let position = *valve.position();
+        self.update_fluid_connections(&position);
+        Ok(())
+    }
+
+    /// Limit the amount of the fluid that the valve at the tile passes in a tick,
+    /// or remove the limit with undefined.
+    pub fn set_valve_max_flow(
+        &mut self,
+        c: i32,
+        r: i32,
+        max_flow: Option<f64>,
+    ) -> Result<(), JsValue> {
+        self.record_input(ReplayInput::SetValveMaxFlow { c, r, max_flow });
+        if let Some(max_flow) = max_flow {
+            if !(0. <= max_flow && max_flow.is_finite()) {
+                return js_err!("Max flow must be a non-negative number: {}", max_flow);
+            }
+        }
+        *self
+            .find_structure_tile_mut(&[c, r])
+            .ok_or_else(|| js_str!("Structure is not found"))?
+            .valve_max_flow_mut()
+            .ok_or_else(|| js_str!("Structure is not a valve"))? = max_flow;
+        Ok(())
+    }
+}
+
+#[test]
+fn test_valve_prevents_backflow() {
+    use super::{
+        fluid_network::{build_fluid_networks, connect_fluid_boxes, simulate_fluid_networks},
+        structure::StructureEntry,
+        water_well::FluidType,
+    };
+
+    // A ring of pipes with a valve pointing right at the top left corner:
+    //   P V P
+    //   P   P
+    //   P P P
+    let pipes = [(0, 0), (2, 0), (2, 1), (2, 2), (1, 2), (0, 2), (0, 1)];
+    let mut structures = vec![StructureEntry::new(Box::new(Valve::new(
+        &Position::new(1, 0),
+        Rotation::Right,
+    )))];
+    structures.extend(
+        pipes
+            .iter()
+            .map(|(x, y)| StructureEntry::new(Box::new(Pipe::new(&Position::new(*x, *y))))),
+    );
+    for i in 0..structures.len() {
+        for j in i + 1..structures.len() {
+            let (left, right) = structures.split_at_mut(j);
+            let a_id = StructureId {
+                id: i as u32,
+                gen: 0,
+            };
+            let b_id = StructureId {
+                id: j as u32,
+                gen: 0,
+            };
+            connect_fluid_boxes(
+                (a_id, left[i].dynamic.as_deref_mut().unwrap()),
+                (b_id, right[0].dynamic.as_deref_mut().unwrap()),
+            );
+        }
+    }
+
+    let valve_boxes = |structures: &[StructureEntry]| {
+        let boxes = structures[0].dynamic.as_ref().unwrap().fluid_box().unwrap();
+        (boxes[0].connect_to, boxes[1].connect_to)
+    };
+    let (input, output) = valve_boxes(&structures);
+    // The input box faces the pipe behind and the output box the pipe in front.
+    assert_eq!(
+        input,
+        [Some(StructureId { id: 1, gen: 0 }), None, None, None]
+    );
+    assert_eq!(
+        output,
+        [None, None, Some(StructureId { id: 2, gen: 0 }), None]
+    );
+
+    // Fill the pipe in front of the valve, whose pressure would push the fluid back through
+    // a plain pipe.
+    {
+        let mut boxes = structures[2]
+            .dynamic
+            .as_deref_mut()
+            .unwrap()
+            .fluid_box_mut()
+            .unwrap();
+        boxes[0].type_ = Some(FluidType::Steam);
+        boxes[0].amount = 100.;
+    }
+    let networks = build_fluid_networks(&StructureDynIter::new_all(&mut structures));
+    assert_eq!(networks.len(), 1);
+    let amounts = |structures: &[StructureEntry]| {
+        let boxes = structures[0].dynamic.as_ref().unwrap().fluid_box().unwrap();
+        (boxes[0].amount, boxes[1].amount)
+    };
+    let mut passed = 0.;
+    for _ in 0..200 {
+        let before = amounts(&structures);
+        simulate_fluid_networks(&networks, &mut structures).unwrap();
+        let after = amounts(&structures);
+        // Nothing flows out of the input box or into the output box.
+        assert!(before.0 <= after.0);
+        assert!(after.1 <= before.1);
+        let mut boxes = structures[0]
+            .dynamic
+            .as_deref_mut()
+            .unwrap()
+            .fluid_box_mut()
+            .unwrap();
+        let (input, output) = boxes.split_at_mut(1);
+        passed += pass_fluid(input[0], output[0], Some(1.));
+    }
+    // The fluid went around the ring and through the valve forward.
+    assert!(0. < passed);
+}
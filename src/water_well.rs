@@ -15,6 +15,18 @@ pub(crate) enum FluidType {
     Steam,
 }
 
+impl std::str::FromStr for FluidType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "Water" => Ok(Self::Water),
+            "Steam" => Ok(Self::Steam),
+            _ => Err(format!("Fluid type not valid: {}", s)),
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 pub(crate) struct FluidBox {
     pub type_: Option<FluidType>,
@@ -25,6 +37,12 @@ pub(crate) struct FluidBox {
     #[serde(skip)]
     pub connect_to: [Option<StructureId>; 4],
     pub filter: Option<FluidType>, // permits undefined
+    /// Whether a neighbor with a different fluid blocked the flow in the last frame.
+    #[serde(skip)]
+    pub blocked: bool,
+    /// The index into `connect_to` and the amount of the biggest outflow in the last frame.
+    #[serde(skip)]
+    pub flow: Option<(usize, f64)>,
 }
 
 impl FluidBox {
@@ -37,6 +55,8 @@ impl FluidBox {
             output_enable,
             connect_to: [None; 4],
             filter: None,
+            blocked: false,
+            flow: None,
         }
     }
 
@@ -53,6 +73,8 @@ impl FluidBox {
             output_enable,
             connect_to: [None; 4],
             filter,
+            blocked: false,
+            flow: None,
         }
     }
 
@@ -64,69 +86,74 @@ impl FluidBox {
     pub(crate) fn desc(&self) -> String {
         let amount_ratio = self.amount / self.max_amount * 100.;
         // Progress bar
-        format!("{}{}{}{:?}",
+        format!("{}{}{}{}{:?}",
             format!("{}: {:.0}%<br>", self.type_.map(|v| format!("{:?}", v)).unwrap_or_else(|| "None".to_string()), amount_ratio),
             "<div style='position: relative; width: 100px; height: 10px; background-color: #001f1f; margin: 2px; border: 1px solid #3f3f3f'>",
             format!("<div style='position: absolute; width: {}px; height: 10px; background-color: #ff00ff'></div></div>",
                 amount_ratio),
+            self.filter.map(|v| format!("Filter: {:?}<br>", v)).unwrap_or_default()
+                + if self.blocked { "Blocked: incompatible fluid<br>" } else { "" },
             self.connect_to,
             )
     }
 
-    pub(crate) fn simulate(&mut self, structures: &mut StructureDynIter) {
-        let mut _biggest_flow_idx = -1;
-        let mut biggest_flow_amount = 1e-3; // At least this amount of flow is required for displaying flow direction
-                                            // In an unlikely event, a fluid box without either input or output ports has nothing to do
+    /// Let the fluid flow from this box into a connected box if this one has higher pressure.
+    /// Called for each connection of the fluid network every frame.
+    /// @returns the amount of the fluid that flowed out of this box.
+    pub(crate) fn flow_to(&mut self, fluid_box: &mut FluidBox) -> f64 {
+        // In an unlikely event, a fluid box without either input or output ports has nothing to do
         if self.amount == 0. || !self.input_enable && !self.output_enable {
-            return;
+            return 0.;
         }
-        let connect_list = self
-            .connect_to
-            .iter()
-            .enumerate()
-            .filter_map(|(i, c)| Some((i, (*c)?)));
-        for (i, id) in connect_list {
-            if let Some(fluid_boxes) = structures.get_mut(id).map(|s| s.fluid_box_mut()).flatten() {
-                for fluid_box in fluid_boxes {
-                    // Different types of fluids won't mix
-                    if 0. < fluid_box.amount
-                        && 0. < self.amount
-                        && fluid_box.type_ != self.type_
-                        && fluid_box.type_.is_some()
-                    {
-                        continue;
-                    }
-                    let pressure = fluid_box.amount - self.amount;
-                    if 0. < pressure {
-                        continue;
-                    }
-                    let flow = pressure * 0.1;
-                    // Check input/output valve state
-                    if if flow < 0. {
-                        !self.output_enable
-                            || !fluid_box.input_enable
-                            || fluid_box.filter.is_some() && fluid_box.filter != self.type_
-                    } else {
-                        !self.input_enable
-                            || !fluid_box.output_enable
-                            || self.filter.is_some() && self.filter != fluid_box.type_
-                    } {
-                        continue;
-                    }
-                    fluid_box.amount -= flow;
-                    self.amount += flow;
-                    if flow < 0. {
-                        fluid_box.type_ = self.type_;
-                    } else {
-                        self.type_ = fluid_box.type_;
-                    }
-                    if biggest_flow_amount < flow.abs() {
-                        biggest_flow_amount = flow;
-                        _biggest_flow_idx = i as isize;
-                    }
-                }
-            }
+        // Different types of fluids won't mix
+        if 0. < fluid_box.amount
+            && 0. < self.amount
+            && fluid_box.type_ != self.type_
+            && fluid_box.type_.is_some()
+        {
+            self.blocked = true;
+            fluid_box.blocked = true;
+            return 0.;
         }
+        let pressure = fluid_box.amount - self.amount;
+        if 0. < pressure {
+            return 0.;
+        }
+        let flow = pressure * 0.1;
+        // Check input/output valve state
+        if if flow < 0. {
+            !self.output_enable
+                || !fluid_box.input_enable
+                || fluid_box.filter.is_some() && fluid_box.filter != self.type_
+        } else {
+            !self.input_enable
+                || !fluid_box.output_enable
+                || self.filter.is_some() && self.filter != fluid_box.type_
+        } {
+            return 0.;
+        }
+        fluid_box.amount -= flow;
+        self.amount += flow;
+        if flow < 0. {
+            fluid_box.type_ = self.type_;
+        } else {
+            self.type_ = fluid_box.type_;
+        }
+        -flow
+    }
+}
+
+/// The total output of a depleting water well at which its rate falls to half.
+const WELL_HALF_OUTPUT: f64 = 36000.;
+
+/// The rate of a water well that has pumped `total_output` units so far. With depletion, the
+/// rate falls as `rate / (1 + total_output / WELL_HALF_OUTPUT)`, so that it is half at
+/// `WELL_HALF_OUTPUT`, a third at twice that, and so on without ever reaching zero.
+fn well_rate(rate: f64, total_output: f64, depletion: bool) -> f64 {
+    if depletion {
+        rate / (1. + total_output / WELL_HALF_OUTPUT)
+    } else {
+        rate
     }
 }
 
@@ -134,6 +161,9 @@ impl FluidBox {
 pub(crate) struct WaterWell {
     position: Position,
     output_fluid_box: FluidBox,
+    /// The total amount of water pumped, which slows down the well if depletion is enabled.
+    #[serde(default)]
+    total_output: f64,
 }
 
 impl WaterWell {
@@ -141,8 +171,17 @@ impl WaterWell {
         WaterWell {
             position: *position,
             output_fluid_box: FluidBox::new(false, true).set_type(&FluidType::Water),
+            total_output: 0.,
         }
     }
+
+    fn rate(&self, state: &FactorishState) -> f64 {
+        well_rate(
+            state.terrain_params.water_well_rate,
+            self.total_output,
+            state.terrain_params.water_well_depletion,
+        )
+    }
 }
 
 impl Structure for WaterWell {
@@ -176,24 +215,28 @@ impl Structure for WaterWell {
         Ok(())
     }
 
-    fn desc(&self, _state: &FactorishState) -> String {
+    fn desc(&self, state: &FactorishState) -> String {
         format!(
-            "{}<br>{}",
+            "{}<br>{}Rate: {:.2} units/tick<br>Total output: {:.0}<br>",
             self.output_fluid_box.desc(),
             "Outputs: Water<br>",
+            self.rate(state),
+            self.total_output,
         )
     }
 
     fn frame_proc(
         &mut self,
         _me: StructureId,
-        _state: &mut FactorishState,
-        structures: &mut StructureDynIter,
+        state: &mut FactorishState,
+        _structures: &mut StructureDynIter,
     ) -> Result<FrameProcResult, ()> {
-        structures.get_mut(StructureId { id: 0, gen: 0 });
-        self.output_fluid_box.amount =
-            (self.output_fluid_box.amount + 1.).min(self.output_fluid_box.max_amount);
-        self.output_fluid_box.simulate(structures);
+        let pumped = self
+            .rate(state)
+            .min(self.output_fluid_box.max_amount - self.output_fluid_box.amount)
+            .max(0.);
+        self.output_fluid_box.amount += pumped;
+        self.total_output += pumped;
         Ok(FrameProcResult::None)
     }
 
@@ -207,3 +250,71 @@ impl Structure for WaterWell {
 
     crate::serialize_impl!();
 }
+
+#[test]
+fn test_filter_rejects_other_fluid() {
+    let mut steam = FluidBox::new(true, true).set_type(&FluidType::Steam);
+    steam.amount = 50.;
+    let mut water_pipe = FluidBox::new_with_filter(true, true, Some(FluidType::Water));
+    steam.flow_to(&mut water_pipe);
+    assert_eq!(water_pipe.amount, 0.);
+    assert_eq!(steam.amount, 50.);
+
+    let mut steam_pipe = FluidBox::new_with_filter(true, true, Some(FluidType::Steam));
+    steam.flow_to(&mut steam_pipe);
+    assert!(0. < steam_pipe.amount);
+    assert_eq!(steam_pipe.type_, Some(FluidType::Steam));
+}
+
+#[test]
+fn test_flow_to_returns_outflow() {
+    let mut source = FluidBox::new(true, true).set_type(&FluidType::Water);
+    source.amount = 50.;
+    let mut sink = FluidBox::new(true, true);
+    assert_eq!(source.flow_to(&mut sink), 5.);
+    assert_eq!(source.amount, 45.);
+    assert_eq!(sink.amount, 5.);
+    // The fluid does not flow back up the pressure gradient
+    assert_eq!(sink.flow_to(&mut source), 0.);
+}
+
+#[test]
+fn test_well_rate_depletion() {
+    assert_eq!(well_rate(1., WELL_HALF_OUTPUT * 10., false), 1.);
+    assert_eq!(well_rate(1., 0., true), 1.);
+    assert_eq!(well_rate(2., WELL_HALF_OUTPUT, true), 1.);
+    // The rate keeps falling but never stops
+    let late = well_rate(1., WELL_HALF_OUTPUT * 100., true);
+    assert!(0. < late && late < well_rate(1., WELL_HALF_OUTPUT * 10., true));
+}
+
+#[test]
+fn test_well_parameters_saved() {
+    use super::scenarios::empty_state;
+
+    let mut state = empty_state(16, 16);
+    state.terrain_params.water_well_rate = 3.;
+    state.terrain_params.water_well_depletion = true;
+    let save = state.serialize_game_value().unwrap();
+
+    let mut loaded = empty_state(16, 16);
+    loaded.deserialize_game_value(save.clone()).unwrap();
+    assert_eq!(loaded.terrain_params.water_well_rate, 3.);
+    assert!(loaded.terrain_params.water_well_depletion);
+
+    // Older saves get the wells of the sandbox
+    let mut save = save;
+    let map = save.as_object_mut().unwrap();
+    map.remove("water_well_rate");
+    map.remove("water_well_depletion");
+    loaded.deserialize_game_value(save).unwrap();
+    assert_eq!(loaded.terrain_params.water_well_rate, 1.);
+    assert!(!loaded.terrain_params.water_well_depletion);
+}
+
+#[test]
+fn test_fluid_type_from_str() {
+    assert_eq!("Water".parse(), Ok(FluidType::Water));
+    assert_eq!("Steam".parse(), Ok(FluidType::Steam));
+    assert!("Oil".parse::<FluidType>().is_err());
+}
@@ -0,0 +1,65 @@
+use super::{drop_items::drop_item_iter, structure::Structure, FactorishState};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use wasm_bindgen::prelude::*;
+
+/// A snapshot of the scale of the world, for the stats screen and performance reports.
+#[derive(Serialize)]
+struct WorldSummary {
+    /// The number of structures of each type by name, in alphabetical order
+    structures: BTreeMap<String, usize>,
+    structure_count: usize,
+    /// The number of drop items on the ground and belts, each of which may be a stack
+    drop_item_count: usize,
+    /// The number of items in the drop items including the stacked ones
+    item_count: usize,
+    chunk_count: usize,
+    power_network_count: usize,
+}
+
+/// Count the structures by their names.
+fn count_structures<'a>(
+    structures: impl Iterator<Item = &'a dyn Structure>,
+) -> BTreeMap<String, usize> {
+    let mut ret = BTreeMap::new();
+    for structure in structures {
+        *ret.entry(structure.name().to_string()).or_insert(0) += 1;
+    }
+    ret
+}
+
+#[wasm_bindgen]
+impl FactorishState {
+    /// Returns `{structures, structure_count, drop_item_count, item_count, chunk_count,
+    /// power_network_count}`, where `structures` maps the structure names to their numbers.
+    pub fn get_world_summary(&self) -> Result<JsValue, JsValue> {
+        let structures = count_structures(self.structure_iter());
+        let summary = WorldSummary {
+            structure_count: structures.values().sum(),
+            structures,
+            drop_item_count: drop_item_iter(&self.drop_items).count(),
+            item_count: drop_item_iter(&self.drop_items)
+                .map(|item| item.count as usize)
+                .sum(),
+            chunk_count: self.board.len(),
+            power_network_count: self.power_networks.len(),
+        };
+        JsValue::from_serde(&summary).map_err(|e| js_str!("serialize error: {}", e))
+    }
+}
+
+#[test]
+fn test_count_structures() {
+    use super::{structure::Position, transport_belt::TransportBelt, Chest, Rotation};
+
+    let structures: Vec<Box<dyn Structure>> = vec![
+        Box::new(TransportBelt::new(0, 0, Rotation::Left)),
+        Box::new(Chest::new(&Position::new(1, 0))),
+        Box::new(TransportBelt::new(2, 0, Rotation::Left)),
+    ];
+    let counts = count_structures(structures.iter().map(|s| s.as_ref()));
+    assert_eq!(
+        counts.into_iter().collect::<Vec<_>>(),
+        [("Chest".to_string(), 1), ("Transport Belt".to_string(), 2)]
+    );
+}
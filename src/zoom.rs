@@ -0,0 +1,114 @@
+use super::{FactorishState, Viewport, TILE_SIZE};
+use wasm_bindgen::prelude::*;
+
+/// Fraction of the remaining zoom, in log scale, to approach in a frame.
+const ZOOM_EASING: f64 = 0.3;
+/// The zoom animation ends when the scale is this close to the target in log scale.
+const ZOOM_EPSILON: f64 = 1e-3;
+
+/// Runtime state of the zooming, which is not saved with the game.
+pub(crate) struct Zoom {
+    min: f64,
+    max: f64,
+    /// Ratio of the scale changed by a mouse wheel step
+    step: f64,
+    /// The scale to approach and the screen position to keep fixed while zooming.
+    target: Option<(f64, f64, f64)>,
+}
+
+impl Default for Zoom {
+    fn default() -> Self {
+        Self {
+            min: 0.5,
+            max: 8.,
+            step: (2_f64).powf(1. / 5.),
+            target: None,
+        }
+    }
+}
+
+/// Set the scale of the viewport, while keeping the world position under (x, y) in screen
+/// coordinates at the same place.
+fn zoom_at(viewport: &mut Viewport, new_scale: f64, x: f64, y: f64) {
+    viewport.x += x / TILE_SIZE * (1. / new_scale - 1. / viewport.scale);
+    viewport.y += y / TILE_SIZE * (1. / new_scale - 1. / viewport.scale);
+    viewport.scale = new_scale;
+}
+
+impl Zoom {
+    /// Stop the zoom animation where it is.
+    pub(crate) fn cancel(&mut self) {
+        self.target = None;
+    }
+}
+
+impl FactorishState {
+    pub(crate) fn zoom_to(&mut self, zoom_in: bool, x: f64, y: f64) {
+        let zoom = &mut self.zoom;
+        // Accumulate from the current target so that quick wheel steps are not lost.
+        let current = zoom
+            .target
+            .map_or(self.viewport.scale, |(scale, _, _)| scale);
+        let scale = if zoom_in {
+            current * zoom.step
+        } else {
+            current / zoom.step
+        };
+        zoom.target = Some((scale.max(zoom.min).min(zoom.max), x, y));
+    }
+
+    /// Ease the viewport scale toward the target scale, called every frame.
+    pub(crate) fn update_zoom(&mut self) {
+        if let Some((target, x, y)) = self.zoom.target {
+            let log_ratio = (target / self.viewport.scale).ln();
+            let new_scale = if log_ratio.abs() < ZOOM_EPSILON {
+                self.zoom.target = None;
+                target
+            } else {
+                self.viewport.scale * (log_ratio * ZOOM_EASING).exp()
+            };
+            zoom_at(&mut self.viewport, new_scale, x, y);
+            // Zooming out reveals more chunks in every frame of the animation.
+            self.gen_chunks_in_viewport();
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl FactorishState {
+    /// Configure the range of the viewport scale and the ratio of a zoom step.
+    pub fn set_zoom_limits(&mut self, min: f64, max: f64, step: f64) -> Result<(), JsValue> {
+        if !(0. < min && min <= max) {
+            return js_err!("Invalid zoom range: [{}, {}]", min, max);
+        }
+        if step <= 1. {
+            return js_err!("Zoom step must be greater than 1: {}", step);
+        }
+        self.zoom.min = min;
+        self.zoom.max = max;
+        self.zoom.step = step;
+        if let Some((target, _, _)) = self.zoom.target.as_mut() {
+            *target = target.max(min).min(max);
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_zoom_keeps_anchor() {
+    let mut viewport = Viewport {
+        x: -3.,
+        y: 2.,
+        ..Viewport::default()
+    };
+    let (x, y) = (200., 120.);
+    let world = |viewport: &Viewport| viewport.screen_to_world((x, y));
+    let before = world(&viewport);
+    for scale in [1.5, 3., 0.7] {
+        zoom_at(&mut viewport, scale, x, y);
+        let after = world(&viewport);
+        assert!((before.0 - after.0).abs() < 1e-9 && (before.1 - after.1).abs() < 1e-9);
+        let screen = viewport.world_to_screen(after);
+        assert!((screen.0 - x).abs() < 1e-9 && (screen.1 - y).abs() < 1e-9);
+    }
+}